@@ -0,0 +1,26 @@
+//! This program samples resources in the background using a `Monitor`,
+//! and prints the latest values once a second.
+
+extern crate resource_monitor;
+
+use resource_monitor::{Monitor, Resource};
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    let resources = Resource::supported();
+    let monitor = Monitor::new(&resources, Duration::from_millis(250))
+        .unwrap();
+    for _ in 0..5 {
+        let snapshot = monitor.latest();
+        println!("{:?}:", snapshot.taken_at);
+        for reading in &snapshot.readings {
+            println!("  {}: limit {:?}, used {:?}, available {:?}",
+                     reading.resource,
+                     reading.limit,
+                     reading.used,
+                     reading.available);
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,172 @@
+//! A tiny command-line resource viewer.  Run it with:
+//!
+//! ```text
+//! cargo run --example resmon -- watch --interval 1s
+//! ```
+//!
+//! ```text
+//! cargo run --example resmon -- record --format jsonl memory.jsonl
+//! ```
+//!
+//! `watch` redraws a table of resources every interval, showing how much
+//! each value has changed since the last refresh, and how fast.  We show
+//! memory, swap, open sockets and CPU time.  `record` appends a sample
+//! every interval to a CSV or JSON Lines file, until it's interrupted.
+
+extern crate resource_monitor;
+
+use std::env;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use resource_monitor::{Bytes, RecordFormat, Recorder, Resource, Result,
+                       Snapshot, SnapshotDiff};
+
+/// Parse a duration like `1s`, `500ms` or `2`, which means seconds.
+fn parse_interval(s: &str) -> Result<Duration> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1.0)
+    } else {
+        (s, 1.0)
+    };
+    let value: f64 = number.parse()
+        .map_err(|_| format!("invalid interval: {}", s))?;
+    if value <= 0.0 {
+        return Err(format!("interval must be positive: {}", s).into());
+    }
+    Ok(Duration::from_secs_f64(value * scale))
+}
+
+/// Format an optional value of `resource`, in its own units.
+fn value(resource: &Resource, value: Option<u64>) -> String {
+    match (resource, value) {
+        // cgroup v1 reports "no limit" as a huge, page-aligned number
+        // rather than `u64::MAX`.
+        (_, Some(v)) if v >= 1 << 60 => "unlimited".to_owned(),
+        (&Resource::Sockets, Some(v)) => v.to_string(),
+        (&Resource::CpuTime, Some(ns)) => format!("{:.2}s", ns as f64 / 1e9),
+        (_, Some(v)) => Bytes(v).to_string(),
+        (_, None) => "-".to_owned(),
+    }
+}
+
+/// Format the change in `resource` in `diff`, and the rate per second.
+fn delta(resource: &Resource, diff: Option<&SnapshotDiff>) -> String {
+    let diff = match diff {
+        Some(diff) => diff,
+        None => return "-".to_owned(),
+    };
+    match (diff.change(resource), diff.rate(resource)) {
+        (Some(d), Some(rate)) => {
+            let sign = if d < 0 { "-" } else { "+" };
+            let size = value(resource, Some(d.unsigned_abs()));
+            let rate = value(resource, Some(rate.abs() as u64));
+            format!("{}{} ({}{}/s)", sign, size, sign, rate)
+        }
+        _ => "-".to_owned(),
+    }
+}
+
+/// Print a table of `snapshot`, compared to `prev`.
+fn draw(snapshot: &Snapshot, prev: Option<&Snapshot>) {
+    let diff = prev.map(|p| snapshot.diff(p));
+    // Clear the screen and move the cursor to the top left.
+    print!("\x1b[2J\x1b[H");
+    println!("{:<18} {:>12} {:>12} {:>12}  CHANGE",
+             "RESOURCE",
+             "LIMIT",
+             "USED",
+             "AVAILABLE");
+    for reading in &snapshot.readings {
+        let r = &reading.resource;
+        println!("{:<18} {:>12} {:>12} {:>12}  {}",
+                 format!("{:?}", r),
+                 value(r, reading.limit),
+                 value(r, reading.used),
+                 value(r, reading.available),
+                 delta(r, diff.as_ref()));
+    }
+    if let Some(diff) = diff {
+        println!();
+        println!("{}", diff);
+    }
+}
+
+/// The resources we display.
+const RESOURCES: &[Resource] = &[Resource::Memory,
+                                  Resource::OsMemory,
+                                  Resource::AllocatorMemory,
+                                  Resource::Swap,
+                                  Resource::Sockets,
+                                  Resource::CpuTime];
+
+/// Redraw our table every `interval`, forever.
+fn watch(interval: Duration) -> Result<()> {
+    let mut prev: Option<Snapshot> = None;
+    loop {
+        let snapshot = Snapshot::capture(RESOURCES);
+        draw(&snapshot, prev.as_ref());
+        prev = Some(snapshot);
+        thread::sleep(interval);
+    }
+}
+
+/// Append a sample to `path` every `interval`, forever.
+fn record(interval: Duration, format: RecordFormat, path: &str) -> Result<()> {
+    let mut recorder = Recorder::create(path, format)?;
+    loop {
+        recorder.record(&Snapshot::capture(RESOURCES))?;
+        thread::sleep(interval);
+    }
+}
+
+/// Our command-line usage.
+const USAGE: &str = "usage: resmon watch [--interval 1s]
+       resmon record [--interval 1s] [--format csv|jsonl] FILE";
+
+/// Parse our command line and run the requested subcommand.
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = args.first().map(|s| s.as_str());
+    let mut interval = Duration::from_secs(1);
+    let mut format = RecordFormat::Csv;
+    let mut files = vec![];
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--interval" | "-n" => {
+                let value = rest.next().ok_or("--interval requires a value")?;
+                interval = parse_interval(value)?;
+            }
+            "--format" => {
+                format = match rest.next().map(|s| s.as_str()) {
+                    Some("csv") => RecordFormat::Csv,
+                    Some("jsonl") => RecordFormat::JsonLines,
+                    _ => return Err("--format must be csv or jsonl".into()),
+                };
+            }
+            other if other.starts_with('-') => {
+                return Err(format!("unknown argument: {}", other).into())
+            }
+            file => files.push(file),
+        }
+    }
+    match (command, files.as_slice()) {
+        (Some("watch"), []) => watch(interval),
+        (Some("record"), [path]) => record(interval, format, path),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Call `run` and print out any errors.  We do this so that we can use `?`
+/// in `run`, because `?` only works in a function that returns a
+/// `Result`.
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
@@ -1,9 +1,9 @@
 //! This program will attempt to use most available memory.
 
-#[macro_use]
-extern crate error_chain;
 extern crate resource_monitor;
 
+use std::process;
+
 use resource_monitor::{Resource, Result};
 
 fn run() -> Result<()> {
@@ -29,8 +29,12 @@ fn run() -> Result<()> {
     Ok(())
 }
 
-/// Allow `error_chain` to declare a `main` function that calls `run`
-/// and prints out any errors.  We basically do this so that so that
-/// we can use `?` in `run`, because `?` only works in a function that
-/// returns a `Result`, and `main` doesn't.
-quick_main!(run);
+/// Call `run` and print out any errors.  We do this so that we can use `?`
+/// in `run`, because `?` only works in a function that returns a
+/// `Result`.
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
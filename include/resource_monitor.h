@@ -0,0 +1,85 @@
+#ifndef RESOURCE_MONITOR_H
+#define RESOURCE_MONITOR_H
+
+/* Generated by cbindgen from src/ffi.rs.  Do not edit. */
+
+#include <stdint.h>
+
+/*
+ The call succeeded.
+ */
+#define RM_OK 0
+
+/*
+ This system can't measure the value, such as when there's no memory
+ cgroup and no other source we can use.
+ */
+#define RM_UNSUPPORTED -1
+
+/*
+ Reading the value failed, possibly temporarily.
+ */
+#define RM_ERROR -2
+
+/*
+ A pointer argument was null.
+ */
+#define RM_NULL_POINTER -3
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Store the memory limit of this process, in bytes, in `*out`.  Returns
+ `RM_OK` on success, or a negative error code.
+
+ # Safety
+
+ `out` must be null or point to a writable `uint64_t`.
+ */
+int rm_memory_limit(uint64_t *out);
+
+/*
+ Store the memory used by this process, in bytes, in `*out`.  Returns
+ `RM_OK` on success, or a negative error code.
+
+ # Safety
+
+ `out` must be null or point to a writable `uint64_t`.
+ */
+int rm_memory_used(uint64_t *out);
+
+/*
+ Store the memory still available to this process, in bytes, in
+ `*out`.  Returns `RM_OK` on success, or a negative error code.
+
+ # Safety
+
+ `out` must be null or point to a writable `uint64_t`.
+ */
+int rm_memory_available(uint64_t *out);
+
+/*
+ Take a snapshot of every resource we know about, and return it as a
+ JSON object, in the same format as `RecordFormat::JsonLines`.  The
+ caller must free the string using `rm_string_free`.  Returns null if
+ the string can't be allocated.
+ */
+char *rm_snapshot_json(void);
+
+/*
+ Free a string returned by this library.  Passing null does nothing.
+
+ # Safety
+
+ `s` must be null, or a string returned by this library which hasn't
+ already been freed.
+ */
+void rm_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RESOURCE_MONITOR_H */
@@ -0,0 +1,36 @@
+//! Virtual address space, which 32-bit processes, and 64-bit processes
+//! which map large files or reserve big arenas, can run out of long before
+//! they run out of RAM.
+
+use std::path::Path;
+
+use cgroup;
+use errors::*;
+use rlimit::{self, Rlimit};
+
+/// How much address space may we map?  This is our `RLIMIT_AS`.
+pub fn limit() -> Result<u64> {
+    rlimit::soft_limit(Rlimit::AddressSpace)
+}
+
+/// How much address space have we mapped?  This is `VmSize` in
+/// `/proc/self/status`.
+pub fn used() -> Result<u64> {
+    status_value("VmSize:")
+}
+
+/// The most address space we've ever had mapped.  This is `VmPeak` in
+/// `/proc/self/status`.
+pub fn peak() -> Result<u64> {
+    status_value("VmPeak:")
+}
+
+/// Read a value in kilobytes from `/proc/self/status`, in bytes.
+fn status_value(key: &str) -> Result<u64> {
+    let path = Path::new("/proc/self/status");
+    let status = cgroup::read_keyed_file(path)?;
+    match status.get(key) {
+        Some(&kb) => Ok(kb * 1024),
+        None => Err(ErrorKind::Parse(path.to_owned()).into()),
+    }
+}
@@ -0,0 +1,193 @@
+//! One-call checks for whether a large allocation or write is likely to
+//! fit.
+
+use std::path::Path;
+
+use errors::*;
+use quota::DiskQuota;
+use read_cache::ReadCache;
+use tmpfs::FsSpace;
+use Resource;
+
+/// How much memory or disk space we try to keep free, as a fraction of the
+/// limit or an absolute number of bytes, whichever is larger.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafetyMargin {
+    /// A fraction of the limit to keep free.  This is
+    /// `Resource::Memory.limit()` for `can_allocate`, and the size of the
+    /// filesystem or our quota, whichever is smaller, for `can_write_to`.
+    pub fraction: f64,
+    /// A minimum number of bytes to keep free.
+    pub min_bytes: u64,
+}
+
+impl Default for SafetyMargin {
+    fn default() -> SafetyMargin {
+        SafetyMargin {
+            fraction: 0.05,
+            min_bytes: 0,
+        }
+    }
+}
+
+impl SafetyMargin {
+    /// The number of bytes to keep free, given `limit`.
+    fn bytes(&self, limit: u64) -> u64 {
+        ((limit as f64 * self.fraction) as u64).max(self.min_bytes)
+    }
+}
+
+/// Whether an allocation or write is likely to fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationVerdict {
+    /// There's plenty of room.
+    Yes,
+    /// It fits, but would leave less than twice our safety margin free.
+    Tight,
+    /// It would eat into our safety margin.
+    No,
+}
+
+/// The result of `can_allocate`, including the numbers behind it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocationCheck {
+    /// Our verdict.
+    pub verdict: AllocationVerdict,
+    /// The number of bytes we were asked about.
+    pub requested: u64,
+    /// `Resource::Memory.available()`, which includes free memory inside
+    /// the heap allocator.
+    pub available: u64,
+    /// The number of bytes we wanted to keep free.
+    pub margin: u64,
+}
+
+impl AllocationCheck {
+    /// Is the verdict anything other than `No`?
+    pub fn is_allowed(&self) -> bool {
+        self.verdict != AllocationVerdict::No
+    }
+}
+
+/// Could we allocate `bytes` right now without eating into the default
+/// `SafetyMargin` of 5% of our memory limit?
+///
+/// ```
+/// use resource_monitor::{can_allocate, AllocationVerdict, Bytes};
+///
+/// let check = can_allocate(512 * Bytes::MIB).unwrap();
+/// if check.verdict == AllocationVerdict::No {
+///     println!("Not enough memory for a 512 MiB buffer");
+/// }
+/// ```
+pub fn can_allocate(bytes: u64) -> Result<AllocationCheck> {
+    can_allocate_with_margin(bytes, &SafetyMargin::default())
+}
+
+/// Like `can_allocate`, but with a custom `SafetyMargin`.
+pub fn can_allocate_with_margin(bytes: u64,
+                                margin: &SafetyMargin)
+                                -> Result<AllocationCheck> {
+    let mut cache = ReadCache::new();
+    let limit = Resource::Memory.limit_with(&mut cache)?;
+    let available = Resource::Memory.available_with(&mut cache)?;
+    let margin = margin.bytes(limit);
+    Ok(AllocationCheck {
+        verdict: verdict(bytes, available, margin),
+        requested: bytes,
+        available,
+        margin,
+    })
+}
+
+/// The result of `can_write_to`, including the numbers behind it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteCheck {
+    /// Our verdict.
+    pub verdict: AllocationVerdict,
+    /// The number of bytes we were asked about.
+    pub requested: u64,
+    /// The space we may still use: the free space on the filesystem, or
+    /// what's left of our quota, whichever is smaller.
+    pub available: u64,
+    /// Free space on the filesystem, not counting space reserved for root.
+    pub fs_available: u64,
+    /// What's left of our disk quota, or `None` if no quota applies.
+    pub quota_available: Option<u64>,
+    /// The number of files we may still create, allowing for both the
+    /// filesystem and our quota.  If this is zero, the verdict is `No`.
+    pub inodes_available: u64,
+    /// The number of bytes we wanted to keep free.
+    pub margin: u64,
+}
+
+impl WriteCheck {
+    /// Is the verdict anything other than `No`?
+    pub fn is_allowed(&self) -> bool {
+        self.verdict != AllocationVerdict::No
+    }
+}
+
+/// Could we write `bytes` to new files in the directory `path` right now,
+/// without eating into the default `SafetyMargin` of 5% of the space we're
+/// allowed?  This allows for free space, free inodes, and any project or
+/// user quota on the filesystem, so that code which spills to disk can
+/// check its plan before starting a long write.
+///
+/// ```no_run
+/// use resource_monitor::{can_write_to, Bytes};
+///
+/// let check = can_write_to("/var/tmp", 4 * Bytes::GIB).unwrap();
+/// if !check.is_allowed() {
+///     println!("Not enough disk space to spill 4 GiB");
+/// }
+/// ```
+pub fn can_write_to<P: AsRef<Path>>(path: P,
+                                    bytes: u64)
+                                    -> Result<WriteCheck> {
+    can_write_to_with_margin(path, bytes, &SafetyMargin::default())
+}
+
+/// Like `can_write_to`, but with a custom `SafetyMargin`.
+pub fn can_write_to_with_margin<P: AsRef<Path>>(path: P,
+                                                bytes: u64,
+                                                margin: &SafetyMargin)
+                                                -> Result<WriteCheck> {
+    let path = path.as_ref();
+    let space = FsSpace::read(path)?;
+    let quota = match DiskQuota::for_path(path) {
+        Ok(quota) => Some(quota),
+        Err(ref e) if e.is_unsupported() => None,
+        Err(e) => return Err(e),
+    };
+    let quota_available = quota.as_ref().map(|q| q.available());
+    let available = space.available.min(quota_available.unwrap_or(u64::MAX));
+    let inodes_available = space.inodes_available
+        .min(quota.as_ref().map_or(u64::MAX, |q| q.inodes_available()));
+    let limit = space.total.min(quota.as_ref().map_or(u64::MAX, |q| q.limit()));
+    let margin = margin.bytes(limit);
+    let verdict = match inodes_available {
+        0 => AllocationVerdict::No,
+        _ => verdict(bytes, available, margin),
+    };
+    Ok(WriteCheck {
+        verdict,
+        requested: bytes,
+        available,
+        fs_available: space.available,
+        quota_available,
+        inodes_available,
+        margin,
+    })
+}
+
+/// Decide whether `bytes` fits in `available`, leaving `margin` free.
+fn verdict(bytes: u64, available: u64, margin: u64) -> AllocationVerdict {
+    match available.checked_sub(bytes) {
+        Some(left) if left >= margin.saturating_mul(2) => {
+            AllocationVerdict::Yes
+        }
+        Some(left) if left >= margin => AllocationVerdict::Tight,
+        _ => AllocationVerdict::No,
+    }
+}
@@ -0,0 +1,65 @@
+//! Heap usage stats from our own `CountingAllocator`.
+
+use std::io::{self, Write};
+
+use counting_allocator::{allocation_counts, reset_peak_bytes};
+use errors::*;
+use super::{AllocatorBackend, StatsOptions};
+
+/// A `CountingAllocator` installed as the global allocator.
+///
+/// Since we only see the bytes requested by the application, we report
+/// them as both `used` and `reserved`.
+pub struct Counting;
+
+impl Counting {
+    /// Return the number of live bytes.
+    fn live_bytes(&self) -> Result<u64> {
+        allocation_counts()
+            .map(|c| c.live_bytes)
+            .ok_or_else(|| ErrorKind::AllocatorUnavailable.into())
+    }
+}
+
+impl AllocatorBackend for Counting {
+    fn name(&self) -> &'static str {
+        "counting"
+    }
+
+    fn stats_enabled(&self) -> bool {
+        allocation_counts().is_some()
+    }
+
+    fn used(&self) -> Result<u64> {
+        self.live_bytes()
+    }
+
+    fn reserved(&self) -> Result<u64> {
+        self.live_bytes()
+    }
+
+    fn peak(&self) -> Option<u64> {
+        allocation_counts().map(|c| c.peak_bytes)
+    }
+
+    fn reset_peak(&self) {
+        reset_peak_bytes()
+    }
+
+    fn allocated_and_freed(&self) -> Option<(u64, u64)> {
+        allocation_counts().map(|c| (c.allocated_bytes, c.freed_bytes))
+    }
+
+    fn write_stats(&self,
+                   out: &mut dyn Write,
+                   _options: &StatsOptions)
+                   -> io::Result<()> {
+        if let Some(counts) = allocation_counts() {
+            writeln!(out, "Live bytes: {}", counts.live_bytes)?;
+            writeln!(out, "Peak bytes: {}", counts.peak_bytes)?;
+            writeln!(out, "Allocations: {}", counts.allocations)?;
+            writeln!(out, "Deallocations: {}", counts.deallocations)?;
+        }
+        Ok(())
+    }
+}
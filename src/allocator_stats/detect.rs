@@ -0,0 +1,130 @@
+//! Working out which heap allocator this process is actually using.
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fmt;
+use std::sync::OnceLock;
+
+use super::BACKENDS;
+
+/// A heap allocator which we might find in a process.  This type may be
+/// extended with new variants; do not attempt to exhaustively match
+/// against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum AllocatorKind {
+    /// jemalloc, including `jemallocator` and `tikv-jemallocator`.
+    Jemalloc,
+    /// The `malloc` in the GNU C library.
+    Glibc,
+    /// The `malloc` in the musl C library, which has no stats API.
+    Musl,
+    /// Microsoft's mimalloc.
+    Mimalloc,
+    /// gperftools' tcmalloc.
+    Tcmalloc,
+    /// We couldn't tell.
+    Unknown,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+impl AllocatorKind {
+    /// The name of this allocator, which matches
+    /// `AllocatorBackend::name` for allocators we can query.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AllocatorKind::Jemalloc => "jemalloc",
+            AllocatorKind::Glibc => "glibc",
+            AllocatorKind::Musl => "musl",
+            AllocatorKind::Mimalloc => "mimalloc",
+            AllocatorKind::Tcmalloc => "tcmalloc",
+            AllocatorKind::Unknown => "unknown",
+            AllocatorKind::__Private => {
+                unreachable!("Do not use AllocatorKind::__Private")
+            }
+        }
+    }
+}
+
+impl fmt::Display for AllocatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Symbols which only exist when a given allocator is linked in, with or
+/// without the prefixes used by the Rust wrapper crates.
+const SIGNATURES: &[(AllocatorKind, &[&str])] = &[
+    (AllocatorKind::Jemalloc, &["mallctl", "je_mallctl", "_rjem_mallctl"]),
+    (AllocatorKind::Mimalloc, &["mi_version"]),
+    (AllocatorKind::Tcmalloc,
+     &["MallocExtension_GetNumericProperty", "tc_malloc"]),
+];
+
+/// Can the dynamic linker find `name`?  This only sees symbols exported
+/// from shared libraries, or from the executable if it was linked with
+/// `-rdynamic`.
+#[cfg(unix)]
+fn has_symbol(name: &str) -> bool {
+    let name = CString::new(name).unwrap();
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+/// Without a dynamic linker, we can't look up symbols.
+#[cfg(not(unix))]
+fn has_symbol(_name: &str) -> bool {
+    false
+}
+
+/// Work out which heap allocator this process is using.
+///
+/// We check, in order, for allocator backends compiled into this crate
+/// which report working stats, for symbols which only a particular
+/// allocator defines, and finally for the C library's own `malloc`.  An
+/// allocator linked statically into the executable, as Rust allocator
+/// crates usually are, is invisible to the dynamic linker, so we may
+/// report `Glibc` for a program whose `#[global_allocator]` is something
+/// else that this crate wasn't built to query.  The result is computed
+/// once and cached.
+pub fn detect_allocator() -> AllocatorKind {
+    static DETECTED: OnceLock<AllocatorKind> = OnceLock::new();
+    *DETECTED.get_or_init(|| {
+        // Backends other than glibc call functions which only exist if
+        // their allocator is linked in, so if one works, it's in use.
+        for backend in BACKENDS {
+            if backend.name() != "glibc" && backend.stats_enabled() {
+                if let Some(kind) = kind_named(backend.name()) {
+                    return kind;
+                }
+            }
+        }
+        for &(kind, symbols) in SIGNATURES {
+            if symbols.iter().any(|s| has_symbol(s)) {
+                return kind;
+            }
+        }
+        if cfg!(target_env = "musl") {
+            AllocatorKind::Musl
+        } else if has_symbol("gnu_get_libc_version") {
+            AllocatorKind::Glibc
+        } else {
+            AllocatorKind::Unknown
+        }
+    })
+}
+
+/// Find the `AllocatorKind` called `name`.
+fn kind_named(name: &str) -> Option<AllocatorKind> {
+    [AllocatorKind::Jemalloc,
+     AllocatorKind::Glibc,
+     AllocatorKind::Musl,
+     AllocatorKind::Mimalloc,
+     AllocatorKind::Tcmalloc]
+        .iter()
+        .cloned()
+        .find(|k| k.name() == name)
+}
@@ -0,0 +1,91 @@
+//! Heap usage stats for the standard glibc `malloc`.
+
+use libc::{self, c_char, c_int, c_void, size_t, FILE};
+use std::io::{self, Write};
+use std::ptr;
+use std::slice;
+
+use errors::*;
+use super::{trim_glibc, AllocatorBackend, StatsOptions};
+
+/// The glibc `struct mallinfo2`, which unlike the older `mallinfo` uses
+/// `size_t` fields and therefore doesn't overflow past 2 GB.  Available
+/// in glibc 2.33 and later.
+#[repr(C)]
+#[allow(dead_code)]
+struct Mallinfo2 {
+    arena: size_t,
+    ordblks: size_t,
+    smblks: size_t,
+    hblks: size_t,
+    hblkhd: size_t,
+    usmblks: size_t,
+    fsmblks: size_t,
+    uordblks: size_t,
+    fordblks: size_t,
+    keepcost: size_t,
+}
+
+extern "C" {
+    fn mallinfo2() -> Mallinfo2;
+    fn malloc_info(options: c_int, stream: *mut FILE) -> c_int;
+    fn open_memstream(ptr: *mut *mut c_char, sizeloc: *mut size_t)
+                      -> *mut FILE;
+}
+
+/// The glibc allocator.
+pub struct Glibc;
+
+impl AllocatorBackend for Glibc {
+    fn name(&self) -> &'static str {
+        "glibc"
+    }
+
+    fn stats_enabled(&self) -> bool {
+        true
+    }
+
+    fn used(&self) -> Result<u64> {
+        // Bytes handed out from the main heap, plus bytes in blocks
+        // allocated directly with `mmap`.
+        let info = unsafe { mallinfo2() };
+        Ok(info.uordblks as u64 + info.hblkhd as u64)
+    }
+
+    fn reserved(&self) -> Result<u64> {
+        // Bytes obtained from the OS using `sbrk`, plus `mmap`ed blocks.
+        let info = unsafe { mallinfo2() };
+        Ok(info.arena as u64 + info.hblkhd as u64)
+    }
+
+    fn write_stats(&self,
+                   out: &mut dyn Write,
+                   _options: &StatsOptions)
+                   -> io::Result<()> {
+        // `malloc_stats` always prints to standard error, so we ask
+        // `malloc_info` to write its XML into a memory buffer instead.
+        let mut buf: *mut c_char = ptr::null_mut();
+        let mut len: size_t = 0;
+        let xml = unsafe {
+            let stream = open_memstream(&mut buf, &mut len);
+            if stream.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let result = malloc_info(0, stream);
+            // Closing the stream sets `buf` and `len`.
+            libc::fclose(stream);
+            if result != 0 {
+                libc::free(buf as *mut c_void);
+                return Err(io::Error::last_os_error());
+            }
+            slice::from_raw_parts(buf as *const u8, len).to_vec()
+        };
+        unsafe { libc::free(buf as *mut c_void) };
+        out.write_all(&xml)
+    }
+
+    fn purge(&self) -> Result<()> {
+        trim_glibc();
+        Ok(())
+    }
+}
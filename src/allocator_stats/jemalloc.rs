@@ -0,0 +1,357 @@
+//! Heap usage stats for jemalloc, using the `mallctl` API.
+
+use libc::{c_char, c_int, c_void, size_t};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::ptr;
+
+use errors::*;
+use super::{auto_refresh_enabled, AllocatorBackend, StatsOptions, StatsSink};
+
+type MallocStatsCallback =
+    unsafe extern "C" fn(*mut c_void, *const c_char);
+
+extern "C" {
+    // Print out current jemalloc stats.
+    fn malloc_stats_print(cb: MallocStatsCallback,
+                          cbopaque: *mut c_void,
+                          opts: *const c_char);
+
+    /// Access the jemalloc API using the C FFI.
+    fn mallctl(name: *const c_char,
+               oldp: *mut c_void,
+               oldlenp: *mut size_t,
+               newp: *mut c_void,
+               newlen: size_t)
+               -> c_int;
+}
+
+/// Fetch a jemalloc internal value.
+unsafe fn mallctl_read<T: Default>(name: &str) -> Result<T> {
+    let key = CString::new(name).unwrap();
+    let mut old: T = T::default();
+    let mut oldlen: size_t = size_of::<T>();
+    let err =
+        mallctl(key.as_ptr(),
+                ((&mut old) as *mut T) as *mut c_void,
+                &mut oldlen as *mut _,
+                ptr::null_mut(),
+                0);
+    if err != 0 {
+        return Err("could not access jemalloc internal data".into());
+    }
+    Ok(old)
+}
+
+/// Advance the jemalloc epoch, which causes jemalloc to refresh the
+/// cached values it returns for `stats.*`.  Without this, jemalloc may
+/// keep returning the same numbers indefinitely.
+fn advance_epoch() -> Result<()> {
+    let key = CString::new("epoch").unwrap();
+    let mut epoch: u64 = 1;
+    let mut len: size_t = size_of::<u64>();
+    let err = unsafe {
+        mallctl(key.as_ptr(),
+                (&mut epoch as *mut u64) as *mut c_void,
+                &mut len as *mut _,
+                (&mut epoch as *mut u64) as *mut c_void,
+                len)
+    };
+    if err != 0 {
+        return Err("could not advance jemalloc epoch".into());
+    }
+    Ok(())
+}
+
+/// Run a jemalloc command which takes no arguments, such as
+/// `arena.<i>.purge`.
+fn mallctl_run(name: &str) -> Result<()> {
+    let key = CString::new(name).unwrap();
+    let err = unsafe {
+        mallctl(key.as_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0)
+    };
+    if err != 0 {
+        return Err(format!("could not run jemalloc {}", name).into());
+    }
+    Ok(())
+}
+
+/// Read a `size_t` jemalloc statistic as a `u64`.
+fn read_stat(name: &str) -> Result<u64> {
+    unsafe { mallctl_read::<size_t>(name) }.map(|v| v as u64)
+}
+
+/// Statistics for a single jemalloc arena.  Threads are assigned to
+/// arenas round-robin, so in a multi-threaded server, comparing these
+/// shows which arena is holding on to memory.  Byte counts which this
+/// version of jemalloc doesn't report are zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArenaStats {
+    /// The index of this arena, as used in `stats.arenas.<i>.*`.
+    pub index: u32,
+    /// Number of threads currently assigned to this arena.
+    pub threads: u32,
+    /// Pages of unused memory which have not yet been purged.
+    pub dirty_pages: u64,
+    /// Pages which have been lazily purged, but which the OS may not yet
+    /// have reclaimed.
+    pub muzzy_pages: u64,
+    /// Bytes allocated by small size classes, which are carved out of
+    /// slabs.
+    pub small_allocated: u64,
+    /// Bytes allocated by large size classes, which get their own extents.
+    pub large_allocated: u64,
+    /// Bytes cached in the thread caches of this arena's threads, which
+    /// count as allocated but aren't in use by the application.
+    pub tcache_bytes: u64,
+    /// Bytes of virtual memory which this arena retained rather than
+    /// returning to the OS.
+    pub retained: u64,
+}
+
+impl ArenaStats {
+    /// Read the stats of arena `index`.  Returns `None` if the arena
+    /// hasn't been initialized, and so has no stats.
+    fn read(index: u32) -> Option<ArenaStats> {
+        let stat = |name: &str| {
+            read_stat(&format!("stats.arenas.{}.{}", index, name))
+        };
+        let dirty_pages = stat("pdirty").ok()?;
+        let muzzy_pages = stat("pmuzzy").ok()?;
+        let threads = unsafe {
+            mallctl_read::<u32>(&format!("stats.arenas.{}.nthreads", index))
+        };
+        Some(ArenaStats {
+            index,
+            threads: threads.unwrap_or(0),
+            dirty_pages,
+            muzzy_pages,
+            small_allocated: stat("small.allocated").unwrap_or(0),
+            large_allocated: stat("large.allocated").unwrap_or(0),
+            tcache_bytes: stat("tcache_bytes").unwrap_or(0),
+            retained: stat("retained").unwrap_or(0),
+        })
+    }
+}
+
+/// A point-in-time copy of jemalloc's global statistics, in bytes. See
+/// the `stats.*` entries in http://jemalloc.net/jemalloc.3.html for the
+/// exact definitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JemallocStats {
+    /// Bytes allocated by the application.
+    pub allocated: u64,
+    /// Bytes in active pages allocated by the application.
+    pub active: u64,
+    /// Bytes in active extents mapped by the allocator.
+    pub mapped: u64,
+    /// Bytes in physically resident data pages mapped by the allocator.
+    pub resident: u64,
+    /// Bytes in virtual memory mappings that were retained rather than
+    /// being returned to the OS.
+    pub retained: u64,
+    /// Bytes dedicated to allocator metadata.
+    pub metadata: u64,
+    /// Per-arena page statistics.
+    pub arenas: Vec<ArenaStats>,
+}
+
+impl JemallocStats {
+    /// Read the current allocator statistics.
+    pub fn snapshot() -> Result<JemallocStats> {
+        if auto_refresh_enabled() {
+            advance_epoch()?;
+        }
+        let narenas = unsafe { mallctl_read::<u32>("arenas.narenas") }?;
+        let mut arenas = Vec::with_capacity(narenas as usize);
+        // Arenas which haven't been initialized yet have no stats, so we
+        // just skip them.
+        arenas.extend((0..narenas).filter_map(ArenaStats::read));
+        Ok(JemallocStats {
+            allocated: read_stat("stats.allocated")?,
+            active: read_stat("stats.active")?,
+            mapped: read_stat("stats.mapped")?,
+            resident: read_stat("stats.resident")?,
+            retained: read_stat("stats.retained")?,
+            metadata: read_stat("stats.metadata")?,
+            arenas,
+        })
+    }
+
+    /// Total dirty pages across all arenas.
+    pub fn dirty_pages(&self) -> u64 {
+        self.arenas.iter().map(|a| a.dirty_pages).sum()
+    }
+
+    /// Total muzzy pages across all arenas.
+    pub fn muzzy_pages(&self) -> u64 {
+        self.arenas.iter().map(|a| a.muzzy_pages).sum()
+    }
+
+    /// Total bytes cached in thread caches across all arenas.
+    pub fn tcache_bytes(&self) -> u64 {
+        self.arenas.iter().map(|a| a.tcache_bytes).sum()
+    }
+
+    /// The arena retaining the most memory, if any.
+    pub fn arena_retaining_most(&self) -> Option<&ArenaStats> {
+        self.arenas.iter().max_by_key(|a| a.retained)
+    }
+
+    /// How have our statistics changed since `earlier`?
+    pub fn diff(&self, earlier: &JemallocStats) -> JemallocStatsDiff {
+        let delta = |now: u64, then: u64| now as i64 - then as i64;
+        JemallocStatsDiff {
+            allocated: delta(self.allocated, earlier.allocated),
+            active: delta(self.active, earlier.active),
+            mapped: delta(self.mapped, earlier.mapped),
+            resident: delta(self.resident, earlier.resident),
+            retained: delta(self.retained, earlier.retained),
+            metadata: delta(self.metadata, earlier.metadata),
+            dirty_pages: delta(self.dirty_pages(), earlier.dirty_pages()),
+            muzzy_pages: delta(self.muzzy_pages(), earlier.muzzy_pages()),
+        }
+    }
+}
+
+/// The change between two `JemallocStats` snapshots. Per-arena values are
+/// summed, because arenas may come and go between snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JemallocStatsDiff {
+    /// Change in `allocated`.
+    pub allocated: i64,
+    /// Change in `active`.
+    pub active: i64,
+    /// Change in `mapped`.
+    pub mapped: i64,
+    /// Change in `resident`.
+    pub resident: i64,
+    /// Change in `retained`.
+    pub retained: i64,
+    /// Change in `metadata`.
+    pub metadata: i64,
+    /// Change in total dirty pages.
+    pub dirty_pages: i64,
+    /// Change in total muzzy pages.
+    pub muzzy_pages: i64,
+}
+
+/// The arena index which means "all arenas" (`MALLCTL_ARENAS_ALL`).
+const ALL_ARENAS: u32 = 4096;
+
+/// Callback used to dump statistics into the `StatsSink` pointed to by
+/// `sink`.
+unsafe extern "C" fn dumpstat(sink: *mut c_void, msg: *const c_char) {
+    (*(sink as *mut StatsSink)).write_c_str(msg);
+}
+
+/// Translate `options` into a `malloc_stats_print` options string, which
+/// lists the sections to leave out.
+fn opts_string(options: &StatsOptions) -> String {
+    let mut opts = String::new();
+    if options.json {
+        opts.push('J');
+    }
+    if !options.per_arena {
+        opts.push('a');
+    }
+    if options.minimal {
+        // General info, bins, large size classes, mutexes and extents.
+        opts.push_str("gblxe");
+    }
+    opts
+}
+
+/// Read jemalloc's statistics as JSON, using the `J` option of
+/// `malloc_stats_print`, so that dashboards can chart its internals
+/// without scraping the text output.  `options` chooses which sections
+/// to include, and is always treated as asking for JSON.  See the
+/// jemalloc documentation for the layout, which varies between versions.
+///
+/// ```no_run
+/// use resource_monitor::{jemalloc_stats_json, StatsOptions};
+///
+/// let stats = jemalloc_stats_json(StatsOptions::new().per_arena(false))
+///     .unwrap();
+/// println!("{}", stats["jemalloc"]["stats"]["resident"]);
+/// ```
+#[cfg(feature = "serde_json")]
+pub fn stats_json(options: StatsOptions) -> Result<::serde_json::Value> {
+    if auto_refresh_enabled() {
+        advance_epoch()?;
+    }
+    let mut json = vec![];
+    Jemalloc.write_stats(&mut json, &options.json(true))
+        .chain_err(|| "could not read jemalloc stats")?;
+    ::serde_json::from_slice(&json)
+        .chain_err(|| "could not parse jemalloc stats JSON")
+}
+
+/// The jemalloc allocator.
+pub struct Jemalloc;
+
+impl AllocatorBackend for Jemalloc {
+    fn name(&self) -> &'static str {
+        "jemalloc"
+    }
+
+    fn stats_enabled(&self) -> bool {
+        let enabled =
+            unsafe { mallctl_read::<u8>("config.stats") }.unwrap_or(0);
+        enabled != 0
+    }
+
+    fn refresh(&self) -> Result<()> {
+        advance_epoch()
+    }
+
+    fn used(&self) -> Result<u64> {
+        // We might prefer "stats.cactive" (it's faster and more
+        // conservative), but that requires messing around with an atomic
+        // pointer read.
+        read_stat("stats.active")
+    }
+
+    fn allocated_and_freed(&self) -> Option<(u64, u64)> {
+        // These are per-thread counters, which jemalloc keeps up to date
+        // without needing a new epoch.
+        unsafe {
+            let allocated = mallctl_read::<u64>("thread.allocated").ok()?;
+            let freed = mallctl_read::<u64>("thread.deallocated").ok()?;
+            Some((allocated, freed))
+        }
+    }
+
+    fn reserved(&self) -> Result<u64> {
+        // TODO: See http://jemalloc.net/jemalloc.3.html, which lists some
+        // other values we might want to check.  This is an underestimate
+        // of RAM we have in use.
+        read_stat("stats.mapped")
+    }
+
+    fn write_stats(&self,
+                   out: &mut dyn Write,
+                   options: &StatsOptions)
+                   -> io::Result<()> {
+        let opts = CString::new(opts_string(options)).unwrap();
+        let mut sink = StatsSink::new(out);
+        unsafe {
+            malloc_stats_print(dumpstat,
+                               &mut sink as *mut StatsSink as *mut c_void,
+                               opts.as_ptr());
+        }
+        sink.result
+    }
+
+    fn purge(&self) -> Result<()> {
+        mallctl_run(&format!("arena.{}.purge", ALL_ARENAS))
+    }
+}
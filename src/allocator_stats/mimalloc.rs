@@ -0,0 +1,83 @@
+//! Heap usage stats for Microsoft's mimalloc.
+
+use libc::{c_char, c_void, size_t};
+use std::io::{self, Write};
+use std::ptr;
+
+use errors::*;
+use super::{AllocatorBackend, StatsOptions, StatsSink};
+
+type MiOutputFun = unsafe extern "C" fn(*const c_char, *mut c_void);
+
+extern "C" {
+    fn mi_process_info(elapsed_msecs: *mut size_t,
+                       user_msecs: *mut size_t,
+                       system_msecs: *mut size_t,
+                       current_rss: *mut size_t,
+                       peak_rss: *mut size_t,
+                       current_commit: *mut size_t,
+                       peak_commit: *mut size_t,
+                       page_faults: *mut size_t);
+
+    fn mi_stats_print_out(out: MiOutputFun, arg: *mut c_void);
+}
+
+/// Return the number of bytes mimalloc has committed.
+fn current_commit() -> u64 {
+    let mut commit: size_t = 0;
+    unsafe {
+        mi_process_info(ptr::null_mut(),
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                        &mut commit,
+                        ptr::null_mut(),
+                        ptr::null_mut());
+    }
+    commit as u64
+}
+
+/// Callback used to dump statistics into the `StatsSink` pointed to by
+/// `sink`.
+unsafe extern "C" fn dumpstat(msg: *const c_char, sink: *mut c_void) {
+    (*(sink as *mut StatsSink)).write_c_str(msg);
+}
+
+/// The mimalloc allocator.
+///
+/// mimalloc doesn't cheaply expose the number of live bytes, so we report
+/// committed memory as both `used` and `reserved`.  This means that
+/// `Resource::AllocatorMemory.available()` will always be zero, which is
+/// conservative but safe.
+pub struct Mimalloc;
+
+impl AllocatorBackend for Mimalloc {
+    fn name(&self) -> &'static str {
+        "mimalloc"
+    }
+
+    fn stats_enabled(&self) -> bool {
+        true
+    }
+
+    fn used(&self) -> Result<u64> {
+        Ok(current_commit())
+    }
+
+    fn reserved(&self) -> Result<u64> {
+        Ok(current_commit())
+    }
+
+    fn write_stats(&self,
+                   out: &mut dyn Write,
+                   _options: &StatsOptions)
+                   -> io::Result<()> {
+        let mut sink = StatsSink::new(out);
+        unsafe {
+            mi_stats_print_out(dumpstat,
+                               &mut sink as *mut StatsSink as *mut c_void);
+        }
+        sink.result
+    }
+}
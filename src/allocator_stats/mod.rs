@@ -0,0 +1,381 @@
+//! Internal interface to our allocator, which attemps to get heap
+//! usage stats.
+//!
+//! Each supported allocator is wrapped in an `AllocatorBackend`.  Which
+//! backends get compiled in is controlled by cargo features, because we
+//! can only call into an allocator's stats API if it has actually been
+//! linked into the binary.  If more than one backend is available, we
+//! pick the first one which reports working statistics at runtime.  If
+//! none of them work, but a `CountingAllocator` has been installed, we fall
+//! back to its counts.  Otherwise, we return
+//! `ErrorKind::UnsupportedAllocator` if we know which allocator is in use,
+//! or `ErrorKind::AllocatorUnavailable` if we don't.
+
+use std::os::raw::c_char;
+#[cfg(target_env = "gnu")]
+use std::os::raw::c_int;
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use cache_policy::clear_cached_values;
+use errors::*;
+use Resource;
+
+#[cfg(feature = "jemalloc")]
+pub use self::jemalloc::{ArenaStats, JemallocStats, JemallocStatsDiff};
+#[cfg(all(feature = "jemalloc", feature = "serde_json"))]
+pub use self::jemalloc::stats_json as jemalloc_stats_json;
+
+pub use self::detect::{detect_allocator, AllocatorKind};
+
+mod counting;
+mod detect;
+#[cfg(feature = "jemalloc")]
+mod jemalloc;
+// The glibc backend needs glibc itself, so it does nothing on musl.
+#[cfg(all(feature = "glibc", target_env = "gnu"))]
+mod glibc;
+#[cfg(feature = "mimalloc")]
+mod mimalloc;
+#[cfg(feature = "tcmalloc")]
+mod tcmalloc;
+
+/// An interface to a specific heap allocator's statistics.
+pub trait AllocatorBackend: Sync {
+    /// A short, human-readable name for this allocator, like `"jemalloc"`.
+    fn name(&self) -> &'static str;
+
+    /// Does this allocator appear to be present and collecting stats?
+    fn stats_enabled(&self) -> bool;
+
+    /// Ask the allocator to update any cached statistics.
+    fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// How much memory is the allocator currently using for actual user
+    /// data?
+    fn used(&self) -> Result<u64>;
+
+    /// How much total memory has the allocator reserved for user
+    /// allocations?
+    fn reserved(&self) -> Result<u64>;
+
+    /// What is the most memory the allocator has ever used?  Return `None`
+    /// if the allocator doesn't keep track, and we'll estimate it by
+    /// sampling `used`.
+    fn peak(&self) -> Option<u64> {
+        None
+    }
+
+    /// Reset the value returned by `peak` to the current usage.
+    fn reset_peak(&self) {}
+
+    /// The total bytes allocated and freed so far, if the allocator keeps
+    /// count.  Backends which can should count only the current thread's
+    /// allocations; others count the whole process.
+    fn allocated_and_freed(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Write this allocator's stats to `out` in its native format.
+    fn write_stats(&self,
+                   out: &mut dyn Write,
+                   options: &StatsOptions)
+                   -> io::Result<()>;
+
+    /// Return as much unused memory as possible to the OS.  Allocators
+    /// which can't do this return `ErrorKind::Unsupported`.
+    fn purge(&self) -> Result<()> {
+        Err(ErrorKind::Unsupported(Resource::AllocatorMemory).into())
+    }
+}
+
+/// All the backends compiled into this crate, in order of preference.
+static BACKENDS: &[&dyn AllocatorBackend] = &[
+    #[cfg(feature = "mimalloc")]
+    &mimalloc::Mimalloc,
+    #[cfg(feature = "tcmalloc")]
+    &tcmalloc::Tcmalloc,
+    #[cfg(feature = "jemalloc")]
+    &jemalloc::Jemalloc,
+    #[cfg(all(feature = "glibc", target_env = "gnu"))]
+    &glibc::Glibc,
+];
+
+/// Index of our chosen backend in `BACKENDS`, plus one.  Zero means we
+/// haven't chosen yet, and `COUNTING_BACKEND` means we're using
+/// `counting::Counting`.
+static CHOSEN_BACKEND: AtomicUsize = AtomicUsize::new(0);
+
+/// Special value of `CHOSEN_BACKEND`.
+const COUNTING_BACKEND: usize = usize::MAX;
+
+/// Return the allocator backend we're using to collect heap stats, or
+/// `None` if we don't know how to query this process's allocator.
+pub fn allocator_backend() -> Option<&'static dyn AllocatorBackend> {
+    match CHOSEN_BACKEND.load(Ordering::Relaxed) {
+        0 => {}
+        COUNTING_BACKEND => return Some(&counting::Counting),
+        chosen => return Some(BACKENDS[chosen - 1]),
+    }
+    let detected = detect_allocator();
+    let matches = |b: &&&dyn AllocatorBackend| {
+        detected == AllocatorKind::Unknown || b.name() == detected.name()
+    };
+    let working = BACKENDS.iter().position(|b| {
+        // glibc's `mallinfo2` works even when some other allocator is
+        // handling our allocations, so we need to check that it's in use.
+        b.stats_enabled() && (b.name() != "glibc" || matches(&b))
+    });
+    let chosen = match working {
+        Some(idx) => idx + 1,
+        None if counting::Counting.stats_enabled() => COUNTING_BACKEND,
+        // Let the backend for our allocator report its own errors.
+        None => {
+            match BACKENDS.iter().position(|b| matches(&b)) {
+                Some(idx) => idx + 1,
+                None => return None,
+            }
+        }
+    };
+    CHOSEN_BACKEND.store(chosen, Ordering::Relaxed);
+    allocator_backend()
+}
+
+/// Like `allocator_backend`, but return an error if we don't have one,
+/// saying which allocator we found if we know.
+fn require_backend() -> Result<&'static dyn AllocatorBackend> {
+    allocator_backend().ok_or_else(|| {
+        match detect_allocator() {
+            AllocatorKind::Unknown => ErrorKind::AllocatorUnavailable.into(),
+            kind => ErrorKind::UnsupportedAllocator(kind).into(),
+        }
+    })
+}
+
+/// Should we refresh allocator statistics before each read?
+static AUTO_REFRESH: AtomicBool = AtomicBool::new(true);
+
+/// Is `AUTO_REFRESH` turned on?
+#[cfg_attr(not(feature = "jemalloc"), allow(dead_code))]
+fn auto_refresh_enabled() -> bool {
+    AUTO_REFRESH.load(Ordering::Relaxed)
+}
+
+/// Ask the allocator to refresh its statistics.  For jemalloc, this
+/// advances the `epoch`, without which jemalloc may keep returning the
+/// same cached numbers indefinitely.
+pub fn refresh_allocator_stats() -> Result<()> {
+    require_backend()?.refresh()
+}
+
+/// Control whether we refresh allocator statistics before each read.
+/// This is enabled by default.  Turning it off makes reads cheaper, but
+/// they will only change when somebody calls `refresh_allocator_stats`.
+pub fn set_allocator_stats_refresh(enabled: bool) {
+    AUTO_REFRESH.store(enabled, Ordering::Relaxed);
+}
+
+/// Refresh allocator statistics unless the caller has asked us not to.
+pub fn refresh() -> Result<()> {
+    if auto_refresh_enabled() {
+        refresh_allocator_stats()
+    } else {
+        Ok(())
+    }
+}
+
+/// The highest value we've ever seen returned from `used`.
+static SAMPLED_PEAK: AtomicU64 = AtomicU64::new(0);
+
+/// How much memory is the allocator currently using for actual user
+/// data?  Call `refresh` first to get up-to-date values.
+pub fn used() -> Result<u64> {
+    let used = require_backend()?.used()?;
+    SAMPLED_PEAK.fetch_max(used, Ordering::Relaxed);
+    Ok(used)
+}
+
+/// What is the most memory the allocator has used?  If the backend doesn't
+/// track this, we can only report the highest value we've seen in `used`.
+pub fn peak() -> Result<u64> {
+    if let Some(peak) = require_backend()?.peak() {
+        return Ok(peak);
+    }
+    refresh()?;
+    used()?;
+    Ok(SAMPLED_PEAK.load(Ordering::Relaxed))
+}
+
+/// Reset our peak usage to the current usage.
+pub fn reset_peak() -> Result<()> {
+    let backend = require_backend()?;
+    backend.reset_peak();
+    refresh()?;
+    SAMPLED_PEAK.store(backend.used()?, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The total bytes allocated and freed so far, as described for
+/// `AllocatorBackend::allocated_and_freed`.
+pub fn allocated_and_freed() -> Option<(u64, u64)> {
+    allocator_backend()?.allocated_and_freed()
+}
+
+/// How much total memory has the allocator reserved for user allocations?
+/// Call `refresh` first to get up-to-date values.
+pub fn reserved() -> Result<u64> {
+    require_backend()?.reserved()
+}
+
+/// Are our allocator stats enabled?
+pub fn allocator_stats_enabled() -> bool {
+    allocator_backend().is_some_and(|b| b.stats_enabled())
+}
+
+/// What to include when writing allocator stats.  These options map onto
+/// jemalloc's `malloc_stats_print` options, and other allocators ignore
+/// them.
+///
+/// ```
+/// use resource_monitor::StatsOptions;
+///
+/// let options = StatsOptions::new().json(true).per_arena(false);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsOptions {
+    json: bool,
+    per_arena: bool,
+    minimal: bool,
+}
+
+impl Default for StatsOptions {
+    fn default() -> StatsOptions {
+        StatsOptions {
+            json: false,
+            per_arena: true,
+            minimal: false,
+        }
+    }
+}
+
+impl StatsOptions {
+    /// Include everything, formatted as text.
+    pub fn new() -> StatsOptions {
+        StatsOptions::default()
+    }
+
+    /// Write JSON instead of text.
+    pub fn json(mut self, json: bool) -> StatsOptions {
+        self.json = json;
+        self
+    }
+
+    /// Include stats for each arena, and not just the totals.
+    pub fn per_arena(mut self, per_arena: bool) -> StatsOptions {
+        self.per_arena = per_arena;
+        self
+    }
+
+    /// Leave out general information about the allocator's configuration,
+    /// and detailed tables of size classes, mutexes and extents, which
+    /// make up most of the output.
+    pub fn minimal(mut self, minimal: bool) -> StatsOptions {
+        self.minimal = minimal;
+        self
+    }
+}
+
+/// Passes the output of an allocator's stats callback to a `Write`.  The
+/// callback can't return errors, so we remember the first one, and skip
+/// any output after it.
+#[cfg_attr(not(any(feature = "jemalloc", feature = "mimalloc")),
+           allow(dead_code))]
+struct StatsSink<'a> {
+    out: &'a mut dyn Write,
+    result: io::Result<()>,
+}
+
+#[cfg_attr(not(any(feature = "jemalloc", feature = "mimalloc")),
+           allow(dead_code))]
+impl<'a> StatsSink<'a> {
+    /// Create a sink writing to `out`.
+    fn new(out: &'a mut dyn Write) -> StatsSink<'a> {
+        StatsSink { out, result: Ok(()) }
+    }
+
+    /// Write the C string `msg`, unless an earlier write failed.
+    unsafe fn write_c_str(&mut self, msg: *const c_char) {
+        if self.result.is_ok() {
+            self.result = self.out.write_all(CStr::from_ptr(msg).to_bytes());
+        }
+    }
+}
+
+/// Write our allocator stats to `out`, in the allocator's native format.
+/// For glibc, this is the XML written by `malloc_info`.
+///
+/// ```
+/// use resource_monitor::{write_allocator_stats, StatsOptions};
+///
+/// let mut out = vec![];
+/// let options = StatsOptions::new().minimal(true);
+/// if write_allocator_stats(&mut out, options).is_ok() {
+///     println!("{}", String::from_utf8_lossy(&out));
+/// }
+/// ```
+pub fn write_allocator_stats(out: &mut dyn Write,
+                             options: StatsOptions)
+                             -> Result<()> {
+    require_backend()?
+        .write_stats(out, &options)
+        .chain_err(|| "could not write allocator stats")
+}
+
+/// Dump our allocator stats to standard output.  Does nothing if we
+/// don't have an allocator backend.
+pub fn print_allocator_stats() {
+    let _ = write_allocator_stats(&mut io::stdout(), StatsOptions::new());
+}
+
+/// Ask the allocator to return the free memory it's holding on to to the
+/// OS right away, instead of waiting for it to decay.  For jemalloc, this
+/// purges the dirty pages of every arena, and for glibc, it calls
+/// `malloc_trim(0)`.  This works with glibc's `malloc` even without the
+/// `glibc` feature.  Call this when memory is tight, for example from a
+/// reclaimer registered with `register_reclaimer`, and `OsMemory.used()`
+/// should drop.  This can take a while if there's a lot to purge, and
+/// later allocations may be slower while the allocator gets its pages
+/// back.
+pub fn purge_allocator() -> Result<()> {
+    match allocator_backend() {
+        Some(backend) => backend.purge()?,
+        None => trim_default_allocator()?,
+    }
+    clear_cached_values();
+    Ok(())
+}
+
+/// Purge the C library's `malloc` when we don't have a backend for it.
+fn trim_default_allocator() -> Result<()> {
+    #[cfg(target_env = "gnu")]
+    {
+        if detect_allocator() == AllocatorKind::Glibc {
+            trim_glibc();
+            return Ok(());
+        }
+    }
+    require_backend().map(|_| ())
+}
+
+/// Ask glibc's `malloc` to return free memory at the top of the heap, and
+/// free pages inside it, to the OS.  Returns `true` if it released any.
+#[cfg(target_env = "gnu")]
+pub fn trim_glibc() -> bool {
+    extern "C" {
+        fn malloc_trim(pad: usize) -> c_int;
+    }
+    unsafe { malloc_trim(0) != 0 }
+}
@@ -0,0 +1,68 @@
+//! Heap usage stats for gperftools' tcmalloc, using the C shims for
+//! `MallocExtension`.
+
+use libc::{c_char, c_int, size_t};
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+
+use errors::*;
+use super::{AllocatorBackend, StatsOptions};
+
+extern "C" {
+    fn MallocExtension_GetNumericProperty(property: *const c_char,
+                                          value: *mut size_t)
+                                          -> c_int;
+    fn MallocExtension_GetStats(buffer: *mut c_char, buffer_length: c_int);
+}
+
+/// Fetch a tcmalloc numeric property.
+fn read_property(name: &str) -> Result<u64> {
+    let key = CString::new(name).unwrap();
+    let mut value: size_t = 0;
+    let ok = unsafe {
+        MallocExtension_GetNumericProperty(key.as_ptr(), &mut value)
+    };
+    if ok == 0 {
+        return Err(format!("could not read tcmalloc property {}", name)
+            .into());
+    }
+    Ok(value as u64)
+}
+
+/// The tcmalloc allocator.
+pub struct Tcmalloc;
+
+impl AllocatorBackend for Tcmalloc {
+    fn name(&self) -> &'static str {
+        "tcmalloc"
+    }
+
+    fn stats_enabled(&self) -> bool {
+        read_property("generic.heap_size").is_ok()
+    }
+
+    fn used(&self) -> Result<u64> {
+        read_property("generic.current_allocated_bytes")
+    }
+
+    fn reserved(&self) -> Result<u64> {
+        // `heap_size` includes pages which have already been returned to
+        // the OS, so don't count those.
+        let heap = read_property("generic.heap_size")?;
+        let unmapped = read_property("tcmalloc.pageheap_unmapped_bytes")?;
+        Ok(heap.saturating_sub(unmapped))
+    }
+
+    fn write_stats(&self,
+                   out: &mut dyn Write,
+                   _options: &StatsOptions)
+                   -> io::Result<()> {
+        let mut buffer = vec![0 as c_char; 64 * 1024];
+        let stats = unsafe {
+            MallocExtension_GetStats(buffer.as_mut_ptr(),
+                                     buffer.len() as c_int);
+            CStr::from_ptr(buffer.as_ptr())
+        };
+        out.write_all(stats.to_bytes())
+    }
+}
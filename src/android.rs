@@ -0,0 +1,133 @@
+//! Android, which mounts cgroups in unusual places, often has no per-app
+//! memory cgroup at all, and doesn't let apps read many of the files we
+//! normally rely on.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use cgroup;
+use errors::*;
+use provider::{ResourceProvider, SystemProvider};
+use Resource;
+
+/// Where Android mounts cgroup v1 controllers, instead of under
+/// `/sys/fs/cgroup`.
+const CONTROLLER_ROOTS: &[(&str, &str)] = &[("memory", "/dev/memcg"),
+                                            ("cpu", "/dev/cpuctl"),
+                                            ("cpuacct", "/acct")];
+
+/// A `ResourceProvider` for Android.  On Android, we install this
+/// automatically unless you call `set_resource_provider`.
+///
+/// It reads `Memory` and `OsMemory` from our memory cgroup where we can,
+/// but the memory cgroup Android gives an app is often the root cgroup,
+/// which has no limit and counts the whole device, or is missing or
+/// unreadable.  So we also read `/proc/meminfo`, which is what
+/// `ActivityManager.getMemoryInfo` reports, and use whichever is
+/// tighter, rather than failing.  Other resources are read as usual.
+///
+/// This also works on other Linux systems, if you'd like the same
+/// fallback there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AndroidProvider;
+
+impl AndroidProvider {
+    /// Is `resource` one that we read differently?
+    fn handles(resource: &Resource) -> bool {
+        matches!(*resource, Resource::Memory | Resource::OsMemory)
+    }
+}
+
+impl ResourceProvider for AndroidProvider {
+    fn limit(&self, resource: &Resource) -> Result<u64> {
+        if !AndroidProvider::handles(resource) {
+            return SystemProvider.limit(resource);
+        }
+        let device = DeviceMemory::read()?;
+        match SystemProvider.limit(resource) {
+            Ok(limit) => Ok(limit.min(device.total)),
+            Err(_) => Ok(device.total),
+        }
+    }
+
+    fn used(&self, resource: &Resource) -> Result<u64> {
+        if !AndroidProvider::handles(resource) {
+            return SystemProvider.used(resource);
+        }
+        Ok(self.limit(resource)?.saturating_sub(self.available(resource)?))
+    }
+
+    fn available(&self, resource: &Resource) -> Result<u64> {
+        if !AndroidProvider::handles(resource) {
+            return SystemProvider.available(resource);
+        }
+        let device = DeviceMemory::read()?;
+        match SystemProvider.available(resource) {
+            Ok(available) => Ok(available.min(device.available)),
+            Err(_) => Ok(device.available),
+        }
+    }
+}
+
+/// Memory on the whole device, from `/proc/meminfo`.
+struct DeviceMemory {
+    total: u64,
+    available: u64,
+}
+
+impl DeviceMemory {
+    /// Read `/proc/meminfo`.  Kernels before 3.14 don't report
+    /// `MemAvailable`, so we estimate it from free memory and the page
+    /// cache, as Android itself does.
+    fn read() -> Result<DeviceMemory> {
+        let path = Path::new("/proc/meminfo");
+        let meminfo = cgroup::read_keyed_file(path)?;
+        let get = |key| meminfo.get(key).map(|&kb| kb * 1024);
+        let available = get("MemAvailable:").or_else(|| {
+            Some(get("MemFree:")? + get("Cached:")?)
+        });
+        match (get("MemTotal:"), available) {
+            (Some(total), Some(available)) => {
+                Ok(DeviceMemory { total, available })
+            }
+            _ => Err(ErrorKind::Parse(path.to_owned()).into()),
+        }
+    }
+}
+
+/// Is this Android?  We check at runtime as well as at compile time, so
+/// that Linux binaries running on Android, such as in Termux, also work.
+pub fn is_android() -> bool {
+    static ANDROID: OnceLock<bool> = OnceLock::new();
+    *ANDROID.get_or_init(|| {
+        cfg!(target_os = "android") ||
+        Path::new("/system/build.prop").exists()
+    })
+}
+
+/// Where Android mounts the cgroup v1 `controller`, if it's one Android
+/// moves and it's actually mounted.  We use our own per-app cgroup within
+/// it if Android created one, and otherwise its root.
+pub fn controller_dir(controller: &str) -> Option<PathBuf> {
+    if !is_android() {
+        return None;
+    }
+    let root = CONTROLLER_ROOTS.iter()
+        .find(|&&(name, _)| name == controller)
+        .map(|&(_, root)| PathBuf::from(root))
+        .filter(|root| root.exists())?;
+    let own = cgroup::v1_path_of_self(controller)
+        .map(|path| root.join(path.trim_start_matches('/')))
+        .filter(|dir| dir.exists());
+    Some(own.unwrap_or(root))
+}
+
+/// The provider we install by default, which is `AndroidProvider` when
+/// built for Android, and nothing otherwise.
+pub fn default_provider() -> Option<Arc<dyn ResourceProvider>> {
+    if cfg!(target_os = "android") {
+        Some(Arc::new(AndroidProvider))
+    } else {
+        None
+    }
+}
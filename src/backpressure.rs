@@ -0,0 +1,130 @@
+//! Turning memory usage into a throttling factor, so that producers can
+//! slow down before the OOM killer steps in.
+
+use snapshot::Snapshot;
+use Resource;
+
+/// Maps the fraction of memory in use to a throttling factor between 0.0
+/// (stop) and 1.0 (full speed).
+///
+/// This is implemented for any `Fn(f64) -> f64`, so a closure can be used
+/// as a curve.
+pub trait BackpressureCurve: Send + Sync {
+    /// Return the throttling factor for `fraction_used`.
+    fn factor(&self, fraction_used: f64) -> f64;
+}
+
+impl<F> BackpressureCurve for F
+    where F: Fn(f64) -> f64 + Send + Sync
+{
+    fn factor(&self, fraction_used: f64) -> f64 {
+        self(fraction_used)
+    }
+}
+
+/// Full speed below `start`, falling linearly to zero at `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearCurve {
+    /// The fraction of memory used at which we start throttling.
+    pub start: f64,
+    /// The fraction of memory used at which we stop completely.
+    pub end: f64,
+}
+
+impl Default for LinearCurve {
+    fn default() -> LinearCurve {
+        LinearCurve {
+            start: 0.7,
+            end: 0.95,
+        }
+    }
+}
+
+impl BackpressureCurve for LinearCurve {
+    fn factor(&self, fraction_used: f64) -> f64 {
+        if fraction_used <= self.start {
+            1.0
+        } else if fraction_used >= self.end {
+            0.0
+        } else {
+            (self.end - fraction_used) / (self.end - self.start)
+        }
+    }
+}
+
+/// A piecewise-constant curve.  Each step is `(fraction_used, factor)`,
+/// and applies from that fraction up to the next step.  Usage below the
+/// first step runs at full speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepCurve {
+    /// Our steps, sorted by `fraction_used`.
+    pub steps: Vec<(f64, f64)>,
+}
+
+impl BackpressureCurve for StepCurve {
+    fn factor(&self, fraction_used: f64) -> f64 {
+        self.steps
+            .iter()
+            .take_while(|&&(fraction, _)| fraction_used >= fraction)
+            .last()
+            .map_or(1.0, |&(_, factor)| factor)
+    }
+}
+
+/// Suggests how hard to throttle work based on memory usage.  One
+/// instance can be shared between all the stages of a pipeline.
+///
+/// ```
+/// use resource_monitor::Backpressure;
+///
+/// let backpressure = Backpressure::default();
+/// let workers = backpressure.scale(16);
+/// println!("Running {} workers", workers);
+/// ```
+///
+/// `factor` and `scale` read `Resource::Memory` every time they're called.
+/// If you're calling them often, use a `Monitor` and pass its latest
+/// snapshot to `factor_for` instead.
+pub struct Backpressure {
+    curve: Box<dyn BackpressureCurve>,
+}
+
+impl Default for Backpressure {
+    fn default() -> Backpressure {
+        Backpressure::new(LinearCurve::default())
+    }
+}
+
+impl Backpressure {
+    /// Throttle work according to `curve`.
+    pub fn new<C: BackpressureCurve + 'static>(curve: C) -> Backpressure {
+        Backpressure { curve: Box::new(curve) }
+    }
+
+    /// Our throttling factor for `fraction_used`, clamped to the range
+    /// 0.0 to 1.0.
+    fn clamped(&self, fraction_used: f64) -> f64 {
+        self.curve.factor(fraction_used).clamp(0.0, 1.0)
+    }
+
+    /// The throttling factor for the current memory usage.  If we can't
+    /// tell how much memory is in use, we don't throttle.
+    pub fn factor(&self) -> f64 {
+        let fraction = Snapshot::capture(&[Resource::Memory])
+            .fraction_used(&Resource::Memory);
+        fraction.map_or(1.0, |f| self.clamped(f))
+    }
+
+    /// The throttling factor for the memory usage in `snapshot`.
+    pub fn factor_for(&self, snapshot: &Snapshot) -> f64 {
+        snapshot.fraction_used(&Resource::Memory)
+            .map_or(1.0, |f| self.clamped(f))
+    }
+
+    /// Scale `max`, such as a worker count or queue depth, by our current
+    /// factor.  This rounds down, so it returns 0 when we're fully
+    /// throttled.
+    pub fn scale(&self, max: usize) -> usize {
+        (max as f64 * self.factor()) as usize
+    }
+}
@@ -0,0 +1,107 @@
+//! A baseline taken when the process starts, so that long-running
+//! services can report how much they've grown since then.
+
+use std::fs;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use cpu;
+use Resource;
+
+/// The baseline, once we've taken it.
+static BASELINE: OnceLock<Baseline> = OnceLock::new();
+
+/// Our usage when we took the baseline.  Values we couldn't read are
+/// `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Baseline {
+    /// When we took it.
+    pub taken_at: SystemTime,
+    /// The value of `Resource::Memory.used()`.
+    pub memory_used: Option<u64>,
+    /// The CPU time this process had used.
+    pub cpu_time: Option<Duration>,
+    /// The number of file descriptors this process had open.
+    pub fds: Option<u64>,
+}
+
+impl Baseline {
+    /// Read our current usage.
+    fn capture() -> Baseline {
+        Baseline {
+            taken_at: SystemTime::now(),
+            memory_used: Resource::Memory.used().ok(),
+            cpu_time: cpu::process_cpu_time().ok(),
+            fds: open_fds(),
+        }
+    }
+}
+
+/// How much our usage has changed since the baseline, as returned by
+/// `since_start`.  Values which we couldn't read either now or at the
+/// baseline are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SinceStart {
+    /// The time since we took the baseline.
+    pub elapsed: Duration,
+    /// How much `Resource::Memory.used()` has grown, in bytes.  This is
+    /// negative if we're using less than we were.
+    pub memory_growth: Option<i64>,
+    /// The CPU time this process has used.
+    pub cpu_time: Option<Duration>,
+    /// How many more file descriptors this process has open.  This is
+    /// negative if we've closed some.
+    pub fds_opened: Option<i64>,
+}
+
+/// Take the baseline for `since_start`, if we haven't already, and return
+/// it.  Call this as early as possible in `main`.  Otherwise, we take the
+/// baseline the first time anyone calls `since_start` or `Monitor::new`.
+///
+/// ```
+/// use resource_monitor::capture_baseline;
+///
+/// // At the top of `main`, before starting the service:
+/// capture_baseline();
+/// ```
+pub fn capture_baseline() -> &'static Baseline {
+    BASELINE.get_or_init(Baseline::capture)
+}
+
+/// How much our usage has changed since `capture_baseline`.
+///
+/// ```
+/// use resource_monitor::since_start;
+///
+/// let delta = since_start();
+/// if let Some(growth) = delta.memory_growth {
+///     println!("Memory has grown by {} bytes in {:?}",
+///              growth,
+///              delta.elapsed);
+/// }
+/// ```
+pub fn since_start() -> SinceStart {
+    let baseline = capture_baseline();
+    let now = Baseline::capture();
+    let delta = |now: Option<u64>, then: Option<u64>| {
+        Some(now? as i64 - then? as i64)
+    };
+    SinceStart {
+        elapsed: now.taken_at
+            .duration_since(baseline.taken_at)
+            .unwrap_or_default(),
+        memory_growth: delta(now.memory_used, baseline.memory_used),
+        cpu_time: match (now.cpu_time, baseline.cpu_time) {
+            (Some(now), Some(then)) => Some(now.saturating_sub(then)),
+            _ => None,
+        },
+        fds_opened: delta(now.fds, baseline.fds),
+    }
+}
+
+/// The number of file descriptors we have open.
+fn open_fds() -> Option<u64> {
+    Some(fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
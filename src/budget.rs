@@ -0,0 +1,118 @@
+//! Admission control: handing out reservations against a fixed amount of
+//! memory, so that subsystems can refuse work before they allocate.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use errors::*;
+use read_cache::ReadCache;
+use Resource;
+
+/// State shared between a `MemoryBudget` and its reservations.
+#[derive(Debug)]
+struct Inner {
+    capacity: u64,
+    reserved: AtomicU64,
+}
+
+/// A pool of memory which subsystems can reserve before they use it.
+///
+/// ```
+/// use resource_monitor::{Bytes, MemoryBudget};
+///
+/// let budget = MemoryBudget::new(64 * Bytes::MIB);
+/// let reservation = budget.reserve(48 * Bytes::MIB).unwrap();
+/// assert!(budget.reserve(32 * Bytes::MIB).is_err());
+/// drop(reservation);
+/// assert!(budget.reserve(32 * Bytes::MIB).is_ok());
+/// ```
+///
+/// A budget is only an accounting tool: it doesn't stop anybody from
+/// allocating memory without a reservation.  Cloning a `MemoryBudget`
+/// returns another handle to the same pool.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+impl MemoryBudget {
+    /// Create a budget which allows `capacity` bytes to be reserved.
+    pub fn new(capacity: u64) -> MemoryBudget {
+        MemoryBudget {
+            inner: Arc::new(Inner {
+                capacity,
+                reserved: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Create a budget from the memory which is currently available,
+    /// allowing reservations until total usage would reach `max_fraction`
+    /// of `Resource::Memory.limit()`.
+    pub fn from_memory(max_fraction: f64) -> Result<MemoryBudget> {
+        // Read everything in one pass, so the values are consistent.
+        let mut cache = ReadCache::new();
+        let limit = Resource::Memory.limit_with(&mut cache)?;
+        let used = Resource::Memory.used_with(&mut cache)?;
+        let available = Resource::Memory.available_with(&mut cache)?;
+        let allowed = (limit as f64 * max_fraction) as u64;
+        let capacity = allowed.saturating_sub(used).min(available);
+        Ok(MemoryBudget::new(capacity))
+    }
+
+    /// The total number of bytes which may be reserved.
+    pub fn capacity(&self) -> u64 {
+        self.inner.capacity
+    }
+
+    /// The number of bytes currently reserved.
+    pub fn reserved(&self) -> u64 {
+        self.inner.reserved.load(Ordering::SeqCst)
+    }
+
+    /// The number of bytes which may still be reserved.
+    pub fn remaining(&self) -> u64 {
+        self.capacity().saturating_sub(self.reserved())
+    }
+
+    /// Reserve `bytes`, returning a guard which releases them when
+    /// dropped.  Fails with `ErrorKind::BudgetExceeded` if there isn't
+    /// enough left.
+    pub fn reserve(&self, bytes: u64) -> Result<Reservation> {
+        let capacity = self.inner.capacity;
+        self.inner
+            .reserved
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |reserved| {
+                reserved.checked_add(bytes).filter(|&r| r <= capacity)
+            })
+            .map_err(|reserved| {
+                let remaining = capacity.saturating_sub(reserved);
+                ErrorKind::BudgetExceeded(bytes, remaining)
+            })?;
+        Ok(Reservation {
+            inner: self.inner.clone(),
+            bytes,
+        })
+    }
+}
+
+/// Memory reserved from a `MemoryBudget`.  It's returned to the budget
+/// when this is dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    inner: Arc<Inner>,
+    bytes: u64,
+}
+
+impl Reservation {
+    /// The number of bytes reserved.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.inner.reserved.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
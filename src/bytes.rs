@@ -0,0 +1,142 @@
+//! A byte count which knows how to format itself for humans.
+
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+
+/// A quantity of memory, in bytes.
+///
+/// We always use `u64` internally, because a `usize` can't hold an 8 GB
+/// cgroup limit on a 32-bit ARM system.  The `Display` implementation
+/// uses binary units:
+///
+/// ```
+/// use resource_monitor::Bytes;
+/// assert_eq!(Bytes(1536 * Bytes::MIB).to_string(), "1.5 GiB");
+/// assert_eq!(Bytes(512).to_string(), "512 B");
+/// ```
+///
+/// It can also be parsed from strings like `512MiB` or `1.5 GiB`.  Units
+/// ending in `iB`, and the single letters `K`, `M`, `G` and `T`, are
+/// binary, but `KB`, `MB`, `GB` and `TB` are decimal:
+///
+/// ```
+/// use resource_monitor::Bytes;
+/// assert_eq!("512MiB".parse::<Bytes>().unwrap(), Bytes(512 * Bytes::MIB));
+/// assert_eq!("1.5 GiB".parse::<Bytes>().unwrap(), Bytes(1536 * Bytes::MIB));
+/// assert_eq!("2MB".parse::<Bytes>().unwrap(), Bytes(2_000_000));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub u64);
+
+impl Bytes {
+    /// One kibibyte (1024 bytes).
+    pub const KIB: u64 = 1 << 10;
+    /// One mebibyte.
+    pub const MIB: u64 = 1 << 20;
+    /// One gibibyte.
+    pub const GIB: u64 = 1 << 30;
+    /// One tebibyte.
+    pub const TIB: u64 = 1 << 40;
+
+    /// Get the raw number of bytes.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Format usage against a limit for humans, such as
+    /// `1.5 GiB / 2.0 GiB (75%)`.  A `limit` of `u64::MAX` is shown as
+    /// `unlimited`, without a percentage.
+    ///
+    /// ```
+    /// use resource_monitor::Bytes;
+    /// assert_eq!(Bytes::format_usage(1536 * Bytes::MIB, 2 * Bytes::GIB),
+    ///            "1.5 GiB / 2.0 GiB (75%)");
+    /// assert_eq!(Bytes::format_usage(512, u64::MAX), "512 B / unlimited");
+    /// ```
+    pub fn format_usage(used: u64, limit: u64) -> String {
+        if limit == u64::MAX {
+            format!("{} / unlimited", Bytes(used))
+        } else if limit == 0 {
+            format!("{} / {}", Bytes(used), Bytes(limit))
+        } else {
+            let percent = used as f64 / limit as f64 * 100.0;
+            format!("{} / {} ({:.0}%)", Bytes(used), Bytes(limit), percent)
+        }
+    }
+
+    /// Convert to a `usize`, returning `None` if the value won't fit on
+    /// this platform.
+    pub fn to_usize(self) -> Option<usize> {
+        if self.0 > usize::MAX as u64 {
+            None
+        } else {
+            Some(self.0 as usize)
+        }
+    }
+}
+
+impl From<u64> for Bytes {
+    fn from(bytes: u64) -> Bytes {
+        Bytes(bytes)
+    }
+}
+
+impl From<Bytes> for u64 {
+    fn from(bytes: Bytes) -> u64 {
+        bytes.0
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = [(Bytes::TIB, "TiB"),
+                     (Bytes::GIB, "GiB"),
+                     (Bytes::MIB, "MiB"),
+                     (Bytes::KIB, "KiB")];
+        for &(size, name) in &units {
+            if self.0 >= size {
+                return write!(f, "{:.1} {}", self.0 as f64 / size as f64, name);
+            }
+        }
+        write!(f, "{} B", self.0)
+    }
+}
+
+impl FromStr for Bytes {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Bytes> {
+        let mkerr = || ErrorKind::InvalidBytes(s.to_owned());
+        let trimmed = s.trim();
+        let split = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => Bytes::KIB,
+            "m" | "mib" => Bytes::MIB,
+            "g" | "gib" => Bytes::GIB,
+            "t" | "tib" => Bytes::TIB,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            "tb" => 1_000_000_000_000,
+            _ => return Err(mkerr().into()),
+        };
+        // Parse whole numbers exactly, so we don't lose precision above
+        // 2^53.
+        if let Ok(n) = number.parse::<u64>() {
+            return n.checked_mul(multiplier).map(Bytes).ok_or_else(|| {
+                mkerr().into()
+            });
+        }
+        let n: f64 = number.parse().chain_err(mkerr)?;
+        let bytes = (n * multiplier as f64).round();
+        if bytes >= u64::MAX as f64 {
+            return Err(mkerr().into());
+        }
+        Ok(Bytes(bytes as u64))
+    }
+}
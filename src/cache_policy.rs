@@ -0,0 +1,238 @@
+//! An opt-in cache of resource values, so that checks on hot paths don't
+//! have to read and parse a file every time.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use errors::*;
+use Resource;
+
+/// Which value of a resource we cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CachedValue {
+    /// The value of `limit`.
+    Limit,
+    /// The value of `used`.
+    Used,
+    /// The value of `available`.
+    Available,
+}
+
+/// Is a policy installed?  Checked before taking any locks, so that the
+/// cache costs almost nothing when it's turned off.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The policy installed by `CachePolicy::install`.
+static POLICY: RwLock<Option<CachePolicy>> = RwLock::new(None);
+
+/// Cached values, and when we read them.
+type Values = HashMap<(Resource, CachedValue), (Instant, u64)>;
+
+/// The values we've cached so far.
+static VALUES: Mutex<Option<Values>> = Mutex::new(None);
+
+/// How long to reuse resource values before reading them again.
+///
+/// By default, every call to `limit`, `used` or `available` reads the
+/// underlying files.  Installing a `CachePolicy` makes repeated calls
+/// within the TTL return the previous value instead, which makes checks
+/// like `can_allocate` nearly free, and limits how often we read each
+/// value.  `Resource::used_timed` and friends say how old the values they
+/// return are.  Limits rarely change, so they can be cached for much
+/// longer than usage.  Errors are never cached, and `Resource::Stack` is
+/// never cached, because it depends on the calling thread.
+///
+/// ```
+/// use std::time::Duration;
+/// use resource_monitor::{CachePolicy, Resource};
+///
+/// CachePolicy::new()
+///     .limit_ttl(Duration::from_secs(60))
+///     .usage_ttl(Duration::from_millis(100))
+///     .resource_ttl(Resource::CpuTime, Duration::from_millis(0))
+///     .install();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicy {
+    limit_ttl: Duration,
+    usage_ttl: Duration,
+    resource_ttls: HashMap<Resource, Duration>,
+    stale_on_error: bool,
+}
+
+impl Default for CachePolicy {
+    fn default() -> CachePolicy {
+        CachePolicy {
+            limit_ttl: Duration::from_secs(60),
+            usage_ttl: Duration::from_millis(100),
+            resource_ttls: HashMap::new(),
+            stale_on_error: false,
+        }
+    }
+}
+
+impl CachePolicy {
+    /// Create a policy which caches limits for 60 seconds, and usage for
+    /// 100 milliseconds.
+    pub fn new() -> CachePolicy {
+        CachePolicy::default()
+    }
+
+    /// Cache `limit` values for `ttl`.
+    pub fn limit_ttl(mut self, ttl: Duration) -> CachePolicy {
+        self.limit_ttl = ttl;
+        self
+    }
+
+    /// Cache `used` and `available` values for `ttl`.
+    pub fn usage_ttl(mut self, ttl: Duration) -> CachePolicy {
+        self.usage_ttl = ttl;
+        self
+    }
+
+    /// Cache `used` and `available` values of `resource` for `ttl`,
+    /// instead of the `usage_ttl`.  A zero `ttl` turns off caching of usage
+    /// for this resource.
+    pub fn resource_ttl(mut self,
+                        resource: Resource,
+                        ttl: Duration)
+                        -> CachePolicy {
+        self.resource_ttls.insert(resource, ttl);
+        self
+    }
+
+    /// Read usage at most `rate` times a second, and return the previous
+    /// value to any calls in between.  This is another way of setting the
+    /// `usage_ttl`.  Use `Resource::used_timed` to find out how old a value
+    /// is.
+    pub fn max_reads_per_second(self, rate: f64) -> CachePolicy {
+        self.usage_ttl(Duration::from_secs_f64(1.0 / rate.max(1e-9)))
+    }
+
+    /// If reading a value fails, return the previous value instead, no
+    /// matter how old it is.  Errors are still returned if we don't have a
+    /// previous value.  Use `Resource::used_timed` and friends to find out
+    /// how old the value is.
+    pub fn stale_on_error(mut self, stale_on_error: bool) -> CachePolicy {
+        self.stale_on_error = stale_on_error;
+        self
+    }
+
+    /// Use this policy for all future reads, replacing any previously
+    /// installed policy, and forgetting any cached values.
+    pub fn install(self) {
+        let mut policy = POLICY.write().unwrap_or_else(|e| e.into_inner());
+        *policy = Some(self);
+        ENABLED.store(true, Ordering::Release);
+        clear_cached_values();
+    }
+
+    /// Turn off caching, and forget any cached values.
+    pub fn uninstall() {
+        let mut policy = POLICY.write().unwrap_or_else(|e| e.into_inner());
+        *policy = None;
+        ENABLED.store(false, Ordering::Release);
+        clear_cached_values();
+    }
+
+    /// How long should we cache `value` of `resource`?
+    fn ttl(&self, resource: &Resource, value: CachedValue) -> Duration {
+        match value {
+            CachedValue::Limit => self.limit_ttl,
+            CachedValue::Used | CachedValue::Available => {
+                self.resource_ttls
+                    .get(resource)
+                    .cloned()
+                    .unwrap_or(self.usage_ttl)
+            }
+        }
+    }
+}
+
+/// Forget all cached values, so that the next read of each resource goes
+/// to the system.  Call this after something which you know changes our
+/// usage, such as freeing a large buffer.
+pub fn clear_cached_values() {
+    let mut values = VALUES.lock().unwrap_or_else(|e| e.into_inner());
+    *values = None;
+}
+
+/// A value, and when we read it, as returned by `Resource::used_timed`
+/// and friends.  Under a `CachePolicy`, this may be a cached value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedValue {
+    /// The value.
+    pub value: u64,
+    /// When we read it.
+    pub sampled_at: Instant,
+}
+
+impl TimedValue {
+    /// How long ago we read this value.
+    pub fn age(&self) -> Duration {
+        self.sampled_at.elapsed()
+    }
+
+    /// Was this value read more than `max_age` ago?
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.age() > max_age
+    }
+}
+
+/// Return the cached `value` of `resource` if it's fresh enough, or call
+/// `read` and cache its result.
+pub fn get_or_read<F>(resource: &Resource,
+                      value: CachedValue,
+                      read: F)
+                      -> Result<TimedValue>
+    where F: FnOnce() -> Result<u64>
+{
+    let read_now = |read: F| {
+        let value = read()?;
+        Ok(TimedValue {
+            value,
+            sampled_at: Instant::now(),
+        })
+    };
+    if !ENABLED.load(Ordering::Acquire) || *resource == Resource::Stack {
+        return read_now(read);
+    }
+    let (ttl, stale_on_error) = {
+        let policy = POLICY.read().unwrap_or_else(|e| e.into_inner());
+        match *policy {
+            Some(ref policy) => {
+                (policy.ttl(resource, value), policy.stale_on_error)
+            }
+            None => return read_now(read),
+        }
+    };
+    if ttl == Duration::from_secs(0) && !stale_on_error {
+        return read_now(read);
+    }
+    let key = (resource.clone(), value);
+    let cached = {
+        let values = VALUES.lock().unwrap_or_else(|e| e.into_inner());
+        values.as_ref().and_then(|v| v.get(&key)).map(|&(sampled_at, v)| {
+            TimedValue {
+                value: v,
+                sampled_at,
+            }
+        })
+    };
+    match cached {
+        Some(cached) if cached.age() < ttl => return Ok(cached),
+        _ => {}
+    }
+    // Don't hold the lock while reading, because that may be slow.
+    let timed = match (read_now(read), cached) {
+        (Ok(timed), _) => timed,
+        (Err(_), Some(cached)) if stale_on_error => return Ok(cached),
+        (Err(err), _) => return Err(err),
+    };
+    let mut values = VALUES.lock().unwrap_or_else(|e| e.into_inner());
+    values.get_or_insert_with(HashMap::new)
+        .insert(key, (timed.sampled_at, timed.value));
+    Ok(timed)
+}
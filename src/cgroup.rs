@@ -0,0 +1,351 @@
+//! Locating and reading cgroup control files.  We support both the older
+//! cgroup v1 hierarchy, where each controller is mounted separately, and
+//! the unified cgroup v2 hierarchy.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use android;
+use config;
+use errors::*;
+use mountinfo::{self, Mount};
+use raw;
+use read_file_u64;
+
+/// The two incompatible versions of the Linux cgroup API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupVersion {
+    /// Separate hierarchies per controller, such as
+    /// `/sys/fs/cgroup/memory`.
+    V1,
+    /// A single unified hierarchy.
+    V2,
+}
+
+/// Any cgroup v1 limit this large means there's no limit.
+pub const V1_UNLIMITED: u64 = 1 << 62;
+
+/// Figure out which cgroup version this system uses.
+pub fn version() -> CgroupVersion {
+    if config::cgroup_root().join("cgroup.controllers").exists() {
+        CgroupVersion::V2
+    } else {
+        CgroupVersion::V1
+    }
+}
+
+/// The cgroup filesystems in `/proc/self/mountinfo`.  We only read these
+/// once, because processes rarely mount cgroups after they start.
+static CGROUP_MOUNTS: OnceLock<Vec<Mount>> = OnceLock::new();
+
+/// Find where the hierarchy containing `controller` is mounted under our
+/// cgroup root.  Under cgroup v2, there's only one hierarchy.  If the
+/// cgroup root has been configured to point somewhere unusual, such as a
+/// test fixture, we won't find anything.
+fn hierarchy_mount(version: CgroupVersion,
+                   controller: &str)
+                   -> Option<&'static Mount> {
+    let mounts = CGROUP_MOUNTS.get_or_init(|| {
+        mountinfo::read()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|m| m.fs_type == "cgroup" || m.fs_type == "cgroup2")
+            .collect()
+    });
+    let root = config::cgroup_root();
+    mounts.iter().find(|m| {
+        m.mount_point.starts_with(&root) &&
+        match version {
+            CgroupVersion::V1 => {
+                m.fs_type == "cgroup" &&
+                m.super_options.split(',').any(|o| o == controller)
+            }
+            CgroupVersion::V2 => m.fs_type == "cgroup2",
+        }
+    })
+}
+
+/// Find the directory of the cgroup at `path` in `mount`, where `path`
+/// comes from `/proc/<pid>/cgroup`.
+///
+/// Both `path` and the mount's root are relative to the root of our cgroup
+/// namespace, so we can't just append `path` to the mount point.  For
+/// example, Docker without a cgroup namespace mounts `/docker/<id>` at
+/// `/sys/fs/cgroup`, and we're in `/docker/<id>`.
+fn resolve(mount: &Mount, path: &str) -> PathBuf {
+    match Path::new(path).strip_prefix(&mount.root) {
+        Ok(rest) if rest.as_os_str().is_empty() => mount.mount_point.clone(),
+        Ok(rest) => mount.mount_point.join(rest),
+        // The mount is outside our namespace, which the kernel shows as a
+        // root like `/..`, so our cgroup is the closest thing we can see.
+        Err(_) => mount.mount_point.clone(),
+    }
+}
+
+/// Our path in the v2 hierarchy, from the `0::/path` line of
+/// `/proc/self/cgroup`.
+fn v2_path_of_self() -> Option<String> {
+    let contents = fs::read_to_string("/proc/self/cgroup").ok()?;
+    contents.lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.to_owned())
+}
+
+/// Find the directory of our own cgroup in the v2 hierarchy, using
+/// `/proc/self/cgroup` and `/proc/self/mountinfo`, unless it has been
+/// configured.
+fn v2_dir() -> PathBuf {
+    if let Some(dir) = config::cgroup_path() {
+        return dir;
+    }
+    let path = match v2_path_of_self() {
+        Some(path) => path,
+        None => return config::cgroup_root(),
+    };
+    match hierarchy_mount(CgroupVersion::V2, "") {
+        Some(mount) => resolve(mount, &path),
+        None => config::cgroup_root().join(path.trim_start_matches('/')),
+    }
+}
+
+/// Find the directory of our own cgroup in the v1 hierarchy of
+/// `controller`, using `/proc/self/cgroup` and `/proc/self/mountinfo`.
+fn v1_dir(controller: &str) -> Option<PathBuf> {
+    let mount = hierarchy_mount(CgroupVersion::V1, controller)?;
+    Some(resolve(mount, &v1_path_of_self(controller)?))
+}
+
+/// Are we in a cgroup namespace other than the host's?  If so, the paths
+/// in `/proc/self/cgroup` are relative to our namespace, and usually just
+/// `/`.
+///
+/// We check whether our namespace differs from that of process 1, which
+/// only works if we share a PID namespace with the host.  Failing that, we
+/// look for cgroup mounts whose root is outside our namespace, and under
+/// cgroup v2, for a "root" cgroup with a memory limit, which the real root
+/// cgroup never has.
+pub fn in_namespace() -> bool {
+    let own = fs::read_link("/proc/self/ns/cgroup");
+    let init = fs::read_link("/proc/1/ns/cgroup");
+    if let (Ok(own), Ok(init)) = (own, init) {
+        if own != init {
+            return true;
+        }
+    }
+    let version = version();
+    let outside = |m: &Mount| m.root.starts_with("/..");
+    if hierarchy_mount(version, "memory").is_some_and(outside) {
+        return true;
+    }
+    version == CgroupVersion::V2 &&
+    v2_path_of_self().as_deref() == Some("/") &&
+    hierarchy_mount(version, "")
+        .is_some_and(|m| m.mount_point.join("memory.max").exists())
+}
+
+/// Find the directory of process `pid`'s cgroup, using
+/// `/proc/<pid>/cgroup`.  Under cgroup v1, this is its directory in the
+/// memory controller's hierarchy.
+pub fn dir_of_pid(pid: u32) -> Result<PathBuf> {
+    let path = PathBuf::from(format!("/proc/{}/cgroup", pid));
+    let contents = fs::read_to_string(&path)
+        .chain_err(|| ErrorKind::File(path.clone()))?;
+    let version = version();
+    for line in contents.lines() {
+        // Each line looks like `id:controllers:path`.
+        let fields: Vec<&str> = line.splitn(3, ':').collect();
+        if fields.len() != 3 {
+            continue;
+        }
+        let mut dir = config::cgroup_root();
+        match version {
+            CgroupVersion::V1 => {
+                if !fields[1].split(',').any(|c| c == "memory") {
+                    continue;
+                }
+                dir.push("memory");
+            }
+            CgroupVersion::V2 => {
+                if fields[0] != "0" {
+                    continue;
+                }
+            }
+        }
+        if let Some(mount) = hierarchy_mount(version, "memory") {
+            return Ok(resolve(mount, fields[2]));
+        }
+        dir.push(fields[2].trim_start_matches('/'));
+        return Ok(dir);
+    }
+    Err(ErrorKind::Parse(path).into())
+}
+
+/// Find our own cgroup in the v1 hierarchy of `controller`, using
+/// `/proc/self/cgroup`.  The path is relative to where the controller is
+/// mounted.
+pub fn v1_path_of_self(controller: &str) -> Option<String> {
+    let contents = fs::read_to_string("/proc/self/cgroup").ok()?;
+    contents.lines()
+        .map(|line| line.splitn(3, ':').collect::<Vec<_>>())
+        .find(|fields| {
+            fields.len() == 3 && fields[1].split(',').any(|c| c == controller)
+        })
+        .map(|fields| fields[2].to_owned())
+}
+
+/// Return the path of a memory controller file, given its name under
+/// cgroup v1 and v2.
+pub fn memory_file(v1: &str, v2: &str) -> PathBuf {
+    controller_file("memory", v1, v2)
+}
+
+/// Return the directory of our cgroup in the memory controller's
+/// hierarchy, for callers which read several files and have already
+/// checked the `version`.
+pub fn memory_dir(version: CgroupVersion) -> PathBuf {
+    controller_dir("memory", version)
+}
+
+/// Return the path of a file belonging to `controller`, given its name
+/// under cgroup v1 and v2.
+pub fn controller_file(controller: &str, v1: &str, v2: &str) -> PathBuf {
+    let version = version();
+    let name = match version {
+        CgroupVersion::V1 => v1,
+        CgroupVersion::V2 => v2,
+    };
+    controller_dir(controller, version).join(name)
+}
+
+/// The directories `find_controller_dir` has found, so that reading a
+/// resource doesn't need to parse `/proc/self/cgroup` every time.
+static OWN_DIRS: Mutex<Vec<(CgroupVersion, String, PathBuf)>> =
+    Mutex::new(Vec::new());
+
+/// Forget the directories we've found for our own cgroup, and close the
+/// `memory.peak` file we keep open, so that we look for them again, such
+/// as after installing a new `Config` or moving this process to another
+/// cgroup.
+pub fn forget_dirs() {
+    OWN_DIRS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    *V2_PEAK_FILE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Return the directory of our cgroup for `controller`.
+pub fn controller_dir(controller: &str, version: CgroupVersion) -> PathBuf {
+    let mut dirs = OWN_DIRS.lock().unwrap_or_else(|e| e.into_inner());
+    let cached = dirs.iter()
+        .find(|(v, c, _)| *v == version && c == controller);
+    if let Some((_, _, dir)) = cached {
+        return dir.clone();
+    }
+    let dir = find_controller_dir(controller, version);
+    dirs.push((version, controller.to_owned(), dir.clone()));
+    dir
+}
+
+/// Look for the directory of our cgroup for `controller`.
+fn find_controller_dir(controller: &str, version: CgroupVersion) -> PathBuf {
+    match version {
+        CgroupVersion::V1 => {
+            config::controller_path(controller)
+                .or_else(|| v1_dir(controller))
+                .or_else(|| android::controller_dir(controller))
+                .unwrap_or_else(|| config::cgroup_root().join(controller))
+        }
+        CgroupVersion::V2 => v2_dir(),
+    }
+}
+
+/// Read a memory limit file.  Under cgroup v2, an unlimited value is
+/// written as `max`, which we report as `u64::MAX`.
+pub fn read_limit(path: &Path) -> Result<u64> {
+    raw::read_limit(path)
+}
+
+/// Read a "flat keyed" file containing lines of the form `name value`,
+/// such as `memory.stat` or `memory.events`.  Lines with values we can't
+/// parse are ignored.
+pub fn read_keyed_file(path: &Path) -> Result<HashMap<String, u64>> {
+    let contents = fs::read_to_string(path)
+        .chain_err(|| ErrorKind::File(path.to_owned()))?;
+    let mut values = HashMap::new();
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+            if let Ok(value) = value.parse() {
+                values.insert(key.to_owned(), value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Write `contents` to a cgroup control file.
+pub fn write_file(path: &Path, contents: &str) -> Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .chain_err(|| ErrorKind::File(path.to_owned()))
+}
+
+/// Under cgroup v2, resetting `memory.peak` only affects reads made
+/// through the same file descriptor, so we keep it open.
+static V2_PEAK_FILE: Mutex<Option<fs::File>> = Mutex::new(None);
+
+/// Run `f` with our shared handle to `memory.peak`, opening it if needed.
+fn with_v2_peak_file<T, F>(f: F) -> Result<T>
+    where F: FnOnce(&mut fs::File) -> ::std::io::Result<T>
+{
+    let path = memory_file("memory.max_usage_in_bytes", "memory.peak");
+    let mkerr = || ErrorKind::File(path.clone());
+    let mut guard = V2_PEAK_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_none() {
+        // We need write access to reset the peak, but fall back to
+        // read-only access if that's all we have.
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .or_else(|_| fs::File::open(&path))
+            .chain_err(mkerr)?;
+        *guard = Some(file);
+    }
+    f(guard.as_mut().unwrap()).chain_err(mkerr)
+}
+
+/// Read the peak memory usage of our cgroup.
+pub fn read_memory_peak() -> Result<u64> {
+    match version() {
+        CgroupVersion::V1 => {
+            read_file_u64(&memory_file("memory.max_usage_in_bytes", ""))
+        }
+        CgroupVersion::V2 => {
+            let s = with_v2_peak_file(|f| {
+                let mut s = String::new();
+                f.seek(SeekFrom::Start(0))?;
+                f.read_to_string(&mut s)?;
+                Ok(s)
+            })?;
+            let path = memory_file("", "memory.peak");
+            s.trim().parse().chain_err(|| ErrorKind::File(path))
+        }
+    }
+}
+
+/// Reset the peak memory usage of our cgroup to its current usage.  This
+/// requires write access to the cgroup, and Linux 6.12 or later when
+/// using cgroup v2.
+pub fn reset_memory_peak() -> Result<()> {
+    match version() {
+        CgroupVersion::V1 => {
+            write_file(&memory_file("memory.max_usage_in_bytes", ""), "0")
+        }
+        CgroupVersion::V2 => with_v2_peak_file(|f| f.write_all(b"reset")),
+    }
+}
@@ -0,0 +1,114 @@
+//! Reading the limits and usage of a cgroup other than our own, for
+//! supervisors and sidecars which keep an eye on their workers.
+
+use std::path::{Path, PathBuf};
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use kmem;
+use read_file_u64;
+use Resource;
+
+/// The resources of an arbitrary cgroup, such as a sibling container or a
+/// child slice.  This supports `Memory`, `OsMemory` and `KernelMemory`.
+/// We can't see inside another process's heap allocator, so `Memory` is
+/// the same as `OsMemory` here.
+///
+/// ```no_run
+/// use resource_monitor::{CgroupResources, Resource};
+///
+/// let worker =
+///     CgroupResources::at("/sys/fs/cgroup/system.slice/worker.service")
+///         .unwrap();
+/// println!("worker: {} bytes available",
+///          worker.available(&Resource::Memory).unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupResources {
+    dir: PathBuf,
+    version: CgroupVersion,
+}
+
+impl CgroupResources {
+    /// Read the cgroup in directory `dir`.  Under cgroup v1, this must be
+    /// in the memory controller's hierarchy, such as
+    /// `/sys/fs/cgroup/memory/docker/<id>`.
+    pub fn at<P: AsRef<Path>>(dir: P) -> Result<CgroupResources> {
+        let dir = dir.as_ref().to_owned();
+        if !dir.is_dir() {
+            return Err(ErrorKind::NotFound(dir).into());
+        }
+        // Every cgroup v2 directory has this file, including the root.
+        let version = if dir.join("cgroup.controllers").exists() {
+            CgroupVersion::V2
+        } else {
+            CgroupVersion::V1
+        };
+        Ok(CgroupResources { dir, version })
+    }
+
+    /// The directory of this cgroup.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The cgroup version of this cgroup's hierarchy.
+    pub fn version(&self) -> CgroupVersion {
+        self.version
+    }
+
+    /// The path of a file in this cgroup, given its name under cgroup v1
+    /// and v2.
+    fn file(&self, v1: &str, v2: &str) -> PathBuf {
+        match self.version {
+            CgroupVersion::V1 => self.dir.join(v1),
+            CgroupVersion::V2 => self.dir.join(v2),
+        }
+    }
+
+    /// What is the maximum amount of `resource` this cgroup may consume?
+    pub fn limit(&self, resource: &Resource) -> Result<u64> {
+        match (resource, self.version) {
+            (&Resource::Memory, _) | (&Resource::OsMemory, _) => {
+                let path = self.file("memory.limit_in_bytes", "memory.max");
+                cgroup::read_limit(&path)
+            }
+            (&Resource::KernelMemory, CgroupVersion::V1) => {
+                cgroup::read_limit(&self.dir.join("memory.kmem.limit_in_bytes"))
+            }
+            _ => not_applicable("limit", resource),
+        }
+    }
+
+    /// What is the current amount of `resource` consumed by this cgroup?
+    pub fn used(&self, resource: &Resource) -> Result<u64> {
+        match (resource, self.version) {
+            (&Resource::Memory, _) | (&Resource::OsMemory, _) => {
+                let path =
+                    self.file("memory.usage_in_bytes", "memory.current");
+                read_file_u64(&path)
+            }
+            (&Resource::KernelMemory, CgroupVersion::V1) => {
+                read_file_u64(&self.dir.join("memory.kmem.usage_in_bytes"))
+            }
+            (&Resource::KernelMemory, CgroupVersion::V2) => {
+                let path = self.dir.join("memory.stat");
+                Ok(kmem::v2_kernel_bytes(&cgroup::read_keyed_file(&path)?))
+            }
+            _ => not_applicable("used", resource),
+        }
+    }
+
+    /// How much of `resource` is available to this cgroup but not yet
+    /// used?
+    pub fn available(&self, resource: &Resource) -> Result<u64> {
+        let limit = self.limit(resource)?;
+        let used = self.used(resource)?;
+        Ok(limit.saturating_sub(used))
+    }
+}
+
+/// Return an error saying that `wanted` isn't available for `resource`.
+fn not_applicable<T>(wanted: &'static str, resource: &Resource) -> Result<T> {
+    Err(ErrorKind::NotApplicable(wanted, resource.clone()).into())
+}
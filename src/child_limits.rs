@@ -0,0 +1,240 @@
+//! Applying resource limits to child processes when we spawn them, so
+//! that a tool which runs many subprocesses can stop any one of them from
+//! taking all our memory.
+
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc;
+
+use errors::*;
+use rlimit::RlimitResource;
+use sub_cgroup::{SubCgroup, SubCgroupLimits};
+use Resource;
+
+/// Per-process limits which we can set on a child with `setrlimit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum ChildRlimit {
+    /// `RLIMIT_AS`, the size of its virtual address space, in bytes.
+    AddressSpace,
+    /// `RLIMIT_DATA`, the size of its heap and other data segments, in
+    /// bytes.
+    Data,
+    /// `RLIMIT_CPU`, the CPU time it may use, in seconds.
+    CpuTime,
+    /// `RLIMIT_NOFILE`, how many file descriptors it may have open.
+    OpenFiles,
+    /// `RLIMIT_NPROC`, how many processes its user may have.
+    Processes,
+    /// `RLIMIT_FSIZE`, the largest file it may write, in bytes.
+    FileSize,
+    /// `RLIMIT_CORE`, the largest core dump it may write, in bytes.
+    Core,
+    /// `RLIMIT_MEMLOCK`, how much memory it may lock into RAM, in bytes.
+    LockedMemory,
+    /// `RLIMIT_STACK`, the size of its main thread's stack, in bytes.
+    Stack,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+impl ChildRlimit {
+    /// The C library's name for this limit.
+    fn raw(self) -> RlimitResource {
+        match self {
+            ChildRlimit::AddressSpace => libc::RLIMIT_AS,
+            ChildRlimit::Data => libc::RLIMIT_DATA,
+            ChildRlimit::CpuTime => libc::RLIMIT_CPU,
+            ChildRlimit::OpenFiles => libc::RLIMIT_NOFILE,
+            ChildRlimit::Processes => libc::RLIMIT_NPROC,
+            ChildRlimit::FileSize => libc::RLIMIT_FSIZE,
+            ChildRlimit::Core => libc::RLIMIT_CORE,
+            ChildRlimit::LockedMemory => libc::RLIMIT_MEMLOCK,
+            ChildRlimit::Stack => libc::RLIMIT_STACK,
+            ChildRlimit::__Private => {
+                unreachable!("Do not use ChildRlimit::__Private")
+            }
+        }
+    }
+}
+
+/// How much memory to allow a child's cgroup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MemoryLimit {
+    /// This many bytes.
+    Bytes(u64),
+    /// This fraction of `Resource::Memory.available()`.
+    ShareOfAvailable(f64),
+}
+
+/// Limits to apply to a child process, using `CommandLimits`.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use resource_monitor::{ChildLimits, ChildRlimit, CommandLimits};
+///
+/// let limits = ChildLimits::new()
+///     .rlimit(ChildRlimit::CpuTime, 60)
+///     .memory_share(0.3);
+/// let mut command = Command::new("convert");
+/// let cgroup = command.apply_limits(&limits).unwrap();
+/// let status = command.status().unwrap();
+/// if let Some(cgroup) = cgroup {
+///     cgroup.remove().unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChildLimits {
+    rlimits: Vec<(ChildRlimit, u64)>,
+    memory: Option<MemoryLimit>,
+    cgroup_name: Option<String>,
+}
+
+impl ChildLimits {
+    /// No limits.
+    pub fn new() -> ChildLimits {
+        ChildLimits::default()
+    }
+
+    /// Set both the soft and hard `limit` to `value`.  `u64::MAX` means
+    /// unlimited.  A child can't raise its hard limit again, but it can't
+    /// be given a higher hard limit than ours, either.
+    pub fn rlimit(mut self, limit: ChildRlimit, value: u64) -> ChildLimits {
+        self.rlimits.retain(|&(l, _)| l != limit);
+        self.rlimits.push((limit, value));
+        self
+    }
+
+    /// Run the child in a new cgroup, nested inside ours, which may use at
+    /// most `bytes` of memory.
+    pub fn memory_limit(mut self, bytes: u64) -> ChildLimits {
+        self.memory = Some(MemoryLimit::Bytes(bytes));
+        self
+    }
+
+    /// Like `memory_limit`, but allow the child `fraction` of the memory
+    /// we have available when the command is set up, such as 0.3 for 30%
+    /// of our headroom.
+    pub fn memory_share(mut self, fraction: f64) -> ChildLimits {
+        self.memory = Some(MemoryLimit::ShareOfAvailable(fraction));
+        self
+    }
+
+    /// Name the child's cgroup `name`, instead of making up a unique name.
+    pub fn cgroup_name<S: Into<String>>(mut self, name: S) -> ChildLimits {
+        self.cgroup_name = Some(name.into());
+        self
+    }
+
+    /// The memory limit for the child's cgroup, if it should have one.
+    fn memory_bytes(&self) -> Result<Option<u64>> {
+        match self.memory {
+            None => Ok(None),
+            Some(MemoryLimit::Bytes(bytes)) => Ok(Some(bytes)),
+            Some(MemoryLimit::ShareOfAvailable(fraction)) => {
+                let available = Resource::Memory.available()?;
+                Ok(Some((available as f64 * fraction.max(0.0)) as u64))
+            }
+        }
+    }
+}
+
+/// Applies `ChildLimits` to a `std::process::Command`.  This is only
+/// available on Unix.
+pub trait CommandLimits {
+    /// Apply `limits` to the process when it's spawned.  If `limits` has a
+    /// memory limit, we create the child's cgroup right away, as described
+    /// for `SubCgroup`, and return it.  The kernel won't remove it when
+    /// the child exits, so call `SubCgroup::remove` once you're done with
+    /// it.
+    fn apply_limits(&mut self,
+                    limits: &ChildLimits)
+                    -> Result<Option<SubCgroup>>;
+}
+
+impl CommandLimits for Command {
+    fn apply_limits(&mut self,
+                    limits: &ChildLimits)
+                    -> Result<Option<SubCgroup>> {
+        let rlimits: Vec<(RlimitResource, libc::rlimit)> = limits.rlimits
+            .iter()
+            .map(|&(limit, value)| (limit.raw(), to_rlimit(value)))
+            .collect();
+        let cgroup = match limits.memory_bytes()? {
+            Some(bytes) => {
+                let name = limits.cgroup_name
+                    .clone()
+                    .unwrap_or_else(unique_name);
+                let limits = SubCgroupLimits {
+                    memory_max: Some(bytes),
+                    ..SubCgroupLimits::default()
+                };
+                Some(SubCgroup::create(&name, &limits)?)
+            }
+            None => None,
+        };
+        // Open `cgroup.procs` now, because we can't allocate memory between
+        // `fork` and `exec`.  Writing 0 to it moves the writing process.
+        let procs = match cgroup {
+            Some(ref cgroup) => {
+                let path = cgroup.path().join("cgroup.procs");
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .chain_err(|| ErrorKind::File(path.clone()))?;
+                Some(file)
+            }
+            None => None,
+        };
+        unsafe {
+            self.pre_exec(move || {
+                for &(resource, ref rlim) in &rlimits {
+                    if libc::setrlimit(resource, rlim) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(ref procs) = procs {
+                    let written = libc::write(procs.as_raw_fd(),
+                                              b"0".as_ptr() as *const _,
+                                              1);
+                    if written != 1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(cgroup)
+    }
+}
+
+/// Convert `value` to an `rlimit` with the same soft and hard limits.
+// `rlim_t` is only 32 bits on some targets.
+#[allow(clippy::unnecessary_cast)]
+fn to_rlimit(value: u64) -> libc::rlimit {
+    let value = if value == u64::MAX {
+        libc::RLIM_INFINITY
+    } else {
+        value as libc::rlim_t
+    };
+    libc::rlimit {
+        rlim_cur: value,
+        rlim_max: value,
+    }
+}
+
+/// Number the cgroups we create, so that their names are unique.
+static NEXT_CGROUP: AtomicUsize = AtomicUsize::new(0);
+
+/// A cgroup name which no other child of ours is using.
+fn unique_name() -> String {
+    format!("resource_monitor-{}-{}",
+            process::id(),
+            NEXT_CGROUP.fetch_add(1, Ordering::Relaxed))
+}
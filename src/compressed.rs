@@ -0,0 +1,164 @@
+//! Compressed swap in RAM, using zram or zswap.  Pages swapped out to
+//! these still take up memory, just less of it.
+
+#[cfg(unix)]
+use libc;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cgroup;
+use errors::*;
+use read_file_u64;
+
+/// Usage of one zram block device, from its `mm_stat` file, in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZramDevice {
+    /// The device name, such as `zram0`.
+    pub name: String,
+    /// The size of the device, which limits how much can be swapped to
+    /// it before compression.
+    pub disk_size: u64,
+    /// Uncompressed size of the data stored on the device.
+    pub original_size: u64,
+    /// Compressed size of the data stored on the device.
+    pub compressed_size: u64,
+    /// RAM used by the device, including fragmentation and metadata.
+    pub memory_used: u64,
+    /// The most RAM the device may use, or zero if there's no limit.
+    pub memory_limit: u64,
+}
+
+impl ZramDevice {
+    /// Read the zram device in `dir`, such as `/sys/block/zram0`.
+    fn read(dir: &Path) -> Result<ZramDevice> {
+        let path = dir.join("mm_stat");
+        let mkerr = || ErrorKind::Parse(path.clone());
+        let contents = fs::read_to_string(&path)
+            .chain_err(|| ErrorKind::File(path.clone()))?;
+        let fields = contents.split_whitespace()
+            .take(4)
+            .map(|f| f.parse())
+            .collect::<::std::result::Result<Vec<u64>, _>>()
+            .chain_err(mkerr)?;
+        if fields.len() < 4 {
+            return Err(mkerr().into());
+        }
+        let name = dir.file_name().map(|n| n.to_string_lossy().into_owned());
+        Ok(ZramDevice {
+            name: name.unwrap_or_default(),
+            disk_size: read_file_u64(&dir.join("disksize"))?,
+            original_size: fields[0],
+            compressed_size: fields[1],
+            memory_used: fields[2],
+            memory_limit: fields[3],
+        })
+    }
+}
+
+/// RAM used by compressed swap, from zram devices and zswap, in bytes.
+///
+/// Pages swapped out to zram or zswap look like free memory, but they
+/// still occupy RAM in compressed form, and that RAM usually isn't charged
+/// to any cgroup.  On hosts which use them, subtract `memory_used` from
+/// free memory, as `adjusted_available` does, so that we don't
+/// overestimate how much room we have.
+///
+/// ```
+/// use resource_monitor::{CompressedSwap, Resource};
+///
+/// let compressed = CompressedSwap::read().unwrap();
+/// let available = Resource::Memory.available().unwrap();
+/// println!("{} bytes available after compressed swap",
+///          compressed.adjusted_available(available));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressedSwap {
+    /// Each zram device which has been set up.
+    pub zram: Vec<ZramDevice>,
+    /// RAM used by the zswap pool, if zswap is in use and we can see it.
+    pub zswap_pool: Option<u64>,
+    /// Uncompressed size of the pages stored in zswap, if we can see it.
+    pub zswap_stored: Option<u64>,
+}
+
+impl CompressedSwap {
+    /// Read zram devices from `/sys/block`, and zswap's pool size from
+    /// `/proc/meminfo` (Linux 5.19 and later) or from debugfs, which is
+    /// usually only readable by root.  Missing information is left out,
+    /// so this returns an empty value on hosts without either.
+    pub fn read() -> Result<CompressedSwap> {
+        let mut zram = vec![];
+        if let Ok(entries) = fs::read_dir("/sys/block") {
+            for entry in entries {
+                let entry = entry.chain_err(|| {
+                    ErrorKind::File(PathBuf::from("/sys/block"))
+                })?;
+                let is_zram =
+                    entry.file_name().to_string_lossy().starts_with("zram");
+                if !is_zram {
+                    continue;
+                }
+                let device = ZramDevice::read(&entry.path())?;
+                // Devices which haven't been given a size aren't in use.
+                if device.disk_size > 0 {
+                    zram.push(device);
+                }
+            }
+        }
+        zram.sort_by(|a, b| a.name.cmp(&b.name));
+        let (zswap_pool, zswap_stored) = read_zswap();
+        Ok(CompressedSwap {
+            zram,
+            zswap_pool,
+            zswap_stored,
+        })
+    }
+
+    /// Total RAM used by compressed swap.
+    pub fn memory_used(&self) -> u64 {
+        self.zram.iter().map(|z| z.memory_used).sum::<u64>() +
+        self.zswap_pool.unwrap_or(0)
+    }
+
+    /// Uncompressed size of everything in compressed swap.
+    pub fn original_size(&self) -> u64 {
+        self.zram.iter().map(|z| z.original_size).sum::<u64>() +
+        self.zswap_stored.unwrap_or(0)
+    }
+
+    /// Reduce `available`, a number of free bytes, by the RAM used by
+    /// compressed swap.  This matters most when the binding limit is the
+    /// machine's physical memory, rather than our cgroup's limit.
+    pub fn adjusted_available(&self, available: u64) -> u64 {
+        available.saturating_sub(self.memory_used())
+    }
+}
+
+/// Read the size of the zswap pool and the uncompressed size of the pages
+/// in it.
+fn read_zswap() -> (Option<u64>, Option<u64>) {
+    if let Ok(meminfo) = cgroup::read_keyed_file(Path::new("/proc/meminfo")) {
+        let get = |key| meminfo.get(key).map(|&kb| kb * 1024);
+        if let (Some(pool), Some(stored)) = (get("Zswap:"), get("Zswapped:")) {
+            return (Some(pool), Some(stored));
+        }
+    }
+    let debugfs = Path::new("/sys/kernel/debug/zswap");
+    let pool = read_file_u64(&debugfs.join("pool_total_size")).ok();
+    let stored = read_file_u64(&debugfs.join("stored_pages"))
+        .ok()
+        .map(|pages| pages * page_size());
+    (pool, stored)
+}
+
+/// The size of a memory page.
+#[cfg(unix)]
+pub fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+/// The size of a memory page, on targets where we can't ask.
+#[cfg(not(unix))]
+pub fn page_size() -> u64 {
+    4096
+}
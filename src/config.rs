@@ -0,0 +1,110 @@
+//! Overriding where we look for cgroup files, for systems which don't
+//! mount them in the usual place.
+
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use cgroup;
+use fallback;
+
+/// The environment variable which overrides `DEFAULT_CGROUP_ROOT`.
+const CGROUP_ROOT_VAR: &str = "RESOURCE_MONITOR_CGROUP_ROOT";
+
+/// Where cgroup filesystems are normally mounted.
+const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The configuration installed by `Config::install`.
+static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+
+/// Where to find cgroup files, for hosts which mount cgroups somewhere
+/// other than `/sys/fs/cgroup`, or use nested namespaces where the paths
+/// in `/proc/self/cgroup` don't resolve.
+///
+/// Anything not set here falls back to the defaults.  The cgroup root can
+/// also be set without code changes using the environment variable
+/// `RESOURCE_MONITOR_CGROUP_ROOT`, but a root set here takes precedence.
+///
+/// ```
+/// use resource_monitor::Config;
+///
+/// Config::new()
+///     .cgroup_root("/cgroup")
+///     .controller_path("memory", "/cgroup/memory/my-job")
+///     .install();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    cgroup_root: Option<PathBuf>,
+    controller_paths: HashMap<String, PathBuf>,
+    cgroup_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// Create a configuration which uses the defaults.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Look for cgroup filesystems under `root` instead of
+    /// `/sys/fs/cgroup`.
+    pub fn cgroup_root<P: AsRef<Path>>(mut self, root: P) -> Config {
+        self.cgroup_root = Some(root.as_ref().to_owned());
+        self
+    }
+
+    /// Under cgroup v1, read `controller` files (such as `memory`) from
+    /// `dir`, instead of from the controller's directory under the cgroup
+    /// root.
+    pub fn controller_path<P: AsRef<Path>>(mut self,
+                                           controller: &str,
+                                           dir: P)
+                                           -> Config {
+        self.controller_paths
+            .insert(controller.to_owned(), dir.as_ref().to_owned());
+        self
+    }
+
+    /// Under cgroup v2, use `dir` as our own cgroup, instead of finding it
+    /// using `/proc/self/cgroup`.
+    pub fn cgroup_path<P: AsRef<Path>>(mut self, dir: P) -> Config {
+        self.cgroup_path = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Use this configuration for all future reads, replacing any
+    /// previously installed configuration.
+    pub fn install(self) {
+        *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(self);
+        // Release our lock first, because finding cgroup directories reads
+        // the configuration while holding the cgroup module's lock.
+        fallback::forget_broken();
+        cgroup::forget_dirs();
+    }
+}
+
+/// Look up a value in the installed configuration.
+fn with_config<T, F>(f: F) -> Option<T>
+    where F: FnOnce(&Config) -> Option<T>
+{
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    config.as_ref().and_then(f)
+}
+
+/// Where cgroup filesystems are mounted.
+pub fn cgroup_root() -> PathBuf {
+    with_config(|c| c.cgroup_root.clone())
+        .or_else(|| env::var_os(CGROUP_ROOT_VAR).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CGROUP_ROOT))
+}
+
+/// The configured directory for a cgroup v1 controller, if any.
+pub fn controller_path(controller: &str) -> Option<PathBuf> {
+    with_config(|c| c.controller_paths.get(controller).cloned())
+}
+
+/// The configured directory of our own cgroup v2 cgroup, if any.
+pub fn cgroup_path() -> Option<PathBuf> {
+    with_config(|c| c.cgroup_path.clone())
+}
@@ -0,0 +1,124 @@
+//! Measuring what a piece of work costs, so that we can compare the memory
+//! and CPU used by different stages of a pipeline in realistic builds.
+
+use std::time::{Duration, Instant};
+
+use allocator_stats;
+use rusage::RusageStats;
+
+/// What a closure cost, as returned by `measure`.  Values which we
+/// couldn't measure are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CostReport {
+    /// The wall-clock time the closure took.
+    pub elapsed: Duration,
+    /// The CPU time we used while it ran, in both user and kernel mode.
+    /// This includes any other threads which were running at the time.
+    pub cpu_time: Option<Duration>,
+    /// How far our peak resident set size grew.  This is zero unless the
+    /// closure pushed our RSS above its previous peak.
+    pub peak_rss_growth: Option<u64>,
+    /// Page faults which didn't require any I/O.
+    pub minor_faults: Option<u64>,
+    /// Page faults which required reading from disk.
+    pub major_faults: Option<u64>,
+    /// Bytes allocated on the heap.  With jemalloc, this only counts the
+    /// current thread.  With a `CountingAllocator`, it counts every
+    /// thread.  Other allocators don't keep count.
+    pub allocated_bytes: Option<u64>,
+    /// Bytes freed on the heap, counted like `allocated_bytes`.
+    pub freed_bytes: Option<u64>,
+}
+
+impl CostReport {
+    /// How many more bytes were allocated than freed.  This is negative
+    /// if the closure freed memory which was allocated before it ran.
+    pub fn net_allocated(&self) -> Option<i64> {
+        Some(self.allocated_bytes? as i64 - self.freed_bytes? as i64)
+    }
+}
+
+/// Run `work`, and return its result along with what it cost.
+///
+/// ```
+/// use resource_monitor::measure;
+///
+/// let (total, cost) = measure(|| (0..1000u64).map(|i| i * i).sum::<u64>());
+/// assert_eq!(total, 332833500);
+/// println!("took {:?}, allocated {:?} bytes",
+///          cost.elapsed,
+///          cost.allocated_bytes);
+/// ```
+pub fn measure<F, T>(work: F) -> (T, CostReport)
+    where F: FnOnce() -> T
+{
+    let meter = CostMeter::start();
+    let result = work();
+    (result, meter.report())
+}
+
+/// Measures the cost of work which doesn't fit in a closure, such as
+/// everything before a guard is dropped.  `measure` is usually simpler.
+///
+/// ```
+/// use resource_monitor::CostMeter;
+///
+/// let meter = CostMeter::start();
+/// let buffer = vec![0u8; 4096];
+/// println!("{:?}", meter.report());
+/// # drop(buffer);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CostMeter {
+    usage: Option<RusageStats>,
+    heap: Option<(u64, u64)>,
+    started: Instant,
+}
+
+impl CostMeter {
+    /// Start measuring.
+    pub fn start() -> CostMeter {
+        let usage = RusageStats::current().ok();
+        let heap = allocator_stats::allocated_and_freed();
+        CostMeter {
+            usage,
+            heap,
+            // Start the clock last, so that it doesn't include our reads.
+            started: Instant::now(),
+        }
+    }
+
+    /// What everything since `start` has cost.  This can be called more
+    /// than once.
+    pub fn report(&self) -> CostReport {
+        let elapsed = self.started.elapsed();
+        let heap_after = allocator_stats::allocated_and_freed();
+        let usage_after = RusageStats::current().ok();
+        let usage = match (self.usage, usage_after) {
+            (Some(before), Some(after)) => Some((before, after)),
+            _ => None,
+        };
+        let heap = match (self.heap, heap_after) {
+            (Some(before), Some(after)) => Some((before, after)),
+            _ => None,
+        };
+        CostReport {
+            elapsed,
+            cpu_time: usage.map(|(b, a)| {
+                a.cpu_time().saturating_sub(b.cpu_time())
+            }),
+            peak_rss_growth: usage.map(|(b, a)| {
+                a.max_rss.saturating_sub(b.max_rss)
+            }),
+            minor_faults: usage.map(|(b, a)| {
+                a.minor_faults.saturating_sub(b.minor_faults)
+            }),
+            major_faults: usage.map(|(b, a)| {
+                a.major_faults.saturating_sub(b.major_faults)
+            }),
+            allocated_bytes: heap.map(|(b, a)| a.0.saturating_sub(b.0)),
+            freed_bytes: heap.map(|(b, a)| a.1.saturating_sub(b.1)),
+        }
+    }
+}
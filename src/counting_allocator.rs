@@ -0,0 +1,241 @@
+//! A `GlobalAlloc` wrapper which counts allocations, for use with heap
+//! allocators that don't provide their own stats API.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cmp::{max, min};
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use memory_scope;
+
+/// Have we ever seen an allocation go through a `CountingAllocator`?
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+/// Bytes currently allocated.
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// The maximum value ever seen in `LIVE_BYTES`.
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// Total number of allocations.
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+/// Total number of deallocations.
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+/// Total bytes ever allocated.
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Total bytes ever freed.
+static FREED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Wraps another allocator and keeps track of how many bytes are in use.
+/// Install it as your global allocator:
+///
+/// ```
+/// use std::alloc::System;
+/// use resource_monitor::CountingAllocator;
+///
+/// #[global_allocator]
+/// static GLOBAL: CountingAllocator<System> = CountingAllocator::new(System);
+///
+/// # fn main() {
+/// let counts = resource_monitor::allocation_counts().unwrap();
+/// println!("{} bytes in use", counts.live_bytes);
+/// # }
+/// ```
+///
+/// Once installed, `Resource::AllocatorMemory` will use these counts if
+/// no native allocator backend is available.  Since a process can only
+/// have one global allocator, the counters themselves are global.
+///
+/// This only tracks the sizes requested by the application, not any
+/// overhead added by the underlying allocator, so it can't tell how much
+/// memory the allocator is holding in reserve.
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A> {
+    inner: A,
+    track_scopes: bool,
+}
+
+impl<A> CountingAllocator<A> {
+    /// Wrap `inner` with allocation counters.
+    pub const fn new(inner: A) -> CountingAllocator<A> {
+        CountingAllocator {
+            inner,
+            track_scopes: false,
+        }
+    }
+
+    /// Wrap `inner` with allocation counters, and also keep track of which
+    /// `MemoryScope` each allocation was made in.  This stores a small
+    /// header in front of every allocation, so it uses a bit more memory.
+    pub const fn with_scopes(inner: A) -> CountingAllocator<A> {
+        CountingAllocator {
+            inner,
+            track_scopes: true,
+        }
+    }
+}
+
+/// When tracking scopes, we prefix each allocation with a header holding
+/// its scope tag.  Return the size of that header (which must preserve
+/// alignment) and the layout of the underlying allocation.
+fn scoped_layout(layout: Layout) -> Option<(usize, Layout)> {
+    let header = max(layout.align(), size_of::<usize>());
+    let size = layout.size().checked_add(header)?;
+    let outer = Layout::from_size_align(size, header).ok()?;
+    Some((header, outer))
+}
+
+/// Record that `size` bytes were allocated.
+fn record_alloc(size: usize) {
+    INSTALLED.store(true, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+/// Record that `size` bytes were freed.
+fn record_dealloc(size: usize) {
+    DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    FREED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
+
+impl<A: GlobalAlloc> CountingAllocator<A> {
+    /// Allocate memory with a scope header, using `alloc_fn` to call
+    /// either `alloc` or `alloc_zeroed` on our inner allocator.
+    unsafe fn alloc_scoped<F>(&self, layout: Layout, alloc_fn: F) -> *mut u8
+        where F: FnOnce(&A, Layout) -> *mut u8
+    {
+        let (header, outer) = match scoped_layout(layout) {
+            Some(layouts) => layouts,
+            None => return ptr::null_mut(),
+        };
+        let base = alloc_fn(&self.inner, outer);
+        if base.is_null() {
+            return base;
+        }
+        let tag = memory_scope::current_tag();
+        let ptr = base.add(header);
+        (ptr.sub(size_of::<usize>()) as *mut usize).write(tag);
+        memory_scope::record_alloc(tag, layout.size());
+        record_alloc(layout.size());
+        ptr
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.track_scopes {
+            return self.alloc_scoped(layout, |a, l| a.alloc(l));
+        }
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if self.track_scopes {
+            return self.alloc_scoped(layout, |a, l| a.alloc_zeroed(l));
+        }
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.track_scopes {
+            // This can't fail, because it succeeded in `alloc_scoped`.
+            let (header, outer) = scoped_layout(layout).unwrap();
+            let tag = (ptr.sub(size_of::<usize>()) as *const usize).read();
+            self.inner.dealloc(ptr.sub(header), outer);
+            memory_scope::record_dealloc(tag, layout.size());
+        } else {
+            self.inner.dealloc(ptr, layout);
+        }
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self,
+                      ptr: *mut u8,
+                      layout: Layout,
+                      new_size: usize)
+                      -> *mut u8 {
+        if self.track_scopes {
+            // Move the data to a new allocation charged to the current
+            // scope.
+            let new_layout =
+                Layout::from_size_align_unchecked(new_size, layout.align());
+            let new_ptr = self.alloc(new_layout);
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr,
+                                         new_ptr,
+                                         min(layout.size(), new_size));
+                self.dealloc(ptr, layout);
+            }
+            return new_ptr;
+        }
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            // Treat this as resizing an existing allocation, without
+            // bumping our allocation counts.
+            if new_size >= layout.size() {
+                let grow = new_size - layout.size();
+                ALLOCATED_BYTES.fetch_add(grow as u64, Ordering::Relaxed);
+                let live = LIVE_BYTES.fetch_add(grow, Ordering::Relaxed) +
+                           grow;
+                PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+            } else {
+                let shrink = layout.size() - new_size;
+                FREED_BYTES.fetch_add(shrink as u64, Ordering::Relaxed);
+                LIVE_BYTES.fetch_sub(shrink, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
+
+/// Counters maintained by `CountingAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationCounts {
+    /// Bytes currently allocated.
+    pub live_bytes: u64,
+    /// The largest value `live_bytes` has ever had.
+    pub peak_bytes: u64,
+    /// Number of allocations performed.
+    pub allocations: u64,
+    /// Number of deallocations performed.
+    pub deallocations: u64,
+    /// Total bytes ever allocated, counting growth from `realloc`.
+    pub allocated_bytes: u64,
+    /// Total bytes ever freed, counting shrinkage from `realloc`.
+    pub freed_bytes: u64,
+}
+
+/// Is a `CountingAllocator` installed as the global allocator?
+pub fn counting_allocator_installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Return the current counts from `CountingAllocator`, or `None` if it
+/// isn't installed.
+pub fn allocation_counts() -> Option<AllocationCounts> {
+    if !counting_allocator_installed() {
+        return None;
+    }
+    Some(AllocationCounts {
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed) as u64,
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed) as u64,
+        allocations: ALLOCATIONS.load(Ordering::Relaxed) as u64,
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed) as u64,
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        freed_bytes: FREED_BYTES.load(Ordering::Relaxed),
+    })
+}
+
+/// Reset the peak counter to the current number of live bytes.
+pub fn reset_peak_bytes() {
+    PEAK_BYTES.store(LIVE_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+}
@@ -0,0 +1,223 @@
+//! CPU usage of the current process.
+
+#[cfg(unix)]
+use libc;
+use std::fs;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::mem;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+#[cfg(not(unix))]
+use Resource;
+
+/// CPU time used by one thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadCpuTime {
+    /// The kernel's ID for this thread.
+    pub tid: u32,
+    /// The thread's name, as set by `std::thread::Builder::name`, truncated
+    /// to 15 bytes by the kernel.
+    pub name: String,
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in the kernel.
+    pub system_time: Duration,
+}
+
+impl ThreadCpuTime {
+    /// Total CPU time, in both user and kernel mode.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+/// User and system CPU time consumed by all threads of this process.
+#[cfg(unix)]
+pub fn process_cpu_time() -> Result<Duration> {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    let clock = libc::CLOCK_PROCESS_CPUTIME_ID;
+    if unsafe { libc::clock_gettime(clock, &mut ts) } != 0 {
+        return Err(io::Error::last_os_error())
+            .chain_err(|| "could not read process CPU time");
+    }
+    Ok(Duration::new(ts.tv_sec.max(0) as u64, ts.tv_nsec.max(0) as u32))
+}
+
+/// Without a CPU time clock, we can't measure CPU time.
+#[cfg(not(unix))]
+pub fn process_cpu_time() -> Result<Duration> {
+    Err(ErrorKind::Unsupported(Resource::CpuTime).into())
+}
+
+/// CPU time used by each thread of the current process, busiest first.
+/// Use this to find out which threads are burning through our CPU quota.
+pub fn thread_cpu_times() -> Result<Vec<ThreadCpuTime>> {
+    thread_cpu_times_in(Path::new("/proc/self"))
+}
+
+/// CPU time used by each thread of the process in `proc_dir`, such as
+/// `/proc/self`, busiest first.  Threads which exit while we're reading
+/// them are skipped.
+pub fn thread_cpu_times_in(proc_dir: &Path) -> Result<Vec<ThreadCpuTime>> {
+    let task_dir = proc_dir.join("task");
+    let entries = fs::read_dir(&task_dir)
+        .chain_err(|| ErrorKind::File(task_dir.clone()))?;
+    let mut threads = vec![];
+    for entry in entries.filter_map(|e| e.ok()) {
+        let tid = match entry.file_name().to_str().map(str::parse) {
+            Some(Ok(tid)) => tid,
+            _ => continue,
+        };
+        if let Ok(stat) = fs::read_to_string(entry.path().join("stat")) {
+            if let Some(thread) = parse_thread_stat(tid, &stat) {
+                threads.push(thread);
+            }
+        }
+    }
+    threads.sort_by_key(|t| ::std::cmp::Reverse(t.cpu_time()));
+    Ok(threads)
+}
+
+/// Parse the `stat` file of thread `tid`.
+fn parse_thread_stat(tid: u32, stat: &str) -> Option<ThreadCpuTime> {
+    // The name is in parentheses, and may itself contain parentheses.
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat.get(start + 1..end)?.to_owned();
+    // `utime` and `stime` are the 14th and 15th fields.
+    let fields: Vec<&str> = stat[end + 1..].split_whitespace().collect();
+    let utime = fields.get(11)?.parse().ok()?;
+    let stime = fields.get(12)?.parse().ok()?;
+    Some(ThreadCpuTime {
+        tid,
+        name,
+        user_time: ticks_to_duration(utime),
+        system_time: ticks_to_duration(stime),
+    })
+}
+
+/// Convert a count of clock ticks, as used in `/proc/<pid>/stat`, to a
+/// `Duration`.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let per_sec = ticks_per_second();
+    Duration::from_secs(ticks / per_sec) +
+    Duration::from_nanos((ticks % per_sec) * 1_000_000_000 / per_sec)
+}
+
+/// The number of clock ticks per second, which is almost always 100.
+#[cfg(unix)]
+fn ticks_per_second() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        n if n > 0 => n as u64,
+        _ => 100,
+    }
+}
+
+/// The number of clock ticks per second, on targets where we can't ask.
+#[cfg(not(unix))]
+fn ticks_per_second() -> u64 {
+    100
+}
+
+/// How many CPUs can we actually use?  This is the smaller of our cgroup's
+/// CPU quota, which may be fractional, and the number of CPUs we're
+/// allowed to run on.
+pub fn effective_cpu_count() -> f64 {
+    // This already accounts for CPU affinity, and for cgroup quotas, but
+    // rounds them up.
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get()) as f64;
+    match cpu_quota() {
+        Some(quota) => quota.min(cpus),
+        None => cpus,
+    }
+}
+
+/// Our cgroup's CPU quota, in CPUs, or `None` if it's unlimited or we
+/// can't read it.
+pub fn cpu_quota() -> Option<f64> {
+    let (quota, period) = match cgroup::version() {
+        CgroupVersion::V1 => {
+            let read = |name| {
+                let path = cgroup::controller_file("cpu", name, "");
+                fs::read_to_string(path).ok()?.trim().parse::<i64>().ok()
+            };
+            (read("cpu.cfs_quota_us")?, read("cpu.cfs_period_us")?)
+        }
+        CgroupVersion::V2 => {
+            // This contains `$MAX $PERIOD`, where `$MAX` may be `max`.
+            let path = cgroup::controller_file("cpu", "", "cpu.max");
+            let max = fs::read_to_string(path).ok()?;
+            let mut fields = max.split_whitespace();
+            (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?)
+        }
+    };
+    if quota > 0 && period > 0 {
+        Some(quota as f64 / period as f64)
+    } else {
+        None
+    }
+}
+
+/// How often the kernel has throttled our cgroup for exceeding its CPU
+/// quota, from `cpu.stat`.  These counters only grow, so compare two
+/// readings with `ratio_since` to see how often we're being throttled now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuThrottling {
+    /// The number of scheduling periods in which we were runnable.
+    pub periods: u64,
+    /// The number of those periods in which we used up our quota.
+    pub throttled_periods: u64,
+    /// The total time we spent throttled.
+    pub throttled_time: Duration,
+}
+
+impl CpuThrottling {
+    /// Read our cgroup's throttling counters.
+    pub fn read() -> Result<CpuThrottling> {
+        let path = cgroup::controller_file("cpu", "cpu.stat", "cpu.stat");
+        let stat = cgroup::read_keyed_file(&path)?;
+        let get = |key: &str| {
+            stat.get(key)
+                .cloned()
+                .ok_or_else(|| Error::from(ErrorKind::Parse(path.clone())))
+        };
+        let throttled_time = match cgroup::version() {
+            CgroupVersion::V1 => Duration::from_nanos(get("throttled_time")?),
+            CgroupVersion::V2 => Duration::from_micros(get("throttled_usec")?),
+        };
+        Ok(CpuThrottling {
+            periods: get("nr_periods")?,
+            throttled_periods: get("nr_throttled")?,
+            throttled_time,
+        })
+    }
+
+    /// The fraction of periods in which we were throttled, since our
+    /// cgroup was created.  Returns `None` if we have no quota.
+    pub fn ratio(&self) -> Option<f64> {
+        ratio(self.throttled_periods, self.periods)
+    }
+
+    /// The fraction of periods in which we were throttled, between
+    /// `earlier` and this reading.  Returns `None` if no periods have
+    /// passed.
+    pub fn ratio_since(&self, earlier: &CpuThrottling) -> Option<f64> {
+        ratio(self.throttled_periods.saturating_sub(earlier.throttled_periods),
+              self.periods.saturating_sub(earlier.periods))
+    }
+}
+
+/// `part / whole`, or `None` if `whole` is 0.
+fn ratio(part: u64, whole: u64) -> Option<f64> {
+    if whole == 0 {
+        None
+    } else {
+        Some(part as f64 / whole as f64)
+    }
+}
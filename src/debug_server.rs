@@ -0,0 +1,178 @@
+//! A tiny HTTP server for poking at a running process's resource usage.
+//! It serves two JSON endpoints:
+//!
+//! - `/resources`: a fresh `Snapshot` of the configured resources, in the
+//!   same format as `RecordFormat::JsonLines`.
+//! - `/allocator`: statistics from our heap allocator backend.
+//!
+//! This is meant for humans and sidecars, not for heavy traffic, so we
+//! handle one request at a time.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use allocator_stats::allocator_backend;
+#[cfg(feature = "jemalloc")]
+use allocator_stats::JemallocStats;
+use errors::*;
+use recorder::snapshot_json;
+use snapshot::Snapshot;
+use Resource;
+
+/// The largest request header we're willing to read.
+const MAX_REQUEST: usize = 8192;
+
+/// Format an optional value as JSON.
+fn json_value(value: Option<u64>) -> String {
+    value.map_or_else(|| "null".to_owned(), |v| v.to_string())
+}
+
+/// Describe our allocator backend as JSON.
+fn allocator_json() -> String {
+    let backend = match allocator_backend() {
+        Some(backend) => backend,
+        None => return "{\"backend\":null}".to_owned(),
+    };
+    let _ = backend.refresh();
+    let mut fields = vec![format!("\"backend\":\"{}\"", backend.name())];
+    let used = backend.used().ok();
+    let reserved = backend.reserved().ok();
+    fields.push(format!("\"used\":{}", json_value(used)));
+    fields.push(format!("\"reserved\":{}", json_value(reserved)));
+    fields.push(format!("\"peak\":{}", json_value(backend.peak())));
+    #[cfg(feature = "jemalloc")]
+    {
+        if backend.name() == "jemalloc" {
+            if let Ok(s) = JemallocStats::snapshot() {
+                fields.push(format!("\"jemalloc\":{{\"allocated\":{},\
+                                     \"active\":{},\"mapped\":{},\
+                                     \"resident\":{},\"retained\":{},\
+                                     \"metadata\":{},\"dirty_pages\":{},\
+                                     \"muzzy_pages\":{}}}",
+                                    s.allocated,
+                                    s.active,
+                                    s.mapped,
+                                    s.resident,
+                                    s.retained,
+                                    s.metadata,
+                                    s.dirty_pages(),
+                                    s.muzzy_pages()));
+            }
+        }
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Read a request from `stream`, and return the path it asked for.
+/// Returns `None` for anything other than a `GET`.
+fn read_request_path(stream: &mut TcpStream) -> Option<String> {
+    let mut request = vec![];
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).ok()?;
+        if n == 0 || request.len() + n > MAX_REQUEST {
+            return None;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.lines().next()?.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("GET"), Some(path)) => Some(path.to_owned()),
+        _ => None,
+    }
+}
+
+/// Answer a single request.
+fn handle(mut stream: TcpStream, resources: &[Resource]) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let (status, body) = match read_request_path(&mut stream).as_deref() {
+        Some("/resources") => {
+            ("200 OK", snapshot_json(&Snapshot::capture(resources)))
+        }
+        Some("/allocator") => ("200 OK", allocator_json()),
+        Some(_) => ("404 Not Found", "{\"error\":\"not found\"}".to_owned()),
+        None => ("400 Bad Request", "{\"error\":\"bad request\"}".to_owned()),
+    };
+    let _ = write!(stream,
+                   "HTTP/1.1 {}\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status,
+                   body.len(),
+                   body);
+}
+
+/// Serves resource information over HTTP on a background thread.  The
+/// server stops when this is dropped.
+///
+/// ```no_run
+/// use resource_monitor::{DebugServer, Resource};
+///
+/// let server = DebugServer::start("127.0.0.1:9091", &[Resource::Memory])
+///     .unwrap();
+/// println!("Try: curl http://{}/resources", server.local_addr());
+/// ```
+///
+/// There is no authentication, so you should normally bind to a loopback
+/// address.
+pub struct DebugServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DebugServer {
+    /// Listen on `addr`, and report on `resources`.  Use port 0 to pick
+    /// any free port, and `local_addr` to find out which one we got.
+    pub fn start<A: ToSocketAddrs>(addr: A,
+                                   resources: &[Resource])
+                                   -> Result<DebugServer> {
+        let listener = TcpListener::bind(addr)
+            .chain_err(|| "could not bind debug server")?;
+        let local_addr = listener.local_addr()
+            .chain_err(|| "could not get debug server address")?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let resources = resources.to_owned();
+            thread::Builder::new()
+                .name("resource_monitor_http".to_owned())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        if stop.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        if let Ok(stream) = stream {
+                            handle(stream, &resources);
+                        }
+                    }
+                })
+                .chain_err(|| "could not spawn debug server thread")?
+        };
+        Ok(DebugServer {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address we're listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for DebugServer {
+    fn drop(&mut self) {
+        // Wake up our thread by connecting to it ourselves.
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.local_addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
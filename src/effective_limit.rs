@@ -0,0 +1,88 @@
+//! Combining the different limits on our memory into the one which
+//! actually binds.
+
+use std::path::Path;
+
+use cgroup;
+use errors::*;
+use provider;
+use rlimit::{self, Rlimit};
+
+/// Where an `EffectiveLimit` came from.  This type may be extended with
+/// new variants; do not attempt to exhaustively match against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum LimitSource {
+    /// Our cgroup's memory limit, or the limit reported by an installed
+    /// `ResourceProvider`.
+    Cgroup,
+    /// Our `RLIMIT_AS` soft limit on address space.  This limits virtual
+    /// memory, not physical memory, so it's only an upper bound, but an
+    /// allocation which would exceed it fails no matter how much memory is
+    /// free.
+    AddressSpace,
+    /// The physical memory of the machine, `MemTotal` in `/proc/meminfo`.
+    PhysicalMemory,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// The tightest of the limits on our memory, and which one it was.  See
+/// `Resource::effective_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveLimit {
+    /// The smallest of the limits we found, in bytes.
+    pub limit: u64,
+    /// Which limit that was.
+    pub source: LimitSource,
+    /// Our cgroup's memory limit, if we have one.
+    pub cgroup: Option<u64>,
+    /// Our `RLIMIT_AS` soft limit, if it's set.
+    pub address_space: Option<u64>,
+    /// The physical memory of the machine, if we could read it.
+    pub physical_memory: Option<u64>,
+}
+
+/// Read the physical memory of the machine.
+fn physical_memory() -> Result<u64> {
+    let path = Path::new("/proc/meminfo");
+    let meminfo = cgroup::read_keyed_file(path)?;
+    match meminfo.get("MemTotal:") {
+        Some(&kb) => Ok(kb * 1024),
+        None => Err(ErrorKind::Parse(path.to_owned()).into()),
+    }
+}
+
+/// Combine `cgroup_limit` with our other limits.
+pub fn read(cgroup_limit: Result<u64>) -> Result<EffectiveLimit> {
+    let cgroup = match cgroup_limit {
+        Ok(limit) => Some(limit),
+        // We may not be in a cgroup with a memory controller at all.
+        Err(ref err) if err.is_unsupported() => None,
+        Err(err) => return Err(err),
+    };
+    // A `ResourceProvider` replaces the system, so don't mix in real
+    // limits.
+    let (address_space, physical_memory) = if provider::current().is_some() {
+        (None, None)
+    } else {
+        let address_space = rlimit::soft_limit(Rlimit::AddressSpace)?;
+        let address_space = Some(address_space).filter(|&l| l != u64::MAX);
+        (address_space, Some(physical_memory()?))
+    };
+    let candidates = [(cgroup, LimitSource::Cgroup),
+                      (address_space, LimitSource::AddressSpace),
+                      (physical_memory, LimitSource::PhysicalMemory)];
+    let (limit, source) = candidates.iter()
+        .filter_map(|&(limit, source)| limit.map(|l| (l, source)))
+        .min_by_key(|&(limit, _)| limit)
+        .ok_or("could not find any memory limit")?;
+    Ok(EffectiveLimit {
+        limit,
+        source,
+        cgroup,
+        address_space,
+        physical_memory,
+    })
+}
@@ -0,0 +1,142 @@
+//! Working out what kind of system we're running in, so that callers can
+//! choose sensible defaults.  All of this is heuristic: container runtimes
+//! don't announce themselves in any standard way, so we look at cgroup
+//! paths, mounts, marker files and environment variables.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use cgroup::{self, CgroupVersion};
+
+/// Container runtimes we know how to recognize.  This type may be
+/// extended with new variants; do not attempt to exhaustively match
+/// against it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerRuntime {
+    /// Docker.
+    Docker,
+    /// containerd, which Kubernetes usually uses under the hood.
+    Containerd,
+    /// CRI-O.
+    CriO,
+    /// Podman.
+    Podman,
+    /// LXC or LXD.
+    Lxc,
+    /// Some other runtime, which set the `container` environment variable
+    /// to this name, as recommended by systemd.
+    Other(String),
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// What we're running in.  See `environment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment {
+    /// Which cgroup version the system uses.
+    pub cgroup_version: CgroupVersion,
+    /// Are we in a private cgroup namespace?  See `in_cgroup_namespace`.
+    pub cgroup_namespace: bool,
+    /// The container runtime we're running under, if any.
+    pub container: Option<ContainerRuntime>,
+    /// Are we running in a Kubernetes pod?
+    pub kubernetes: bool,
+    /// The systemd slice containing our cgroup, such as `user.slice`.
+    pub systemd_slice: Option<String>,
+    /// The systemd service or scope we're running in, such as
+    /// `nginx.service`.
+    pub systemd_unit: Option<String>,
+    /// Are we running under the Windows Subsystem for Linux?
+    pub wsl: bool,
+}
+
+impl Environment {
+    /// Are we running in a container, or a Kubernetes pod?
+    pub fn in_container(&self) -> bool {
+        self.container.is_some() || self.kubernetes
+    }
+
+    /// Are we running directly on a Linux host (or virtual machine),
+    /// rather than in a container or WSL?
+    pub fn is_bare_metal(&self) -> bool {
+        !self.in_container() && !self.wsl
+    }
+}
+
+/// Work out what we're running in, using `/proc/1/cgroup`,
+/// `/proc/self/cgroup`, `/proc/self/mountinfo`, marker files such as
+/// `/.dockerenv`, and environment variables.
+pub fn environment() -> Environment {
+    let read = |path: &str| fs::read_to_string(path).unwrap_or_default();
+    let own_cgroup = read("/proc/self/cgroup");
+    // Cgroup namespaces hide our cgroup path, but bind mounts of files
+    // like `/etc/hostname` often reveal the container's directory.
+    let evidence = [read("/proc/1/cgroup"),
+                    own_cgroup.clone(),
+                    read("/proc/self/mountinfo")]
+        .concat();
+    let contains = |needles: &[&str]| {
+        needles.iter().any(|n| evidence.contains(n))
+    };
+
+    let container = if contains(&["cri-containerd", "/containerd/"]) {
+        Some(ContainerRuntime::Containerd)
+    } else if contains(&["crio-", "/crio/"]) {
+        Some(ContainerRuntime::CriO)
+    } else if contains(&["libpod"]) ||
+              Path::new("/run/.containerenv").exists() {
+        Some(ContainerRuntime::Podman)
+    } else if contains(&["/docker/", "docker-"]) ||
+              Path::new("/.dockerenv").exists() {
+        Some(ContainerRuntime::Docker)
+    } else if contains(&["/lxc/", "lxc.payload"]) {
+        Some(ContainerRuntime::Lxc)
+    } else {
+        env::var("container").ok().map(|name| {
+            match name.as_str() {
+                "docker" => ContainerRuntime::Docker,
+                "podman" => ContainerRuntime::Podman,
+                "lxc" | "lxc-libvirt" => ContainerRuntime::Lxc,
+                _ => ContainerRuntime::Other(name),
+            }
+        })
+    };
+
+    let kubernetes = env::var_os("KUBERNETES_SERVICE_HOST").is_some() ||
+                     contains(&["kubepods", "/var/lib/kubelet/"]);
+
+    // Find the systemd units in our own cgroup path.
+    let mut systemd_slice = None;
+    let mut systemd_unit = None;
+    for line in own_cgroup.lines() {
+        let path = line.splitn(3, ':').nth(2).unwrap_or("");
+        for component in path.split('/') {
+            if component.ends_with(".slice") {
+                systemd_slice = Some(component.to_owned());
+            } else if component.ends_with(".service") ||
+                      component.ends_with(".scope") {
+                systemd_unit = Some(component.to_owned());
+            }
+        }
+        if systemd_unit.is_some() {
+            break;
+        }
+    }
+
+    let wsl = env::var_os("WSL_DISTRO_NAME").is_some() ||
+              read("/proc/sys/kernel/osrelease")
+        .to_lowercase()
+        .contains("microsoft");
+
+    Environment {
+        cgroup_version: cgroup::version(),
+        cgroup_namespace: cgroup::in_namespace(),
+        container,
+        kubernetes,
+        systemd_slice,
+        systemd_unit,
+        wsl,
+    }
+}
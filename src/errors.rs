@@ -0,0 +1,272 @@
+//! Our error type.
+//!
+//! Each `Error` has an `ErrorKind`, which says what went wrong in terms
+//! callers can act on, and optionally the lower-level error which caused
+//! it, available through `std::error::Error::source`.  `Error` is `Send`
+//! and `Sync`, so it works with async code and with crates like `anyhow`.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::path::PathBuf;
+use std::result;
+
+use {AllocatorKind, Resource};
+
+/// A `Result` type using our `Error`.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The different kinds of error we can return.  This type may be extended
+/// with new variants; do not attempt to exhaustively match against it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An error occurred while trying to access the specified path.
+    File(PathBuf),
+    /// The specified path doesn't exist.  For cgroup files, this usually
+    /// means the controller isn't enabled, or the kernel is too old.
+    NotFound(PathBuf),
+    /// We don't have permission to access the specified path.
+    PermissionDenied(PathBuf),
+    /// The specified file contained a value we couldn't parse.
+    Parse(PathBuf),
+    /// This resource can't be measured on this system, or in this build of
+    /// the crate.
+    Unsupported(Resource),
+    /// We can't read heap allocator statistics, because no allocator
+    /// feature is enabled and no `CountingAllocator` is installed.
+    AllocatorUnavailable,
+    /// The process is using a heap allocator which this build of the crate
+    /// can't read statistics from, either because its cargo feature isn't
+    /// enabled, or because it has no statistics API.
+    UnsupportedAllocator(AllocatorKind),
+    /// This feature needs operating system support which the target we
+    /// were built for doesn't have, such as signals on WASI.
+    UnsupportedPlatform,
+    /// The requested value was not applicable.
+    NotApplicable(&'static str, Resource),
+    /// A string couldn't be parsed as a quantity of `Bytes`.
+    InvalidBytes(String),
+    /// A string couldn't be parsed as a `Resource`.
+    InvalidResource(String),
+    /// A `MemoryBudget` didn't have enough memory left for a reservation.
+    BudgetExceeded(u64, u64),
+    /// Some other error, described by a message.
+    Msg(String),
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+impl ErrorKind {
+    /// Will an error of this kind happen every time we try?  This is true
+    /// if the resource isn't supported on this system or in this build, or
+    /// if a file we need is missing or unreadable, as in a rootless
+    /// container.  Callers can remember this and stop asking.  Note that
+    /// per-process files disappear when the process exits.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(*self,
+                 ErrorKind::Unsupported(_) |
+                 ErrorKind::AllocatorUnavailable |
+                 ErrorKind::UnsupportedAllocator(_) |
+                 ErrorKind::UnsupportedPlatform |
+                 ErrorKind::NotFound(_) |
+                 ErrorKind::PermissionDenied(_))
+    }
+
+    /// Might an error of this kind go away if we try again?  This is true
+    /// for I/O errors, and for files containing values we couldn't parse,
+    /// which can happen if we read a file while the kernel is updating it.
+    pub fn is_transient(&self) -> bool {
+        matches!(*self,
+                 ErrorKind::File(_) | ErrorKind::Parse(_) | ErrorKind::Msg(_))
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Note that we have to call `.display()` on path objects to get
+        // something that's valid, printable UTF-8.
+        match *self {
+            ErrorKind::File(ref path) => {
+                write!(f, "could not access {}", path.display())
+            }
+            ErrorKind::NotFound(ref path) => {
+                write!(f, "{} does not exist", path.display())
+            }
+            ErrorKind::PermissionDenied(ref path) => {
+                write!(f, "permission denied for {}", path.display())
+            }
+            ErrorKind::Parse(ref path) => {
+                write!(f, "could not parse {}", path.display())
+            }
+            ErrorKind::Unsupported(ref r) => {
+                write!(f, "{:?} is not supported", r)
+            }
+            ErrorKind::AllocatorUnavailable => {
+                write!(f, "heap allocator statistics are not available")
+            }
+            ErrorKind::UnsupportedAllocator(kind) => {
+                write!(f,
+                       "cannot read statistics from the {} heap allocator",
+                       kind)
+            }
+            ErrorKind::UnsupportedPlatform => {
+                write!(f, "not supported on this platform")
+            }
+            ErrorKind::NotApplicable(wanted, ref r) => {
+                write!(f, "{:?}.{} is not applicable", r, wanted)
+            }
+            ErrorKind::InvalidBytes(ref s) => {
+                write!(f, "could not parse {:?} as a quantity of bytes", s)
+            }
+            ErrorKind::InvalidResource(ref s) => {
+                write!(f, "unknown resource {:?}", s)
+            }
+            ErrorKind::BudgetExceeded(requested, remaining) => {
+                write!(f,
+                       "could not reserve {} bytes, only {} remaining",
+                       requested,
+                       remaining)
+            }
+            ErrorKind::Msg(ref msg) => write!(f, "{}", msg),
+            ErrorKind::__Private => {
+                unreachable!("Do not use ErrorKind::__Private")
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a str> for ErrorKind {
+    fn from(msg: &'a str) -> ErrorKind {
+        ErrorKind::Msg(msg.to_owned())
+    }
+}
+
+impl From<String> for ErrorKind {
+    fn from(msg: String) -> ErrorKind {
+        ErrorKind::Msg(msg)
+    }
+}
+
+/// An error returned by this crate.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Box<dyn StdError + Send + Sync + 'static>>,
+}
+
+impl Error {
+    /// What kind of error is this?
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Will this error happen every time we try?  See
+    /// `ErrorKind::is_unsupported`.
+    pub fn is_unsupported(&self) -> bool {
+        self.kind.is_unsupported()
+    }
+
+    /// Might this error go away if we try again?  See
+    /// `ErrorKind::is_transient`.
+    pub fn is_transient(&self) -> bool {
+        self.kind.is_transient()
+    }
+
+    /// Create an error of `kind`, caused by `source`.  If `kind` is
+    /// `File`, we look at `source` to see whether we can be more specific.
+    fn chained<E>(kind: ErrorKind, source: E) -> Error
+        where E: StdError + Send + Sync + 'static
+    {
+        let kind = match kind {
+            ErrorKind::File(path) => refine_file_error(path, &source),
+            kind => kind,
+        };
+        Error {
+            kind,
+            source: Some(Box::new(source)),
+        }
+    }
+}
+
+/// Turn a `File` error for `path` into `NotFound`, `PermissionDenied` or
+/// `Parse` if `source` tells us which it was.
+fn refine_file_error(path: PathBuf,
+                     source: &(dyn StdError + 'static))
+                     -> ErrorKind {
+    if let Some(err) = source.downcast_ref::<io::Error>() {
+        match err.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound(path),
+            io::ErrorKind::PermissionDenied => {
+                ErrorKind::PermissionDenied(path)
+            }
+            _ => ErrorKind::File(path),
+        }
+    } else if source.is::<ParseIntError>() || source.is::<ParseFloatError>() {
+        ErrorKind::Parse(path)
+    } else if let Some(err) = source.downcast_ref::<Error>() {
+        match err.kind {
+            ErrorKind::NotFound(_) => ErrorKind::NotFound(path),
+            ErrorKind::PermissionDenied(_) => ErrorKind::PermissionDenied(path),
+            ErrorKind::Parse(_) => ErrorKind::Parse(path),
+            _ => ErrorKind::File(path),
+        }
+    } else {
+        ErrorKind::File(path)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.source {
+            Some(ref err) => Some(&**err),
+            None => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error { kind, source: None }
+    }
+}
+
+impl<'a> From<&'a str> for Error {
+    fn from(msg: &'a str) -> Error {
+        ErrorKind::from(msg).into()
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Error {
+        ErrorKind::from(msg).into()
+    }
+}
+
+/// Adds `chain_err` to any `Result`, so that we can wrap low-level errors
+/// in an `ErrorKind` that explains what we were doing.
+pub trait ResultExt<T> {
+    /// If this is an error, wrap it in the `ErrorKind` returned by
+    /// `callback`.
+    fn chain_err<F, EK>(self, callback: F) -> Result<T>
+        where F: FnOnce() -> EK,
+              EK: Into<ErrorKind>;
+}
+
+impl<T, E> ResultExt<T> for result::Result<T, E>
+    where E: StdError + Send + Sync + 'static
+{
+    fn chain_err<F, EK>(self, callback: F) -> Result<T>
+        where F: FnOnce() -> EK,
+              EK: Into<ErrorKind>
+    {
+        self.map_err(|err| Error::chained(callback().into(), err))
+    }
+}
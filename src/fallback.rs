@@ -0,0 +1,171 @@
+//! Best-effort readings for systems where our usual sources are missing
+//! or unreadable, such as Alpine images without jemalloc, or rootless
+//! containers whose cgroup files belong to root.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+
+use cache_policy::CachedValue;
+use cgroup;
+use errors::*;
+use process::ProcessStatus;
+use provider;
+use Resource;
+
+/// Where a best-effort value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum ValueSource {
+    /// The usual source for this resource, such as our cgroup, or the
+    /// heap allocator's statistics.
+    Primary,
+    /// System-wide memory from `/proc/meminfo`, which ignores any cgroup
+    /// limit we can't see.
+    MemInfo,
+    /// Our own process's memory from `/proc/self/status`, which leaves out
+    /// any other processes in our cgroup.
+    ProcStatus,
+    /// An assumption, made because we have nothing to read.  For example,
+    /// without allocator statistics, we assume the heap allocator has no
+    /// free memory in reserve.
+    Assumed,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// A value which may have come from a fallback source, as returned by
+/// `Resource::limit_best_effort` and friends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BestEffort {
+    /// The value.
+    pub value: u64,
+    /// Where it came from.
+    pub source: ValueSource,
+}
+
+impl BestEffort {
+    /// Did this value come from somewhere other than the usual source?
+    /// If so, it's probably less accurate.
+    pub fn is_fallback(&self) -> bool {
+        self.source != ValueSource::Primary
+    }
+}
+
+/// Values whose primary source failed in a way that will happen every
+/// time, so we go straight to the fallback.  We find these out the first
+/// time each value is read.
+static BROKEN: Mutex<Option<HashSet<(Resource, CachedValue)>>> =
+    Mutex::new(None);
+
+/// Read `value` of `resource` using `primary`, or using a fallback source
+/// if `primary` fails for good and we have one.
+pub fn read<F>(resource: &Resource,
+               value: CachedValue,
+               primary: F)
+               -> Result<BestEffort>
+    where F: FnOnce() -> Result<u64>
+{
+    // An installed provider's values are what the caller wants to see,
+    // even if they're errors.
+    if provider::current().is_some() {
+        return primary().map(|value| {
+            BestEffort {
+                value,
+                source: ValueSource::Primary,
+            }
+        });
+    }
+    let key = (resource.clone(), value);
+    if is_broken(&key) {
+        if let Some(result) = fallback(resource, value) {
+            return result;
+        }
+    }
+    let err = match primary() {
+        Ok(value) => {
+            return Ok(BestEffort {
+                value,
+                source: ValueSource::Primary,
+            })
+        }
+        Err(err) => err,
+    };
+    if !err.is_unsupported() {
+        return Err(err);
+    }
+    match fallback(resource, value) {
+        Some(result) => {
+            let mut broken = BROKEN.lock().unwrap_or_else(|e| e.into_inner());
+            broken.get_or_insert_with(HashSet::new).insert(key);
+            result
+        }
+        None => Err(err),
+    }
+}
+
+/// Forget which primary sources failed, so that we try them again, such
+/// as after installing a new `Config`.
+pub fn forget_broken() {
+    let mut broken = BROKEN.lock().unwrap_or_else(|e| e.into_inner());
+    *broken = None;
+}
+
+/// Have we already found that the primary source of `key` doesn't work?
+fn is_broken(key: &(Resource, CachedValue)) -> bool {
+    let broken = BROKEN.lock().unwrap_or_else(|e| e.into_inner());
+    broken.as_ref().is_some_and(|b| b.contains(key))
+}
+
+/// Read `value` of `resource` from a fallback source, or return `None` if
+/// there isn't one.
+fn fallback(resource: &Resource,
+            value: CachedValue)
+            -> Option<Result<BestEffort>> {
+    let from = |source, result: Result<u64>| {
+        Some(result.map(|value| BestEffort { value, source }))
+    };
+    match (resource, value) {
+        (&Resource::Memory, CachedValue::Limit) |
+        (&Resource::OsMemory, CachedValue::Limit) => {
+            from(ValueSource::MemInfo, meminfo("MemTotal:"))
+        }
+        (&Resource::Memory, CachedValue::Available) |
+        (&Resource::OsMemory, CachedValue::Available) => {
+            from(ValueSource::MemInfo, meminfo("MemAvailable:"))
+        }
+        (&Resource::Memory, CachedValue::Used) |
+        (&Resource::OsMemory, CachedValue::Used) => {
+            from(ValueSource::ProcStatus, status(|s| s.vm_rss))
+        }
+        // Most of the heap is anonymous memory.
+        (&Resource::AllocatorMemory, CachedValue::Used) => {
+            from(ValueSource::ProcStatus, status(|s| s.rss_anon))
+        }
+        (&Resource::AllocatorMemory, CachedValue::Available) => {
+            from(ValueSource::Assumed, Ok(0))
+        }
+        _ => None,
+    }
+}
+
+/// Read a value from `/proc/meminfo`, in bytes.
+fn meminfo(key: &str) -> Result<u64> {
+    let path = Path::new("/proc/meminfo");
+    let meminfo = cgroup::read_keyed_file(path)?;
+    match meminfo.get(key) {
+        Some(&kb) => Ok(kb * 1024),
+        None => Err(ErrorKind::Parse(path.to_owned()).into()),
+    }
+}
+
+/// Read a value from `/proc/self/status`.
+fn status<F>(get: F) -> Result<u64>
+    where F: FnOnce(&ProcessStatus) -> Option<u64>
+{
+    let status = ProcessStatus::read()?;
+    get(&status).ok_or_else(|| {
+        ErrorKind::Parse("/proc/self/status".into()).into()
+    })
+}
@@ -0,0 +1,102 @@
+//! A small C API, so that C and C++ programs can use our cgroup-aware
+//! memory readings.  The declarations are in `include/resource_monitor.h`,
+//! which is generated from this file by `cbindgen`.
+//!
+//! Build a library for C programs to link against using:
+//!
+//! ```text
+//! cargo rustc --release --features ffi --crate-type cdylib
+//! ```
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use errors::*;
+use recorder::snapshot_json;
+use snapshot::Snapshot;
+use Resource;
+
+/// The call succeeded.
+pub const RM_OK: c_int = 0;
+/// This system can't measure the value, such as when there's no memory
+/// cgroup and no other source we can use.
+pub const RM_UNSUPPORTED: c_int = -1;
+/// Reading the value failed, possibly temporarily.
+pub const RM_ERROR: c_int = -2;
+/// A pointer argument was null.
+pub const RM_NULL_POINTER: c_int = -3;
+
+/// Store `value` in `out`, and return a status code.
+unsafe fn store(value: Result<u64>, out: *mut u64) -> c_int {
+    if out.is_null() {
+        return RM_NULL_POINTER;
+    }
+    match value {
+        Ok(value) => {
+            *out = value;
+            RM_OK
+        }
+        Err(ref err) if err.is_unsupported() => RM_UNSUPPORTED,
+        Err(_) => RM_ERROR,
+    }
+}
+
+/// Store the memory limit of this process, in bytes, in `*out`.  Returns
+/// `RM_OK` on success, or a negative error code.
+///
+/// # Safety
+///
+/// `out` must be null or point to a writable `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn rm_memory_limit(out: *mut u64) -> c_int {
+    store(Resource::Memory.limit(), out)
+}
+
+/// Store the memory used by this process, in bytes, in `*out`.  Returns
+/// `RM_OK` on success, or a negative error code.
+///
+/// # Safety
+///
+/// `out` must be null or point to a writable `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn rm_memory_used(out: *mut u64) -> c_int {
+    store(Resource::Memory.used(), out)
+}
+
+/// Store the memory still available to this process, in bytes, in
+/// `*out`.  Returns `RM_OK` on success, or a negative error code.
+///
+/// # Safety
+///
+/// `out` must be null or point to a writable `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn rm_memory_available(out: *mut u64) -> c_int {
+    store(Resource::Memory.available(), out)
+}
+
+/// Take a snapshot of every resource we know about, and return it as a
+/// JSON object, in the same format as `RecordFormat::JsonLines`.  The
+/// caller must free the string using `rm_string_free`.  Returns null if
+/// the string can't be allocated.
+#[no_mangle]
+pub extern "C" fn rm_snapshot_json() -> *mut c_char {
+    let snapshot = Snapshot::capture(&Resource::all());
+    match CString::new(snapshot_json(&snapshot)) {
+        Ok(json) => json.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Free a string returned by this library.  Passing null does nothing.
+///
+/// # Safety
+///
+/// `s` must be null, or a string returned by this library which hasn't
+/// already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rm_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -0,0 +1,158 @@
+//! GPU memory, read through NVIDIA's management library (NVML).
+//!
+//! We number devices the same way CUDA does: each GPU in MIG mode is
+//! replaced by its MIG instances, so that a container which has been given
+//! a single MIG slice sees it as device 0, with the slice's memory.
+
+use errors::*;
+#[cfg(not(feature = "nvml"))]
+use Resource;
+
+/// Memory of one GPU device, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpuMemoryInfo {
+    /// Total memory on the device, or in the MIG slice.
+    pub total: u64,
+    /// Memory in use by any process.
+    pub used: u64,
+    /// Memory which is free.
+    pub free: u64,
+}
+
+/// Read the memory of GPU `index`.
+#[cfg(feature = "nvml")]
+pub fn memory_info(index: u32) -> Result<GpuMemoryInfo> {
+    nvml::memory_info(index)
+}
+
+/// Read the memory of GPU `index`.
+#[cfg(not(feature = "nvml"))]
+pub fn memory_info(index: u32) -> Result<GpuMemoryInfo> {
+    Err(ErrorKind::Unsupported(Resource::GpuMemory(index)).into())
+}
+
+#[cfg(feature = "nvml")]
+mod nvml {
+    use libc::{c_char, c_int, c_uint, c_ulonglong, c_void};
+    use std::ffi::CStr;
+    use std::sync::OnceLock;
+
+    use errors::*;
+    use super::GpuMemoryInfo;
+    use Resource;
+
+    type NvmlReturn = c_int;
+    type NvmlDevice = *mut c_void;
+
+    const NVML_SUCCESS: NvmlReturn = 0;
+    const NVML_DEVICE_MIG_ENABLE: c_uint = 1;
+
+    #[repr(C)]
+    struct NvmlMemory {
+        total: c_ulonglong,
+        free: c_ulonglong,
+        used: c_ulonglong,
+    }
+
+    #[link(name = "nvidia-ml")]
+    extern "C" {
+        fn nvmlInit_v2() -> NvmlReturn;
+        fn nvmlErrorString(result: NvmlReturn) -> *const c_char;
+        fn nvmlDeviceGetCount_v2(count: *mut c_uint) -> NvmlReturn;
+        fn nvmlDeviceGetHandleByIndex_v2(index: c_uint,
+                                         device: *mut NvmlDevice)
+                                         -> NvmlReturn;
+        fn nvmlDeviceGetMigMode(device: NvmlDevice,
+                                current: *mut c_uint,
+                                pending: *mut c_uint)
+                                -> NvmlReturn;
+        fn nvmlDeviceGetMaxMigDeviceCount(device: NvmlDevice,
+                                          count: *mut c_uint)
+                                          -> NvmlReturn;
+        fn nvmlDeviceGetMigDeviceHandleByIndex(device: NvmlDevice,
+                                               index: c_uint,
+                                               mig: *mut NvmlDevice)
+                                               -> NvmlReturn;
+        fn nvmlDeviceGetMemoryInfo(device: NvmlDevice,
+                                   memory: *mut NvmlMemory)
+                                   -> NvmlReturn;
+    }
+
+    /// Turn an NVML return code into a `Result`.
+    fn check(result: NvmlReturn, what: &str) -> Result<()> {
+        if result == NVML_SUCCESS {
+            return Ok(());
+        }
+        let msg = unsafe { CStr::from_ptr(nvmlErrorString(result)) };
+        Err(format!("NVML could not {}: {}", what, msg.to_string_lossy())
+            .into())
+    }
+
+    /// Initialize NVML, once.  We never shut it down, because other
+    /// threads may still be using it.
+    fn init() -> Result<()> {
+        static INIT: OnceLock<NvmlReturn> = OnceLock::new();
+        check(*INIT.get_or_init(|| unsafe { nvmlInit_v2() }), "initialize")
+    }
+
+    /// Find the handle of device `index`, counting MIG instances in place
+    /// of their parent GPU.
+    fn device(index: u32) -> Result<NvmlDevice> {
+        init()?;
+        let mut count: c_uint = 0;
+        check(unsafe { nvmlDeviceGetCount_v2(&mut count) },
+              "count devices")?;
+        let mut seen = 0;
+        for i in 0..count {
+            let mut gpu: NvmlDevice = ::std::ptr::null_mut();
+            check(unsafe { nvmlDeviceGetHandleByIndex_v2(i, &mut gpu) },
+                  "get device handle")?;
+            let (mut current, mut pending) = (0, 0);
+            let mig = unsafe {
+                nvmlDeviceGetMigMode(gpu, &mut current, &mut pending)
+            };
+            if mig != NVML_SUCCESS || current != NVML_DEVICE_MIG_ENABLE {
+                if seen == index {
+                    return Ok(gpu);
+                }
+                seen += 1;
+                continue;
+            }
+            let mut max: c_uint = 0;
+            check(unsafe { nvmlDeviceGetMaxMigDeviceCount(gpu, &mut max) },
+                  "count MIG devices")?;
+            for j in 0..max {
+                let mut instance: NvmlDevice = ::std::ptr::null_mut();
+                let result = unsafe {
+                    nvmlDeviceGetMigDeviceHandleByIndex(gpu, j, &mut instance)
+                };
+                // Unused MIG slots return an error, so skip them.
+                if result != NVML_SUCCESS {
+                    continue;
+                }
+                if seen == index {
+                    return Ok(instance);
+                }
+                seen += 1;
+            }
+        }
+        Err(ErrorKind::Unsupported(Resource::GpuMemory(index)).into())
+    }
+
+    /// Read the memory of device `index`.
+    pub fn memory_info(index: u32) -> Result<GpuMemoryInfo> {
+        let device = device(index)?;
+        let mut memory = NvmlMemory {
+            total: 0,
+            free: 0,
+            used: 0,
+        };
+        check(unsafe { nvmlDeviceGetMemoryInfo(device, &mut memory) },
+              "read memory info")?;
+        Ok(GpuMemoryInfo {
+            total: memory.total,
+            used: memory.used,
+            free: memory.free,
+        })
+    }
+}
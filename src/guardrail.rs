@@ -0,0 +1,332 @@
+//! Checking several resources against declared limits at once, so that
+//! services don't each reinvent the same threshold logic.
+
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use cpu::CpuThrottling;
+use errors::*;
+use rlimit::{self, Rlimit};
+use snapshot::Snapshot;
+#[cfg(feature = "toml")]
+use toml;
+use Resource;
+
+/// Something a `GuardrailRule` can measure, as a fraction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum GuardrailMetric {
+    /// The fraction of its limit which a resource is using.
+    Usage(Resource),
+    /// Our open file descriptors, as a fraction of `RLIMIT_NOFILE`.
+    OpenFiles,
+    /// The fraction of CPU scheduling periods in which our cgroup was
+    /// throttled for exceeding its quota, since the policy was last
+    /// evaluated.
+    CpuThrottling,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// Metrics are named `open_files`, `cpu_throttling`, or the name of a
+/// resource, as accepted by `Resource::from_str`.
+impl FromStr for GuardrailMetric {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<GuardrailMetric> {
+        match s.trim() {
+            "open_files" => Ok(GuardrailMetric::OpenFiles),
+            "cpu_throttling" => Ok(GuardrailMetric::CpuThrottling),
+            resource => Ok(GuardrailMetric::Usage(resource.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for GuardrailMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GuardrailMetric::Usage(ref resource) => write!(f, "{}", resource),
+            GuardrailMetric::OpenFiles => write!(f, "open_files"),
+            GuardrailMetric::CpuThrottling => write!(f, "cpu_throttling"),
+            GuardrailMetric::__Private => {
+                unreachable!("Do not use GuardrailMetric::__Private")
+            }
+        }
+    }
+}
+
+/// A metric, and the largest fraction we allow it to reach.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardrailRule {
+    /// What we measure.
+    pub metric: GuardrailMetric,
+    /// The rule is violated when `metric` is at or above this, between
+    /// 0.0 and 1.0.
+    pub max: f64,
+}
+
+/// How a `GuardrailRule` fared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleStatus {
+    /// The metric is below its maximum.
+    Pass,
+    /// The metric has reached its maximum.
+    Violated,
+    /// We couldn't measure the metric, or it has no limit.
+    Unknown,
+}
+
+/// The result of checking a single rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleResult {
+    /// The rule we checked.
+    pub rule: GuardrailRule,
+    /// The value of its metric, if we could measure it.
+    pub value: Option<f64>,
+    /// Whether it passed.
+    pub status: RuleStatus,
+    /// Is this the first evaluation in which the rule was violated, after
+    /// one in which it wasn't?
+    pub newly_violated: bool,
+}
+
+/// The result of `GuardrailPolicy::evaluate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    /// The result of each rule, in the order they were added.
+    pub results: Vec<RuleResult>,
+}
+
+impl Verdict {
+    /// Did every rule we could measure pass?
+    pub fn is_ok(&self) -> bool {
+        self.violations().next().is_none()
+    }
+
+    /// The rules which were violated.
+    pub fn violations(&self) -> impl Iterator<Item = &RuleResult> {
+        self.results.iter().filter(|r| r.status == RuleStatus::Violated)
+    }
+}
+
+/// Something which wants to know when a rule is newly violated.
+type ViolationCallback = Box<dyn FnMut(&RuleResult) + Send>;
+
+/// A set of rules which we check together.
+///
+/// ```
+/// use resource_monitor::{GuardrailPolicy, Resource};
+///
+/// let mut policy = GuardrailPolicy::new()
+///     .max_usage(Resource::Memory, 0.85)
+///     .max_open_files(0.9)
+///     .max_cpu_throttling(0.2)
+///     .on_violation(|result| {
+///         eprintln!("{} is at {:?}", result.rule.metric, result.value);
+///     });
+/// if !policy.evaluate().is_ok() {
+///     println!("shedding load");
+/// }
+/// ```
+///
+/// Policies can also be read from TOML with `from_toml`, which requires the
+/// `toml` feature.
+#[derive(Default)]
+pub struct GuardrailPolicy {
+    rules: Vec<GuardrailRule>,
+    violated: Vec<bool>,
+    last_throttling: Option<CpuThrottling>,
+    callbacks: Vec<ViolationCallback>,
+}
+
+impl fmt::Debug for GuardrailPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GuardrailPolicy")
+            .field("rules", &self.rules)
+            .field("violated", &self.violated)
+            .finish()
+    }
+}
+
+impl GuardrailPolicy {
+    /// A policy with no rules.
+    pub fn new() -> GuardrailPolicy {
+        GuardrailPolicy::default()
+    }
+
+    /// Read a policy from TOML, which maps metric names, as accepted by
+    /// `GuardrailMetric::from_str`, to a fraction or a percentage.
+    ///
+    /// ```
+    /// use resource_monitor::GuardrailPolicy;
+    ///
+    /// let policy = GuardrailPolicy::from_toml(r#"
+    ///     memory = "85%"
+    ///     open_files = 0.9
+    ///     cpu_throttling = "20%"
+    /// "#).unwrap();
+    /// assert_eq!(policy.rules().len(), 3);
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn from_toml(source: &str) -> Result<GuardrailPolicy> {
+        let table: toml::Table = source.parse()
+            .map_err(|e| format!("could not parse guardrail policy: {}", e))?;
+        let mut policy = GuardrailPolicy::new();
+        for (name, value) in table {
+            let max = match value {
+                toml::Value::Float(f) => f,
+                toml::Value::Integer(i) => i as f64,
+                toml::Value::String(ref s) => parse_fraction(s)?,
+                _ => {
+                    return Err(format!("invalid maximum for {}: {}",
+                                       name,
+                                       value)
+                        .into())
+                }
+            };
+            policy = policy.rule(name.parse()?, max);
+        }
+        Ok(policy)
+    }
+
+    /// Add a rule that `metric` stays below `max`.
+    pub fn rule(mut self,
+                metric: GuardrailMetric,
+                max: f64)
+                -> GuardrailPolicy {
+        self.rules.push(GuardrailRule { metric, max });
+        self.violated.push(false);
+        self
+    }
+
+    /// Add a rule that `resource` uses less than `max` of its limit.
+    pub fn max_usage(self, resource: Resource, max: f64) -> GuardrailPolicy {
+        self.rule(GuardrailMetric::Usage(resource), max)
+    }
+
+    /// Add a rule that we use less than `max` of our file descriptors.
+    pub fn max_open_files(self, max: f64) -> GuardrailPolicy {
+        self.rule(GuardrailMetric::OpenFiles, max)
+    }
+
+    /// Add a rule that our cgroup is throttled in less than `max` of CPU
+    /// scheduling periods.
+    pub fn max_cpu_throttling(self, max: f64) -> GuardrailPolicy {
+        self.rule(GuardrailMetric::CpuThrottling, max)
+    }
+
+    /// Call `callback` whenever `evaluate` finds that a rule which
+    /// previously passed is now violated.
+    pub fn on_violation<F>(mut self, callback: F) -> GuardrailPolicy
+        where F: FnMut(&RuleResult) + Send + 'static
+    {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Our rules.
+    pub fn rules(&self) -> &[GuardrailRule] {
+        &self.rules
+    }
+
+    /// The resources we need to read to check our `Usage` rules.
+    pub fn resources(&self) -> Vec<Resource> {
+        let mut resources = vec![];
+        for rule in &self.rules {
+            if let GuardrailMetric::Usage(ref r) = rule.metric {
+                if !resources.contains(r) {
+                    resources.push(r.clone());
+                }
+            }
+        }
+        resources
+    }
+
+    /// Check every rule.
+    pub fn evaluate(&mut self) -> Verdict {
+        let snapshot = Snapshot::capture(&self.resources());
+        self.evaluate_snapshot(&snapshot)
+    }
+
+    /// Check every rule, using `snapshot` for resource usage.  Resources
+    /// which `snapshot` doesn't include are `Unknown`.
+    pub fn evaluate_snapshot(&mut self, snapshot: &Snapshot) -> Verdict {
+        let throttling = if self.rules
+            .iter()
+            .any(|r| r.metric == GuardrailMetric::CpuThrottling) {
+            self.cpu_throttling()
+        } else {
+            None
+        };
+        let mut results = Vec::with_capacity(self.rules.len());
+        for (rule, violated) in self.rules.iter().zip(&mut self.violated) {
+            let value = match rule.metric {
+                GuardrailMetric::Usage(ref resource) => {
+                    snapshot.fraction_used(resource)
+                }
+                GuardrailMetric::OpenFiles => open_files_fraction(),
+                GuardrailMetric::CpuThrottling => throttling,
+                GuardrailMetric::__Private => None,
+            };
+            let status = match value {
+                Some(v) if v >= rule.max => RuleStatus::Violated,
+                Some(_) => RuleStatus::Pass,
+                None => RuleStatus::Unknown,
+            };
+            let was_violated = *violated;
+            // Unknown values don't change whether we count as violated.
+            if status != RuleStatus::Unknown {
+                *violated = status == RuleStatus::Violated;
+            }
+            results.push(RuleResult {
+                rule: rule.clone(),
+                value,
+                status,
+                newly_violated: !was_violated &&
+                                status == RuleStatus::Violated,
+            });
+        }
+        for result in results.iter().filter(|r| r.newly_violated) {
+            for callback in &mut self.callbacks {
+                callback(result);
+            }
+        }
+        Verdict { results }
+    }
+
+    /// The fraction of periods we were throttled in since we were last
+    /// called.  The first call covers the lifetime of our cgroup.
+    fn cpu_throttling(&mut self) -> Option<f64> {
+        let now = CpuThrottling::read().ok()?;
+        let ratio = match self.last_throttling {
+            Some(ref earlier) => now.ratio_since(earlier),
+            None => now.ratio(),
+        };
+        self.last_throttling = Some(now);
+        ratio
+    }
+}
+
+/// Our open file descriptors, as a fraction of our limit.
+fn open_files_fraction() -> Option<f64> {
+    let limit = rlimit::soft_limit(Rlimit::OpenFiles).ok()?;
+    let open = fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+    if limit == 0 || limit == u64::MAX {
+        None
+    } else {
+        Some(open as f64 / limit as f64)
+    }
+}
+
+/// Parse a fraction like `0.85`, or a percentage like `85%`.
+#[cfg(feature = "toml")]
+fn parse_fraction(s: &str) -> Result<f64> {
+    let s = s.trim();
+    let parsed = match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("invalid fraction: {:?}", s).into())
+}
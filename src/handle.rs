@@ -0,0 +1,202 @@
+//! A cheap, cloneable handle for reading resources from many threads.
+
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use errors::*;
+use reader::ResourceReader;
+use snapshot::{ReadError, Reading, Snapshot};
+use Resource;
+
+/// The most recent snapshot, and when we took it.
+struct Latest {
+    snapshot: Arc<Snapshot>,
+    errors: Arc<Vec<ReadError>>,
+    taken_at: Instant,
+}
+
+/// State shared by all clones of a `ResourceMonitorHandle`.
+struct Inner {
+    max_age: Duration,
+    latest: RwLock<Latest>,
+    /// Only one thread reads the system at a time.  Others wait for it,
+    /// and then use its snapshot.
+    reader: Mutex<ResourceReader>,
+}
+
+/// A handle for reading a fixed set of resources, meant to be stored in
+/// application state and shared between threads.
+///
+/// Cloning a handle is cheap, and all clones share the same snapshot.
+/// When a caller asks for a value, we use the latest snapshot if it's
+/// younger than `max_age`.  Otherwise, one thread takes a new snapshot,
+/// while any others who need it wait, so the files are read at most once
+/// per `max_age` no matter how many threads are asking.  Unlike `Monitor`,
+/// there's no background thread, so an idle handle costs nothing.
+///
+/// ```
+/// use std::thread;
+/// use std::time::Duration;
+/// use resource_monitor::{Resource, ResourceMonitorHandle};
+///
+/// let handle = ResourceMonitorHandle::new(&[Resource::Memory],
+///                                         Duration::from_millis(100));
+/// let workers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let handle = handle.clone();
+///         thread::spawn(move || handle.available(&Resource::Memory))
+///     })
+///     .collect();
+/// for worker in workers {
+///     println!("{:?}", worker.join().unwrap());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ResourceMonitorHandle {
+    inner: Arc<Inner>,
+}
+
+impl ResourceMonitorHandle {
+    /// Create a handle which reads `resources`, reusing each snapshot for
+    /// up to `max_age`.  We take the first snapshot before returning.
+    pub fn new(resources: &[Resource],
+               max_age: Duration)
+               -> ResourceMonitorHandle {
+        let mut reader = ResourceReader::new(resources);
+        let (snapshot, errors) = reader.snapshot_with_errors();
+        ResourceMonitorHandle {
+            inner: Arc::new(Inner {
+                max_age,
+                latest: RwLock::new(Latest {
+                    snapshot: Arc::new(snapshot),
+                    errors: Arc::new(errors),
+                    taken_at: Instant::now(),
+                }),
+                reader: Mutex::new(reader),
+            }),
+        }
+    }
+
+    /// How long we reuse each snapshot.
+    pub fn max_age(&self) -> Duration {
+        self.inner.max_age
+    }
+
+    /// The resources we read.
+    pub fn resources(&self) -> Vec<Resource> {
+        self.lock_reader().resources().to_owned()
+    }
+
+    /// Return a snapshot no older than `max_age`, taking a new one if
+    /// necessary.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.latest().0
+    }
+
+    /// Like `snapshot`, but also return any errors we got while taking
+    /// it.
+    pub fn snapshot_with_errors(&self)
+                                -> (Arc<Snapshot>, Arc<Vec<ReadError>>) {
+        self.latest()
+    }
+
+    /// Take a new snapshot now, even if the latest one is still fresh.
+    /// Call this after something which you know changes our usage.
+    pub fn refresh(&self) -> Arc<Snapshot> {
+        let reader = self.lock_reader();
+        self.take_snapshot(reader).0
+    }
+
+    /// The limit of `resource`, as of our latest snapshot.  Resources
+    /// which this handle doesn't track are read directly.
+    pub fn limit(&self, resource: &Resource) -> Result<u64> {
+        self.value(resource, "limit", |r| r.limit)
+            .unwrap_or_else(|| resource.limit())
+    }
+
+    /// The usage of `resource`, as of our latest snapshot.  Resources
+    /// which this handle doesn't track are read directly.
+    pub fn used(&self, resource: &Resource) -> Result<u64> {
+        self.value(resource, "used", |r| r.used)
+            .unwrap_or_else(|| resource.used())
+    }
+
+    /// The amount of `resource` available, as of our latest snapshot.
+    /// Resources which this handle doesn't track are read directly.
+    pub fn available(&self, resource: &Resource) -> Result<u64> {
+        self.value(resource, "available", |r| r.available)
+            .unwrap_or_else(|| resource.available())
+    }
+
+    /// Look up one `value` of `resource` in our latest snapshot, turning a
+    /// missing value back into the error which caused it.  Returns `None`
+    /// if we don't track `resource`.
+    fn value<F>(&self,
+                resource: &Resource,
+                value: &'static str,
+                get: F)
+                -> Option<Result<u64>>
+        where F: FnOnce(&Reading) -> Option<u64>
+    {
+        let (snapshot, errors) = self.latest();
+        let reading = snapshot.get(resource)?;
+        if let Some(v) = get(reading) {
+            return Some(Ok(v));
+        }
+        let kind = errors.iter()
+            .find(|e| &e.resource == resource && e.value == value)
+            .map(|e| e.kind.clone())
+            .unwrap_or_else(|| {
+                ErrorKind::NotApplicable(value, resource.clone())
+            });
+        Some(Err(kind.into()))
+    }
+
+    /// Return our latest snapshot, taking a new one if it's too old.
+    fn latest(&self) -> (Arc<Snapshot>, Arc<Vec<ReadError>>) {
+        {
+            let latest = self.read_latest();
+            if latest.taken_at.elapsed() < self.inner.max_age {
+                return (latest.snapshot.clone(), latest.errors.clone());
+            }
+        }
+        let reader = self.lock_reader();
+        // Another thread may have taken a snapshot while we were waiting
+        // for the lock.
+        {
+            let latest = self.read_latest();
+            if latest.taken_at.elapsed() < self.inner.max_age {
+                return (latest.snapshot.clone(), latest.errors.clone());
+            }
+        }
+        self.take_snapshot(reader)
+    }
+
+    /// Lock our reader, even if a thread panicked while holding it, so
+    /// that one panic doesn't break every clone of this handle.
+    fn lock_reader(&self) -> MutexGuard<'_, ResourceReader> {
+        self.inner.reader.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Lock our latest snapshot for reading, ignoring poisoning like
+    /// `lock_reader`.
+    fn read_latest(&self) -> RwLockReadGuard<'_, Latest> {
+        self.inner.latest.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Take a new snapshot using `reader`, and make it our latest.
+    fn take_snapshot(&self,
+                     mut reader: MutexGuard<ResourceReader>)
+                     -> (Arc<Snapshot>, Arc<Vec<ReadError>>) {
+        let (snapshot, errors) = reader.snapshot_with_errors();
+        let (snapshot, errors) = (Arc::new(snapshot), Arc::new(errors));
+        let mut latest =
+            self.inner.latest.write().unwrap_or_else(|e| e.into_inner());
+        *latest = Latest {
+            snapshot: snapshot.clone(),
+            errors: errors.clone(),
+            taken_at: Instant::now(),
+        };
+        (snapshot, errors)
+    }
+}
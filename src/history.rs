@@ -0,0 +1,226 @@
+//! A window of recent samples, so that we can answer questions like "what
+//! was our peak memory usage over the last five minutes?"
+
+use std::collections::vec_deque;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use snapshot::Snapshot;
+use Resource;
+
+/// Summary statistics for the `used` values of a resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsageStats {
+    /// The number of samples with a `used` value.
+    pub samples: usize,
+    /// The smallest value.
+    pub min: u64,
+    /// The largest value.
+    pub max: u64,
+    /// The mean value.
+    pub mean: f64,
+}
+
+/// Percentiles of the `used` values of a resource, for capacity planning.
+/// Each percentile is one of the values we saw, using the nearest-rank
+/// method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsagePercentiles {
+    /// The number of samples with a `used` value.
+    pub samples: usize,
+    /// The median value.
+    pub p50: u64,
+    /// The value which 95% of samples were at or below.
+    pub p95: u64,
+    /// The value which 99% of samples were at or below.
+    pub p99: u64,
+    /// The largest value.
+    pub max: u64,
+}
+
+/// The most recent snapshots, oldest first, up to a fixed number.  Adding
+/// a snapshot to a full history forgets the oldest one.
+///
+/// ```
+/// use std::time::Duration;
+/// use resource_monitor::{Resource, SampleHistory, Snapshot};
+///
+/// let mut history = SampleHistory::new(60);
+/// history.push(Snapshot::capture(&[Resource::Memory]));
+/// let minute = Duration::from_secs(60);
+/// if let Some(stats) = history.stats(&Resource::Memory, minute) {
+///     println!("Peak memory in the last minute: {}", stats.max);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SampleHistory {
+    capacity: usize,
+    samples: VecDeque<Arc<Snapshot>>,
+}
+
+impl SampleHistory {
+    /// Create an empty history which holds up to `capacity` samples.  We
+    /// always have room for at least one.
+    pub fn new(capacity: usize) -> SampleHistory {
+        SampleHistory {
+            capacity: capacity.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// The most samples we'll keep.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change how many samples we keep, forgetting the oldest ones if we
+    /// now have too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    /// The number of samples we have.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Do we have no samples at all?
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Add `snapshot`, forgetting the oldest sample if we're full.
+    pub fn push<S>(&mut self, snapshot: S)
+        where S: Into<Arc<Snapshot>>
+    {
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(snapshot.into());
+    }
+
+    /// The newest sample, if we have one.
+    pub fn latest(&self) -> Option<&Arc<Snapshot>> {
+        self.samples.back()
+    }
+
+    /// Iterate over our samples, oldest first.  This is useful for
+    /// exporting them, such as by passing each one to a `Recorder`.
+    pub fn iter(&self) -> vec_deque::Iter<'_, Arc<Snapshot>> {
+        self.samples.iter()
+    }
+
+    /// Samples taken at or after `time`, oldest first.
+    pub fn since(&self, time: SystemTime) -> Vec<Arc<Snapshot>> {
+        self.iter().filter(|s| s.taken_at >= time).cloned().collect()
+    }
+
+    /// Samples taken within `window` of the newest one, oldest first.  We
+    /// measure from the newest sample rather than from now, so that a
+    /// stalled monitor still returns its last few samples.
+    pub fn within(&self, window: Duration) -> Vec<Arc<Snapshot>> {
+        let latest = match self.latest() {
+            Some(latest) => latest.taken_at,
+            None => return vec![],
+        };
+        self.iter()
+            .filter(|s| {
+                latest.duration_since(s.taken_at)
+                    .map_or(true, |age| age <= window)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The `used` values of `resource` within `window`, oldest first.
+    /// Samples which couldn't read `resource` are skipped.
+    pub fn used_values(&self, resource: &Resource, window: Duration)
+                       -> Vec<u64> {
+        self.within(window)
+            .iter()
+            .filter_map(|s| s.get(resource).and_then(|r| r.used))
+            .collect()
+    }
+
+    /// The minimum, maximum and mean `used` value of `resource` within
+    /// `window`.  Returns `None` if we have no values.
+    pub fn stats(&self, resource: &Resource, window: Duration)
+                 -> Option<UsageStats> {
+        let values = self.used_values(resource, window);
+        let min = *values.iter().min()?;
+        let max = *values.iter().max()?;
+        let total: f64 = values.iter().map(|&v| v as f64).sum();
+        Some(UsageStats {
+            samples: values.len(),
+            min,
+            max,
+            mean: total / values.len() as f64,
+        })
+    }
+
+    /// The `percentile`th `used` value of `resource` within `window`,
+    /// where `percentile` is between 0.0 and 100.0.  Returns `None` if we
+    /// have no values.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Resource, SampleHistory, Snapshot};
+    ///
+    /// let mut history = SampleHistory::new(60);
+    /// history.push(Snapshot::capture(&[Resource::Memory]));
+    /// let hour = Duration::from_secs(60 * 60);
+    /// let p95 = history.percentile(&Resource::Memory, hour, 95.0);
+    /// println!("p95 memory over the last hour: {:?}", p95);
+    /// ```
+    pub fn percentile(&self,
+                      resource: &Resource,
+                      window: Duration,
+                      percentile: f64)
+                      -> Option<u64> {
+        let mut values = self.used_values(resource, window);
+        values.sort_unstable();
+        nearest_rank(&values, percentile)
+    }
+
+    /// The p50, p95, p99 and maximum `used` values of `resource` within
+    /// `window`.  Returns `None` if we have no values.
+    pub fn percentiles(&self,
+                       resource: &Resource,
+                       window: Duration)
+                       -> Option<UsagePercentiles> {
+        let mut values = self.used_values(resource, window);
+        values.sort_unstable();
+        Some(UsagePercentiles {
+            samples: values.len(),
+            p50: nearest_rank(&values, 50.0)?,
+            p95: nearest_rank(&values, 95.0)?,
+            p99: nearest_rank(&values, 99.0)?,
+            max: *values.last()?,
+        })
+    }
+}
+
+/// The `percentile`th value in `sorted`, using the nearest-rank method:
+/// the smallest value which at least `percentile`% of values are at or
+/// below.
+fn nearest_rank(sorted: &[u64], percentile: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let fraction = percentile.clamp(0.0, 100.0) / 100.0;
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+impl<'a> IntoIterator for &'a SampleHistory {
+    type Item = &'a Arc<Snapshot>;
+    type IntoIter = vec_deque::Iter<'a, Arc<Snapshot>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,186 @@
+//! Huge pages, which databases and packet-processing applications often
+//! reserve up front.  These come from separate pools with their own
+//! cgroup controller, and don't count against our normal memory limit.
+
+use std::fs;
+use std::path::PathBuf;
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use read_file_u64;
+use Resource;
+
+const KB: u64 = 1 << 10;
+const MB: u64 = 1 << 20;
+const GB: u64 = 1 << 30;
+
+/// The system-wide pool of huge pages of one size.  All counts are in
+/// pages, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HugePagePool {
+    /// The size of each page, in bytes.
+    pub page_size: u64,
+    /// The number of pages in the pool, including `surplus` pages.
+    pub total: u64,
+    /// The number of pages which haven't been faulted in yet.  This
+    /// includes `reserved` pages.
+    pub free: u64,
+    /// The number of free pages which have been promised to a mapping,
+    /// and so can't be used by anybody else.
+    pub reserved: u64,
+    /// The number of pages allocated beyond the persistent pool size, up
+    /// to the kernel's overcommit limit.
+    pub surplus: u64,
+}
+
+impl HugePagePool {
+    /// The default huge page size, in bytes, from `/proc/meminfo`.
+    pub fn default_page_size() -> Result<u64> {
+        let path = PathBuf::from("/proc/meminfo");
+        let meminfo = cgroup::read_keyed_file(&path)?;
+        match meminfo.get("Hugepagesize:") {
+            Some(&kb) => Ok(kb * KB),
+            None => Err(ErrorKind::Parse(path).into()),
+        }
+    }
+
+    /// Read the pool of pages of `page_size` bytes.  We use
+    /// `/proc/meminfo` for the default page size, and
+    /// `/sys/kernel/mm/hugepages` for any others.
+    pub fn read(page_size: u64) -> Result<HugePagePool> {
+        let path = PathBuf::from("/proc/meminfo");
+        let meminfo = cgroup::read_keyed_file(&path)?;
+        if meminfo.get("Hugepagesize:") == Some(&(page_size / KB)) {
+            let get = |key| meminfo.get(key).cloned().unwrap_or(0);
+            return Ok(HugePagePool {
+                page_size,
+                total: get("HugePages_Total:"),
+                free: get("HugePages_Free:"),
+                reserved: get("HugePages_Rsvd:"),
+                surplus: get("HugePages_Surp:"),
+            });
+        }
+        let dir = PathBuf::from(format!("/sys/kernel/mm/hugepages/\
+                                         hugepages-{}kB",
+                                        page_size / KB));
+        Ok(HugePagePool {
+            page_size,
+            total: read_file_u64(&dir.join("nr_hugepages"))?,
+            free: read_file_u64(&dir.join("free_hugepages"))?,
+            reserved: read_file_u64(&dir.join("resv_hugepages"))?,
+            surplus: read_file_u64(&dir.join("surplus_hugepages"))?,
+        })
+    }
+
+    /// The number of pages which are in use or reserved.
+    pub fn in_use(&self) -> u64 {
+        self.total.saturating_sub(self.free) + self.reserved
+    }
+}
+
+/// The huge page sizes this kernel supports, in bytes, from
+/// `/sys/kernel/mm/hugepages`.  This is empty if huge pages are disabled.
+pub fn page_sizes() -> Vec<u64> {
+    let entries = match fs::read_dir("/sys/kernel/mm/hugepages") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    // Entries are named like `hugepages-2048kB`.
+    let mut sizes: Vec<u64> = entries.filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let kb = name.strip_prefix("hugepages-")?.strip_suffix("kB")?;
+            kb.parse::<u64>().ok().map(|kb| kb * KB)
+        })
+        .collect();
+    sizes.sort_unstable();
+    sizes
+}
+
+/// The kernel's name for a page size, such as `2MB` or `1GB`, as used in
+/// hugetlb cgroup file names.
+pub fn size_name(page_size: u64) -> String {
+    if page_size >= GB && page_size.is_multiple_of(GB) {
+        format!("{}GB", page_size / GB)
+    } else if page_size >= MB && page_size.is_multiple_of(MB) {
+        format!("{}MB", page_size / MB)
+    } else {
+        format!("{}KB", page_size / KB)
+    }
+}
+
+/// Parse a page size name produced by `size_name`, such as `2MB`.
+pub fn parse_size_name(name: &str) -> Option<u64> {
+    let split = name.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = name.split_at(split);
+    let unit = match unit {
+        "KB" => KB,
+        "MB" => MB,
+        "GB" => GB,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+/// The path of a hugetlb controller file for `page_size`, given its
+/// suffix under cgroup v1 and v2.
+pub fn hugetlb_file(page_size: u64, v1: &str, v2: &str) -> PathBuf {
+    let name = size_name(page_size);
+    cgroup::controller_file("hugetlb",
+                            &format!("hugetlb.{}.{}", name, v1),
+                            &format!("hugetlb.{}.{}", name, v2))
+}
+
+/// Our limit for pages of `page_size` bytes.  This is the smaller of our
+/// hugetlb cgroup limit and the size of the system-wide pool, because
+/// huge pages can't be allocated once the pool is empty.
+pub fn limit(page_size: u64) -> Result<u64> {
+    let pool = HugePagePool::read(page_size)?;
+    let pool_bytes = pool.total * page_size;
+    let path = hugetlb_file(page_size, "limit_in_bytes", "max");
+    if path.exists() {
+        Ok(cgroup::read_limit(&path)?.min(pool_bytes))
+    } else {
+        Ok(pool_bytes)
+    }
+}
+
+/// Bytes of huge pages of `page_size` charged to our cgroup.  If the
+/// hugetlb controller isn't enabled for our cgroup, this is the usage of
+/// the whole system-wide pool.
+pub fn used(page_size: u64) -> Result<u64> {
+    let path = hugetlb_file(page_size, "usage_in_bytes", "current");
+    if path.exists() {
+        read_file_u64(&path)
+    } else {
+        Ok(HugePagePool::read(page_size)?.in_use() * page_size)
+    }
+}
+
+/// The most huge pages of `page_size` our cgroup has used at once
+/// (cgroup v1 only).
+pub fn peak(page_size: u64) -> Result<u64> {
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            read_file_u64(&hugetlb_file(page_size, "max_usage_in_bytes", ""))
+        }
+        CgroupVersion::V2 => {
+            let r = Resource::HugePages(page_size);
+            Err(ErrorKind::NotApplicable("peak", r).into())
+        }
+    }
+}
+
+/// Reset `peak` to the current usage (cgroup v1 only).
+pub fn reset_peak(page_size: u64) -> Result<()> {
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let path = hugetlb_file(page_size, "max_usage_in_bytes", "");
+            cgroup::write_file(&path, "0")
+        }
+        CgroupVersion::V2 => {
+            let r = Resource::HugePages(page_size);
+            Err(ErrorKind::NotApplicable("reset_peak", r).into())
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! A bounded log of notable events, so that post-mortems can see what the
+//! monitor saw without digging through application logs.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::SystemTime;
+
+use guardrail::GuardrailMetric;
+use monitor::MonitorEvent;
+use pressure_state::MemoryState;
+use threshold::Crossing;
+use Resource;
+
+/// The number of incidents a `Monitor` keeps by default.
+pub const EVENT_LOG_LEN: usize = 100;
+
+/// What kind of thing happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum IncidentKind {
+    /// Usage rose above a threshold.
+    ThresholdExceeded,
+    /// Usage fell back below a threshold.
+    ThresholdRecovered,
+    /// Our `MemoryState` changed.
+    StateChanged,
+    /// We couldn't read a value which we could read in the previous
+    /// sample.  We only log the first failure in a row, so that a value
+    /// which is never available doesn't fill up the log.
+    ReadFailed,
+    /// The OOM killer killed processes in our cgroup.
+    OomKill,
+    /// The watchdog set with `Monitor::set_watchdog` fired.  If the
+    /// watchdog aborts the process, this is never logged.
+    WatchdogTriggered,
+    /// Leak detection suspects a leak.
+    LeakSuspected,
+    /// A guardrail rule was violated.
+    GuardrailViolated,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// A notable event, as returned by `Monitor::recent_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Incident {
+    /// When it happened.
+    pub at: SystemTime,
+    /// What happened.
+    pub kind: IncidentKind,
+    /// The resource involved, if any.
+    pub resource: Option<Resource>,
+    /// The most relevant number: the usage for threshold crossings, the
+    /// memory available when the watchdog fired, the number of processes
+    /// killed for OOM kills, and the growth rate in bytes per second for
+    /// leaks.
+    pub value: Option<u64>,
+    /// A description, for humans.
+    pub message: String,
+}
+
+/// The incidents we've seen, oldest first.
+#[derive(Debug)]
+pub struct IncidentLog {
+    capacity: usize,
+    incidents: VecDeque<Incident>,
+    /// The values which failed to read in the last sample.
+    failing: HashSet<(Resource, &'static str)>,
+}
+
+impl IncidentLog {
+    /// Create an empty log which holds up to `capacity` incidents.
+    pub fn new(capacity: usize) -> IncidentLog {
+        IncidentLog {
+            capacity,
+            incidents: VecDeque::new(),
+            failing: HashSet::new(),
+        }
+    }
+
+    /// Change how many incidents we keep, forgetting the oldest ones if we
+    /// now have too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.incidents.len() > capacity {
+            self.incidents.pop_front();
+        }
+    }
+
+    /// Our incidents, oldest first.
+    pub fn recent(&self) -> Vec<Incident> {
+        self.incidents.iter().cloned().collect()
+    }
+
+    /// Log anything notable in `events`, which are the events from a
+    /// single sample.
+    pub fn record(&mut self, events: &[MonitorEvent]) {
+        let sampled_at = events.iter()
+            .find_map(|event| match *event {
+                MonitorEvent::Sample(ref snapshot) => Some(snapshot.taken_at),
+                _ => None,
+            })
+            .unwrap_or_else(SystemTime::now);
+        let mut failing = HashSet::new();
+        for event in events {
+            let incident = |kind, resource, value, message| {
+                Incident {
+                    at: sampled_at,
+                    kind,
+                    resource,
+                    value,
+                    message,
+                }
+            };
+            let incident = match *event {
+                MonitorEvent::Sample(_) => continue,
+                MonitorEvent::ReadError(ref err) => {
+                    let key = (err.resource.clone(), err.value);
+                    let new = !self.failing.contains(&key);
+                    failing.insert(key);
+                    if !new {
+                        continue;
+                    }
+                    incident(IncidentKind::ReadFailed,
+                             Some(err.resource.clone()),
+                             None,
+                             format!("could not read {:?} {}: {}",
+                                     err.resource,
+                                     err.value,
+                                     err.message))
+                }
+                MonitorEvent::Threshold(ref evt) => {
+                    let (kind, verb) = match evt.crossing {
+                        Crossing::Exceeded => {
+                            (IncidentKind::ThresholdExceeded, "exceeded")
+                        }
+                        Crossing::Recovered => {
+                            (IncidentKind::ThresholdRecovered, "recovered")
+                        }
+                    };
+                    let r = &evt.reading;
+                    incident(kind,
+                             Some(r.resource.clone()),
+                             r.used,
+                             format!("{:?} threshold {}", r.resource, verb))
+                }
+                MonitorEvent::StateChanged(ref t) => {
+                    Incident {
+                        at: t.at,
+                        ..incident(IncidentKind::StateChanged,
+                                   Some(Resource::Memory),
+                                   None,
+                                   format!("memory state changed from \
+                                            {} to {} at {:.1}% used",
+                                           state_name(t.from),
+                                           state_name(t.to),
+                                           t.fraction * 100.0))
+                    }
+                }
+                MonitorEvent::OomKill(count) => {
+                    incident(IncidentKind::OomKill,
+                             Some(Resource::Memory),
+                             Some(count),
+                             format!("the OOM killer killed {} process(es) \
+                                      in our cgroup",
+                                     count))
+                }
+                MonitorEvent::WatchdogTriggered(ref snapshot) => {
+                    let available = snapshot.get(&Resource::Memory)
+                        .and_then(|r| r.available);
+                    let message = match available {
+                        Some(a) => {
+                            format!("watchdog triggered with {} bytes \
+                                     available",
+                                    a)
+                        }
+                        None => "watchdog triggered".to_owned(),
+                    };
+                    incident(IncidentKind::WatchdogTriggered,
+                             Some(Resource::Memory),
+                             available,
+                             message)
+                }
+                MonitorEvent::LeakSuspected(ref leak) => {
+                    incident(IncidentKind::LeakSuspected,
+                             Some(leak.resource.clone()),
+                             Some(leak.growth_rate.max(0.0) as u64),
+                             format!("{:?} has grown by {:.0} bytes per \
+                                      second for {:?}",
+                                     leak.resource,
+                                     leak.growth_rate,
+                                     leak.duration))
+                }
+                MonitorEvent::GuardrailViolated(ref result) => {
+                    let resource = match result.rule.metric {
+                        GuardrailMetric::Usage(ref r) => Some(r.clone()),
+                        _ => None,
+                    };
+                    incident(IncidentKind::GuardrailViolated,
+                             resource,
+                             None,
+                             format!("guardrail {} < {:.0}% violated at \
+                                      {:.1}%",
+                                     result.rule.metric,
+                                     result.rule.max * 100.0,
+                                     result.value.unwrap_or(0.0) * 100.0))
+                }
+            };
+            self.push(incident);
+        }
+        self.failing = failing;
+    }
+
+    /// Add `incident`, forgetting the oldest one if we're full.
+    fn push(&mut self, incident: Incident) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.incidents.len() >= self.capacity {
+            self.incidents.pop_front();
+        }
+        self.incidents.push_back(incident);
+    }
+}
+
+/// A lowercase name for `state`.
+fn state_name(state: MemoryState) -> &'static str {
+    match state {
+        MemoryState::Normal => "normal",
+        MemoryState::Elevated => "elevated",
+        MemoryState::Critical => "critical",
+    }
+}
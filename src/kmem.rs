@@ -0,0 +1,78 @@
+//! Kernel memory charged to our cgroup, such as slab caches, socket
+//! buffers and kernel stacks.  This can push a container over its limit
+//! even when user memory looks fine.
+
+use std::collections::HashMap;
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use read_file_u64;
+use Resource;
+
+/// Fields of the cgroup v2 `memory.stat` which count kernel memory, for
+/// kernels older than 5.18, which don't report a `kernel` total.
+const V2_KERNEL_FIELDS: &[&str] =
+    &["slab", "kernel_stack", "pagetables", "sock", "percpu", "vmalloc"];
+
+/// Return an error saying that `wanted` isn't available under cgroup v2.
+fn not_applicable<T>(wanted: &'static str) -> Result<T> {
+    Err(ErrorKind::NotApplicable(wanted, Resource::KernelMemory).into())
+}
+
+/// Our cgroup's kernel memory limit.  Only cgroup v1 has a separate
+/// limit; under cgroup v2, kernel memory counts against `memory.max`.
+pub fn limit() -> Result<u64> {
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let path = cgroup::memory_file("memory.kmem.limit_in_bytes", "");
+            cgroup::read_limit(&path)
+        }
+        CgroupVersion::V2 => not_applicable("limit"),
+    }
+}
+
+/// Kernel memory charged to our cgroup.
+pub fn used() -> Result<u64> {
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let path = cgroup::memory_file("memory.kmem.usage_in_bytes", "");
+            read_file_u64(&path)
+        }
+        CgroupVersion::V2 => {
+            let path = cgroup::memory_file("", "memory.stat");
+            Ok(v2_kernel_bytes(&cgroup::read_keyed_file(&path)?))
+        }
+    }
+}
+
+/// Kernel memory from the fields of a cgroup v2 `memory.stat`.
+pub fn v2_kernel_bytes(stat: &HashMap<String, u64>) -> u64 {
+    match stat.get("kernel") {
+        Some(&kernel) => kernel,
+        None => V2_KERNEL_FIELDS.iter().filter_map(|&f| stat.get(f)).sum(),
+    }
+}
+
+/// The most kernel memory our cgroup has used at once (cgroup v1 only).
+pub fn peak() -> Result<u64> {
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let path =
+                cgroup::memory_file("memory.kmem.max_usage_in_bytes", "");
+            read_file_u64(&path)
+        }
+        CgroupVersion::V2 => not_applicable("peak"),
+    }
+}
+
+/// Reset `peak` to the current usage (cgroup v1 only).
+pub fn reset_peak() -> Result<()> {
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let path =
+                cgroup::memory_file("memory.kmem.max_usage_in_bytes", "");
+            cgroup::write_file(&path, "0")
+        }
+        CgroupVersion::V2 => not_applicable("reset_peak"),
+    }
+}
@@ -0,0 +1,194 @@
+//! Kubernetes resource requests and limits, from the Downward API.
+//!
+//! A pod's memory limit is enforced by its cgroup, which we can already
+//! read.  But its memory request is only known to Kubernetes: under node
+//! memory pressure, the kubelet evicts pods using more than they
+//! requested, long before they reach their limit.  To see the request, you
+//! need to expose it to the container using the Downward API, for example:
+//!
+//! ```yaml
+//! env:
+//!   - name: MEMORY_REQUEST
+//!     valueFrom:
+//!       resourceFieldRef:
+//!         resource: requests.memory
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use errors::*;
+use memory_stat::{MemoryStat, ReclaimableCache};
+use Resource;
+
+/// Where the Kubernetes documentation mounts Downward API volumes.
+const DEFAULT_DIR: &str = "/etc/podinfo";
+
+/// Resource requests and limits of this container, as reported by the
+/// Kubernetes Downward API.  Fields which weren't exposed are `None`.
+///
+/// We assume the default `divisor` of 1, so the Downward API will round
+/// CPU values up to whole cores.  If a container has no limit, the
+/// Downward API reports the node's allocatable capacity instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KubernetesResources {
+    /// Requested memory, in bytes.
+    pub memory_request: Option<u64>,
+    /// Memory limit, in bytes.
+    pub memory_limit: Option<u64>,
+    /// Requested CPU, in cores.
+    pub cpu_request: Option<f64>,
+    /// CPU limit, in cores.
+    pub cpu_limit: Option<f64>,
+}
+
+/// Where our memory usage stands relative to our Kubernetes request and
+/// limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryStanding {
+    /// We're using no more than we requested, or we have no request.
+    WithinRequest,
+    /// We're using more than we requested, so the kubelet may evict us
+    /// if the node runs short of memory.
+    AboveRequest,
+    /// We're close to our hard limit, and may be OOM killed.
+    NearLimit,
+}
+
+impl KubernetesResources {
+    /// Read the environment variables `MEMORY_REQUEST`, `MEMORY_LIMIT`,
+    /// `CPU_REQUEST` and `CPU_LIMIT`.
+    pub fn from_env() -> KubernetesResources {
+        let var = |name| env::var(name).ok();
+        KubernetesResources::from_values(var("MEMORY_REQUEST"),
+                                         var("MEMORY_LIMIT"),
+                                         var("CPU_REQUEST"),
+                                         var("CPU_LIMIT"))
+    }
+
+    /// Read a Downward API volume mounted at `dir`, containing any of the
+    /// files `mem_request`, `mem_limit`, `cpu_request` and `cpu_limit`.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<KubernetesResources> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            return Err(ErrorKind::NotFound(dir.to_owned()).into());
+        }
+        let file = |name| fs::read_to_string(dir.join(name)).ok();
+        Ok(KubernetesResources::from_values(file("mem_request"),
+                                            file("mem_limit"),
+                                            file("cpu_request"),
+                                            file("cpu_limit")))
+    }
+
+    /// Read our requests and limits from the environment, falling back to
+    /// a volume mounted at `/etc/podinfo` for anything not set there.
+    pub fn detect() -> KubernetesResources {
+        let env = KubernetesResources::from_env();
+        let dir = KubernetesResources::from_dir(DEFAULT_DIR)
+            .unwrap_or_default();
+        KubernetesResources {
+            memory_request: env.memory_request.or(dir.memory_request),
+            memory_limit: env.memory_limit.or(dir.memory_limit),
+            cpu_request: env.cpu_request.or(dir.cpu_request),
+            cpu_limit: env.cpu_limit.or(dir.cpu_limit),
+        }
+    }
+
+    /// Parse raw Downward API values.
+    fn from_values(memory_request: Option<String>,
+                   memory_limit: Option<String>,
+                   cpu_request: Option<String>,
+                   cpu_limit: Option<String>)
+                   -> KubernetesResources {
+        let parse = KubernetesResources::parse_quantity;
+        let bytes = |s: Option<String>| {
+            s.and_then(|s| parse(&s)).map(|q| q.ceil() as u64)
+        };
+        let cores = |s: Option<String>| s.and_then(|s| parse(&s));
+        KubernetesResources {
+            memory_request: bytes(memory_request),
+            memory_limit: bytes(memory_limit),
+            cpu_request: cores(cpu_request),
+            cpu_limit: cores(cpu_limit),
+        }
+    }
+
+    /// Parse a Kubernetes quantity, such as `512Mi`, `1.5G`, `250m` or
+    /// `1e3`.  Returns `None` if `s` isn't a valid quantity.
+    ///
+    /// ```
+    /// use resource_monitor::KubernetesResources;
+    ///
+    /// let parse = KubernetesResources::parse_quantity;
+    /// assert_eq!(parse("512Mi"), Some(512.0 * 1024.0 * 1024.0));
+    /// assert_eq!(parse("1.5G"), Some(1.5e9));
+    /// assert_eq!(parse("250m"), Some(0.25));
+    /// assert_eq!(parse("1e3"), Some(1000.0));
+    /// assert_eq!(parse("128974848e0"), Some(128974848.0));
+    /// assert_eq!(parse("2E"), Some(2e18));
+    /// assert_eq!(parse("lots"), None);
+    /// assert_eq!(parse("12Q"), None);
+    /// assert_eq!(parse(""), None);
+    /// ```
+    pub fn parse_quantity(s: &str) -> Option<f64> {
+        let s = s.trim();
+        let split = s.find(|c: char| {
+                !(c.is_ascii_digit() || c == '.' || c == '+' || c == '-')
+            })
+            .unwrap_or(s.len());
+        let (number, suffix) = s.split_at(split);
+        let multiplier = match suffix {
+            "" => 1.0,
+            "m" => 1e-3,
+            "k" => 1e3,
+            "M" => 1e6,
+            "G" => 1e9,
+            "T" => 1e12,
+            "P" => 1e15,
+            "E" => 1e18,
+            "Ki" => 1024.0,
+            "Mi" => 1024.0f64.powi(2),
+            "Gi" => 1024.0f64.powi(3),
+            "Ti" => 1024.0f64.powi(4),
+            "Pi" => 1024.0f64.powi(5),
+            "Ei" => 1024.0f64.powi(6),
+            // A decimal exponent, such as `e3` or `E-2`.
+            _ if suffix.starts_with(['e', 'E']) => {
+                10.0f64.powi(suffix[1..].parse::<i32>().ok()?)
+            }
+            _ => return None,
+        };
+        number.parse::<f64>().ok().map(|n| n * multiplier)
+    }
+
+    /// Compare `used` bytes of memory with our request, and with `limit`.
+    /// We're `NearLimit` once we've used `near_limit` of it, as a
+    /// fraction between 0.0 and 1.0.
+    pub fn standing(&self,
+                    used: u64,
+                    limit: u64,
+                    near_limit: f64)
+                    -> MemoryStanding {
+        if limit != u64::MAX && used as f64 >= limit as f64 * near_limit {
+            MemoryStanding::NearLimit
+        } else if self.memory_request.is_some_and(|r| used > r) {
+            MemoryStanding::AboveRequest
+        } else {
+            MemoryStanding::WithinRequest
+        }
+    }
+
+    /// Compare our current memory usage with our request and limit, as
+    /// in `standing`.  Like the kubelet, we measure usage as our
+    /// "working set", which excludes inactive page cache.  Our limit is
+    /// read from our cgroup, which the kubelet sets from our pod spec.
+    pub fn memory_standing(&self, near_limit: f64) -> Result<MemoryStanding> {
+        let used = Resource::OsMemory.used()?;
+        let stat = MemoryStat::read()?;
+        let inactive = ReclaimableCache::InactiveFile.bytes(&stat);
+        let working_set = used.saturating_sub(inactive);
+        let limit = Resource::OsMemory.limit()?;
+        Ok(self.standing(working_set, limit, near_limit))
+    }
+}
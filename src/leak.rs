@@ -0,0 +1,119 @@
+//! A heuristic for spotting slow leaks during soak tests: usage which
+//! keeps going up, window after window, and never comes back down.
+
+use std::time::{Duration, SystemTime};
+
+use snapshot::Snapshot;
+use Resource;
+
+/// Configuration for `Monitor::detect_leaks`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeakDetection {
+    /// The resource to watch.  Defaults to `Resource::AllocatorMemory`,
+    /// because heap usage isn't affected by page cache churn.
+    pub resource: Resource,
+    /// We average usage over windows of this length.
+    pub window: Duration,
+    /// Report a leak when the average has increased for this many
+    /// windows in a row.
+    pub windows: usize,
+    /// Ignore growth slower than this many bytes per second.
+    pub min_growth_rate: f64,
+}
+
+impl Default for LeakDetection {
+    fn default() -> LeakDetection {
+        LeakDetection {
+            resource: Resource::AllocatorMemory,
+            window: Duration::from_secs(60),
+            windows: 10,
+            min_growth_rate: 1024.0,
+        }
+    }
+}
+
+/// Reported when `Monitor::detect_leaks` suspects a leak.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeakWarning {
+    /// The resource which keeps growing.
+    pub resource: Resource,
+    /// How fast it grew, in bytes per second, averaged over all the
+    /// windows we looked at.
+    pub growth_rate: f64,
+    /// How long it has been growing.
+    pub duration: Duration,
+}
+
+/// Tracks window averages for a `LeakDetection`.
+#[derive(Debug)]
+pub struct LeakDetector {
+    config: LeakDetection,
+    window_start: Option<SystemTime>,
+    window_sum: f64,
+    window_count: u64,
+    /// Averages of our completed windows, oldest first.
+    averages: Vec<f64>,
+}
+
+impl LeakDetector {
+    /// Create a detector using `config`.
+    pub fn new(config: LeakDetection) -> LeakDetector {
+        LeakDetector {
+            config,
+            window_start: None,
+            window_sum: 0.0,
+            window_count: 0,
+            averages: vec![],
+        }
+    }
+
+    /// The resource we're watching.
+    pub fn resource(&self) -> &Resource {
+        &self.config.resource
+    }
+
+    /// Add `snapshot` to our current window, and return a warning if we
+    /// now suspect a leak.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Option<LeakWarning> {
+        let used = snapshot.get(&self.config.resource)?.used? as f64;
+        let start = *self.window_start.get_or_insert(snapshot.taken_at);
+        let age = snapshot.taken_at.duration_since(start).unwrap_or_default();
+        if age < self.config.window {
+            self.window_sum += used;
+            self.window_count += 1;
+            return None;
+        }
+
+        // Close this window, and start a new one with this sample.
+        if self.window_count > 0 {
+            self.averages.push(self.window_sum / self.window_count as f64);
+        }
+        self.window_start = Some(snapshot.taken_at);
+        self.window_sum = used;
+        self.window_count = 1;
+
+        // Only keep the run of increasing averages at the end.
+        let n = self.averages.len();
+        if n >= 2 && self.averages[n - 1] <= self.averages[n - 2] {
+            self.averages.drain(..n - 1);
+        }
+        let needed = self.config.windows.max(1) + 1;
+        if self.averages.len() < needed {
+            return None;
+        }
+        let growth = self.averages[self.averages.len() - 1] -
+                     self.averages[self.averages.len() - needed];
+        let duration = self.config.window * (needed as u32 - 1);
+        let growth_rate = growth / duration.as_secs_f64();
+        // Start over, so we don't report the same leak every window.
+        self.averages.clear();
+        if growth_rate < self.config.min_growth_rate {
+            return None;
+        }
+        Some(LeakWarning {
+            resource: self.config.resource.clone(),
+            growth_rate,
+            duration,
+        })
+    }
+}
@@ -18,71 +18,323 @@
 
 #![warn(missing_docs)]
 
-// Needed for `error_chain`, which does evil things with macros.
-#![recursion_limit = "1024"]
-
-#[macro_use]
-extern crate error_chain;
+#[cfg(unix)]
 extern crate libc;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+#[cfg(feature = "prometheus")]
+extern crate prometheus;
+#[cfg(feature = "metrics")]
+#[macro_use]
+extern crate metrics;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "toml")]
+extern crate toml;
 
-use std::fs;
-use std::io::prelude::*;
-use std::path::Path;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-// Re-export our error types declared by `error-chain`.
+// Re-export our error types.
 pub use errors::{Error, ErrorKind, Result};
 use errors::ResultExt;
+use read_cache::{RawValue, ReadCache};
 
-/// Rust's standard error-handling boilerplate is obnoxious, so we use
-/// the `[error-chain][]` crate's `error_chain!` macro to declare a new error
-/// type with all the bells and whistles. This is a semi-official crate
-/// by brson, a Rust core team member.
-///
-/// [error-chain]: https://docs.rs/error-chain/0.7.2/error_chain/
-mod errors {
-    use std::path::PathBuf;
+mod errors;
+
+pub use allocator_stats::{allocator_backend, allocator_stats_enabled,
+                          detect_allocator, print_allocator_stats,
+                          purge_allocator, refresh_allocator_stats,
+                          set_allocator_stats_refresh,
+                          write_allocator_stats, AllocatorBackend,
+                          AllocatorKind, StatsOptions};
+#[cfg(feature = "jemalloc")]
+pub use allocator_stats::{ArenaStats, JemallocStats, JemallocStatsDiff};
+#[cfg(all(feature = "jemalloc", feature = "serde_json"))]
+pub use allocator_stats::jemalloc_stats_json;
+mod allocator_stats;
 
-    use super::Resource;
+pub use counting_allocator::{allocation_counts,
+                             counting_allocator_installed, AllocationCounts,
+                             CountingAllocator};
+mod counting_allocator;
 
-    error_chain! {
-        errors {
-            /// An error occurred while trying to access the specified path.
-            File(path: PathBuf) {
-                // This is used when Rust wants to display this error
-                // without allocating memory.
-                description("could not access file with limit data")
-                // This is used to display the error to the user. Note
-                // that we have to call `.display()` on path objects to
-                // get something that's valid, printable UTF-8.
-                display("could not access {}", path.display())
-            }
-            /// The requested value was not applicable.
-            NotApplicable(wanted: &'static str, r: Resource) {
-                description("requested value is not applicable to the \
-                            specified resource")
-                display("{:?}.{} is not applicable", &r, wanted)
-            }
+pub use memory_scope::{scope_report, scope_usage, MemoryScope, ScopeUsage,
+                       MAX_SCOPES};
+mod memory_scope;
+
+pub use bytes::Bytes;
+mod bytes;
+
+pub use budget::{MemoryBudget, Reservation};
+mod budget;
+
+pub use admission::{can_allocate, can_allocate_with_margin, can_write_to,
+                    can_write_to_with_margin, AllocationCheck,
+                    AllocationVerdict, SafetyMargin, WriteCheck};
+mod admission;
+
+pub use backpressure::{Backpressure, BackpressureCurve, LinearCurve,
+                       StepCurve};
+mod backpressure;
+
+pub use cgroup::{in_namespace as in_cgroup_namespace,
+                 version as cgroup_version, CgroupVersion};
+mod cgroup;
+mod mountinfo;
+
+pub use config::Config;
+mod config;
+
+pub use cgroup_resources::CgroupResources;
+mod cgroup_resources;
+
+pub use sub_cgroup::{SubCgroup, SubCgroupLimits};
+mod sub_cgroup;
+
+pub use effective_limit::{EffectiveLimit, LimitSource};
+mod effective_limit;
+
+pub use environment::{environment, ContainerRuntime, Environment};
+mod environment;
+
+pub use android::AndroidProvider;
+mod android;
+
+pub use fallback::{BestEffort, ValueSource};
+mod fallback;
+
+pub use rusage::RusageStats;
+mod rusage;
+
+pub use cost::{measure, CostMeter, CostReport};
+mod cost;
+
+pub use sched::{thread_scheduler_stats, thread_scheduler_stats_in,
+                SchedulerStats, ThreadSchedStats};
+mod sched;
+
+pub use oom::{oom_score, oom_score_adj, set_oom_score_adj,
+              OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN};
+mod oom;
+
+pub use process::{ProcessResources, ProcessStatus, ProcessTree,
+                  ProcessTreeUsage, ProcessUsage, SmapsRollup};
+mod process;
+
+pub use memory_stat::{MemoryStat, ReclaimableCache};
+mod memory_stat;
+
+pub use protection::MemoryProtection;
+mod protection;
+
+mod kmem;
+
+pub use cpu::{effective_cpu_count, thread_cpu_times, CpuThrottling,
+              ThreadCpuTime};
+mod cpu;
+
+pub use gpu::GpuMemoryInfo;
+mod gpu;
+
+pub use probe::Support;
+mod probe;
+
+pub use tmpfs::FsSpace;
+mod tmpfs;
+
+#[cfg(unix)]
+pub use child_limits::{ChildLimits, ChildRlimit, CommandLimits};
+#[cfg(unix)]
+mod child_limits;
+
+mod address_space;
+mod mlock;
+mod rlimit;
+mod stack;
+
+pub use swap::swap_accounting_enabled;
+mod swap;
+
+pub use compressed::{CompressedSwap, ZramDevice};
+mod compressed;
+
+pub use quota::{DiskQuota, QuotaKind};
+mod quota;
+
+pub use sockets::SocketCounts;
+mod sockets;
+
+pub use load::{CpuLoad, LoadAverage, PressureStall};
+mod load;
+
+pub use hugepages::HugePagePool;
+mod hugepages;
+
+pub use overcommit::{Overcommit, OvercommitPolicy};
+mod overcommit;
+
+pub use writeback::Writeback;
+mod writeback;
+
+pub use pressure_events::PressureEvents;
+mod pressure_events;
+
+pub use pressure_watcher::{MemoryPressureWatcher, PressureLevel};
+mod pressure_watcher;
+
+mod read_cache;
+
+pub mod raw;
+
+pub mod test_support;
+
+pub use reader::ResourceReader;
+mod reader;
+
+pub use cache_policy::{clear_cached_values, CachePolicy, TimedValue};
+use cache_policy::CachedValue;
+mod cache_policy;
+
+pub use provider::{clear_resource_provider, set_resource_provider,
+                   MockProvider, ResourceProvider, SystemProvider};
+mod provider;
+
+pub use simulation::SimulatedProvider;
+mod simulation;
+
+pub use snapshot::{ReadError, Reading, ResourceDelta, Snapshot,
+                   SnapshotDiff};
+mod snapshot;
+
+pub use profile::{SampleDetails, SamplingProfile};
+mod profile;
+
+pub use baseline::{capture_baseline, since_start, Baseline, SinceStart};
+mod baseline;
+
+pub use source::{ReadSource, ReadingSources};
+mod source;
+
+pub use handle::ResourceMonitorHandle;
+mod handle;
+
+pub use monitor::{Monitor, MonitorEvent, Watchdog, HISTORY_LEN};
+mod monitor;
+
+pub use incident::{Incident, IncidentKind, EVENT_LOG_LEN};
+mod incident;
+
+pub use history::{SampleHistory, UsagePercentiles, UsageStats};
+mod history;
+
+pub use reclaim::{reclaim, register_reclaimer, unregister_reclaimer,
+                  ReclaimerId};
+mod reclaim;
+
+pub use recorder::{RecordFormat, Recorder};
+mod recorder;
+
+#[cfg(feature = "http")]
+pub use debug_server::DebugServer;
+#[cfg(feature = "http")]
+mod debug_server;
+
+#[cfg(feature = "ffi")]
+pub use ffi::{rm_memory_available, rm_memory_limit, rm_memory_used,
+              rm_snapshot_json, rm_string_free, RM_ERROR, RM_NULL_POINTER,
+              RM_OK, RM_UNSUPPORTED};
+#[cfg(feature = "ffi")]
+mod ffi;
+
+#[cfg(feature = "systemd")]
+pub use systemd::{LimitDiscrepancy, SystemdUnit};
+#[cfg(feature = "systemd")]
+mod systemd;
+
+#[cfg(feature = "kubernetes")]
+pub use kubernetes::{KubernetesResources, MemoryStanding};
+#[cfg(feature = "kubernetes")]
+mod kubernetes;
+
+pub use signal_dump::{dump_on_signal, on_signal_dump};
+mod signal_dump;
+
+pub use statsd::StatsdEmitter;
+mod statsd;
+
+pub use threshold::{Crossing, Threshold, ThresholdEvent, ThresholdWatch};
+mod threshold;
+
+pub use guardrail::{GuardrailMetric, GuardrailPolicy, GuardrailRule,
+                    RuleResult, RuleStatus, Verdict};
+mod guardrail;
+
+mod trend;
+
+pub use leak::{LeakDetection, LeakWarning};
+mod leak;
+
+pub use smoothing::Smoothing;
+mod smoothing;
+
+pub use pressure_state::{MemoryState, PressureBands, PressureStateMachine,
+                         StateTransition};
+mod pressure_state;
+
+#[cfg(feature = "async")]
+pub use stream::{ResourceStream, ThresholdStream};
+#[cfg(feature = "async")]
+mod stream;
+
+#[cfg(feature = "prometheus")]
+pub use prometheus_exporter::{render_prometheus_text, ResourceCollector};
+#[cfg(feature = "prometheus")]
+mod prometheus_exporter;
+
+#[cfg(feature = "metrics")]
+pub use metrics_facade::{record_read_error, record_snapshot};
+#[cfg(feature = "metrics")]
+mod metrics_facade;
+
+#[cfg(feature = "log")]
+mod logging;
+
+#[cfg(feature = "tracing")]
+mod tracing_events;
+
+/// Read a file containing an integer.
+fn read_file_u64(path: &Path) -> Result<u64> {
+    raw::read_u64(path)
+}
+
+/// How much memory is free inside the heap allocator?  If we can't look
+/// inside the allocator, assume that it's holding nothing in reserve.
+fn allocator_available_or_zero(cache: &mut ReadCache) -> Result<u64> {
+    match Resource::AllocatorMemory.system_available_with(cache) {
+        Err(ref e) if e.is_unsupported() => {
+            cache.note_fallback();
+            Ok(0)
         }
+        result => result,
     }
 }
 
-pub use allocator_stats::{allocator_stats_enabled, print_allocator_stats};
-mod allocator_stats;
-
-/// Read a file containing an integer.
-fn read_file_usize(path: &Path) -> Result<usize> {
-    // Declare a helper function to create an error wrapper containing
-    // the path we were trying to read, or our callers will hate us.
-    let mkerr = || ErrorKind::File(path.to_owned());
-
-    // Read a number out of the specified file and parse it.  We have
-    // to allocate a string because Rust is allergic to allocating strings
-    // in APIs we might use from inside loops.  The `?` operator checks
-    // for an error and `return`s immediately if it finds one.
-    let mut s = String::new();
-    let mut f: fs::File = fs::File::open(path).chain_err(&mkerr)?;
-    f.read_to_string(&mut s).chain_err(&mkerr)?;
-    s.trim().parse().chain_err(&mkerr)
+/// The mount point of `Resource::SharedMemory`.
+fn shm_path() -> &'static Path {
+    Path::new("/dev/shm")
 }
 
 /// Types of resource we can monitor.  This type may be extended with
@@ -91,34 +343,242 @@ fn read_file_usize(path: &Path) -> Result<usize> {
 /// Note that `r.used() + r.available()` may not equal `r.limit()`.
 /// There are various estimates and bookkeeping overhead taking place
 /// under the hood.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// All quantities are reported as `u64`, even on 32-bit targets, because
+/// cgroup limits may easily exceed 4 GB. Wrap a value in `Bytes` to format
+/// it for humans.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Resource {
     /// Total RAM in bytes, including both RAM available at the OS level, and
     /// RAM which has been reserved by the heap allocator and not used.
     Memory,
-    /// Heap allocator RAM in bytes.  This does not generally support `limit`.
+    /// Heap allocator RAM in bytes.  This does not generally support `limit`,
+    /// and it requires one of the allocator features (such as `jemalloc`)
+    /// to be enabled, or a `CountingAllocator` to be installed.
     AllocatorMemory,
     /// OS memory, in bytes. Some of the RAM shown as `used` here may still
     /// be available from the heap allocator.
     OsMemory,
+    /// Heap bytes allocated inside the named `MemoryScope`.  This only
+    /// supports `used`, and requires `CountingAllocator::with_scopes` to be
+    /// installed as the global allocator.
+    ScopedMemory(String),
+    /// Kernel memory charged to our cgroup, in bytes, such as slab caches
+    /// and socket buffers.  This is already included in `OsMemory`.  Only
+    /// cgroup v1 supports `limit`, `peak` and `reset_peak`.
+    KernelMemory,
+    /// Huge pages of the given size in bytes, such as `2 << 20`.  `limit`
+    /// is the smaller of our hugetlb cgroup limit and the system-wide pool.
+    /// If the hugetlb controller isn't enabled for our cgroup, `used`
+    /// reports the whole pool.  Only cgroup v1 supports `peak` and
+    /// `reset_peak`.
+    HugePages(u64),
+    /// User and system CPU time consumed by this process so far, in
+    /// nanoseconds.  This only supports `used`.  See
+    /// `Monitor::cpu_utilization` to turn this into a percentage, and
+    /// `RusageStats` to split it into user and system time.
+    CpuTime,
+    /// Memory on the GPU with this index, in bytes.  If a GPU is in MIG
+    /// mode, its MIG instances are numbered in its place, and `limit` is
+    /// the size of the MIG slice.  This requires the `nvml` feature, and
+    /// does not support `peak`.
+    GpuMemory(u32),
+    /// Space on the filesystem mounted at this path, in bytes.  This is
+    /// meant for `tmpfs` scratch directories, which live in RAM but are
+    /// usually much smaller than our memory limit.  This does not support
+    /// `peak`.
+    Tmpfs(PathBuf),
+    /// Space on `/dev/shm`, which backs POSIX shared memory, in bytes.
+    /// This is the same as `Tmpfs("/dev/shm")`.
+    SharedMemory,
+    /// Memory locked into RAM with `mlock` or `mlockall`, in bytes.
+    /// `limit` is our `RLIMIT_MEMLOCK`, which is often only a few
+    /// megabytes, so check `available` before pinning large buffers.  This
+    /// does not support `peak`.
+    LockedMemory,
+    /// Stack space of the calling thread, in bytes.  `limit` is the size
+    /// of the thread's stack, and `used` is an estimate of how deep the
+    /// current call is, so recursive code such as parsers can check
+    /// `available` and bail out before overflowing.  Because this measures
+    /// whichever thread reads it, it's not useful with `Monitor`.  This does
+    /// not support `peak`.
+    Stack,
+    /// Swap charged to our cgroup, in bytes.  This requires the kernel's
+    /// swap accounting, which is sometimes turned off; see
+    /// `swap_accounting_enabled`.  This does not support `peak`.
+    Swap,
+    /// Disk quota for files under this path, in bytes: its XFS or ext4
+    /// project quota if it belongs to a project, or otherwise our user's
+    /// quota.  On shared storage, this is often much less than the free
+    /// space on the filesystem.  See `DiskQuota` for inode counts.  This
+    /// does not support `peak`.
+    Quota(PathBuf),
+    /// Sockets this process has open.  `limit` is our `RLIMIT_NOFILE`,
+    /// and because sockets share that limit with every other file
+    /// descriptor, `available` counts all our open descriptors against it.
+    /// See `SocketCounts` for a breakdown by type.  This does not support
+    /// `peak`.
+    Sockets,
+    /// Virtual address space mapped by this process, in bytes.  `limit`
+    /// is our `RLIMIT_AS`, and `used` is `VmSize`, which counts every
+    /// mapping whether or not it's backed by RAM, so 32-bit processes and
+    /// code which maps large files or reserves big arenas can check
+    /// `available` before calling `mmap`.  `peak` is `VmPeak`, which can't
+    /// be reset.
+    AddressSpace,
+    /// Time this process's threads have spent ready to run but waiting for
+    /// a CPU, in nanoseconds, from the kernel's `schedstat`.  Only threads
+    /// which are still running are counted.  This only supports `used`.
+    /// When it grows quickly, CPU contention rather than our own code is
+    /// adding to our latency; see `SchedulerStats` for context switches,
+    /// and `thread_scheduler_stats` for a breakdown by thread.
+    RunDelay,
     /// A private internal variant to allow future extensibility.
     #[doc(hidden)]
     __Private,
 }
 
 impl Resource {
+    /// Every kind of resource we know about, except for those which take a
+    /// parameter, such as `ScopedMemory` and `Tmpfs`.  Some of these may
+    /// not work on this system; see `supported`.
+    pub fn all() -> Vec<Resource> {
+        vec![Resource::Memory,
+             Resource::AllocatorMemory,
+             Resource::OsMemory,
+             Resource::KernelMemory,
+             Resource::CpuTime,
+             Resource::SharedMemory,
+             Resource::LockedMemory,
+             Resource::Stack,
+             Resource::Swap,
+             Resource::Sockets,
+             Resource::AddressSpace,
+             Resource::RunDelay]
+    }
+
+    /// The resources from `all` which we can actually read on this system,
+    /// plus `HugePages` for each page size the kernel supports.  This
+    /// reads each resource once, so call it at startup rather than in a
+    /// loop.
+    pub fn supported() -> Vec<Resource> {
+        let huge_pages = hugepages::page_sizes()
+            .into_iter()
+            .map(Resource::HugePages);
+        Resource::all()
+            .into_iter()
+            .chain(huge_pages)
+            .filter(Resource::is_supported)
+            .collect()
+    }
+
+    /// Can we read this resource on this system?  See `probe` to find out
+    /// why not.
+    pub fn is_supported(&self) -> bool {
+        self.probe().is_supported()
+    }
+
+    /// Check whether we can read this resource, and if not, whether that's
+    /// because this system doesn't support it, or because a file is
+    /// missing or unreadable.  This is meant for building a list of
+    /// features at startup, so that errors from later reads can be treated
+    /// as real failures.
+    ///
+    /// ```
+    /// use resource_monitor::{Resource, Support};
+    ///
+    /// match Resource::KernelMemory.probe() {
+    ///     Support::Supported => println!("kernel memory is available"),
+    ///     Support::Missing(path) => println!("no {}", path.display()),
+    ///     other => println!("can't read kernel memory: {:?}", other),
+    /// }
+    /// ```
+    pub fn probe(&self) -> Support {
+        probe::probe(self)
+    }
+
     /// What is the maximum amount of the resource this process may consume?
     /// This will return `Ok(None)` if there is no limit imposed by this
     /// particular subsystem.
-    pub fn limit(&self) -> Result<usize> {
+    pub fn limit(&self) -> Result<u64> {
+        self.limit_with(&mut ReadCache::new())
+    }
+
+    /// Implementation of `limit`, reading raw values through `cache`.
+    fn limit_with(&self, cache: &mut ReadCache) -> Result<u64> {
+        self.value_with(CachedValue::Limit, cache).map(|t| t.value)
+    }
+
+    /// Like `limit`, but also say when we read the value.  Under a
+    /// `CachePolicy`, this may be a cached value, so check its age before
+    /// relying on it.
+    pub fn limit_timed(&self) -> Result<TimedValue> {
+        self.value_with(CachedValue::Limit, &mut ReadCache::new())
+    }
+
+    /// Get `value`, from the `CachePolicy` cache, the installed
+    /// `ResourceProvider` or the system, noting in `cache` where it came
+    /// from.
+    fn value_with(&self,
+                  value: CachedValue,
+                  cache: &mut ReadCache)
+                  -> Result<TimedValue> {
+        let mut fresh = false;
+        let result = cache_policy::get_or_read(self, value, || {
+            let result = if let Some(provider) = provider::current() {
+                cache.note(ReadSource::Provider);
+                match value {
+                    CachedValue::Limit => provider.limit(self),
+                    CachedValue::Used => provider.used(self),
+                    CachedValue::Available => provider.available(self),
+                }
+            } else {
+                let result = match value {
+                    CachedValue::Limit => self.system_limit_with(cache),
+                    CachedValue::Used => self.system_used_with(cache),
+                    CachedValue::Available => {
+                        self.system_available_with(cache)
+                    }
+                };
+                cache.note_static(self, value);
+                result
+            };
+            fresh = result.is_ok();
+            result
+        });
+        if !fresh {
+            cache.take_sources();
+            cache.note(ReadSource::Cached);
+        }
+        result
+    }
+
+    /// Read `limit` from the system.
+    fn system_limit_with(&self, cache: &mut ReadCache) -> Result<u64> {
         match *self {
             Resource::Memory | Resource::OsMemory => {
-                let path = "/sys/fs/cgroup/memory/memory.limit_in_bytes";
-                read_file_usize(Path::new(path))
+                cache.get(RawValue::CgroupMemoryLimit)
             }
-            Resource::AllocatorMemory => {
+            Resource::AllocatorMemory |
+            Resource::ScopedMemory(_) |
+            Resource::CpuTime |
+            Resource::RunDelay => {
                 Err(ErrorKind::NotApplicable("limit", self.clone()).into())
             }
+            Resource::KernelMemory => {
+                cache.get(RawValue::KernelMemoryLimit)
+            }
+            Resource::HugePages(size) => hugepages::limit(size),
+            Resource::GpuMemory(index) => Ok(gpu::memory_info(index)?.total),
+            Resource::Tmpfs(ref path) => Ok(FsSpace::read(path)?.total),
+            Resource::SharedMemory => Ok(FsSpace::read(shm_path())?.total),
+            Resource::LockedMemory => mlock::limit(),
+            Resource::Stack => stack::limit(),
+            Resource::Swap => swap::limit(),
+            Resource::Quota(ref path) => Ok(DiskQuota::for_path(path)?.limit()),
+            Resource::Sockets => sockets::limit(),
+            Resource::AddressSpace => address_space::limit(),
             Resource::__Private => {
                 unreachable!("Do not use Resource::__Private")
             }
@@ -126,19 +586,142 @@ impl Resource {
     }
 
     /// What is the current amount of the resource consumed by this process?
-    pub fn used(&self) -> Result<usize> {
+    pub fn used(&self) -> Result<u64> {
+        self.used_with(&mut ReadCache::new())
+    }
+
+    /// Implementation of `used`, reading raw values through `cache`.
+    fn used_with(&self, cache: &mut ReadCache) -> Result<u64> {
+        self.value_with(CachedValue::Used, cache).map(|t| t.value)
+    }
+
+    /// Like `used`, but also say when we read the value.  On hot paths,
+    /// install a `CachePolicy` to limit how often we read usage, and use
+    /// this to notice when the value you get is too old to trust.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{CachePolicy, Resource};
+    ///
+    /// CachePolicy::new().max_reads_per_second(10.0).install();
+    /// if let Ok(used) = Resource::Memory.used_timed() {
+    ///     if used.is_stale(Duration::from_secs(1)) {
+    ///         println!("memory usage is {:?} old", used.age());
+    ///     }
+    /// }
+    /// ```
+    pub fn used_timed(&self) -> Result<TimedValue> {
+        self.value_with(CachedValue::Used, &mut ReadCache::new())
+    }
+
+    /// Read `used` from the system.
+    fn system_used_with(&self, cache: &mut ReadCache) -> Result<u64> {
         match *self {
             Resource::Memory => {
-                let os_used = Resource::OsMemory.used()?;
-                let alloc_avail = Resource::AllocatorMemory.available()?;
-                Ok(os_used - alloc_avail)
+                let os_used = Resource::OsMemory.system_used_with(cache)?;
+                let alloc_avail = allocator_available_or_zero(cache)?;
+                // The allocator's slack may be counted in a different
+                // cgroup from our usage, or read at a different moment.
+                Ok(os_used.saturating_sub(alloc_avail))
             }
-            Resource::AllocatorMemory => {
-                allocator_stats::used()
+            Resource::AllocatorMemory => cache.get(RawValue::AllocatorUsed),
+            Resource::OsMemory => cache.get(RawValue::CgroupMemoryUsage),
+            Resource::ScopedMemory(ref name) => {
+                if !memory_scope::scopes_enabled() {
+                    return Err(ErrorKind::Unsupported(self.clone()).into());
+                }
+                Ok(scope_usage(name).map_or(0, |u| u.live_bytes))
+            }
+            Resource::KernelMemory => {
+                cache.get(RawValue::KernelMemoryUsage)
+            }
+            Resource::HugePages(size) => hugepages::used(size),
+            Resource::CpuTime => {
+                cpu::process_cpu_time().map(|t| t.as_nanos() as u64)
+            }
+            Resource::GpuMemory(index) => Ok(gpu::memory_info(index)?.used),
+            Resource::Tmpfs(ref path) => Ok(FsSpace::read(path)?.used),
+            Resource::SharedMemory => Ok(FsSpace::read(shm_path())?.used),
+            Resource::LockedMemory => mlock::used(),
+            Resource::Stack => stack::used(),
+            Resource::Swap => swap::used(),
+            Resource::Quota(ref path) => {
+                Ok(DiskQuota::for_path(path)?.space_used)
+            }
+            Resource::Sockets => sockets::used(),
+            Resource::AddressSpace => address_space::used(),
+            Resource::RunDelay => sched::run_delay(),
+            Resource::__Private => {
+                unreachable!("Do not use Resource::__Private")
+            }
+        }
+    }
+
+    /// What is the most of this resource that has been in use at once?
+    /// For `Memory` and `OsMemory`, this is the peak usage of our cgroup.
+    /// For `AllocatorMemory`, this is exact if the allocator keeps track
+    /// of it, and otherwise it's the highest value `used` ever returned.
+    pub fn peak(&self) -> Result<u64> {
+        match *self {
+            Resource::Memory | Resource::OsMemory => {
+                cgroup::read_memory_peak()
+            }
+            Resource::AllocatorMemory => allocator_stats::peak(),
+            Resource::ScopedMemory(ref name) => {
+                if !memory_scope::scopes_enabled() {
+                    return Err(ErrorKind::Unsupported(self.clone()).into());
+                }
+                Ok(scope_usage(name).map_or(0, |u| u.peak_bytes))
+            }
+            Resource::KernelMemory => kmem::peak(),
+            Resource::HugePages(size) => hugepages::peak(size),
+            Resource::AddressSpace => address_space::peak(),
+            Resource::CpuTime |
+            Resource::RunDelay |
+            Resource::GpuMemory(_) |
+            Resource::Tmpfs(_) |
+            Resource::SharedMemory |
+            Resource::LockedMemory |
+            Resource::Stack |
+            Resource::Swap |
+            Resource::Quota(_) |
+            Resource::Sockets => {
+                Err(ErrorKind::NotApplicable("peak", self.clone()).into())
+            }
+            Resource::__Private => {
+                unreachable!("Do not use Resource::__Private")
+            }
+        }
+    }
+
+    /// Reset `peak` to the current usage, so that it can be used to
+    /// measure the next task.  For `Memory` and `OsMemory`, this requires
+    /// write access to our cgroup.
+    pub fn reset_peak(&self) -> Result<()> {
+        match *self {
+            Resource::Memory | Resource::OsMemory => {
+                cgroup::reset_memory_peak()
+            }
+            Resource::AllocatorMemory => allocator_stats::reset_peak(),
+            Resource::ScopedMemory(ref name) => {
+                memory_scope::reset_scope_peak(name);
+                Ok(())
             }
-            Resource::OsMemory => {
-                let path = "/sys/fs/cgroup/memory/memory.usage_in_bytes";
-                read_file_usize(Path::new(path))
+            Resource::KernelMemory => kmem::reset_peak(),
+            Resource::HugePages(size) => hugepages::reset_peak(size),
+            Resource::CpuTime |
+            Resource::RunDelay |
+            Resource::GpuMemory(_) |
+            Resource::Tmpfs(_) |
+            Resource::SharedMemory |
+            Resource::LockedMemory |
+            Resource::Stack |
+            Resource::Swap |
+            Resource::Quota(_) |
+            Resource::Sockets |
+            Resource::AddressSpace => {
+                let wanted = "reset_peak";
+                Err(ErrorKind::NotApplicable(wanted, self.clone()).into())
             }
             Resource::__Private => {
                 unreachable!("Do not use Resource::__Private")
@@ -146,25 +729,331 @@ impl Resource {
         }
     }
 
+    /// The soft limit of this resource, above which the kernel starts
+    /// pushing back, but below the hard `limit` at which it kills us.
+    /// For `Memory` and `OsMemory`, this is `memory.high` under cgroup v2,
+    /// where the kernel throttles us and reclaims aggressively, and
+    /// `memory.soft_limit_in_bytes` under cgroup v1, where it reclaims from
+    /// us first when the system is short of memory.  Staying under this
+    /// avoids slowdowns, not just the OOM killer.  If no soft limit is set,
+    /// this is `u64::MAX` or a similarly huge value.  Other resources
+    /// return `ErrorKind::NotApplicable`.
+    pub fn soft_limit(&self) -> Result<u64> {
+        match *self {
+            Resource::Memory | Resource::OsMemory => {
+                let path = cgroup::memory_file("memory.soft_limit_in_bytes",
+                                               "memory.high");
+                cgroup::read_limit(&path)
+            }
+            _ => {
+                let wanted = "soft_limit";
+                Err(ErrorKind::NotApplicable(wanted, self.clone()).into())
+            }
+        }
+    }
+
+    /// What fraction of its limit is this resource using?  This reads
+    /// `limit` and `used` together, like `Snapshot`.  If there's no limit,
+    /// this will be close to zero.
+    pub fn fraction_used(&self) -> Result<f64> {
+        let mut cache = ReadCache::new();
+        let limit = self.limit_with(&mut cache)?;
+        let used = self.used_with(&mut cache)?;
+        if limit == 0 {
+            let wanted = "fraction_used";
+            return Err(ErrorKind::NotApplicable(wanted, self.clone()).into());
+        }
+        Ok(used as f64 / limit as f64)
+    }
+
+    /// Like `fraction_used`, but as a percentage.
+    pub fn percent_used(&self) -> Result<f64> {
+        self.fraction_used().map(|f| f * 100.0)
+    }
+
+    /// Like `available`, but also count page cache that the kernel can
+    /// reclaim under pressure, as selected by
+    /// `ReclaimableCache::default()`.  The kernel charges page cache to our
+    /// cgroup, so `available` is pessimistic for jobs which do a lot of
+    /// I/O.  For resources other than `Memory` and `OsMemory`, or when a
+    /// `ResourceProvider` is installed, this is the same as `available`.
+    pub fn effective_available(&self) -> Result<u64> {
+        self.effective_available_with(ReclaimableCache::default())
+    }
+
+    /// Like `effective_available`, but choose which page cache counts as
+    /// reclaimable.
+    pub fn effective_available_with(&self,
+                                    policy: ReclaimableCache)
+                                    -> Result<u64> {
+        if provider::current().is_some() {
+            return self.available();
+        }
+        match *self {
+            Resource::Memory | Resource::OsMemory => {
+                let mut cache = ReadCache::new();
+                let available = self.available_with(&mut cache)?;
+                let used = Resource::OsMemory.used_with(&mut cache)?;
+                let reclaimable = if policy == ReclaimableCache::Nothing {
+                    0
+                } else {
+                    policy.bytes(&MemoryStat::read()?)
+                };
+                Ok(available.saturating_add(reclaimable.min(used)))
+            }
+            _ => self.available(),
+        }
+    }
+
+    /// The tightest limit on our memory, and where it comes from.  Our
+    /// cgroup limit, our `RLIMIT_AS` address space limit and the physical
+    /// memory of the machine can each be the one that binds, depending on
+    /// how we were started, so we take the smallest.  Only `Memory` and
+    /// `OsMemory` support this, and they return the same value.
+    ///
+    /// ```
+    /// use resource_monitor::Resource;
+    ///
+    /// let limit = Resource::Memory.effective_limit().unwrap();
+    /// println!("{} bytes, from {:?}", limit.limit, limit.source);
+    /// ```
+    pub fn effective_limit(&self) -> Result<EffectiveLimit> {
+        match *self {
+            Resource::Memory | Resource::OsMemory => {
+                effective_limit::read(self.limit())
+            }
+            _ => {
+                let wanted = "effective_limit";
+                Err(ErrorKind::NotApplicable(wanted, self.clone()).into())
+            }
+        }
+    }
+
+    /// How often has the kernel already had to enforce our limits?  This
+    /// is only supported for `Memory` and `OsMemory`.
+    pub fn pressure_events(&self) -> Result<PressureEvents> {
+        match *self {
+            Resource::Memory | Resource::OsMemory => PressureEvents::read(),
+            _ => {
+                let wanted = "pressure_events";
+                Err(ErrorKind::NotApplicable(wanted, self.clone()).into())
+            }
+        }
+    }
+
     /// How much of the resource is available to the process but not yet used?
     /// Returns `Ok(None)` if the resource in question appears to be unlimited.
-    pub fn available(&self) -> Result<usize> {
+    pub fn available(&self) -> Result<u64> {
+        self.available_with(&mut ReadCache::new())
+    }
+
+    /// Implementation of `available`, reading raw values through `cache`.
+    fn available_with(&self, cache: &mut ReadCache) -> Result<u64> {
+        self.value_with(CachedValue::Available, cache).map(|t| t.value)
+    }
+
+    /// Like `available`, but also say when we read the value, as for
+    /// `used_timed`.
+    pub fn available_timed(&self) -> Result<TimedValue> {
+        self.value_with(CachedValue::Available, &mut ReadCache::new())
+    }
+
+    /// Like `limit`, but if our usual source is missing or unreadable, as
+    /// in a rootless container, fall back to another source where we have
+    /// one, such as `/proc/meminfo`.  The result says which source we used.
+    /// We remember which sources fail, so we only try them once.
+    pub fn limit_best_effort(&self) -> Result<BestEffort> {
+        fallback::read(self, CachedValue::Limit, || self.limit())
+    }
+
+    /// Like `used`, but with fallbacks, as for `limit_best_effort`.  For
+    /// `Memory` and `OsMemory`, the fallback is our own resident memory,
+    /// and for `AllocatorMemory`, our anonymous resident memory.
+    pub fn used_best_effort(&self) -> Result<BestEffort> {
+        fallback::read(self, CachedValue::Used, || self.used())
+    }
+
+    /// Like `available`, but with fallbacks, as for `limit_best_effort`.
+    pub fn available_best_effort(&self) -> Result<BestEffort> {
+        fallback::read(self, CachedValue::Available, || self.available())
+    }
+
+    /// Read `available` from the system.
+    fn system_available_with(&self, cache: &mut ReadCache) -> Result<u64> {
         match *self {
             Resource::Memory => {
-                let os_avail = Resource::OsMemory.available()?;
-                let alloc_avail = Resource::AllocatorMemory.available()?;
-                Ok(os_avail + alloc_avail)
+                let os_avail = Resource::OsMemory.system_available_with(cache)?;
+                let alloc_avail = allocator_available_or_zero(cache)?;
+                Ok(os_avail.saturating_add(alloc_avail))
             }
             Resource::AllocatorMemory => {
-                let reserved = allocator_stats::reserved()?;
-                let used = allocator_stats::used()?;
-                Ok(reserved - used)
+                let reserved = cache.get(RawValue::AllocatorReserved)?;
+                let used = cache.get(RawValue::AllocatorUsed)?;
+                Ok(reserved.saturating_sub(used))
+            }
+            Resource::GpuMemory(index) => Ok(gpu::memory_info(index)?.free),
+            // Unlike `limit - used`, this leaves out space reserved for
+            // root.
+            Resource::Tmpfs(ref path) => Ok(FsSpace::read(path)?.available),
+            Resource::SharedMemory => {
+                Ok(FsSpace::read(shm_path())?.available)
+            }
+            // Processes with `CAP_IPC_LOCK` may lock more than their limit.
+            Resource::LockedMemory => {
+                Ok(mlock::limit()?.saturating_sub(mlock::used()?))
+            }
+            Resource::Stack => {
+                Ok(stack::limit()?.saturating_sub(stack::used()?))
+            }
+            Resource::Swap => {
+                Ok(swap::limit()?.saturating_sub(swap::used()?))
+            }
+            Resource::Quota(ref path) => {
+                Ok(DiskQuota::for_path(path)?.available())
+            }
+            Resource::Sockets => sockets::available(),
+            Resource::AddressSpace => {
+                let limit = address_space::limit()?;
+                Ok(limit.saturating_sub(address_space::used()?))
             }
             _ => {
-                let l = self.limit()?;
-                let u = self.used()?;
-                Ok(l - u)
+                let l = self.system_limit_with(cache)?;
+                let u = self.system_used_with(cache)?;
+                // A cgroup's usage can briefly exceed its limit.
+                Ok(l.saturating_sub(u))
             }
         }
     }
+    /// A short, stable name for this kind of resource, for use in metric
+    /// names, plus a label telling apart resources of the same kind: the
+    /// scope name for `ScopedMemory`, the page size for `HugePages`, the
+    /// device index for `GpuMemory`, or the path for `Tmpfs` and `Quota`.
+    fn metric_name(&self) -> (&'static str, Option<Cow<'_, str>>) {
+        match *self {
+            Resource::Memory => ("memory", None),
+            Resource::AllocatorMemory => ("allocator", None),
+            Resource::OsMemory => ("os_memory", None),
+            Resource::ScopedMemory(ref name) => {
+                ("scoped_memory", Some(Cow::Borrowed(name)))
+            }
+            Resource::KernelMemory => ("kernel_memory", None),
+            Resource::HugePages(size) => {
+                ("huge_pages", Some(Cow::Owned(hugepages::size_name(size))))
+            }
+            Resource::CpuTime => ("cpu_time", None),
+            Resource::GpuMemory(index) => {
+                ("gpu_memory", Some(Cow::Owned(index.to_string())))
+            }
+            Resource::Tmpfs(ref path) => {
+                ("tmpfs", Some(path.to_string_lossy()))
+            }
+            Resource::SharedMemory => ("shared_memory", None),
+            Resource::LockedMemory => ("locked_memory", None),
+            Resource::Stack => ("stack", None),
+            Resource::Swap => ("swap", None),
+            Resource::Quota(ref path) => {
+                ("quota", Some(path.to_string_lossy()))
+            }
+            Resource::Sockets => ("sockets", None),
+            Resource::AddressSpace => ("address_space", None),
+            Resource::RunDelay => ("run_delay", None),
+            Resource::__Private => {
+                unreachable!("Do not use Resource::__Private")
+            }
+        }
+    }
+
+    /// The unit of this resource's values, for use in metric names.
+    fn metric_unit(&self) -> &'static str {
+        match *self {
+            Resource::CpuTime | Resource::RunDelay => "nanoseconds",
+            Resource::Sockets => "sockets",
+            _ => "bytes",
+        }
+    }
+}
+
+/// Resources are displayed using the same names as `FromStr` accepts, so
+/// that they can round-trip through config files and command lines.
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.metric_name() {
+            (name, None) => write!(f, "{}", name),
+            (name, Some(label)) => write!(f, "{}:{}", name, label),
+        }
+    }
+}
+
+/// Parse a resource name, such as `memory` or `tmpfs:/scratch`.  These
+/// names are stable, and match the names we use for metrics:
+///
+/// | Name                  | Resource                       |
+/// |-----------------------|--------------------------------|
+/// | `memory`              | `Memory`                       |
+/// | `allocator`           | `AllocatorMemory`              |
+/// | `os_memory`           | `OsMemory`                     |
+/// | `scoped_memory:NAME`  | `ScopedMemory(NAME)`           |
+/// | `kernel_memory`       | `KernelMemory`                 |
+/// | `huge_pages:SIZE`     | `HugePages`, such as `2MB`     |
+/// | `cpu_time`            | `CpuTime`                      |
+/// | `gpu_memory:INDEX`    | `GpuMemory(INDEX)`             |
+/// | `tmpfs:PATH`          | `Tmpfs(PATH)`                  |
+/// | `shared_memory`       | `SharedMemory`                 |
+/// | `locked_memory`       | `LockedMemory`                 |
+/// | `stack`               | `Stack`                        |
+/// | `swap`                | `Swap`                         |
+/// | `quota:PATH`          | `Quota(PATH)`                  |
+/// | `sockets`             | `Sockets`                      |
+/// | `address_space`       | `AddressSpace`                 |
+/// | `run_delay`           | `RunDelay`                     |
+///
+/// ```
+/// use resource_monitor::Resource;
+///
+/// let resources = "memory,tmpfs:/scratch"
+///     .split(',')
+///     .map(|s| s.parse())
+///     .collect::<Result<Vec<Resource>, _>>()
+///     .unwrap();
+/// assert_eq!(resources[1], Resource::Tmpfs("/scratch".into()));
+/// assert_eq!(resources[1].to_string(), "tmpfs:/scratch");
+/// ```
+impl FromStr for Resource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Resource> {
+        let mkerr = || ErrorKind::InvalidResource(s.to_owned());
+        // Labels may themselves contain `:`, so only split once.
+        let mut parts = s.trim().splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let label = parts.next().filter(|l| !l.is_empty());
+        let resource = match (name, label) {
+            ("memory", None) => Resource::Memory,
+            ("allocator", None) => Resource::AllocatorMemory,
+            ("os_memory", None) => Resource::OsMemory,
+            ("scoped_memory", Some(scope)) => {
+                Resource::ScopedMemory(scope.to_owned())
+            }
+            ("kernel_memory", None) => Resource::KernelMemory,
+            ("huge_pages", Some(size)) => {
+                let size = hugepages::parse_size_name(size).ok_or_else(mkerr)?;
+                Resource::HugePages(size)
+            }
+            ("cpu_time", None) => Resource::CpuTime,
+            ("gpu_memory", Some(index)) => {
+                Resource::GpuMemory(index.parse().chain_err(mkerr)?)
+            }
+            ("tmpfs", Some(path)) => Resource::Tmpfs(PathBuf::from(path)),
+            ("shared_memory", None) => Resource::SharedMemory,
+            ("locked_memory", None) => Resource::LockedMemory,
+            ("stack", None) => Resource::Stack,
+            ("swap", None) => Resource::Swap,
+            ("quota", Some(path)) => Resource::Quota(PathBuf::from(path)),
+            ("sockets", None) => Resource::Sockets,
+            ("address_space", None) => Resource::AddressSpace,
+            ("run_delay", None) => Resource::RunDelay,
+            _ => return Err(mkerr().into()),
+        };
+        Ok(resource)
+    }
 }
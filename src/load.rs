@@ -0,0 +1,140 @@
+//! CPU load, for telling "the host is busy" apart from "we've used up our
+//! own CPU quota".
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use cgroup::{self, CgroupVersion};
+use cpu::effective_cpu_count;
+use errors::*;
+
+/// The host's load averages, from `/proc/loadavg`.  These count tasks
+/// which are running or waiting to run across the whole host, not just
+/// our cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadAverage {
+    /// Average over the last minute.
+    pub one: f64,
+    /// Average over the last 5 minutes.
+    pub five: f64,
+    /// Average over the last 15 minutes.
+    pub fifteen: f64,
+    /// Tasks which are runnable right now.
+    pub runnable: u64,
+    /// Total number of tasks on the host.
+    pub tasks: u64,
+}
+
+impl LoadAverage {
+    /// Read `/proc/loadavg`.
+    pub fn read() -> Result<LoadAverage> {
+        let path = Path::new("/proc/loadavg");
+        let mkerr = || ErrorKind::File(path.to_owned());
+        let contents = fs::read_to_string(path).chain_err(mkerr)?;
+        // For example: `0.20 0.18 0.12 1/80 11206`.
+        let fields: Vec<&str> = contents.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(ErrorKind::Parse(path.to_owned()).into());
+        }
+        let mut tasks = fields[3].splitn(2, '/');
+        Ok(LoadAverage {
+            one: fields[0].parse().chain_err(mkerr)?,
+            five: fields[1].parse().chain_err(mkerr)?,
+            fifteen: fields[2].parse().chain_err(mkerr)?,
+            runnable: tasks.next().unwrap_or("").parse().chain_err(mkerr)?,
+            tasks: tasks.next().unwrap_or("").parse().chain_err(mkerr)?,
+        })
+    }
+}
+
+/// Pressure stall information from a PSI file, such as `cpu.pressure`.
+/// The averages are the percentage of time during which at least one task
+/// was stalled waiting for the resource.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PressureStall {
+    /// Average over the last 10 seconds.
+    pub avg10: f64,
+    /// Average over the last 60 seconds.
+    pub avg60: f64,
+    /// Average over the last 300 seconds.
+    pub avg300: f64,
+    /// Total time stalled.
+    pub total: Duration,
+}
+
+impl PressureStall {
+    /// Read the `some` line of the PSI file at `path`.
+    pub fn read(path: &Path) -> Result<PressureStall> {
+        let mkerr = || ErrorKind::File(path.to_owned());
+        let contents = fs::read_to_string(path).chain_err(mkerr)?;
+        // For example:
+        // `some avg10=1.23 avg60=0.50 avg300=0.10 total=123456`.
+        let parse_err = || ErrorKind::Parse(path.to_owned());
+        let line = contents.lines()
+            .find(|l| l.starts_with("some "))
+            .ok_or_else(parse_err)?;
+        let mut stall = PressureStall::default();
+        for field in line.split_whitespace().skip(1) {
+            let mut kv = field.splitn(2, '=');
+            let (key, value) = (kv.next().unwrap_or(""), kv.next());
+            let value = value.ok_or_else(parse_err)?;
+            match key {
+                "avg10" => stall.avg10 = value.parse().chain_err(mkerr)?,
+                "avg60" => stall.avg60 = value.parse().chain_err(mkerr)?,
+                "avg300" => stall.avg300 = value.parse().chain_err(mkerr)?,
+                "total" => {
+                    let us = value.parse().chain_err(mkerr)?;
+                    stall.total = Duration::from_micros(us);
+                }
+                _ => {}
+            }
+        }
+        Ok(stall)
+    }
+}
+
+/// CPU load on the host, and CPU pressure in our own cgroup.
+///
+/// A high `normalized_load` means the host is busy.  High `pressure`
+/// with a low normalized load means that our tasks are waiting for CPU
+/// even though the host has some to spare, which usually means we've used
+/// up our cgroup's CPU quota.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuLoad {
+    /// The host's load averages.
+    pub load_average: LoadAverage,
+    /// The number of CPUs we can use, from `effective_cpu_count`.
+    pub cpu_count: f64,
+    /// How often our cgroup's tasks were stalled waiting for CPU, from
+    /// `cpu.pressure`.  This requires cgroup v2 and a kernel with PSI
+    /// enabled, and is `None` otherwise.
+    pub pressure: Option<PressureStall>,
+}
+
+impl CpuLoad {
+    /// Read the current load and pressure.
+    pub fn read() -> Result<CpuLoad> {
+        let pressure = match cgroup::version() {
+            CgroupVersion::V1 => None,
+            CgroupVersion::V2 => {
+                let path = cgroup::controller_file("cpu", "", "cpu.pressure");
+                PressureStall::read(&path).ok()
+            }
+        };
+        Ok(CpuLoad {
+            load_average: LoadAverage::read()?,
+            cpu_count: effective_cpu_count(),
+            pressure,
+        })
+    }
+
+    /// The 1, 5 and 15 minute load averages, divided by `cpu_count`.  A
+    /// value of 1.0 means there's one runnable task for each CPU we can
+    /// use.
+    pub fn normalized_load(&self) -> [f64; 3] {
+        let cpus = self.cpu_count.max(1e-3);
+        let load = &self.load_average;
+        [load.one / cpus, load.five / cpus, load.fifteen / cpus]
+    }
+}
@@ -0,0 +1,61 @@
+//! Periodic one-line summaries of resource usage, for the `log` crate.
+
+use bytes::Bytes;
+use snapshot::Snapshot;
+use Resource;
+
+/// Format `limit`, which may be `u64::MAX` if there is no limit.
+fn format_limit(limit: u64) -> String {
+    if limit == u64::MAX {
+        "unlimited".to_owned()
+    } else {
+        Bytes(limit).to_string()
+    }
+}
+
+/// Summarize `snapshot` in one line, for example:
+///
+/// ```text
+/// memory 1.2 GiB/2.0 GiB (60.0%), allocator 300.0 MiB active/400.0 MiB mapped
+/// ```
+///
+/// Anything we couldn't read is left out.
+pub fn summary(snapshot: &Snapshot) -> String {
+    let mut parts = vec![];
+    if let Some(r) = snapshot.get(&Resource::Memory) {
+        match (r.used, r.limit) {
+            (Some(used), Some(limit)) => {
+                let mut part = format!("memory {}/{}",
+                                       Bytes(used),
+                                       format_limit(limit));
+                if let Some(f) = snapshot.fraction_used(&Resource::Memory) {
+                    if limit != u64::MAX {
+                        part.push_str(&format!(" ({:.1}%)", f * 100.0));
+                    }
+                }
+                parts.push(part);
+            }
+            (Some(used), None) => parts.push(format!("memory {}", Bytes(used))),
+            _ => {}
+        }
+    }
+    if let Some(r) = snapshot.get(&Resource::AllocatorMemory) {
+        if let (Some(used), Some(available)) = (r.used, r.available) {
+            parts.push(format!("allocator {} active/{} mapped",
+                               Bytes(used),
+                               Bytes(used + available)));
+        }
+    }
+    for r in &snapshot.readings {
+        if let Resource::ScopedMemory(ref name) = r.resource {
+            if let Some(used) = r.used {
+                parts.push(format!("scope {} {}", name, Bytes(used)));
+            }
+        }
+    }
+    if parts.is_empty() {
+        "no resource data".to_owned()
+    } else {
+        parts.join(", ")
+    }
+}
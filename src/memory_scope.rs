@@ -0,0 +1,174 @@
+//! Attribute heap usage to named subsystems.  This is layered on top of
+//! `CountingAllocator::with_scopes`, which records the active scope of
+//! every allocation.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The maximum number of distinct scope names, including the implicit
+/// `"untagged"` scope.
+pub const MAX_SCOPES: usize = 64;
+
+/// The name we report for allocations made outside of any scope.
+pub const UNTAGGED: &str = "untagged";
+
+/// Live and peak bytes for a single scope.
+struct ScopeCounters {
+    live: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+/// Used to initialize `COUNTERS`.
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_COUNTERS: ScopeCounters = ScopeCounters {
+    live: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+/// Per-scope counters, indexed by tag.  Tag 0 is `UNTAGGED`.
+static COUNTERS: [ScopeCounters; MAX_SCOPES] = [ZERO_COUNTERS; MAX_SCOPES];
+
+/// Names of our scopes.  `NAMES[i]` is the name for tag `i + 1`.
+static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Has a scope-tracking allocator been installed?
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The tag of the scope that this thread is currently in.
+    static CURRENT_TAG: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Return the tag of the current thread's scope.  This is called from
+/// inside the allocator, so it must never allocate or panic.
+pub fn current_tag() -> usize {
+    CURRENT_TAG.try_with(|t| t.get()).unwrap_or(0)
+}
+
+/// Record an allocation of `size` bytes in scope `tag`.
+pub fn record_alloc(tag: usize, size: usize) {
+    ENABLED.store(true, Ordering::Relaxed);
+    let counters = &COUNTERS[tag];
+    let live = counters.live.fetch_add(size, Ordering::Relaxed) + size;
+    counters.peak.fetch_max(live, Ordering::Relaxed);
+}
+
+/// Record a deallocation of `size` bytes in scope `tag`.
+pub fn record_dealloc(tag: usize, size: usize) {
+    COUNTERS[tag].live.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Look up the tag for `name`, returning `None` if it has never been
+/// used.
+fn find_tag(names: &[&'static str], name: &str) -> Option<usize> {
+    if name == UNTAGGED {
+        return Some(0);
+    }
+    names.iter().position(|&n| n == name).map(|i| i + 1)
+}
+
+/// While this guard exists, all heap allocations made by the current
+/// thread will be charged to the named scope.  Memory is charged to the
+/// scope that allocated it, even if it's freed from another scope.
+///
+/// ```
+/// use resource_monitor::MemoryScope;
+///
+/// let _scope = MemoryScope::enter("cache");
+/// let cached = vec![0u8; 4096];
+/// ```
+///
+/// Scopes may be nested; dropping the guard restores the previous scope.
+/// Only `MAX_SCOPES - 1` distinct names are supported, and allocations in
+/// scopes beyond that limit are counted as `"untagged"`.  This does
+/// nothing unless `CountingAllocator::with_scopes` is installed as the
+/// global allocator.
+pub struct MemoryScope {
+    previous: usize,
+    // Scopes are per-thread, so our guard shouldn't be `Send`.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl MemoryScope {
+    /// Enter the scope `name`.
+    pub fn enter(name: &'static str) -> MemoryScope {
+        let tag = {
+            let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+            match find_tag(&names, name) {
+                Some(tag) => tag,
+                None if names.len() + 1 < MAX_SCOPES => {
+                    names.push(name);
+                    names.len()
+                }
+                None => 0,
+            }
+        };
+        let previous = CURRENT_TAG.with(|t| t.replace(tag));
+        MemoryScope {
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for MemoryScope {
+    fn drop(&mut self) {
+        CURRENT_TAG.with(|t| t.set(self.previous));
+    }
+}
+
+/// Heap usage for a single `MemoryScope`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeUsage {
+    /// The name passed to `MemoryScope::enter`.
+    pub name: &'static str,
+    /// Bytes allocated in this scope which haven't been freed.
+    pub live_bytes: u64,
+    /// The largest value `live_bytes` has ever had.
+    pub peak_bytes: u64,
+}
+
+/// Are we tracking per-scope heap usage?
+pub fn scopes_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Return usage for the scope with the specified tag.
+fn usage_for(tag: usize, name: &'static str) -> ScopeUsage {
+    ScopeUsage {
+        name,
+        live_bytes: COUNTERS[tag].live.load(Ordering::Relaxed) as u64,
+        peak_bytes: COUNTERS[tag].peak.load(Ordering::Relaxed) as u64,
+    }
+}
+
+/// Return usage for `name`, or `None` if we've never seen that scope.
+pub fn scope_usage(name: &str) -> Option<ScopeUsage> {
+    let names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    find_tag(&names, name).map(|tag| {
+        let name = if tag == 0 { UNTAGGED } else { names[tag - 1] };
+        usage_for(tag, name)
+    })
+}
+
+/// Reset the peak usage of `name` to its current usage.  Does nothing if
+/// we've never seen that scope.
+pub fn reset_scope_peak(name: &str) {
+    let names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(tag) = find_tag(&names, name) {
+        let live = COUNTERS[tag].live.load(Ordering::Relaxed);
+        COUNTERS[tag].peak.store(live, Ordering::Relaxed);
+    }
+}
+
+/// Return usage for every scope we've seen, starting with `"untagged"`.
+pub fn scope_report() -> Vec<ScopeUsage> {
+    let names = NAMES.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let mut report = vec![usage_for(0, UNTAGGED)];
+    for (i, name) in names.into_iter().enumerate() {
+        report.push(usage_for(i + 1, name));
+    }
+    report
+}
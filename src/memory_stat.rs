@@ -0,0 +1,154 @@
+//! A breakdown of what our cgroup's memory is being used for, from
+//! `memory.stat`.
+
+use std::collections::HashMap;
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+
+/// Which page cache we count as reclaimable when computing
+/// `Resource::effective_available`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReclaimableCache {
+    /// Don't count any page cache as reclaimable.  This is the same as
+    /// `available`.
+    Nothing,
+    /// Count the inactive file LRU list, which the kernel will drop
+    /// first under pressure.  This is the default, and matches what
+    /// `docker stats` and the Kubernetes kubelet report as "working set".
+    #[default]
+    InactiveFile,
+    /// Count all page cache except dirty pages and pages under
+    /// writeback.  This is optimistic: some of this cache may be in
+    /// active use, and dropping it will slow down I/O.
+    CleanFile,
+}
+
+impl ReclaimableCache {
+    /// How many bytes of `stat` does this policy consider reclaimable?
+    pub fn bytes(&self, stat: &MemoryStat) -> u64 {
+        match *self {
+            ReclaimableCache::Nothing => 0,
+            ReclaimableCache::InactiveFile => {
+                stat.inactive_file.unwrap_or(0)
+            }
+            ReclaimableCache::CleanFile => {
+                let busy = stat.dirty.unwrap_or(0) +
+                           stat.writeback.unwrap_or(0) +
+                           stat.shmem.unwrap_or(0);
+                stat.file.unwrap_or(0).saturating_sub(busy)
+            }
+        }
+    }
+}
+
+/// Selected fields of `memory.stat`, in bytes unless noted otherwise.
+/// This tells you whether "used" memory is page cache which the kernel
+/// can reclaim, or anonymous memory which it can't.
+///
+/// cgroup v1 and v2 use different names for most fields, and we translate
+/// them to a common set here.  Under cgroup v1, we use the hierarchical
+/// `total_*` values, which include child cgroups, just like
+/// `memory.usage_in_bytes`.  Fields which aren't available are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MemoryStat {
+    /// Anonymous memory, such as the heap and stacks (v1 `rss`).
+    pub anon: Option<u64>,
+    /// Page cache, including shared memory (v1 `cache`).
+    pub file: Option<u64>,
+    /// Page cache mapped into processes with `mmap` (v2 `file_mapped`).
+    pub mapped_file: Option<u64>,
+    /// Page cache waiting to be written to disk (v2 `file_dirty`).
+    pub dirty: Option<u64>,
+    /// Page cache being written to disk (v2 `file_writeback`).
+    pub writeback: Option<u64>,
+    /// Shared memory and tmpfs.
+    pub shmem: Option<u64>,
+    /// Kernel slab memory (v2 only).
+    pub slab: Option<u64>,
+    /// The part of `slab` which can be reclaimed, such as dentry caches
+    /// (v2 only).
+    pub slab_reclaimable: Option<u64>,
+    /// Anonymous memory on the active LRU list.
+    pub active_anon: Option<u64>,
+    /// Anonymous memory on the inactive LRU list.
+    pub inactive_anon: Option<u64>,
+    /// Page cache on the active LRU list.
+    pub active_file: Option<u64>,
+    /// Page cache on the inactive LRU list, which is the first thing the
+    /// kernel reclaims.
+    pub inactive_file: Option<u64>,
+    /// Number of evicted pages which were faulted back in soon after,
+    /// suggesting we're short of memory.  This is a count of pages.
+    pub workingset_refault: Option<u64>,
+    /// Number of refaulted pages which were immediately activated.  This
+    /// is a count of pages.
+    pub workingset_activate: Option<u64>,
+    /// Every field in `memory.stat`, under its original name.
+    pub raw: HashMap<String, u64>,
+}
+
+impl MemoryStat {
+    /// Read `memory.stat` for our cgroup.
+    pub fn read() -> Result<MemoryStat> {
+        let version = cgroup::version();
+        let raw =
+            cgroup::read_keyed_file(&cgroup::memory_file("memory.stat",
+                                                         "memory.stat"))?;
+        Ok(MemoryStat::from_raw(version, raw))
+    }
+
+    /// Translate the raw fields of `memory.stat`.
+    fn from_raw(version: CgroupVersion,
+                raw: HashMap<String, u64>)
+                -> MemoryStat {
+        // Look up the first of `names` that we have.
+        let get = |names: &[&str]| {
+            names.iter().filter_map(|&n| raw.get(n)).cloned().next()
+        };
+        let stat = match version {
+            CgroupVersion::V1 => {
+                MemoryStat {
+                    anon: get(&["total_rss", "rss"]),
+                    file: get(&["total_cache", "cache"]),
+                    mapped_file: get(&["total_mapped_file", "mapped_file"]),
+                    dirty: get(&["total_dirty", "dirty"]),
+                    writeback: get(&["total_writeback", "writeback"]),
+                    shmem: get(&["total_shmem", "shmem"]),
+                    active_anon: get(&["total_active_anon", "active_anon"]),
+                    inactive_anon: get(&["total_inactive_anon",
+                                         "inactive_anon"]),
+                    active_file: get(&["total_active_file", "active_file"]),
+                    inactive_file: get(&["total_inactive_file",
+                                         "inactive_file"]),
+                    ..MemoryStat::default()
+                }
+            }
+            CgroupVersion::V2 => {
+                MemoryStat {
+                    anon: get(&["anon"]),
+                    file: get(&["file"]),
+                    mapped_file: get(&["file_mapped"]),
+                    dirty: get(&["file_dirty"]),
+                    writeback: get(&["file_writeback"]),
+                    shmem: get(&["shmem"]),
+                    slab: get(&["slab"]),
+                    slab_reclaimable: get(&["slab_reclaimable"]),
+                    active_anon: get(&["active_anon"]),
+                    inactive_anon: get(&["inactive_anon"]),
+                    active_file: get(&["active_file"]),
+                    inactive_file: get(&["inactive_file"]),
+                    // Linux 5.9 split these into `_anon` and `_file`
+                    // variants.
+                    workingset_refault: get(&["workingset_refault_file",
+                                              "workingset_refault"]),
+                    workingset_activate: get(&["workingset_activate_file",
+                                               "workingset_activate"]),
+                    ..MemoryStat::default()
+                }
+            }
+        };
+        MemoryStat { raw, ..stat }
+    }
+}
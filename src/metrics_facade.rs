@@ -0,0 +1,44 @@
+//! Recording resource readings through the `metrics` crate, so that they
+//! go to whichever exporter the application has installed.
+//!
+//! We record the same gauges as `ResourceCollector`, named
+//! `resource_<name>_limit_bytes`, `resource_<name>_used_bytes` and
+//! `resource_<name>_available_bytes`, plus a
+//! `resource_read_errors_total` counter.  `Resource::CpuTime` is recorded
+//! in nanoseconds rather than bytes.
+
+use metrics::Label;
+
+use snapshot::{ReadError, Snapshot};
+
+/// Record every value in `snapshot` as a gauge.  Values which weren't
+/// read are skipped.
+pub fn record_snapshot(snapshot: &Snapshot) {
+    for reading in &snapshot.readings {
+        let (kind, scope) = reading.resource.metric_name();
+        let labels: Vec<Label> = scope.into_iter()
+            .map(|s| Label::new("scope", s.into_owned()))
+            .collect();
+        let values = [("limit", reading.limit),
+                      ("used", reading.used),
+                      ("available", reading.available)];
+        for &(value, v) in &values {
+            if let Some(v) = v {
+                let name = format!("resource_{}_{}_{}",
+                                   kind,
+                                   value,
+                                   reading.resource.metric_unit());
+                gauge!(name, labels.clone()).set(v as f64);
+            }
+        }
+    }
+}
+
+/// Count `error`, labelled with the resource and value we failed to read.
+pub fn record_read_error(error: &ReadError) {
+    let (kind, _) = error.resource.metric_name();
+    counter!("resource_read_errors_total",
+             "resource" => kind,
+             "value" => error.value)
+        .increment(1);
+}
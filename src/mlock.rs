@@ -0,0 +1,25 @@
+//! Locked memory, which `mlock`, `io_uring` buffer registration and many
+//! crypto libraries use to pin pages in RAM.
+
+use std::path::Path;
+
+use cgroup;
+use errors::*;
+use rlimit::{self, Rlimit};
+
+/// How much memory may we lock?  This is our `RLIMIT_MEMLOCK`.  Processes
+/// with `CAP_IPC_LOCK` may exceed it.
+pub fn limit() -> Result<u64> {
+    rlimit::soft_limit(Rlimit::LockedMemory)
+}
+
+/// How much memory have we locked?  This is `VmLck` in
+/// `/proc/self/status`.
+pub fn used() -> Result<u64> {
+    let path = Path::new("/proc/self/status");
+    let status = cgroup::read_keyed_file(path)?;
+    match status.get("VmLck:") {
+        Some(&kb) => Ok(kb * 1024),
+        None => Err(ErrorKind::Parse(path.to_owned()).into()),
+    }
+}
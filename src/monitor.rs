@@ -0,0 +1,921 @@
+//! A background thread which samples resources periodically, so that hot
+//! code paths can check resource usage without touching the filesystem.
+
+use std::io::Write;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard,
+                RwLockWriteGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "log")]
+use allocator_backend;
+use baseline::capture_baseline;
+use errors::*;
+#[cfg(feature = "log")]
+use log;
+use guardrail::{GuardrailPolicy, RuleResult};
+use history::{SampleHistory, UsagePercentiles, UsageStats};
+use incident::{Incident, IncidentLog, EVENT_LOG_LEN};
+use leak::{LeakDetection, LeakDetector, LeakWarning};
+#[cfg(feature = "log")]
+use logging;
+#[cfg(feature = "metrics")]
+use metrics_facade;
+use pressure_events::PressureEvents;
+use pressure_state::{MemoryState, PressureBands, PressureStateMachine,
+                     StateTransition};
+use profile::{SampleDetails, SamplingProfile};
+use reader::ResourceReader;
+use reclaim::reclaim;
+use recorder::Recorder;
+use signal_dump::print_dump;
+use smoothing::Smoothing;
+use snapshot::{ReadError, Reading, Snapshot};
+use statsd::StatsdEmitter;
+use threshold::{Threshold, ThresholdEvent, ThresholdWatch};
+use trend;
+#[cfg(feature = "tracing")]
+use tracing;
+#[cfg(feature = "tracing")]
+use tracing_events;
+use Resource;
+
+/// Events which a `Monitor` sends to its subscribers.
+#[derive(Debug, Clone)]
+pub enum MonitorEvent {
+    /// We took a new sample.
+    Sample(Arc<Snapshot>),
+    /// We couldn't read a value.
+    ReadError(ReadError),
+    /// A threshold registered with `on_threshold` or `add_threshold` was
+    /// crossed.
+    Threshold(ThresholdEvent),
+    /// Our `MemoryState` changed.
+    StateChanged(StateTransition),
+    /// Leak detection enabled with `detect_leaks` found a resource which
+    /// keeps growing.
+    LeakSuspected(LeakWarning),
+    /// The OOM killer killed this many processes in our cgroup since our
+    /// last sample.
+    OomKill(u64),
+    /// The watchdog set with `set_watchdog` fired, because of this
+    /// snapshot.
+    WatchdogTriggered(Arc<Snapshot>),
+    /// A rule of a policy added with `add_guardrails` was violated, after
+    /// previously passing.
+    GuardrailViolated(RuleResult),
+}
+
+/// The number of samples a `Monitor` keeps in its history by default.
+pub const HISTORY_LEN: usize = 600;
+
+/// Configuration for `Monitor::set_watchdog`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchdog {
+    /// Trigger when `Resource::Memory` has less than this many bytes
+    /// available.
+    pub min_available: u64,
+    /// How many samples in a row must be below `min_available`.
+    pub samples: usize,
+    /// Abort the process with a diagnostic dump after calling the
+    /// trigger callback.
+    pub abort: bool,
+}
+
+/// Something which wants to see every new snapshot.  It may add events to
+/// send to our subscribers.
+type Observer = Box<dyn FnMut(&Snapshot, &mut Vec<MonitorEvent>) + Send>;
+
+/// Something which wants to know when our `MemoryState` changes.
+type StateListener = Box<dyn FnMut(&StateTransition) + Send>;
+
+/// A `SamplingProfile`, and when it next wants a sample.
+struct Scheduled {
+    profile: SamplingProfile,
+    next: Instant,
+}
+
+/// State shared between a `Monitor` and its background thread.
+struct Shared {
+    resources: RwLock<Vec<Resource>>,
+    /// How often to check for new profiles when we don't have any.
+    interval: Duration,
+    profiles: Mutex<Vec<Scheduled>>,
+    latest: RwLock<Arc<Snapshot>>,
+    history: Mutex<SampleHistory>,
+    incidents: Mutex<IncidentLog>,
+    smoothing: Mutex<Smoothing>,
+    observers: Mutex<Vec<Observer>>,
+    memory_state: Mutex<PressureStateMachine>,
+    state_listeners: Mutex<Vec<StateListener>>,
+    subscribers: Mutex<Vec<mpsc::Sender<MonitorEvent>>>,
+    stopped: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl Shared {
+    /// Take samples until we're asked to stop.
+    fn run(&self) {
+        let mut reader = ResourceReader::new(&[]);
+        let mut oom_kills = read_oom_kills();
+        while let Some(details) = self.wait_for_sample() {
+            reader.set_resources(&read_lock(&self.resources));
+            let (mut snapshot, errors) = reader.snapshot_with_errors();
+            if details {
+                snapshot.details = Some(SampleDetails::capture());
+            }
+            let snapshot = Arc::new(snapshot);
+            *write_lock(&self.latest) = snapshot.clone();
+            lock(&self.history).push(snapshot.clone());
+
+            let mut events = vec![MonitorEvent::Sample(snapshot.clone())];
+            events.extend(errors.into_iter().map(MonitorEvent::ReadError));
+            let new_oom_kills = read_oom_kills();
+            if let (Some(old), Some(new)) = (oom_kills, new_oom_kills) {
+                if new > old {
+                    events.push(MonitorEvent::OomKill(new - old));
+                }
+            }
+            oom_kills = new_oom_kills.or(oom_kills);
+            call_each(&self.observers,
+                      |observer| observer(&snapshot, &mut events));
+            if let Some(transition) = self.update_memory_state(&snapshot) {
+                call_each(&self.state_listeners,
+                          |listener| listener(&transition));
+                events.push(MonitorEvent::StateChanged(transition));
+            }
+            self.reclaim_if_needed(&snapshot);
+            lock(&self.incidents).record(&events);
+            #[cfg(feature = "tracing")]
+            tracing_events::trace_events(&events);
+            self.broadcast(events);
+        }
+    }
+
+    /// Send `events` to all our subscribers, and forget about any
+    /// subscribers who have hung up.
+    fn broadcast(&self, events: Vec<MonitorEvent>) {
+        let mut subscribers = lock(&self.subscribers);
+        subscribers.retain(|tx| {
+            events.iter().all(|event| tx.send(event.clone()).is_ok())
+        });
+    }
+
+    /// Feed `snapshot` to our memory state machine.
+    fn update_memory_state(&self,
+                           snapshot: &Snapshot)
+                           -> Option<StateTransition> {
+        let fraction = snapshot.fraction_used(&Resource::Memory)?;
+        lock(&self.memory_state).update(fraction)
+    }
+
+    /// If our memory state isn't `Normal`, ask any registered reclaimers
+    /// to free enough memory to get us back out of the `Elevated` band.
+    fn reclaim_if_needed(&self, snapshot: &Snapshot) {
+        let (used, limit) = match snapshot.get(&Resource::Memory) {
+            Some(&Reading { used: Some(u), limit: Some(l), .. }) => (u, l),
+            _ => return,
+        };
+        let target = {
+            let machine = lock(&self.memory_state);
+            if machine.state() == MemoryState::Normal {
+                return;
+            }
+            let bands = machine.bands();
+            ((bands.elevated - bands.hysteresis) * limit as f64) as u64
+        };
+        if used > target {
+            reclaim(used - target);
+        }
+    }
+
+    /// Sleep until one of our profiles wants a sample.  Returns whether
+    /// any profile which wants one also wants `SampleDetails`, or `None`
+    /// if we were asked to stop in the meantime.
+    fn wait_for_sample(&self) -> Option<bool> {
+        loop {
+            let now = Instant::now();
+            let mut wait = self.interval;
+            let mut due = None;
+            for scheduled in lock(&self.profiles).iter_mut() {
+                if scheduled.next > now {
+                    wait = wait.min(scheduled.next - now);
+                    continue;
+                }
+                let details = due.unwrap_or(false);
+                due = Some(details || scheduled.profile.details);
+                // If we fell behind, skip the samples we missed.
+                scheduled.next += scheduled.profile.interval;
+                if scheduled.next <= now {
+                    scheduled.next = now + scheduled.profile.interval;
+                }
+            }
+            if due.is_some() {
+                return due;
+            }
+            if self.sleep(wait) {
+                return None;
+            }
+        }
+    }
+
+    /// Sleep for up to `duration`, returning `true` if we were asked to
+    /// stop.  We wake up early when our profiles change.
+    fn sleep(&self, duration: Duration) -> bool {
+        let stopped = lock(&self.stopped);
+        if *stopped {
+            return true;
+        }
+        *self.wakeup
+            .wait_timeout(stopped, duration)
+            .unwrap_or_else(|e| e.into_inner())
+            .0
+    }
+}
+
+/// The number of processes the OOM killer has killed in our cgroup, if we
+/// can find out.
+fn read_oom_kills() -> Option<u64> {
+    PressureEvents::read().ok()?.oom_kill
+}
+
+/// Lock `mutex`, even if a thread panicked while holding it, so that one
+/// failure doesn't stop the monitor.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lock `rwlock` for reading, even if it's poisoned, like `lock`.
+fn read_lock<T>(rwlock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    rwlock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lock `rwlock` for writing, even if it's poisoned, like `lock`.
+fn write_lock<T>(rwlock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    rwlock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Pass each of the callbacks in `callbacks` to `call`.  We don't hold the
+/// lock meanwhile, so callbacks may register more callbacks, and we drop
+/// any callback which panics, so that it can't stop the monitor.
+fn call_each<T, F>(callbacks: &Mutex<Vec<T>>, mut call: F)
+    where F: FnMut(&mut T)
+{
+    let mut called = mem::take(&mut *lock(callbacks));
+    called.retain_mut(|callback| {
+        panic::catch_unwind(AssertUnwindSafe(|| call(callback))).is_ok()
+    });
+    let mut current = lock(callbacks);
+    // Keep any callbacks which were added while we were calling these.
+    called.append(&mut current);
+    *current = called;
+}
+
+/// Samples a set of resources on a background thread at a fixed interval.
+///
+/// ```
+/// use std::time::Duration;
+/// use resource_monitor::{Monitor, Resource};
+///
+/// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(1))
+///     .unwrap();
+/// let latest = monitor.latest();
+/// if let Some(reading) = latest.get(&Resource::Memory) {
+///     println!("Memory available: {:?}", reading.available);
+/// }
+/// ```
+///
+/// The background thread stops when the `Monitor` is dropped.
+pub struct Monitor {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Monitor {
+    /// Start monitoring `resources` every `interval`.  We take the first
+    /// sample before returning, so `latest` always has data.  This also
+    /// takes the baseline for `since_start`, if nobody has yet.  Fails if
+    /// we can't spawn the background thread.
+    pub fn new(resources: &[Resource],
+               interval: Duration)
+               -> Result<Monitor> {
+        capture_baseline();
+        let first = Arc::new(Snapshot::capture(resources));
+        let mut history = SampleHistory::new(HISTORY_LEN);
+        history.push(first.clone());
+        let cheap = Scheduled {
+            profile: SamplingProfile::cheap(interval),
+            next: Instant::now() + interval,
+        };
+        let shared = Arc::new(Shared {
+            resources: RwLock::new(resources.to_owned()),
+            interval,
+            profiles: Mutex::new(vec![cheap]),
+            latest: RwLock::new(first.clone()),
+            history: Mutex::new(history),
+            incidents: Mutex::new(IncidentLog::new(EVENT_LOG_LEN)),
+            smoothing: Mutex::new(Smoothing::default()),
+            observers: Mutex::new(vec![]),
+            memory_state: Mutex::new(PressureStateMachine::new(
+                PressureBands::default())),
+            state_listeners: Mutex::new(vec![]),
+            subscribers: Mutex::new(vec![]),
+            stopped: Mutex::new(false),
+            wakeup: Condvar::new(),
+        });
+        shared.update_memory_state(&read_lock(&shared.latest));
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("resource_monitor".to_owned())
+                .spawn(move || shared.run())
+                .chain_err(|| "could not spawn resource monitor thread")?
+        };
+        Ok(Monitor {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// How often we take samples: the shortest interval of any of our
+    /// profiles, or the interval passed to `new` if we have none.
+    pub fn interval(&self) -> Duration {
+        lock(&self.shared.profiles)
+            .iter()
+            .map(|s| s.profile.interval)
+            .min()
+            .unwrap_or(self.shared.interval)
+    }
+
+    /// Our sampling profiles.  We start with a `SamplingProfile::cheap`
+    /// profile at the interval passed to `new`.
+    pub fn profiles(&self) -> Vec<SamplingProfile> {
+        lock(&self.shared.profiles)
+            .iter()
+            .map(|s| s.profile.clone())
+            .collect()
+    }
+
+    /// Add `profile`, replacing any profile with the same name.  Its first
+    /// sample is taken right away.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource, SamplingProfile};
+    ///
+    /// // Read usage ten times a second, and everything once a minute.
+    /// let monitor = Monitor::new(&[Resource::Memory],
+    ///                            Duration::from_millis(100)).unwrap();
+    /// monitor.add_profile(SamplingProfile::detailed(Duration::from_secs(60)));
+    /// ```
+    pub fn add_profile(&self, profile: SamplingProfile) {
+        let mut profiles = lock(&self.shared.profiles);
+        profiles.retain(|s| s.profile.name != profile.name);
+        profiles.push(Scheduled {
+            profile,
+            next: Instant::now(),
+        });
+        self.shared.wakeup.notify_all();
+    }
+
+    /// Remove the profile named `name`, returning `false` if we don't have
+    /// one.  Without any profiles, we stop taking samples.
+    pub fn remove_profile(&self, name: &str) -> bool {
+        let mut profiles = lock(&self.shared.profiles);
+        let len = profiles.len();
+        profiles.retain(|s| s.profile.name != name);
+        profiles.len() < len
+    }
+
+    /// The most recent snapshot with `SampleDetails`, if we have a detailed
+    /// profile and it has taken a sample which we still have in our
+    /// history.
+    pub fn latest_details(&self) -> Option<Arc<Snapshot>> {
+        lock(&self.shared.history)
+            .iter()
+            .rev()
+            .find(|s| s.details.is_some())
+            .cloned()
+    }
+
+    /// Return the most recent snapshot.  This never touches the
+    /// filesystem, so it's cheap enough to call on every request.
+    pub fn latest(&self) -> Arc<Snapshot> {
+        read_lock(&self.shared.latest).clone()
+    }
+
+    /// Record memory usage from our latest snapshot on `span`, which must
+    /// declare `memory_used`, `memory_limit` and `memory_fraction` fields.
+    /// This requires the `tracing` feature.
+    ///
+    /// ```
+    /// # extern crate resource_monitor;
+    /// # #[macro_use] extern crate tracing;
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource};
+    /// use tracing::field::Empty;
+    ///
+    /// # fn main() {
+    /// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(1))
+    ///     .unwrap();
+    /// let span = info_span!("request",
+    ///                       memory_used = Empty,
+    ///                       memory_limit = Empty,
+    ///                       memory_fraction = Empty);
+    /// monitor.record_memory(&span);
+    /// let _guard = span.enter();
+    /// # }
+    /// ```
+    #[cfg(feature = "tracing")]
+    pub fn record_memory(&self, span: &tracing::Span) {
+        tracing_events::record_memory(span, &self.latest())
+    }
+
+    /// Our recent snapshots, oldest first.  We keep the last
+    /// `history_len` samples.
+    pub fn history(&self) -> Vec<Arc<Snapshot>> {
+        lock(&self.shared.history).iter().cloned().collect()
+    }
+
+    /// A copy of our history, which can be queried without blocking the
+    /// background thread.  This only copies pointers, not snapshots.
+    pub fn sample_history(&self) -> SampleHistory {
+        lock(&self.shared.history).clone()
+    }
+
+    /// How many samples we keep.  The default is `HISTORY_LEN`.
+    pub fn history_len(&self) -> usize {
+        lock(&self.shared.history).capacity()
+    }
+
+    /// Keep the last `len` samples, instead of `HISTORY_LEN`.  With a
+    /// single profile, multiply by our `interval` to find out how far back
+    /// this goes.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource};
+    ///
+    /// // Keep an hour of samples.
+    /// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(5))
+    ///     .unwrap();
+    /// monitor.set_history_len(60 * 60 / 5);
+    /// ```
+    pub fn set_history_len(&self, len: usize) {
+        lock(&self.shared.history).set_capacity(len)
+    }
+
+    /// Snapshots taken at or after `time`, oldest first.
+    pub fn samples_since(&self, time: SystemTime) -> Vec<Arc<Snapshot>> {
+        lock(&self.shared.history).since(time)
+    }
+
+    /// The minimum, maximum and mean `used` value of `resource` over the
+    /// last `window`.  Returns `None` if we have no readings for
+    /// `resource` in that window.
+    pub fn usage_stats(&self,
+                       resource: &Resource,
+                       window: Duration)
+                       -> Option<UsageStats> {
+        lock(&self.shared.history).stats(resource, window)
+    }
+
+    /// The p50, p95, p99 and maximum `used` values of `resource` over the
+    /// last `window`.  Returns `None` if we have no readings for
+    /// `resource` in that window.  Our history must be long enough to
+    /// cover `window`; see `set_history_len`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource};
+    ///
+    /// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(5))
+    ///     .unwrap();
+    /// monitor.set_history_len(60 * 60 / 5);
+    /// let hour = Duration::from_secs(60 * 60);
+    /// if let Some(p) = monitor.usage_percentiles(&Resource::Memory, hour) {
+    ///     println!("p95 memory over the last hour: {}", p.p95);
+    /// }
+    /// ```
+    pub fn usage_percentiles(&self,
+                             resource: &Resource,
+                             window: Duration)
+                             -> Option<UsagePercentiles> {
+        lock(&self.shared.history).percentiles(resource, window)
+    }
+
+    /// Notable events, such as threshold crossings, OOM kills and read
+    /// failures, oldest first.  We keep the last `EVENT_LOG_LEN` by
+    /// default.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource};
+    ///
+    /// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(1))
+    ///     .unwrap();
+    /// for incident in monitor.recent_events() {
+    ///     println!("{:?}: {}", incident.at, incident.message);
+    /// }
+    /// ```
+    pub fn recent_events(&self) -> Vec<Incident> {
+        lock(&self.shared.incidents).recent()
+    }
+
+    /// Keep the last `len` notable events, instead of `EVENT_LOG_LEN`.
+    pub fn set_event_log_len(&self, len: usize) {
+        lock(&self.shared.incidents).set_capacity(len)
+    }
+
+    /// Snapshots taken within `window` of the latest one, oldest first.
+    fn recent(&self, window: Duration) -> Vec<Arc<Snapshot>> {
+        lock(&self.shared.history).within(window)
+    }
+
+    /// Change how `smoothed_used` filters our samples.  The default is
+    /// `Smoothing::Ewma(0.3)`.
+    pub fn set_smoothing(&self, smoothing: Smoothing) {
+        *lock(&self.shared.smoothing) = smoothing;
+    }
+
+    /// The amount of `resource` used, smoothed over our history to filter
+    /// out noise.  Returns `None` if we have no readings for `resource`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource, Smoothing};
+    ///
+    /// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(1))
+    ///     .unwrap();
+    /// monitor.set_smoothing(Smoothing::Median(5));
+    /// println!("{:?}", monitor.smoothed_used(&Resource::Memory));
+    /// ```
+    pub fn smoothed_used(&self, resource: &Resource) -> Option<u64> {
+        let values: Vec<u64> = self.history()
+            .iter()
+            .filter_map(|s| s.get(resource).and_then(|r| r.used))
+            .collect();
+        let smoothing = *lock(&self.shared.smoothing);
+        smoothing.apply(&values)
+    }
+
+    /// How fast has `Resource::Memory` usage grown over the last
+    /// `window`, in bytes per second?  This fits a line to our samples, so
+    /// it isn't thrown off by a single noisy reading.  Returns `None` if
+    /// we don't have at least two samples in `window`.
+    pub fn memory_growth_rate(&self, window: Duration) -> Option<f64> {
+        let recent = self.recent(window);
+        trend::growth_rate(recent.iter().map(|s| &**s), &Resource::Memory)
+    }
+
+    /// If `Resource::Memory` keeps growing at the rate it has over the
+    /// last `window`, how long until it reaches its limit?  Returns `None`
+    /// if usage isn't growing, or there is no limit.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource};
+    ///
+    /// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(1))
+    ///     .unwrap();
+    /// let window = Duration::from_secs(60);
+    /// if let Some(t) = monitor.estimated_time_to_limit(window) {
+    ///     if t < Duration::from_secs(300) {
+    ///         println!("Out of memory in {:?}, spilling to disk", t);
+    ///     }
+    /// }
+    /// ```
+    pub fn estimated_time_to_limit(&self, window: Duration)
+                                   -> Option<Duration> {
+        let rate = self.memory_growth_rate(window)?;
+        trend::time_to_limit(&self.latest(), &Resource::Memory, rate)
+    }
+
+    /// What percentage of a CPU did this process use between our last two
+    /// samples?  This may exceed 100% if we're using more than one core.
+    /// Returns `None` unless we're monitoring `Resource::CpuTime`, and
+    /// have at least two samples.
+    pub fn cpu_utilization(&self) -> Option<f64> {
+        let history = lock(&self.shared.history);
+        let mut samples = history.iter().rev().filter_map(|s| {
+            let used = s.get(&Resource::CpuTime)?.used?;
+            Some((s.taken_at, used))
+        });
+        let (t1, cpu1) = samples.next()?;
+        let (t0, cpu0) = samples.next()?;
+        let wall = t1.duration_since(t0).ok()?.as_nanos() as f64;
+        if wall == 0.0 {
+            return None;
+        }
+        Some(cpu1.saturating_sub(cpu0) as f64 / wall * 100.0)
+    }
+
+    /// The resources we're monitoring.
+    pub fn resources(&self) -> Vec<Resource> {
+        read_lock(&self.shared.resources).clone()
+    }
+
+    /// Start monitoring `resource`, if we aren't already.  It will appear
+    /// in snapshots starting with the next sample.
+    pub fn add_resource(&self, resource: Resource) {
+        let mut resources = write_lock(&self.shared.resources);
+        if !resources.contains(&resource) {
+            resources.push(resource);
+        }
+    }
+
+    /// Call `observer` from the background thread after every sample.
+    fn add_observer<F>(&self, observer: F)
+        where F: FnMut(&Snapshot, &mut Vec<MonitorEvent>) + Send + 'static
+    {
+        lock(&self.shared.observers).push(Box::new(observer));
+    }
+
+    /// Call `callback` when usage of `resource` reaches `threshold`, and
+    /// again once it falls 5% below `threshold`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, Resource};
+    /// use resource_monitor::Threshold::Fraction;
+    ///
+    /// let monitor = Monitor::new(&[], Duration::from_secs(1)).unwrap();
+    /// monitor.on_threshold(Resource::Memory, Fraction(0.9), |evt| {
+    ///     println!("Memory: {:?}", evt.crossing);
+    /// });
+    /// ```
+    ///
+    /// Callbacks run on the monitor's background thread, so they should
+    /// return quickly.  A callback which panics is dropped, and never
+    /// called again.
+    pub fn on_threshold<F>(&self,
+                           resource: Resource,
+                           threshold: Threshold,
+                           callback: F)
+        where F: FnMut(&ThresholdEvent) + Send + 'static
+    {
+        let watch = ThresholdWatch::with_default_recovery(resource,
+                                                          threshold);
+        self.watch_threshold(watch, callback)
+    }
+
+    /// Like `on_threshold`, but specify the recovery threshold explicitly.
+    pub fn on_threshold_with_recovery<F>(&self,
+                                         resource: Resource,
+                                         trigger: Threshold,
+                                         recover: Threshold,
+                                         callback: F)
+        where F: FnMut(&ThresholdEvent) + Send + 'static
+    {
+        let watch = ThresholdWatch::new(resource, trigger, recover);
+        self.watch_threshold(watch, callback)
+    }
+
+    /// Our current `MemoryState`, based on `Resource::Memory`.  This will
+    /// always be `Normal` unless we're monitoring `Resource::Memory` and it
+    /// has a limit.
+    pub fn memory_state(&self) -> MemoryState {
+        lock(&self.shared.memory_state).state()
+    }
+
+    /// Replace the bands used to compute `memory_state`.  This resets our
+    /// state to `Normal` until the next sample.
+    pub fn set_pressure_bands(&self, bands: PressureBands) {
+        *lock(&self.shared.memory_state) =
+            PressureStateMachine::new(bands);
+    }
+
+    /// Call `callback` from the background thread whenever `memory_state`
+    /// changes.  This starts monitoring `Resource::Memory` if we weren't
+    /// already.  If `callback` panics, we drop it, like `on_threshold`.
+    pub fn on_state_change<F>(&self, callback: F)
+        where F: FnMut(&StateTransition) + Send + 'static
+    {
+        self.add_resource(Resource::Memory);
+        lock(&self.shared.state_listeners).push(Box::new(callback));
+    }
+
+    /// Run `callback` whenever `watch` reports an event.
+    fn watch_threshold<F>(&self, mut watch: ThresholdWatch, mut callback: F)
+        where F: FnMut(&ThresholdEvent) + Send + 'static
+    {
+        self.add_resource(watch.resource().clone());
+        self.add_observer(move |snapshot, events| {
+            if let Some(event) = watch.update(snapshot) {
+                callback(&event);
+                events.push(MonitorEvent::Threshold(event));
+            }
+        });
+    }
+
+    /// Watch for `resource` crossing `threshold`, but only report it to
+    /// our subscribers, without running a callback.
+    pub fn add_threshold(&self, resource: Resource, threshold: Threshold) {
+        self.on_threshold(resource, threshold, |_| {})
+    }
+
+    /// Send every sample to a StatsD server using `emitter`.  Errors are
+    /// ignored, because StatsD delivery is best-effort anyway.
+    pub fn emit_to_statsd(&self, emitter: StatsdEmitter) {
+        self.add_observer(move |snapshot, _| {
+            let _ = emitter.emit(snapshot);
+        });
+    }
+
+    /// Watch for memory running out.  When `Resource::Memory` has less
+    /// than `watchdog.min_available` bytes available for
+    /// `watchdog.samples` samples in a row, call `on_trigger` with the
+    /// latest snapshot, so the application can flush its state and shut
+    /// down.  If `watchdog.abort` is set, we then print a diagnostic dump
+    /// to standard error and abort the process.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use resource_monitor::{Bytes, Monitor, Watchdog};
+    ///
+    /// let monitor = Monitor::new(&[], Duration::from_secs(1)).unwrap();
+    /// let watchdog = Watchdog {
+    ///     min_available: 64 * Bytes::MIB,
+    ///     samples: 3,
+    ///     abort: true,
+    /// };
+    /// monitor.set_watchdog(watchdog, |_| {
+    ///     eprintln!("Out of memory, flushing logs");
+    /// });
+    /// ```
+    ///
+    /// The watchdog only fires once.  This starts monitoring
+    /// `Resource::Memory` if we weren't already.
+    pub fn set_watchdog<F>(&self, watchdog: Watchdog, on_trigger: F)
+        where F: FnOnce(&Snapshot) + Send + 'static
+    {
+        self.add_resource(Resource::Memory);
+        let mut on_trigger = Some(on_trigger);
+        let mut low_samples = 0;
+        self.add_observer(move |snapshot, events| {
+            let available = snapshot.get(&Resource::Memory)
+                .and_then(|r| r.available);
+            match available {
+                Some(a) if a < watchdog.min_available => low_samples += 1,
+                _ => low_samples = 0,
+            }
+            if low_samples < watchdog.samples.max(1) {
+                return;
+            }
+            if let Some(on_trigger) = on_trigger.take() {
+                events.push(MonitorEvent::WatchdogTriggered(Arc::new(
+                    snapshot.clone())));
+                on_trigger(snapshot);
+                if watchdog.abort {
+                    eprintln!("resource_monitor: watchdog triggered, \
+                               aborting");
+                    print_dump(snapshot, &[]);
+                    process::abort();
+                }
+            }
+        });
+    }
+
+    /// Watch for usage that keeps growing, as described by `config`, and
+    /// send `MonitorEvent::LeakSuspected` to our subscribers when we see
+    /// it.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{LeakDetection, Monitor};
+    ///
+    /// let monitor = Monitor::new(&[], Duration::from_secs(1)).unwrap();
+    /// monitor.detect_leaks(LeakDetection {
+    ///     window: Duration::from_secs(300),
+    ///     ..LeakDetection::default()
+    /// });
+    /// ```
+    ///
+    /// This is only a heuristic: a cache which is still warming up looks a
+    /// lot like a leak.
+    pub fn detect_leaks(&self, config: LeakDetection) {
+        let mut detector = LeakDetector::new(config);
+        self.add_resource(detector.resource().clone());
+        self.add_observer(move |snapshot, events| {
+            if let Some(warning) = detector.update(snapshot) {
+                events.push(MonitorEvent::LeakSuspected(warning));
+            }
+        });
+    }
+
+    /// Evaluate `policy` against every sample, and send
+    /// `MonitorEvent::GuardrailViolated` to our subscribers whenever one of
+    /// its rules is newly violated.  This starts monitoring the resources
+    /// its rules need.  The policy's own `on_violation` callbacks run on
+    /// our background thread.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{GuardrailPolicy, Monitor, Resource};
+    ///
+    /// let monitor = Monitor::new(&[], Duration::from_secs(1)).unwrap();
+    /// monitor.add_guardrails(GuardrailPolicy::new()
+    ///     .max_usage(Resource::Memory, 0.85)
+    ///     .max_open_files(0.9));
+    /// ```
+    pub fn add_guardrails(&self, mut policy: GuardrailPolicy) {
+        for resource in policy.resources() {
+            self.add_resource(resource);
+        }
+        self.add_observer(move |snapshot, events| {
+            let verdict = policy.evaluate_snapshot(snapshot);
+            for result in verdict.results {
+                if result.newly_violated {
+                    events.push(MonitorEvent::GuardrailViolated(result));
+                }
+            }
+        });
+    }
+
+    /// Append every sample to `recorder`.  Write errors are ignored, so
+    /// that a full disk doesn't take down the monitor.
+    pub fn record_to<W>(&self, mut recorder: Recorder<W>)
+        where W: Write + Send + 'static
+    {
+        self.add_observer(move |snapshot, _| {
+            let _ = recorder.record(snapshot);
+        });
+    }
+
+    /// Log a one-line summary of memory usage through the `log` crate,
+    /// at most once every `period`.  Since we only log when we take a
+    /// sample, `period` is effectively rounded up to a multiple of our
+    /// interval.  This requires the `log` feature.
+    ///
+    /// This starts monitoring `Resource::Memory`, and also
+    /// `Resource::AllocatorMemory` if we can see inside the allocator.
+    #[cfg(feature = "log")]
+    pub fn log_every(&self, period: Duration, level: log::Level) {
+        self.add_resource(Resource::Memory);
+        if allocator_backend().is_some() {
+            self.add_resource(Resource::AllocatorMemory);
+        }
+        let mut last_logged: Option<Instant> = None;
+        self.add_observer(move |snapshot, _| {
+            let now = Instant::now();
+            if last_logged.is_some_and(|t| now.duration_since(t) < period) {
+                return;
+            }
+            last_logged = Some(now);
+            log!(level, "{}", logging::summary(snapshot));
+        });
+    }
+
+    /// Record every sample, and every read error, through the `metrics`
+    /// crate.  This requires the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn record_to_metrics(&self) {
+        self.add_observer(|snapshot, events| {
+            metrics_facade::record_snapshot(snapshot);
+            for event in events.iter() {
+                if let MonitorEvent::ReadError(ref err) = *event {
+                    metrics_facade::record_read_error(err);
+                }
+            }
+        });
+    }
+
+    /// Return a channel which will receive all future `MonitorEvent`s.
+    /// Each subscriber receives its own copy of each event, so multiple
+    /// subsystems can share a single `Monitor`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use resource_monitor::{Monitor, MonitorEvent, Resource};
+    ///
+    /// let monitor = Monitor::new(&[Resource::Memory],
+    ///                            Duration::from_millis(10)).unwrap();
+    /// let events = monitor.subscribe();
+    /// match events.recv().unwrap() {
+    ///     MonitorEvent::Sample(snapshot) => println!("{:?}", snapshot),
+    ///     other => println!("{:?}", other),
+    /// }
+    /// ```
+    ///
+    /// Events are buffered until they're received, so drop the receiver
+    /// when you're no longer interested.
+    pub fn subscribe(&self) -> mpsc::Receiver<MonitorEvent> {
+        let (tx, rx) = mpsc::channel();
+        lock(&self.shared.subscribers).push(tx);
+        rx
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        *lock(&self.shared.stopped) = true;
+        self.shared.wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Parsing `/proc/self/mountinfo`, which lists every filesystem we can see
+//! and which part of it is mounted where.
+
+use std::fs;
+use std::path::PathBuf;
+
+use errors::*;
+
+/// One line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// The directory within the filesystem which is mounted, such as
+    /// `/docker/<id>` for a bind mount of part of a cgroup hierarchy.
+    pub root: String,
+    /// Where it's mounted.
+    pub mount_point: PathBuf,
+    /// The filesystem type, such as `cgroup2`.
+    pub fs_type: String,
+    /// The device or other source of the filesystem.
+    pub source: String,
+    /// Options specific to the filesystem, such as the controllers of a
+    /// cgroup v1 hierarchy.
+    pub super_options: String,
+}
+
+/// Read and parse `/proc/self/mountinfo`.  Lines we can't parse are
+/// skipped.
+pub fn read() -> Result<Vec<Mount>> {
+    let path = PathBuf::from("/proc/self/mountinfo");
+    let contents = fs::read_to_string(&path)
+        .chain_err(|| ErrorKind::File(path.clone()))?;
+    Ok(contents.lines().filter_map(parse_line).collect())
+}
+
+/// Parse one line, which looks like `id parent dev root mount_point
+/// options [optional fields...] - fs_type source super_options`.
+fn parse_line(line: &str) -> Option<Mount> {
+    let mut halves = line.splitn(2, " - ");
+    let (left, right) = (halves.next()?, halves.next()?);
+    let mut left = left.split(' ').skip(3);
+    let root = unescape(left.next()?);
+    let mount_point = PathBuf::from(unescape(left.next()?));
+    let mut right = right.split(' ');
+    Some(Mount {
+        root,
+        mount_point,
+        fs_type: right.next()?.to_owned(),
+        source: unescape(right.next()?),
+        super_options: right.next().unwrap_or("").to_owned(),
+    })
+}
+
+/// Undo the octal escapes, such as `\040` for a space, which
+/// `/proc/self/mountinfo` uses in paths.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4)
+            .and_then(|o| ::std::str::from_utf8(o).ok())
+            .and_then(|o| u8::from_str_radix(o, 8).ok());
+        match octal {
+            Some(byte) if bytes[i] == b'\\' => {
+                out.push(byte);
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,83 @@
+//! The OOM killer's opinion of a process.  When memory runs out, the
+//! kernel kills the process with the highest `oom_score`, which
+//! supervisors can adjust using `oom_score_adj`, so that expendable
+//! workers die before the supervisor does.
+
+use std::fs;
+use std::path::Path;
+
+use errors::*;
+
+/// The lowest `oom_score_adj`, which stops the OOM killer from choosing a
+/// process at all.
+pub const OOM_SCORE_ADJ_MIN: i32 = -1000;
+
+/// The highest `oom_score_adj`, which makes a process the OOM killer's
+/// first choice.
+pub const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
+/// How likely the OOM killer is to choose the current process, from 0 to
+/// 2000.  This is roughly the fraction of memory we use, in thousandths,
+/// plus our `oom_score_adj`.
+pub fn oom_score() -> Result<u32> {
+    read_oom_score(Path::new("/proc/self"))
+}
+
+/// Our adjustment to `oom_score`, from `OOM_SCORE_ADJ_MIN` to
+/// `OOM_SCORE_ADJ_MAX`.
+pub fn oom_score_adj() -> Result<i32> {
+    read_oom_score_adj(Path::new("/proc/self"))
+}
+
+/// Change our adjustment to `oom_score`.  Child processes inherit this, so
+/// a supervisor can set it just before spawning a worker, or set it for
+/// the worker later using `ProcessResources::set_oom_score_adj`.
+///
+/// ```no_run
+/// use resource_monitor::{set_oom_score_adj, OOM_SCORE_ADJ_MAX};
+///
+/// // We're a cache, so we'd rather be killed than our neighbours.
+/// set_oom_score_adj(OOM_SCORE_ADJ_MAX).unwrap();
+/// ```
+///
+/// Anyone may raise their own adjustment, but lowering it below its
+/// previous lowest value requires `CAP_SYS_RESOURCE`, and fails with
+/// `ErrorKind::PermissionDenied` otherwise.
+pub fn set_oom_score_adj(adj: i32) -> Result<()> {
+    write_oom_score_adj(Path::new("/proc/self"), adj)
+}
+
+/// Read `oom_score` from the `/proc` directory `proc_dir` of a process.
+pub fn read_oom_score(proc_dir: &Path) -> Result<u32> {
+    let path = proc_dir.join("oom_score");
+    fs::read_to_string(&path)
+        .chain_err(|| ErrorKind::File(path.clone()))?
+        .trim()
+        .parse()
+        .chain_err(|| ErrorKind::Parse(path))
+}
+
+/// Read `oom_score_adj` from the `/proc` directory `proc_dir` of a
+/// process.
+pub fn read_oom_score_adj(proc_dir: &Path) -> Result<i32> {
+    let path = proc_dir.join("oom_score_adj");
+    fs::read_to_string(&path)
+        .chain_err(|| ErrorKind::File(path.clone()))?
+        .trim()
+        .parse()
+        .chain_err(|| ErrorKind::Parse(path))
+}
+
+/// Write `adj` to `oom_score_adj` in the `/proc` directory `proc_dir` of a
+/// process.
+pub fn write_oom_score_adj(proc_dir: &Path, adj: i32) -> Result<()> {
+    if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&adj) {
+        return Err(format!("oom_score_adj must be between {} and {}, not {}",
+                           OOM_SCORE_ADJ_MIN,
+                           OOM_SCORE_ADJ_MAX,
+                           adj)
+            .into());
+    }
+    let path = proc_dir.join("oom_score_adj");
+    fs::write(&path, adj.to_string()).chain_err(|| ErrorKind::File(path))
+}
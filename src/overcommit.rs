@@ -0,0 +1,107 @@
+//! The kernel's overcommit policy.  With `vm.overcommit_memory=2`, the
+//! kernel refuses to hand out more virtual memory than its commit limit,
+//! so allocations fail long before our cgroup fills up.
+
+use std::path::{Path, PathBuf};
+
+use cgroup;
+use errors::*;
+use read_file_u64;
+
+/// How the kernel decides whether to grant a request for memory, as set by
+/// `vm.overcommit_memory`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum OvercommitPolicy {
+    /// Mode 0, the default: refuse requests which obviously can't be
+    /// met, and grant everything else.
+    Heuristic,
+    /// Mode 1: grant every request.
+    Always,
+    /// Mode 2: refuse requests which would take the system's committed
+    /// memory over its commit limit.
+    Never,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// The system's overcommit settings, and how much memory has been
+/// committed.
+///
+/// ```
+/// use resource_monitor::{Overcommit, OvercommitPolicy};
+///
+/// if let Ok(overcommit) = Overcommit::read() {
+///     if overcommit.policy == OvercommitPolicy::Never {
+///         println!("We can commit {} more bytes", overcommit.headroom());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overcommit {
+    /// The policy, from `vm.overcommit_memory`.
+    pub policy: OvercommitPolicy,
+    /// The percentage of RAM which counts towards the commit limit, from
+    /// `vm.overcommit_ratio`.
+    pub ratio: u64,
+    /// The amount of RAM which counts towards the commit limit, in bytes,
+    /// from `vm.overcommit_kbytes`.  If this isn't 0, it's used instead of
+    /// `ratio`.
+    pub kbytes: u64,
+    /// The most memory which may be committed under
+    /// `OvercommitPolicy::Never`, in bytes, from `CommitLimit` in
+    /// `/proc/meminfo`.  This is swap plus the portion of RAM set by
+    /// `ratio` or `kbytes`.
+    pub commit_limit: u64,
+    /// The memory committed by every process on the system, in bytes, from
+    /// `Committed_AS` in `/proc/meminfo`.
+    pub committed: u64,
+}
+
+impl Overcommit {
+    /// Read the current settings from `/proc/sys/vm` and `/proc/meminfo`.
+    pub fn read() -> Result<Overcommit> {
+        let policy = match read_sysctl("overcommit_memory")? {
+            0 => OvercommitPolicy::Heuristic,
+            1 => OvercommitPolicy::Always,
+            2 => OvercommitPolicy::Never,
+            _ => {
+                let path = PathBuf::from("/proc/sys/vm/overcommit_memory");
+                return Err(ErrorKind::Parse(path).into());
+            }
+        };
+        let path = PathBuf::from("/proc/meminfo");
+        let meminfo = cgroup::read_keyed_file(&path)?;
+        let get = |key| {
+            meminfo.get(key)
+                .map(|&kb| kb * 1024)
+                .ok_or_else(|| Error::from(ErrorKind::Parse(path.clone())))
+        };
+        Ok(Overcommit {
+            policy,
+            ratio: read_sysctl("overcommit_ratio")?,
+            kbytes: read_sysctl("overcommit_kbytes")? * 1024,
+            commit_limit: get("CommitLimit:")?,
+            committed: get("Committed_AS:")?,
+        })
+    }
+
+    /// Does the kernel refuse allocations beyond `commit_limit`?
+    pub fn is_strict(&self) -> bool {
+        self.policy == OvercommitPolicy::Never
+    }
+
+    /// How much more memory could be committed before reaching
+    /// `commit_limit`, in bytes.  This is advisory: the kernel only
+    /// enforces it when `is_strict`, and it's shared by every process on
+    /// the system.
+    pub fn headroom(&self) -> u64 {
+        self.commit_limit.saturating_sub(self.committed)
+    }
+}
+
+/// Read a numeric setting from `/proc/sys/vm`.
+fn read_sysctl(name: &str) -> Result<u64> {
+    read_file_u64(&Path::new("/proc/sys/vm").join(name))
+}
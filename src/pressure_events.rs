@@ -0,0 +1,71 @@
+//! Counters showing how often the kernel has already had to enforce our
+//! memory limits.
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use read_file_u64;
+
+/// Kernel counters of memory limit enforcement.  Any non-zero value here
+/// means that we've already run into trouble, which is often more
+/// actionable than a point-in-time usage number.
+///
+/// Which fields are available depends on the cgroup version, so each of
+/// them is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PressureEvents {
+    /// Number of times usage hit the limit (cgroup v1 `memory.failcnt`).
+    pub failcnt: Option<u64>,
+    /// Number of times usage exceeded `memory.high` and the kernel
+    /// throttled us (cgroup v2).
+    pub high: Option<u64>,
+    /// Number of times usage was about to exceed `memory.max` (cgroup
+    /// v2).
+    pub max: Option<u64>,
+    /// Number of times we hit the limit and the OOM killer was invoked
+    /// (cgroup v2).
+    pub oom: Option<u64>,
+    /// Number of processes in our cgroup killed by the OOM killer.
+    pub oom_kill: Option<u64>,
+    /// Are we currently out of memory, with the OOM killer disabled?
+    /// (cgroup v1).
+    pub under_oom: Option<bool>,
+}
+
+impl PressureEvents {
+    /// Read the current counters for our cgroup.
+    pub fn read() -> Result<PressureEvents> {
+        match cgroup::version() {
+            CgroupVersion::V1 => {
+                let failcnt =
+                    read_file_u64(&cgroup::memory_file("memory.failcnt", ""))?;
+                let oom_control = cgroup::read_keyed_file(
+                    &cgroup::memory_file("memory.oom_control", ""))?;
+                Ok(PressureEvents {
+                    failcnt: Some(failcnt),
+                    oom_kill: oom_control.get("oom_kill").cloned(),
+                    under_oom: oom_control.get("under_oom").map(|&v| v != 0),
+                    ..PressureEvents::default()
+                })
+            }
+            CgroupVersion::V2 => {
+                let events = cgroup::read_keyed_file(
+                    &cgroup::memory_file("", "memory.events"))?;
+                Ok(PressureEvents {
+                    high: events.get("high").cloned(),
+                    max: events.get("max").cloned(),
+                    oom: events.get("oom").cloned(),
+                    oom_kill: events.get("oom_kill").cloned(),
+                    ..PressureEvents::default()
+                })
+            }
+        }
+    }
+
+    /// Has the kernel ever refused us memory or killed one of our
+    /// processes?
+    pub fn any(&self) -> bool {
+        let counts = [self.failcnt, self.max, self.oom, self.oom_kill];
+        counts.iter().any(|c| c.unwrap_or(0) > 0) ||
+        self.under_oom.unwrap_or(false)
+    }
+}
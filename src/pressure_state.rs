@@ -0,0 +1,124 @@
+//! Classifying memory usage into a few coarse states, so that
+//! backpressure logic doesn't flap every time usage wobbles.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// How close we are to running out of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemoryState {
+    /// Plenty of memory available.
+    Normal,
+    /// Usage is high enough that we should consider shedding load.
+    Elevated,
+    /// We're close to the limit, and should refuse new work.
+    Critical,
+}
+
+/// Configuration for `PressureStateMachine`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PressureBands {
+    /// Fraction of the limit at which we enter `Elevated`.
+    pub elevated: f64,
+    /// Fraction of the limit at which we enter `Critical`.
+    pub critical: f64,
+    /// To leave a state, usage must fall this far below the fraction at
+    /// which we entered it.
+    pub hysteresis: f64,
+    /// We move to a higher state immediately, but we must stay in a state
+    /// at least this long before we move to a lower one.
+    pub min_dwell: Duration,
+}
+
+impl Default for PressureBands {
+    fn default() -> PressureBands {
+        PressureBands {
+            elevated: 0.75,
+            critical: 0.9,
+            hysteresis: 0.05,
+            min_dwell: Duration::from_secs(5),
+        }
+    }
+}
+
+impl PressureBands {
+    /// Classify `fraction` used, as if all thresholds were lowered by
+    /// `offset`.
+    fn classify(&self, fraction: f64, offset: f64) -> MemoryState {
+        if fraction >= self.critical - offset {
+            MemoryState::Critical
+        } else if fraction >= self.elevated - offset {
+            MemoryState::Elevated
+        } else {
+            MemoryState::Normal
+        }
+    }
+}
+
+/// Reported when `PressureStateMachine` changes state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateTransition {
+    /// The state we were in.
+    pub from: MemoryState,
+    /// The state we're in now.
+    pub to: MemoryState,
+    /// The fraction of memory in use which caused the transition.
+    pub fraction: f64,
+    /// When the transition happened.
+    pub at: SystemTime,
+}
+
+/// Tracks a `MemoryState` using hysteresis and minimum dwell times.
+#[derive(Debug, Clone)]
+pub struct PressureStateMachine {
+    bands: PressureBands,
+    state: MemoryState,
+    entered_at: Instant,
+}
+
+impl PressureStateMachine {
+    /// Create a new state machine, starting in `Normal`.
+    pub fn new(bands: PressureBands) -> PressureStateMachine {
+        PressureStateMachine {
+            bands,
+            state: MemoryState::Normal,
+            entered_at: Instant::now(),
+        }
+    }
+
+    /// Our current configuration.
+    pub fn bands(&self) -> &PressureBands {
+        &self.bands
+    }
+
+    /// Our current state.
+    pub fn state(&self) -> MemoryState {
+        self.state
+    }
+
+    /// Update our state based on the `fraction` of memory in use.
+    pub fn update(&mut self, fraction: f64) -> Option<StateTransition> {
+        let now = Instant::now();
+        let raw = self.bands.classify(fraction, 0.0);
+        let next = if raw > self.state {
+            raw
+        } else {
+            let lowered = self.bands.classify(fraction, self.bands.hysteresis);
+            let dwelled = now.duration_since(self.entered_at) >=
+                          self.bands.min_dwell;
+            if lowered < self.state && dwelled {
+                lowered
+            } else {
+                return None;
+            }
+        };
+        let transition = StateTransition {
+            from: self.state,
+            to: next,
+            fraction,
+            at: SystemTime::now(),
+        };
+        self.state = next;
+        self.entered_at = now;
+        Some(transition)
+    }
+}
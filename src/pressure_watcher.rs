@@ -0,0 +1,152 @@
+//! Kernel memory pressure notifications, using cgroup v1's
+//! `memory.pressure_level` and an `eventfd`.
+
+#[cfg(target_os = "linux")]
+use libc;
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::io;
+use std::io::{Read, Write};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use Resource;
+
+/// How hard the kernel is working to find free memory for our cgroup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PressureLevel {
+    /// The kernel is reclaiming memory for new allocations, which is
+    /// normal, but a good time to drop caches.
+    Low,
+    /// The kernel is swapping or evicting active file caches.
+    Medium,
+    /// We're about to hit the OOM killer.
+    Critical,
+}
+
+impl PressureLevel {
+    /// The name used for this level by `memory.pressure_level`.
+    #[cfg(target_os = "linux")]
+    fn as_str(&self) -> &'static str {
+        match *self {
+            PressureLevel::Low => "low",
+            PressureLevel::Medium => "medium",
+            PressureLevel::Critical => "critical",
+        }
+    }
+}
+
+/// Watches for kernel memory pressure notifications on a background
+/// thread.  Notifications stop when this is dropped.
+///
+/// Under the default kernel notification mode, watching for `Low` will
+/// also report `Medium` and `Critical` pressure, but they'll be reported
+/// as `Low`.  Create one watcher per level if you need to tell them
+/// apart.
+///
+/// This requires cgroup v1 and write access to `cgroup.event_control`.
+/// Under cgroup v2 it returns `ErrorKind::Unsupported`.
+pub struct MemoryPressureWatcher {
+    event_fd: Arc<fs::File>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MemoryPressureWatcher {
+    /// Call `callback` from a background thread every time the kernel
+    /// reports pressure at `level`.
+    pub fn new<F>(level: PressureLevel,
+                  mut callback: F)
+                  -> Result<MemoryPressureWatcher>
+        where F: FnMut(PressureLevel) + Send + 'static
+    {
+        if cgroup::version() != CgroupVersion::V1 {
+            return Err(ErrorKind::Unsupported(Resource::Memory).into());
+        }
+        let (event_fd, pressure) = register(level)?;
+        let event_fd = Arc::new(event_fd);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let event_fd = event_fd.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                // Closing this would cancel our registration.
+                let _pressure = pressure;
+                let mut buf = [0u8; 8];
+                while (&*event_fd).read_exact(&mut buf).is_ok() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    callback(level);
+                }
+            })
+        };
+
+        Ok(MemoryPressureWatcher {
+            event_fd,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Deliver pressure notifications to a channel instead of a callback.
+    pub fn channel(level: PressureLevel)
+                   -> Result<(MemoryPressureWatcher,
+                              mpsc::Receiver<PressureLevel>)> {
+        let (tx, rx) = mpsc::channel();
+        let watcher = MemoryPressureWatcher::new(level, move |lvl| {
+            // If nobody is listening, there's nothing useful to do.
+            let _ = tx.send(lvl);
+        })?;
+        Ok((watcher, rx))
+    }
+}
+
+impl Drop for MemoryPressureWatcher {
+    fn drop(&mut self) {
+        // Wake up our thread by signalling the eventfd ourselves.
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = (&*self.event_fd).write_all(&1u64.to_ne_bytes());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Create an eventfd and ask the kernel to signal it when there's
+/// pressure at `level`.  Returns the eventfd, and the open
+/// `memory.pressure_level` file, which must stay open to keep our
+/// registration.
+#[cfg(target_os = "linux")]
+fn register(level: PressureLevel) -> Result<(fs::File, fs::File)> {
+    let pressure_path = cgroup::memory_file("memory.pressure_level", "");
+    let control_path = cgroup::memory_file("cgroup.event_control", "");
+    let pressure = fs::File::open(&pressure_path)
+        .chain_err(|| ErrorKind::File(pressure_path.clone()))?;
+    let efd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if efd < 0 {
+        return Err(io::Error::last_os_error())
+            .chain_err(|| "could not create eventfd");
+    }
+    let event_fd = unsafe { fs::File::from_raw_fd(efd) };
+    let registration = format!("{} {} {}",
+                               efd,
+                               pressure.as_raw_fd(),
+                               level.as_str());
+    cgroup::write_file(&control_path, &registration)?;
+    Ok((event_fd, pressure))
+}
+
+/// Without Linux's eventfds and cgroups, we can't receive pressure
+/// notifications.
+#[cfg(not(target_os = "linux"))]
+fn register(_level: PressureLevel) -> Result<(fs::File, fs::File)> {
+    Err(ErrorKind::Unsupported(Resource::Memory).into())
+}
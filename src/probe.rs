@@ -0,0 +1,55 @@
+//! Checking up front whether we can read a resource, so that callers can
+//! tell "this system doesn't have it" apart from "we failed to read it".
+
+use std::path::PathBuf;
+
+use errors::*;
+use Resource;
+
+/// Whether we can read a resource, as reported by `Resource::probe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Support {
+    /// We can read this resource.
+    Supported,
+    /// This system, or this build of the crate, can't measure this
+    /// resource.  For example, `AllocatorMemory` needs an allocator
+    /// feature, and `GpuMemory` needs the `nvml` feature and a GPU.
+    Unsupported,
+    /// A file we need doesn't exist, usually because a cgroup controller
+    /// isn't enabled, or the kernel is too old.
+    Missing(PathBuf),
+    /// We don't have permission to read a file we need.
+    PermissionDenied(PathBuf),
+    /// Reading the resource failed for some other reason.  This may be
+    /// temporary, so it's worth trying again later.
+    Failed(String),
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+impl Support {
+    /// Is this `Support::Supported`?
+    pub fn is_supported(&self) -> bool {
+        *self == Support::Supported
+    }
+}
+
+/// Try reading `resource`, and work out why it failed.
+pub fn probe(resource: &Resource) -> Support {
+    let err = match resource.used() {
+        Ok(_) => return Support::Supported,
+        Err(err) => err,
+    };
+    match *err.kind() {
+        ErrorKind::Unsupported(_) |
+        ErrorKind::AllocatorUnavailable |
+        ErrorKind::UnsupportedAllocator(_) |
+        ErrorKind::UnsupportedPlatform => Support::Unsupported,
+        ErrorKind::NotFound(ref path) => Support::Missing(path.clone()),
+        ErrorKind::PermissionDenied(ref path) => {
+            Support::PermissionDenied(path.clone())
+        }
+        _ => Support::Failed(err.to_string()),
+    }
+}
@@ -0,0 +1,439 @@
+//! Resources used by another process, such as a child spawned by a
+//! supervisor, read from `/proc/<pid>/`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cgroup;
+use cgroup_resources::CgroupResources;
+use cpu::{self, ThreadCpuTime};
+use errors::*;
+use oom;
+
+/// Point-in-time resource usage of one process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessUsage {
+    /// Resident set size, in bytes.
+    pub rss: u64,
+    /// Number of open file descriptors.
+    pub fds: u64,
+    /// Number of threads.
+    pub threads: u64,
+    /// User and system CPU time consumed so far.
+    pub cpu_time: Duration,
+}
+
+/// The memory fields of `/proc/<pid>/status`, in bytes.  These work
+/// without cgroups, and `vm_hwm` and `vm_peak` are high-water marks kept
+/// by the kernel, so they catch spikes between samples.  Fields which
+/// this kernel doesn't report are `None`.
+///
+/// ```
+/// use resource_monitor::ProcessStatus;
+///
+/// let status = ProcessStatus::read().unwrap();
+/// println!("RSS: {:?}, peak RSS: {:?}", status.vm_rss, status.vm_hwm);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessStatus {
+    /// Peak virtual memory size (`VmPeak`).
+    pub vm_peak: Option<u64>,
+    /// Virtual memory size (`VmSize`).
+    pub vm_size: Option<u64>,
+    /// Peak resident set size (`VmHWM`).
+    pub vm_hwm: Option<u64>,
+    /// Resident set size (`VmRSS`).
+    pub vm_rss: Option<u64>,
+    /// Anonymous memory which has been swapped out (`VmSwap`).
+    pub vm_swap: Option<u64>,
+    /// Resident anonymous memory (`RssAnon`, Linux 4.5 and later).
+    pub rss_anon: Option<u64>,
+    /// Resident file mappings (`RssFile`, Linux 4.5 and later).
+    pub rss_file: Option<u64>,
+    /// Resident shared memory (`RssShmem`, Linux 4.5 and later).
+    pub rss_shmem: Option<u64>,
+}
+
+impl ProcessStatus {
+    /// Read the status of this process.
+    pub fn read() -> Result<ProcessStatus> {
+        ProcessStatus::read_from(Path::new("/proc/self/status"))
+    }
+
+    /// Read a `status` file.
+    fn read_from(path: &Path) -> Result<ProcessStatus> {
+        let status = cgroup::read_keyed_file(path)?;
+        // The kernel reports these in kB.
+        let get = |key: &str| status.get(key).map(|&kb| kb * 1024);
+        Ok(ProcessStatus {
+            vm_peak: get("VmPeak:"),
+            vm_size: get("VmSize:"),
+            vm_hwm: get("VmHWM:"),
+            vm_rss: get("VmRSS:"),
+            vm_swap: get("VmSwap:"),
+            rss_anon: get("RssAnon:"),
+            rss_file: get("RssFile:"),
+            rss_shmem: get("RssShmem:"),
+        })
+    }
+}
+
+/// Memory totals from `/proc/<pid>/smaps_rollup`, in bytes, which
+/// account for memory shared between processes.
+///
+/// RSS counts every shared page in full in every process which maps it,
+/// so adding up the RSS of forked workers overstates their memory use,
+/// sometimes several times over.  PSS divides each shared page between
+/// the processes sharing it, so it adds up correctly, and USS counts only
+/// the pages which would be freed if the process exited.
+///
+/// This is much slower than reading RSS, because the kernel has to walk
+/// every page table of the process, so don't read it many times a second.
+/// It requires Linux 4.14 or later.
+///
+/// ```
+/// use resource_monitor::SmapsRollup;
+///
+/// let rollup = SmapsRollup::read().unwrap();
+/// println!("PSS: {}, USS: {}", rollup.pss, rollup.uss);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SmapsRollup {
+    /// Resident set size, counting shared pages in full.
+    pub rss: u64,
+    /// Proportional set size: resident memory, with each shared page
+    /// divided between the processes which share it.
+    pub pss: u64,
+    /// Unique set size: resident pages used only by this process
+    /// (`Private_Clean` plus `Private_Dirty`).
+    pub uss: u64,
+    /// Resident pages which are also mapped by other processes
+    /// (`Shared_Clean` plus `Shared_Dirty`).
+    pub shared: u64,
+    /// Anonymous memory which has been swapped out.
+    pub swap: u64,
+    /// Swapped-out memory, with each shared page divided between the
+    /// processes which share it (Linux 4.15 and later).
+    pub swap_pss: Option<u64>,
+}
+
+impl SmapsRollup {
+    /// Read the smaps rollup of this process.
+    pub fn read() -> Result<SmapsRollup> {
+        SmapsRollup::read_from(Path::new("/proc/self/smaps_rollup"))
+    }
+
+    /// Read a `smaps_rollup` file.
+    fn read_from(path: &Path) -> Result<SmapsRollup> {
+        let rollup = cgroup::read_keyed_file(path)?;
+        // The kernel reports these in kB.
+        let get = |key: &str| rollup.get(key).map(|&kb| kb * 1024);
+        let require = |key: &str| {
+            get(key).ok_or_else(|| Error::from(ErrorKind::Parse(path.into())))
+        };
+        Ok(SmapsRollup {
+            rss: require("Rss:")?,
+            pss: require("Pss:")?,
+            uss: require("Private_Clean:")? + require("Private_Dirty:")?,
+            shared: require("Shared_Clean:")? + require("Shared_Dirty:")?,
+            swap: require("Swap:")?,
+            swap_pss: get("SwapPss:"),
+        })
+    }
+}
+
+/// The resources of a single process, identified by PID.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use resource_monitor::ProcessResources;
+///
+/// let child = Command::new("sleep").arg("10").spawn().unwrap();
+/// let process = ProcessResources::for_pid(child.id()).unwrap();
+/// println!("child RSS: {} bytes", process.rss().unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProcessResources {
+    pid: u32,
+    dir: PathBuf,
+    cgroup: Option<CgroupResources>,
+}
+
+impl ProcessResources {
+    /// Look up process `pid`, and find its cgroup.  This fails if the
+    /// process doesn't exist.
+    pub fn for_pid(pid: u32) -> Result<ProcessResources> {
+        let mut process = ProcessResources::without_cgroup(pid);
+        if !process.dir.is_dir() {
+            return Err(ErrorKind::NotFound(process.dir).into());
+        }
+        process.cgroup = cgroup::dir_of_pid(pid)
+            .and_then(CgroupResources::at)
+            .ok();
+        Ok(process)
+    }
+
+    /// Look up process `pid` without checking that it exists, or finding
+    /// its cgroup.
+    fn without_cgroup(pid: u32) -> ProcessResources {
+        ProcessResources {
+            pid,
+            dir: PathBuf::from(format!("/proc/{}", pid)),
+            cgroup: None,
+        }
+    }
+
+    /// The PID of this process.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The cgroup this process belonged to when we looked it up, if we
+    /// could find it.  Use this to read the limits it's subject to.
+    pub fn cgroup(&self) -> Option<&CgroupResources> {
+        self.cgroup.as_ref()
+    }
+
+    /// Resident set size of this process, in bytes.
+    pub fn rss(&self) -> Result<u64> {
+        self.status_value("VmRSS:").map(|kb| kb * 1024)
+    }
+
+    /// The largest resident set size of this process so far, in bytes.
+    pub fn peak_rss(&self) -> Result<u64> {
+        self.status_value("VmHWM:").map(|kb| kb * 1024)
+    }
+
+    /// The memory fields of this process's `status` file.
+    pub fn status(&self) -> Result<ProcessStatus> {
+        ProcessStatus::read_from(&self.dir.join("status"))
+    }
+
+    /// Number of threads in this process.
+    pub fn threads(&self) -> Result<u64> {
+        self.status_value("Threads:")
+    }
+
+    /// Number of file descriptors this process has open.  Reading
+    /// another user's processes requires privileges.
+    pub fn fds(&self) -> Result<u64> {
+        let path = self.dir.join("fd");
+        let entries = fs::read_dir(&path)
+            .chain_err(|| ErrorKind::File(path.clone()))?;
+        Ok(entries.count() as u64)
+    }
+
+    /// Proportional set size of this process, in bytes: its resident
+    /// memory, with each shared page divided between the processes that
+    /// share it.  Unlike RSS, this can be summed across processes.  This
+    /// requires Linux 4.14 or later.
+    pub fn pss(&self) -> Result<u64> {
+        let path = self.dir.join("smaps_rollup");
+        let rollup = cgroup::read_keyed_file(&path)?;
+        match rollup.get("Pss:") {
+            Some(&kb) => Ok(kb * 1024),
+            None => Err(ErrorKind::Parse(path).into()),
+        }
+    }
+
+    /// Read this process's smaps rollup, which includes its PSS and USS.
+    /// See `SmapsRollup` for why this is slow.
+    pub fn smaps_rollup(&self) -> Result<SmapsRollup> {
+        SmapsRollup::read_from(&self.dir.join("smaps_rollup"))
+    }
+
+    /// The PID of this process's parent.
+    pub fn parent_pid(&self) -> Result<u32> {
+        let fields = self.stat_fields()?;
+        let mkerr = || ErrorKind::File(self.dir.join("stat"));
+        fields[1].parse().chain_err(mkerr)
+    }
+
+    /// User and system CPU time consumed by this process so far.
+    pub fn cpu_time(&self) -> Result<Duration> {
+        // `utime` and `stime` are the 14th and 15th fields.
+        let fields = self.stat_fields()?;
+        let mkerr = || ErrorKind::File(self.dir.join("stat"));
+        let utime: u64 = fields[11].parse().chain_err(mkerr)?;
+        let stime: u64 = fields[12].parse().chain_err(mkerr)?;
+        Ok(cpu::ticks_to_duration(utime + stime))
+    }
+
+    /// How likely the OOM killer is to choose this process.  See
+    /// `oom_score`.
+    pub fn oom_score(&self) -> Result<u32> {
+        oom::read_oom_score(&self.dir)
+    }
+
+    /// This process's adjustment to its `oom_score`.
+    pub fn oom_score_adj(&self) -> Result<i32> {
+        oom::read_oom_score_adj(&self.dir)
+    }
+
+    /// Change this process's adjustment to its `oom_score`, such as to
+    /// make an expendable worker the OOM killer's first choice.  Changing
+    /// another user's process, or lowering the adjustment, requires
+    /// `CAP_SYS_RESOURCE`.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use resource_monitor::{ProcessResources, OOM_SCORE_ADJ_MAX};
+    ///
+    /// let worker = Command::new("sleep").arg("10").spawn().unwrap();
+    /// ProcessResources::for_pid(worker.id())
+    ///     .and_then(|p| p.set_oom_score_adj(OOM_SCORE_ADJ_MAX))
+    ///     .unwrap();
+    /// ```
+    pub fn set_oom_score_adj(&self, adj: i32) -> Result<()> {
+        oom::write_oom_score_adj(&self.dir, adj)
+    }
+
+    /// CPU time used by each of this process's threads, busiest first.
+    pub fn thread_cpu_times(&self) -> Result<Vec<ThreadCpuTime>> {
+        cpu::thread_cpu_times_in(&self.dir)
+    }
+
+    /// Read all of this process's usage at once.
+    pub fn usage(&self) -> Result<ProcessUsage> {
+        Ok(ProcessUsage {
+            rss: self.rss()?,
+            fds: self.fds()?,
+            threads: self.threads()?,
+            cpu_time: self.cpu_time()?,
+        })
+    }
+
+    /// Read the fields of `/proc/<pid>/stat` which follow the command
+    /// name, so that `fields[0]` is the 3rd field.
+    fn stat_fields(&self) -> Result<Vec<String>> {
+        let path = self.dir.join("stat");
+        let mkerr = || ErrorKind::File(path.clone());
+        let stat = fs::read_to_string(&path).chain_err(mkerr)?;
+        // The command name is in parentheses, and may contain spaces, so
+        // we start after the last `)`.
+        let rest = stat.rfind(')')
+            .map(|i| &stat[i + 1..])
+            .ok_or_else(|| ErrorKind::Parse(path.clone()))?;
+        let fields: Vec<String> =
+            rest.split_whitespace().map(|f| f.to_owned()).collect();
+        if fields.len() < 13 {
+            return Err(ErrorKind::Parse(path).into());
+        }
+        Ok(fields)
+    }
+
+    /// Read a field of `/proc/<pid>/status`.
+    fn status_value(&self, key: &str) -> Result<u64> {
+        let path = self.dir.join("status");
+        let status = cgroup::read_keyed_file(&path)?;
+        match status.get(key) {
+            Some(&value) => Ok(value),
+            None => Err(ErrorKind::Parse(path).into()),
+        }
+    }
+}
+
+/// Combined resource usage of a process and its descendants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessTreeUsage {
+    /// Number of processes counted.
+    pub processes: u64,
+    /// Total resident set size, in bytes.  Pages shared between
+    /// processes are counted once for each of them, so this overestimates
+    /// memory usage, especially after `fork`.
+    pub rss: u64,
+    /// Total proportional set size, in bytes, which counts shared pages
+    /// only once.  This is `None` if we couldn't read it for every
+    /// process.
+    pub pss: Option<u64>,
+    /// Total number of open file descriptors.
+    pub fds: u64,
+    /// Total number of threads.
+    pub threads: u64,
+    /// Total CPU time consumed by the processes which are still running.
+    /// This doesn't include children which have already exited.
+    pub cpu_time: Duration,
+}
+
+/// A process and all of its descendants, such as a server and the worker
+/// processes it forks.  Our cgroup is charged for all of them, so
+/// per-process numbers undercount our usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessTree {
+    root: u32,
+}
+
+impl ProcessTree {
+    /// The tree rooted at process `root`.
+    pub fn new(root: u32) -> ProcessTree {
+        ProcessTree { root }
+    }
+
+    /// The tree rooted at the current process.
+    pub fn current() -> ProcessTree {
+        ProcessTree::new(::std::process::id())
+    }
+
+    /// The PIDs of the root process and its current descendants, found by
+    /// scanning `/proc`.
+    pub fn pids(&self) -> Result<Vec<u32>> {
+        let root = ProcessResources::without_cgroup(self.root);
+        if !root.dir.is_dir() {
+            return Err(ErrorKind::NotFound(root.dir).into());
+        }
+        let proc_dir = PathBuf::from("/proc");
+        let entries = fs::read_dir(&proc_dir)
+            .chain_err(|| ErrorKind::File(proc_dir.clone()))?;
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        for entry in entries.filter_map(|e| e.ok()) {
+            let pid = match entry.file_name().to_str().map(str::parse) {
+                Some(Ok(pid)) => pid,
+                _ => continue,
+            };
+            // Processes may exit while we're looking at them.
+            let process = ProcessResources::without_cgroup(pid);
+            if let Ok(ppid) = process.parent_pid() {
+                children.entry(ppid).or_default().push(pid);
+            }
+        }
+        let mut pids = vec![self.root];
+        let mut i = 0;
+        while i < pids.len() {
+            if let Some(c) = children.get(&pids[i]) {
+                pids.extend(c);
+            }
+            i += 1;
+        }
+        Ok(pids)
+    }
+
+    /// Add up the usage of every process in the tree.  Descendants which
+    /// exit while we're reading them are skipped.
+    pub fn usage(&self) -> Result<ProcessTreeUsage> {
+        let mut total = ProcessTreeUsage {
+            pss: Some(0),
+            ..ProcessTreeUsage::default()
+        };
+        for pid in self.pids()? {
+            let process = ProcessResources::without_cgroup(pid);
+            let usage = match process.usage() {
+                Ok(usage) => usage,
+                Err(_) if pid != self.root => continue,
+                Err(err) => return Err(err),
+            };
+            total.processes += 1;
+            total.rss += usage.rss;
+            total.fds += usage.fds;
+            total.threads += usage.threads;
+            total.cpu_time += usage.cpu_time;
+            total.pss = match (total.pss, process.pss()) {
+                (Some(sum), Ok(pss)) => Some(sum + pss),
+                _ => None,
+            };
+        }
+        Ok(total)
+    }
+}
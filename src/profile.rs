@@ -0,0 +1,90 @@
+//! Sampling profiles, which let a `Monitor` read cheap values often and
+//! expensive ones rarely.
+
+use std::time::Duration;
+
+#[cfg(feature = "jemalloc")]
+use allocator_stats::{ArenaStats, JemallocStats};
+use memory_stat::MemoryStat;
+use process::SmapsRollup;
+
+/// How often a `Monitor` takes a particular kind of sample.
+///
+/// Every sample reads the limit, usage and availability of the monitored
+/// resources.  A profile with `details` set also reads `SampleDetails`,
+/// which is much more expensive: reading `smaps_rollup` walks every page
+/// table in the process.  Samples from every profile go into the same
+/// history, and are sent to the same observers and subscribers.
+///
+/// ```
+/// use std::time::Duration;
+/// use resource_monitor::{Monitor, SamplingProfile};
+///
+/// let monitor = Monitor::new(&[], Duration::from_secs(1)).unwrap();
+/// monitor.add_profile(SamplingProfile::detailed(Duration::from_secs(60)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingProfile {
+    /// A name for this profile.  A `Monitor` has at most one profile with
+    /// each name.
+    pub name: String,
+    /// How often to take samples.
+    pub interval: Duration,
+    /// Read `SampleDetails` as well?
+    pub details: bool,
+}
+
+impl SamplingProfile {
+    /// The name of the profile created by `cheap`.
+    pub const CHEAP: &'static str = "cheap";
+
+    /// The name of the profile created by `detailed`.
+    pub const DETAILED: &'static str = "detailed";
+
+    /// A profile which reads only limits and usage, every `interval`.
+    /// Every `Monitor` starts with one of these, at the interval passed to
+    /// `Monitor::new`.
+    pub fn cheap(interval: Duration) -> SamplingProfile {
+        SamplingProfile {
+            name: SamplingProfile::CHEAP.to_owned(),
+            interval,
+            details: false,
+        }
+    }
+
+    /// A profile which also reads `SampleDetails`, every `interval`.
+    pub fn detailed(interval: Duration) -> SamplingProfile {
+        SamplingProfile {
+            name: SamplingProfile::DETAILED.to_owned(),
+            interval,
+            details: true,
+        }
+    }
+}
+
+/// Expensive values read by a detailed `SamplingProfile`.  Values we
+/// couldn't read are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SampleDetails {
+    /// Our cgroup's `memory.stat`.
+    pub memory_stat: Option<MemoryStat>,
+    /// Our process's `smaps_rollup`.
+    pub smaps_rollup: Option<SmapsRollup>,
+    /// Statistics for each jemalloc arena.  This requires the `jemalloc`
+    /// feature.
+    #[cfg(feature = "jemalloc")]
+    pub arenas: Option<Vec<ArenaStats>>,
+}
+
+impl SampleDetails {
+    /// Read everything we can.
+    pub fn capture() -> SampleDetails {
+        SampleDetails {
+            memory_stat: MemoryStat::read().ok(),
+            smaps_rollup: SmapsRollup::read().ok(),
+            #[cfg(feature = "jemalloc")]
+            arenas: JemallocStats::snapshot().ok().map(|s| s.arenas),
+        }
+    }
+}
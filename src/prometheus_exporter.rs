@@ -0,0 +1,133 @@
+//! Exporting resource readings to Prometheus, either by registering a
+//! collector with an existing registry, or by rendering the text
+//! exposition format directly.
+//!
+//! Each resource is exported as up to three gauges, named
+//! `resource_<name>_limit_bytes`, `resource_<name>_used_bytes` and
+//! `resource_<name>_available_bytes`, where `<name>` is `memory`,
+//! `allocator`, `os_memory` and so on.  The `scoped_memory` gauges have a
+//! `scope` label, as do the `huge_pages` gauges, for the page size.  For
+//! the allocator, `used` is the number of active bytes reported by the
+//! allocator.  `Resource::CpuTime` is exported as
+//! `resource_cpu_time_used_nanoseconds`.
+
+use std::collections::BTreeMap;
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, GaugeVec, Opts, Registry, TextEncoder};
+
+use errors::*;
+use snapshot::Snapshot;
+use Resource;
+
+/// The values we export for each resource.
+const VALUES: &[&str] = &["limit", "used", "available"];
+
+/// The full Prometheus name of a gauge.
+fn gauge_name(resource: &Resource, value: &str) -> String {
+    let (kind, _) = resource.metric_name();
+    format!("resource_{}_{}_{}", kind, value, resource.metric_unit())
+}
+
+/// A Prometheus collector which reads its resources every time it's
+/// scraped.
+///
+/// ```
+/// # extern crate prometheus;
+/// # extern crate resource_monitor;
+/// use resource_monitor::{Resource, ResourceCollector};
+///
+/// # fn main() {
+/// let registry = prometheus::Registry::new();
+/// let collector = ResourceCollector::new(&[Resource::Memory]).unwrap();
+/// registry.register(Box::new(collector)).unwrap();
+/// # }
+/// ```
+///
+/// Values which can't be read, or which don't apply to a resource, are
+/// omitted from that scrape.
+pub struct ResourceCollector {
+    resources: Vec<Resource>,
+    gauges: BTreeMap<String, GaugeVec>,
+    descs: Vec<Desc>,
+}
+
+impl ResourceCollector {
+    /// Create a collector for `resources`.
+    pub fn new(resources: &[Resource]) -> Result<ResourceCollector> {
+        let mut gauges = BTreeMap::new();
+        for resource in resources {
+            let (kind, scope) = resource.metric_name();
+            let labels: &[&str] =
+                if scope.is_some() { &["scope"] } else { &[] };
+            for value in VALUES {
+                let name = gauge_name(resource, value);
+                if gauges.contains_key(&name) {
+                    continue;
+                }
+                let help = format!("The {} value of the {} resource, in {}.",
+                                   value,
+                                   kind,
+                                   resource.metric_unit());
+                let opts = Opts::new(name.clone(), help);
+                let gauge = GaugeVec::new(opts, labels)
+                    .chain_err(|| "could not create Prometheus gauge")?;
+                gauges.insert(name, gauge);
+            }
+        }
+        let descs = gauges.values()
+            .flat_map(|g| g.desc().into_iter().cloned())
+            .collect();
+        Ok(ResourceCollector {
+            resources: resources.to_owned(),
+            gauges,
+            descs,
+        })
+    }
+
+    /// Set our gauges from `snapshot`, clearing any values it doesn't
+    /// have.
+    fn update(&self, snapshot: &Snapshot) {
+        for gauge in self.gauges.values() {
+            gauge.reset();
+        }
+        for reading in &snapshot.readings {
+            let (_, scope) = reading.resource.metric_name();
+            let labels: Vec<&str> = scope.as_deref().into_iter().collect();
+            let values = [reading.limit, reading.used, reading.available];
+            for (value, v) in VALUES.iter().zip(values.iter()) {
+                if let (Some(gauge), Some(v)) =
+                    (self.gauges.get(&gauge_name(&reading.resource, value)),
+                     *v) {
+                    gauge.with_label_values(&labels).set(v as f64);
+                }
+            }
+        }
+    }
+}
+
+impl Collector for ResourceCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.update(&Snapshot::capture(&self.resources));
+        self.gauges.values().flat_map(|g| g.collect()).collect()
+    }
+}
+
+/// Read `resources` and render them in the Prometheus text exposition
+/// format, for serving from a `/metrics` endpoint.
+pub fn render_prometheus_text(resources: &[Resource]) -> Result<String> {
+    let registry = Registry::new();
+    registry.register(Box::new(ResourceCollector::new(resources)?))
+        .chain_err(|| "could not register Prometheus collector")?;
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buffer)
+        .chain_err(|| "could not encode Prometheus metrics")?;
+    String::from_utf8(buffer)
+        .chain_err(|| "Prometheus metrics were not valid UTF-8")
+}
@@ -0,0 +1,65 @@
+//! Memory which the kernel protects from reclaim, under cgroup v2.
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use read_file_u64;
+use Resource;
+
+/// How much of our cgroup's memory is protected from reclaim, using the
+/// cgroup v2 `memory.min` and `memory.low` settings, in bytes.
+///
+/// When the machine is short of memory, the kernel reclaims memory from
+/// cgroups which are above their protection first, so memory we use
+/// beyond the protected amount, such as a cache, is the first to go when
+/// a noisy neighbor needs room.  Protection is also limited by that of
+/// our parent cgroups, so the kernel may protect less than this.
+///
+/// ```no_run
+/// use resource_monitor::MemoryProtection;
+///
+/// let protection = MemoryProtection::read().unwrap();
+/// println!("{} bytes protected, {} bytes at risk",
+///          protection.protected(),
+///          protection.at_risk());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryProtection {
+    /// Memory which is never reclaimed (`memory.min`).
+    pub min: u64,
+    /// Memory which is only reclaimed if there's nothing unprotected
+    /// left to reclaim (`memory.low`).
+    pub low: u64,
+    /// Our cgroup's memory usage (`memory.current`).
+    pub usage: u64,
+}
+
+impl MemoryProtection {
+    /// Read our cgroup's protection settings and usage.  This returns
+    /// `ErrorKind::Unsupported` under cgroup v1, which has no equivalent.
+    pub fn read() -> Result<MemoryProtection> {
+        if cgroup::version() == CgroupVersion::V1 {
+            return Err(ErrorKind::Unsupported(Resource::Memory).into());
+        }
+        Ok(MemoryProtection {
+            min: cgroup::read_limit(&cgroup::memory_file("", "memory.min"))?,
+            low: cgroup::read_limit(&cgroup::memory_file("", "memory.low"))?,
+            usage: read_file_u64(&cgroup::memory_file("", "memory.current"))?,
+        })
+    }
+
+    /// How much of our usage is protected by either setting.
+    pub fn protected(&self) -> u64 {
+        self.usage.min(self.min.max(self.low))
+    }
+
+    /// How much of our usage the kernel will never reclaim.
+    pub fn hard_protected(&self) -> u64 {
+        self.usage.min(self.min)
+    }
+
+    /// How much of our usage is unprotected, and is the first to be
+    /// reclaimed when the machine is short of memory.
+    pub fn at_risk(&self) -> u64 {
+        self.usage - self.protected()
+    }
+}
@@ -0,0 +1,196 @@
+//! Replacing where readings come from, so that code built on this crate
+//! can be tested without real cgroups.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use android;
+use errors::*;
+use read_cache::ReadCache;
+use simulation;
+use Resource;
+
+/// A source of resource readings.  Install one with
+/// `set_resource_provider` to replace the values returned by
+/// `Resource::limit`, `used` and `available`, and everything built on
+/// them, such as `Snapshot` and `Monitor`.
+pub trait ResourceProvider: Send + Sync {
+    /// What is the maximum amount of `resource` this process may consume?
+    fn limit(&self, resource: &Resource) -> Result<u64>;
+
+    /// What is the current amount of `resource` consumed by this process?
+    fn used(&self, resource: &Resource) -> Result<u64>;
+
+    /// How much of `resource` is available but not yet used?  By
+    /// default, this is `limit - used`.
+    fn available(&self, resource: &Resource) -> Result<u64> {
+        Ok(self.limit(resource)?.saturating_sub(self.used(resource)?))
+    }
+}
+
+/// Reads real values from the system.  This is what we use when no other
+/// provider is installed, and custom providers can use it for resources
+/// they don't want to replace.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemProvider;
+
+impl ResourceProvider for SystemProvider {
+    fn limit(&self, resource: &Resource) -> Result<u64> {
+        resource.system_limit_with(&mut ReadCache::new())
+    }
+
+    fn used(&self, resource: &Resource) -> Result<u64> {
+        resource.system_used_with(&mut ReadCache::new())
+    }
+
+    fn available(&self, resource: &Resource) -> Result<u64> {
+        resource.system_available_with(&mut ReadCache::new())
+    }
+}
+
+/// The provider installed by `set_resource_provider`, if any.
+static PROVIDER: RwLock<Option<Arc<dyn ResourceProvider>>> =
+    RwLock::new(None);
+
+/// Read all resource values from `provider` instead of from the system.
+/// This affects the whole process, so tests which install different
+/// providers shouldn't run in parallel.
+pub fn set_resource_provider(provider: Arc<dyn ResourceProvider>) {
+    let mut current = PROVIDER.write().unwrap_or_else(|e| e.into_inner());
+    *current = Some(provider);
+}
+
+/// Go back to reading resource values from the system, or from the
+/// `SimulatedProvider` configured by our environment variables.  On
+/// Android, this goes back to the `AndroidProvider`.
+pub fn clear_resource_provider() {
+    let mut current = PROVIDER.write().unwrap_or_else(|e| e.into_inner());
+    *current = None;
+}
+
+/// The installed provider, if any, or else the `SimulatedProvider`
+/// configured by our environment variables, if any, or else the
+/// `AndroidProvider` on Android.
+pub fn current() -> Option<Arc<dyn ResourceProvider>> {
+    let current = PROVIDER.read().unwrap_or_else(|e| e.into_inner());
+    current.clone()
+        .or_else(simulation::from_env)
+        .or_else(android::default_provider)
+}
+
+/// Values set for one resource in a `MockProvider`.
+#[derive(Debug, Clone, Default)]
+struct MockValues {
+    limit: Option<u64>,
+    used: Option<u64>,
+    available: Option<u64>,
+}
+
+/// A `ResourceProvider` which returns whatever values you give it, for
+/// use in tests.  Resources which have no values set are `Unsupported`.
+///
+/// ```
+/// use std::sync::Arc;
+/// use resource_monitor::{clear_resource_provider, set_resource_provider,
+///                        MockProvider, Resource};
+///
+/// let mock = Arc::new(MockProvider::new());
+/// mock.set_limit(Resource::Memory, 512 << 20);
+/// mock.set_used(Resource::Memory, 500 << 20);
+/// set_resource_provider(mock.clone());
+/// assert_eq!(Resource::Memory.available().unwrap(), 12 << 20);
+///
+/// // Simulate freeing some memory.
+/// mock.set_used(Resource::Memory, 100 << 20);
+/// assert_eq!(Resource::Memory.available().unwrap(), 412 << 20);
+/// clear_resource_provider();
+/// ```
+#[derive(Debug, Default)]
+pub struct MockProvider {
+    values: Mutex<Vec<(Resource, MockValues)>>,
+}
+
+impl MockProvider {
+    /// Create a provider with no values set.
+    pub fn new() -> MockProvider {
+        MockProvider::default()
+    }
+
+    /// Change the values for `resource`.
+    fn update<F>(&self, resource: Resource, f: F)
+        where F: FnOnce(&mut MockValues)
+    {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        match values.iter().position(|v| v.0 == resource) {
+            Some(i) => f(&mut values[i].1),
+            None => {
+                let mut v = MockValues::default();
+                f(&mut v);
+                values.push((resource, v));
+            }
+        }
+    }
+
+    /// Look up one of the values for `resource`.
+    fn get<F>(&self,
+              resource: &Resource,
+              wanted: &'static str,
+              f: F)
+              -> Result<u64>
+        where F: FnOnce(&MockValues) -> Option<u64>
+    {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        match values.iter().find(|v| v.0 == *resource) {
+            Some(v) => {
+                f(&v.1).ok_or_else(|| {
+                    ErrorKind::NotApplicable(wanted, resource.clone()).into()
+                })
+            }
+            None => Err(ErrorKind::Unsupported(resource.clone()).into()),
+        }
+    }
+
+    /// Set the limit of `resource`.
+    pub fn set_limit(&self, resource: Resource, limit: u64) {
+        self.update(resource, |v| v.limit = Some(limit));
+    }
+
+    /// Set the usage of `resource`.
+    pub fn set_used(&self, resource: Resource, used: u64) {
+        self.update(resource, |v| v.used = Some(used));
+    }
+
+    /// Set the available amount of `resource`.  If this isn't set, we
+    /// report `limit - used`.
+    pub fn set_available(&self, resource: Resource, available: u64) {
+        self.update(resource, |v| v.available = Some(available));
+    }
+
+    /// Forget all the values set for `resource`.
+    pub fn remove(&self, resource: &Resource) {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.retain(|v| v.0 != *resource);
+    }
+}
+
+impl ResourceProvider for MockProvider {
+    fn limit(&self, resource: &Resource) -> Result<u64> {
+        self.get(resource, "limit", |v| v.limit)
+    }
+
+    fn used(&self, resource: &Resource) -> Result<u64> {
+        self.get(resource, "used", |v| v.used)
+    }
+
+    fn available(&self, resource: &Resource) -> Result<u64> {
+        self.get(resource, "available", |v| {
+            v.available.or_else(|| {
+                match (v.limit, v.used) {
+                    (Some(limit), Some(used)) => {
+                        Some(limit.saturating_sub(used))
+                    }
+                    _ => None,
+                }
+            })
+        })
+    }
+}
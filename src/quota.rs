@@ -0,0 +1,249 @@
+//! Disk quotas, which in shared storage often limit us long before the
+//! filesystem itself fills up.  We only read quotas on Linux, because
+//! other systems' `quotactl` takes different arguments and structures.
+
+#[cfg(target_os = "linux")]
+use libc::{self, c_char, c_int, c_ulong};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+
+use errors::*;
+#[cfg(target_os = "linux")]
+use mountinfo::{self, Mount};
+use Resource;
+
+/// `quotactl` subcommand to read a quota.
+#[cfg(target_os = "linux")]
+const Q_GETQUOTA: c_int = 0x800007;
+/// Quota types, for `quotactl`.
+#[cfg(target_os = "linux")]
+const USRQUOTA: c_int = 0;
+#[cfg(target_os = "linux")]
+const PRJQUOTA: c_int = 2;
+/// `ioctl` to read a file's extended attributes, including its project.
+#[cfg(target_os = "linux")]
+const FS_IOC_FSGETXATTR: c_ulong = 0x801c581f;
+/// The units of block limits in `if_dqblk`.
+const QIF_DQBLKSIZE: u64 = 1024;
+
+/// The kernel's `struct if_dqblk`.
+#[repr(C)]
+#[derive(Default)]
+struct IfDqblk {
+    dqb_bhardlimit: u64,
+    dqb_bsoftlimit: u64,
+    dqb_curspace: u64,
+    dqb_ihardlimit: u64,
+    dqb_isoftlimit: u64,
+    dqb_curinodes: u64,
+    dqb_btime: u64,
+    dqb_itime: u64,
+    dqb_valid: u32,
+}
+
+/// The kernel's `struct fsxattr`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct FsXattr {
+    fsx_xflags: u32,
+    fsx_extsize: u32,
+    fsx_nextents: u32,
+    fsx_projid: u32,
+    fsx_cowextsize: u32,
+    fsx_pad: [u8; 8],
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn quotactl(cmd: c_int,
+                special: *const c_char,
+                id: c_int,
+                addr: *mut c_char)
+                -> c_int;
+}
+
+/// Which kind of quota applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuotaKind {
+    /// A quota on the files owned by a user.
+    User,
+    /// A quota on a directory tree, as used by XFS and ext4 project
+    /// quotas, and by container runtimes to limit volumes.
+    Project,
+}
+
+/// A disk quota, in bytes and inodes.  Limits of zero mean there's no
+/// limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskQuota {
+    /// Which kind of quota this is.
+    pub kind: QuotaKind,
+    /// The user or project ID the quota belongs to.
+    pub id: u32,
+    /// The hard limit on space, which can't be exceeded.
+    pub space_limit: u64,
+    /// The soft limit on space, which can be exceeded for a grace period.
+    pub space_soft_limit: u64,
+    /// Space in use.
+    pub space_used: u64,
+    /// The hard limit on the number of files.
+    pub inode_limit: u64,
+    /// Number of files in use.
+    pub inodes_used: u64,
+}
+
+impl DiskQuota {
+    /// Read the quota which applies to `path`: its project quota if it
+    /// belongs to a project, or otherwise our user's quota.  Returns
+    /// `ErrorKind::Unsupported` if quotas aren't enabled on the
+    /// filesystem.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Result<DiskQuota> {
+        let path = path.as_ref();
+        match project_id(path)? {
+            0 => DiskQuota::read(path, QuotaKind::User, current_uid()),
+            project => DiskQuota::read(path, QuotaKind::Project, project),
+        }
+    }
+
+    /// Read the `kind` quota of `id` on the filesystem containing `path`.
+    pub fn read<P: AsRef<Path>>(path: P,
+                                kind: QuotaKind,
+                                id: u32)
+                                -> Result<DiskQuota> {
+        let path = path.as_ref();
+        let dqblk = get_quota(path, kind, id)?;
+        Ok(DiskQuota {
+            kind,
+            id,
+            space_limit: dqblk.dqb_bhardlimit * QIF_DQBLKSIZE,
+            space_soft_limit: dqblk.dqb_bsoftlimit * QIF_DQBLKSIZE,
+            space_used: dqblk.dqb_curspace,
+            inode_limit: dqblk.dqb_ihardlimit,
+            inodes_used: dqblk.dqb_curinodes,
+        })
+    }
+
+    /// The space limit, with "no limit" reported as `u64::MAX`.
+    pub fn limit(&self) -> u64 {
+        match self.space_limit {
+            0 => u64::MAX,
+            limit => limit,
+        }
+    }
+
+    /// Space we may still use before hitting the hard limit.
+    pub fn available(&self) -> u64 {
+        self.limit().saturating_sub(self.space_used)
+    }
+
+    /// Files we may still create, or `u64::MAX` if there's no limit.
+    pub fn inodes_available(&self) -> u64 {
+        match self.inode_limit {
+            0 => u64::MAX,
+            limit => limit.saturating_sub(self.inodes_used),
+        }
+    }
+}
+
+/// Call `quotactl` to read the `kind` quota of `id` on the filesystem
+/// containing `path`.
+#[cfg(target_os = "linux")]
+fn get_quota(path: &Path, kind: QuotaKind, id: u32) -> Result<IfDqblk> {
+    let device = mount_source(path)?;
+    let device = CString::new(device.as_os_str().as_bytes())
+        .chain_err(|| ErrorKind::File(path.to_owned()))?;
+    let quota_type = match kind {
+        QuotaKind::User => USRQUOTA,
+        QuotaKind::Project => PRJQUOTA,
+    };
+    let mut dqblk = IfDqblk::default();
+    let result = unsafe {
+        quotactl((Q_GETQUOTA << 8) | quota_type,
+                 device.as_ptr(),
+                 id as c_int,
+                 &mut dqblk as *mut IfDqblk as *mut c_char)
+    };
+    if result != 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            // Quotas are off, or this filesystem doesn't do them.
+            Some(libc::ESRCH) | Some(libc::ENOSYS) | Some(libc::ENOTSUP) |
+            Some(libc::EINVAL) => {
+                let resource = Resource::Quota(path.to_owned());
+                Err(ErrorKind::Unsupported(resource).into())
+            }
+            _ => Err(err).chain_err(|| ErrorKind::File(path.to_owned())),
+        };
+    }
+    Ok(dqblk)
+}
+
+/// Other targets' `quotactl` works differently, so we don't read quotas.
+#[cfg(not(target_os = "linux"))]
+fn get_quota(path: &Path, _kind: QuotaKind, _id: u32) -> Result<IfDqblk> {
+    Err(ErrorKind::Unsupported(Resource::Quota(path.to_owned())).into())
+}
+
+/// Our user ID.
+#[cfg(target_os = "linux")]
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Our user ID, on targets where we don't read quotas.
+#[cfg(not(target_os = "linux"))]
+fn current_uid() -> u32 {
+    0
+}
+
+/// Find the project ID of `path`, which is zero if it doesn't belong to a
+/// project, or if the filesystem doesn't support projects.
+#[cfg(target_os = "linux")]
+fn project_id(path: &Path) -> Result<u32> {
+    let file = fs::File::open(path)
+        .chain_err(|| ErrorKind::File(path.to_owned()))?;
+    let mut attr = FsXattr::default();
+    let result = unsafe {
+        libc::ioctl(file.as_raw_fd(), FS_IOC_FSGETXATTR as _, &mut attr)
+    };
+    Ok(if result == 0 { attr.fsx_projid } else { 0 })
+}
+
+/// Without Linux's `FS_IOC_FSGETXATTR`, nothing belongs to a project.
+#[cfg(not(target_os = "linux"))]
+fn project_id(_path: &Path) -> Result<u32> {
+    Ok(0)
+}
+
+/// Find the device mounted at the mount point containing `path`, using
+/// `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+fn mount_source(path: &Path) -> Result<PathBuf> {
+    let path = fs::canonicalize(path)
+        .chain_err(|| ErrorKind::File(path.to_owned()))?;
+    let mut best: Option<Mount> = None;
+    for mount in mountinfo::read()? {
+        let longer = best.as_ref().is_none_or(|best| {
+            mount.mount_point.as_os_str().len() >=
+            best.mount_point.as_os_str().len()
+        });
+        if path.starts_with(&mount.mount_point) && longer {
+            best = Some(mount);
+        }
+    }
+    let mountinfo = PathBuf::from("/proc/self/mountinfo");
+    best.map(|mount| PathBuf::from(mount.source))
+        .ok_or_else(|| ErrorKind::Parse(mountinfo).into())
+}
@@ -0,0 +1,81 @@
+//! Reading integers from small files, such as cgroup and `/proc` files,
+//! into a buffer on the stack instead of a `String`.
+//!
+//! The rest of this crate uses these functions to read integer files.
+//! This only avoids allocating for the contents of the file: finding the
+//! file, and computing resource values from what we read, still
+//! allocate.  They're public so that you can read files this crate
+//! doesn't know about in the same way.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use resource_monitor::raw;
+//!
+//! let path = Path::new("/sys/fs/cgroup/memory.swap.max");
+//! println!("swap limit: {}", raw::read_limit(path).unwrap());
+//! ```
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str;
+
+use errors::*;
+
+/// The longest file we'll read.  A `u64` has at most 20 digits, so this
+/// leaves plenty of room for whitespace.
+const MAX_LEN: usize = 64;
+
+/// Read a file containing a single integer.
+pub fn read_u64(path: &Path) -> Result<u64> {
+    let mut buf = [0; MAX_LEN];
+    let len = read_small(path, &mut buf)?;
+    match parse_u64(&buf[..len]) {
+        Some(value) => Ok(value),
+        None => Err(ErrorKind::Parse(path.to_owned()).into()),
+    }
+}
+
+/// Read a cgroup limit file, which contains either an integer or `max`.
+/// We return `max` as `u64::MAX`.
+pub fn read_limit(path: &Path) -> Result<u64> {
+    let mut buf = [0; MAX_LEN];
+    let len = read_small(path, &mut buf)?;
+    match parse_limit(&buf[..len]) {
+        Some(value) => Ok(value),
+        None => Err(ErrorKind::Parse(path.to_owned()).into()),
+    }
+}
+
+/// Parse `bytes` as an integer, ignoring surrounding whitespace.
+pub fn parse_u64(bytes: &[u8]) -> Option<u64> {
+    str::from_utf8(bytes).ok()?.trim().parse().ok()
+}
+
+/// Parse `bytes` as an integer or `max`, ignoring surrounding whitespace.
+pub fn parse_limit(bytes: &[u8]) -> Option<u64> {
+    match str::from_utf8(bytes).ok()?.trim() {
+        "max" => Some(u64::MAX),
+        value => value.parse().ok(),
+    }
+}
+
+/// Read all of `path` into `buf`, and return the number of bytes read.
+/// Files which don't fit are a `Parse` error, because they can't contain
+/// a single integer.
+fn read_small(path: &Path, buf: &mut [u8; MAX_LEN]) -> Result<usize> {
+    let mkerr = || ErrorKind::File(path.to_owned());
+    let mut f = fs::File::open(path).chain_err(mkerr)?;
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            return Err(ErrorKind::Parse(path.to_owned()).into());
+        }
+        match f.read(&mut buf[len..]) {
+            Ok(0) => return Ok(len),
+            Ok(n) => len += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err).chain_err(mkerr),
+        }
+    }
+}
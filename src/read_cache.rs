@@ -0,0 +1,251 @@
+//! Caches the low-level values we read while computing resource values,
+//! so that each underlying file is read only once per pass, and the
+//! values we compute from it are consistent with each other.
+
+use std::collections::HashMap;
+
+use allocator_stats;
+use cache_policy::CachedValue;
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use kmem;
+use provider;
+use raw;
+use read_file_u64;
+use reader::OpenFiles;
+use source::{self, ReadSource};
+use Resource;
+
+/// The low-level values we know how to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RawValue {
+    /// Our cgroup's memory limit.
+    CgroupMemoryLimit,
+    /// Our cgroup's memory usage.
+    CgroupMemoryUsage,
+    /// Our cgroup's kernel memory limit.
+    KernelMemoryLimit,
+    /// Kernel memory charged to our cgroup.
+    KernelMemoryUsage,
+    /// Bytes in use according to the heap allocator.
+    AllocatorUsed,
+    /// Bytes reserved by the heap allocator.
+    AllocatorReserved,
+}
+
+/// Memory controller files which `prefetch_cgroup_memory` reads: the
+/// value each one holds, its name under cgroup v1 and v2, and whether it's
+/// a limit, which may be `max`.  Empty names don't exist in that version.
+const MEMORY_FILES: &[(RawValue, &str, &str, bool)] = &[
+    (RawValue::CgroupMemoryLimit, "memory.limit_in_bytes", "memory.max", true),
+    (RawValue::CgroupMemoryUsage,
+     "memory.usage_in_bytes",
+     "memory.current",
+     false),
+    (RawValue::KernelMemoryLimit, "memory.kmem.limit_in_bytes", "", true),
+    (RawValue::KernelMemoryUsage, "memory.kmem.usage_in_bytes", "", false),
+];
+
+/// Raw values read during a single pass.  Errors are not cached, so
+/// asking for a value which failed will try to read it again.
+#[derive(Debug, Default)]
+pub struct ReadCache {
+    values: HashMap<RawValue, u64>,
+    allocator_refreshed: bool,
+    files: Option<OpenFiles>,
+    /// Are we noting where values come from?
+    recording: bool,
+    /// The sources noted since the last `take_sources`.
+    noted: Vec<ReadSource>,
+    /// Did we make any assumptions since the last `take_sources`?
+    fallback: bool,
+}
+
+impl ReadCache {
+    /// Create a new, empty cache.
+    pub fn new() -> ReadCache {
+        ReadCache::default()
+    }
+
+    /// Create a new, empty cache which reads files through `files`, which
+    /// keeps them open between passes.
+    pub fn with_files(files: OpenFiles) -> ReadCache {
+        ReadCache {
+            files: Some(files),
+            ..ReadCache::default()
+        }
+    }
+
+    /// Return the files we read through, so that they can be reused for
+    /// the next pass.
+    pub fn into_files(self) -> OpenFiles {
+        self.files.unwrap_or_default()
+    }
+
+    /// Read all the memory controller files that `resources` need in a
+    /// single pass, before we compute any resource values.  We find our
+    /// cgroup directory only once, instead of once per file, and read the
+    /// files back to back, so that their values are as close together in
+    /// time as we can make them.  Values we can't read here are read again
+    /// on their own when they're asked for, which reports the error.
+    pub fn prefetch_cgroup_memory(&mut self, resources: &[Resource]) {
+        if provider::current().is_some() {
+            return;
+        }
+        let memory = resources.iter()
+            .any(|r| *r == Resource::Memory || *r == Resource::OsMemory);
+        let kernel = resources.contains(&Resource::KernelMemory);
+        if !memory && !kernel {
+            return;
+        }
+        let version = cgroup::version();
+        let mut path = cgroup::memory_dir(version);
+        for &(key, v1, v2, is_limit) in MEMORY_FILES {
+            let wanted = match key {
+                RawValue::KernelMemoryLimit |
+                RawValue::KernelMemoryUsage => kernel,
+                _ => memory,
+            };
+            let name = match version {
+                CgroupVersion::V1 => v1,
+                CgroupVersion::V2 => v2,
+            };
+            if !wanted || name.is_empty() || self.values.contains_key(&key) {
+                continue;
+            }
+            path.push(name);
+            let value = match self.files {
+                Some(ref mut files) if is_limit => files.read_limit(&path),
+                Some(ref mut files) => files.read_u64(&path),
+                None if is_limit => raw::read_limit(&path),
+                None => raw::read_u64(&path),
+            };
+            path.pop();
+            if let Ok(value) = value {
+                self.values.insert(key, value);
+            }
+        }
+        // cgroup v2 only reports kernel memory in `memory.stat`.
+        if kernel && version == CgroupVersion::V2 {
+            path.push("memory.stat");
+            if let Ok(stat) = cgroup::read_keyed_file(&path) {
+                self.values.insert(RawValue::KernelMemoryUsage,
+                                   kmem::v2_kernel_bytes(&stat));
+            }
+        }
+    }
+
+    /// Get `raw`, reading it if we haven't already.
+    pub fn get(&mut self, raw: RawValue) -> Result<u64> {
+        let value = match self.values.get(&raw) {
+            Some(&value) => value,
+            None => {
+                let value = self.read(raw)?;
+                self.values.insert(raw, value);
+                value
+            }
+        };
+        if self.recording {
+            self.note(raw_source(raw));
+        }
+        Ok(value)
+    }
+
+    /// Start noting where the values we read come from, for
+    /// `take_sources`.
+    pub fn record_sources(&mut self) {
+        self.recording = true;
+    }
+
+    /// Note that the value we're computing uses `source`.
+    pub fn note(&mut self, source: ReadSource) {
+        if self.recording && !self.noted.contains(&source) {
+            self.noted.push(source);
+        }
+    }
+
+    /// Note the sources of `value` of `resource`, if it doesn't read
+    /// anything through us.
+    pub fn note_static(&mut self, resource: &Resource, value: CachedValue) {
+        if self.recording && self.noted.is_empty() {
+            self.noted = source::static_sources(resource, value);
+        }
+    }
+
+    /// Note that the value we're computing assumes something we couldn't
+    /// read.
+    pub fn note_fallback(&mut self) {
+        self.fallback = true;
+    }
+
+    /// Return the sources noted since we last asked, and whether we made
+    /// any assumptions.
+    pub fn take_sources(&mut self) -> (Vec<ReadSource>, bool) {
+        let fallback = self.fallback;
+        self.fallback = false;
+        (::std::mem::take(&mut self.noted), fallback)
+    }
+
+    /// Actually read `raw`.
+    fn read(&mut self, raw: RawValue) -> Result<u64> {
+        match raw {
+            RawValue::CgroupMemoryLimit => {
+                let path =
+                    cgroup::memory_file("memory.limit_in_bytes", "memory.max");
+                match self.files {
+                    Some(ref mut files) => files.read_limit(&path),
+                    None => cgroup::read_limit(&path),
+                }
+            }
+            RawValue::CgroupMemoryUsage => {
+                let path = cgroup::memory_file("memory.usage_in_bytes",
+                                               "memory.current");
+                match self.files {
+                    Some(ref mut files) => files.read_u64(&path),
+                    None => read_file_u64(&path),
+                }
+            }
+            RawValue::KernelMemoryLimit => kmem::limit(),
+            RawValue::KernelMemoryUsage => kmem::used(),
+            RawValue::AllocatorUsed => {
+                self.refresh_allocator()?;
+                allocator_stats::used()
+            }
+            RawValue::AllocatorReserved => {
+                self.refresh_allocator()?;
+                allocator_stats::reserved()
+            }
+        }
+    }
+
+    /// Refresh allocator stats once per pass, so that all the values we
+    /// read come from the same epoch.
+    fn refresh_allocator(&mut self) -> Result<()> {
+        if !self.allocator_refreshed {
+            allocator_stats::refresh()?;
+            self.allocator_refreshed = true;
+        }
+        Ok(())
+    }
+}
+
+/// Where we read `raw` from.
+fn raw_source(raw: RawValue) -> ReadSource {
+    let file = match raw {
+        RawValue::CgroupMemoryLimit => {
+            cgroup::memory_file("memory.limit_in_bytes", "memory.max")
+        }
+        RawValue::CgroupMemoryUsage => {
+            cgroup::memory_file("memory.usage_in_bytes", "memory.current")
+        }
+        RawValue::KernelMemoryLimit => {
+            cgroup::memory_file("memory.kmem.limit_in_bytes", "")
+        }
+        RawValue::KernelMemoryUsage => {
+            cgroup::memory_file("memory.kmem.usage_in_bytes", "memory.stat")
+        }
+        RawValue::AllocatorUsed |
+        RawValue::AllocatorReserved => return ReadSource::Allocator,
+    };
+    ReadSource::File(file)
+}
@@ -0,0 +1,156 @@
+//! Sampling resources repeatedly without reopening files every time.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem;
+#[cfg(not(unix))]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use errors::*;
+use raw;
+use read_cache::ReadCache;
+use snapshot::{self, ReadError, Snapshot};
+use Resource;
+
+/// Files we keep open between reads, plus a buffer to read them into.
+#[derive(Debug, Default)]
+pub struct OpenFiles {
+    files: HashMap<PathBuf, fs::File>,
+    buf: Vec<u8>,
+}
+
+impl OpenFiles {
+    /// Read the contents of `path` from the start using `pread`, opening
+    /// it first if we haven't already, and pass them to `parse`.  If the
+    /// read fails, we close the file, so that the next read reopens it.
+    fn read<F>(&mut self, path: &Path, parse: F) -> Result<u64>
+        where F: FnOnce(&[u8]) -> Option<u64>
+    {
+        let mkerr = || ErrorKind::File(path.to_owned());
+        if !self.files.contains_key(path) {
+            let file = fs::File::open(path).chain_err(mkerr)?;
+            self.files.insert(path.to_owned(), file);
+        }
+        let len = match read_all_at(&self.files[path], &mut self.buf) {
+            Ok(len) => len,
+            Err(err) => {
+                self.files.remove(path);
+                return Err(err).chain_err(mkerr);
+            }
+        };
+        match parse(&self.buf[..len]) {
+            Some(value) => Ok(value),
+            None => Err(ErrorKind::Parse(path.to_owned()).into()),
+        }
+    }
+
+    /// Read an integer from `path`.
+    pub fn read_u64(&mut self, path: &Path) -> Result<u64> {
+        self.read(path, raw::parse_u64)
+    }
+
+    /// Read a cgroup limit from `path`, where `max` means `u64::MAX`.
+    pub fn read_limit(&mut self, path: &Path) -> Result<u64> {
+        self.read(path, raw::parse_limit)
+    }
+}
+
+/// Read all of `file` from offset 0 into `buf`, growing it as needed, and
+/// return the number of bytes read.
+fn read_all_at(file: &fs::File, buf: &mut Vec<u8>) -> io::Result<usize> {
+    if buf.len() < 64 {
+        buf.resize(64, 0);
+    }
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            let bigger = buf.len() * 2;
+            buf.resize(bigger, 0);
+        }
+        match read_at(file, &mut buf[len..], len as u64)? {
+            0 => return Ok(len),
+            n => len += n,
+        }
+    }
+}
+
+/// Read from `file` at `offset`, without changing its position.
+#[cfg(unix)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.read_at(buf, offset)
+}
+
+/// Read from `file` at `offset`.  Without `pread`, we have to seek first.
+#[cfg(not(unix))]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// Reads the same resources over and over, keeping the underlying files
+/// open between reads.
+///
+/// `Snapshot::capture` opens, reads and closes each file it needs.  When
+/// sampling many times a second, the `open` and `close` calls are a large
+/// part of the cost, so a `ResourceReader` opens each file once, and then
+/// rereads it from the start with `pread`.  `Monitor` uses one of these
+/// internally.
+///
+/// ```
+/// use resource_monitor::{Resource, ResourceReader};
+///
+/// let mut reader = ResourceReader::new(&[Resource::Memory]);
+/// for _ in 0..10 {
+///     let snapshot = reader.snapshot();
+///     println!("{:?}", snapshot.get(&Resource::Memory));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ResourceReader {
+    resources: Vec<Resource>,
+    files: OpenFiles,
+}
+
+impl ResourceReader {
+    /// Create a reader for `resources`.  Files are opened the first time
+    /// we need them.
+    pub fn new(resources: &[Resource]) -> ResourceReader {
+        ResourceReader {
+            resources: resources.to_owned(),
+            files: OpenFiles::default(),
+        }
+    }
+
+    /// The resources we read.
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    /// Read `resources` from now on.  Files we've already opened stay
+    /// open.
+    pub fn set_resources(&mut self, resources: &[Resource]) {
+        if self.resources != resources {
+            self.resources = resources.to_owned();
+        }
+    }
+
+    /// Read all our resources in a single pass, like `Snapshot::capture`.
+    pub fn snapshot(&mut self) -> Snapshot {
+        self.snapshot_with_errors().0
+    }
+
+    /// Like `snapshot`, but also report any errors, like
+    /// `Snapshot::capture_with_errors`.
+    pub fn snapshot_with_errors(&mut self) -> (Snapshot, Vec<ReadError>) {
+        let files = mem::take(&mut self.files);
+        let mut cache = ReadCache::with_files(files);
+        let result = snapshot::capture_with_cache(&self.resources, &mut cache);
+        self.files = cache.into_files();
+        result
+    }
+}
@@ -0,0 +1,90 @@
+//! A process-wide registry of callbacks which can free memory on demand,
+//! such as caches which can drop entries.  This is a poor man's version
+//! of the JVM's soft references.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A function which tries to free at least the requested number of
+/// bytes, and returns how many bytes it actually freed.
+type ReclaimFn = Arc<dyn Fn(u64) -> u64 + Send + Sync>;
+
+/// Identifies a registered reclaimer, so it can be removed later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReclaimerId(usize);
+
+/// A registered reclaimer.
+struct Reclaimer {
+    id: ReclaimerId,
+    priority: i32,
+    reclaim: ReclaimFn,
+}
+
+/// All registered reclaimers, sorted by priority.
+static RECLAIMERS: Mutex<Vec<Reclaimer>> = Mutex::new(Vec::new());
+
+/// The next ID to hand out.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Register `reclaim`, which will be asked to free memory when we're
+/// under pressure.  It's passed the number of bytes we'd like to free,
+/// and should return the number of bytes it actually freed.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use resource_monitor::register_reclaimer;
+///
+/// let cache: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(vec![]));
+/// let cache2 = cache.clone();
+/// register_reclaimer(10, move |_needed| {
+///     let mut cache = cache2.lock().unwrap();
+///     let freed = cache.iter().map(|v| v.len() as u64).sum();
+///     cache.clear();
+///     freed
+/// });
+/// ```
+///
+/// Reclaimers with lower `priority` values are called first, so register
+/// cheap-to-rebuild caches with low numbers.  Reclaimers may be called
+/// from any thread, including a `Monitor`'s background thread.
+pub fn register_reclaimer<F>(priority: i32, reclaim: F) -> ReclaimerId
+    where F: Fn(u64) -> u64 + Send + Sync + 'static
+{
+    let id = ReclaimerId(NEXT_ID.fetch_add(1, Ordering::SeqCst));
+    let mut reclaimers = RECLAIMERS.lock().unwrap_or_else(|e| e.into_inner());
+    reclaimers.push(Reclaimer {
+        id,
+        priority,
+        reclaim: Arc::new(reclaim),
+    });
+    // A stable sort keeps reclaimers with equal priority in registration
+    // order.
+    reclaimers.sort_by_key(|r| r.priority);
+    id
+}
+
+/// Remove a reclaimer registered with `register_reclaimer`.
+pub fn unregister_reclaimer(id: ReclaimerId) {
+    let mut reclaimers = RECLAIMERS.lock().unwrap_or_else(|e| e.into_inner());
+    reclaimers.retain(|r| r.id != id);
+}
+
+/// Call reclaimers in priority order until they've freed at least
+/// `needed` bytes, and return the total number of bytes freed.
+pub fn reclaim(needed: u64) -> u64 {
+    // Don't hold the lock while calling out, in case a reclaimer wants to
+    // register or unregister something.
+    let reclaimers: Vec<ReclaimFn> = RECLAIMERS.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|r| r.reclaim.clone())
+        .collect();
+    let mut freed = 0u64;
+    for reclaim in reclaimers {
+        if freed >= needed {
+            break;
+        }
+        freed = freed.saturating_add(reclaim(needed - freed));
+    }
+    freed
+}
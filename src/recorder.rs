@@ -0,0 +1,172 @@
+//! Recording snapshots to a file, so that the memory profile of a long
+//! job can be graphed afterwards.
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use errors::*;
+use snapshot::Snapshot;
+
+/// File formats supported by `Recorder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// Comma-separated values, with one row per resource per sample, and
+    /// the columns `timestamp,resource,scope,limit,used,available`.
+    Csv,
+    /// One JSON object per line for each sample, containing a `timestamp`
+    /// and an array of `readings`.
+    JsonLines,
+}
+
+/// Format `time` as fractional seconds since the Unix epoch.
+fn format_timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:03}", since.as_secs(), since.subsec_millis())
+}
+
+/// Format an optional value, using `missing` if it's not there.
+fn format_value(value: Option<u64>, missing: &str) -> String {
+    value.map_or_else(|| missing.to_owned(), |v| v.to_string())
+}
+
+/// Quote `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Format `snapshot` as a single-line JSON object, containing a
+/// `timestamp` and an array of `readings`.
+pub fn snapshot_json(snapshot: &Snapshot) -> String {
+    let readings: Vec<String> = snapshot.readings
+        .iter()
+        .map(|r| {
+            let (kind, scope) = r.resource.metric_name();
+            let scope = scope.map_or_else(String::new, |s| {
+                format!("\"scope\":{},", json_string(&s))
+            });
+            format!("{{\"resource\":\"{}\",{}\"limit\":{},\
+                     \"used\":{},\"available\":{}}}",
+                    kind,
+                    scope,
+                    format_value(r.limit, "null"),
+                    format_value(r.used, "null"),
+                    format_value(r.available, "null"))
+        })
+        .collect();
+    format!("{{\"timestamp\":{},\"readings\":[{}]}}",
+            format_timestamp(snapshot.taken_at),
+            readings.join(","))
+}
+
+/// Quote `s` as a CSV field, if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Appends snapshots to a CSV or JSON Lines file.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use resource_monitor::{Monitor, RecordFormat, Recorder, Resource};
+///
+/// let recorder = Recorder::create("memory.csv", RecordFormat::Csv)
+///     .unwrap();
+/// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(1))
+///     .unwrap();
+/// monitor.record_to(recorder);
+/// ```
+///
+/// We flush after every sample, so a file is still useful if the process
+/// is killed.
+pub struct Recorder<W: Write> {
+    out: W,
+    format: RecordFormat,
+    needs_header: bool,
+}
+
+impl Recorder<BufWriter<fs::File>> {
+    /// Append to the file at `path`, creating it if it doesn't exist.  We
+    /// only write a CSV header if the file is empty.
+    pub fn create<P: AsRef<Path>>(path: P,
+                                  format: RecordFormat)
+                                  -> Result<Recorder<BufWriter<fs::File>>> {
+        let path = path.as_ref();
+        let mkerr = || ErrorKind::File(path.to_owned());
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .chain_err(mkerr)?;
+        let empty = file.metadata().chain_err(mkerr)?.len() == 0;
+        let mut recorder = Recorder::new(BufWriter::new(file), format);
+        recorder.needs_header = empty && format == RecordFormat::Csv;
+        Ok(recorder)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    /// Write records to `out`.  We write a CSV header before the first
+    /// record.
+    pub fn new(out: W, format: RecordFormat) -> Recorder<W> {
+        Recorder {
+            out,
+            format,
+            needs_header: format == RecordFormat::Csv,
+        }
+    }
+
+    /// Append `snapshot` to our output, and flush it.
+    pub fn record(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.write(snapshot)
+            .and_then(|_| self.out.flush())
+            .chain_err(|| "could not write resource recording")
+    }
+
+    /// Write `snapshot` in our format.
+    fn write(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        match self.format {
+            RecordFormat::Csv => {
+                let timestamp = format_timestamp(snapshot.taken_at);
+                if self.needs_header {
+                    writeln!(self.out,
+                             "timestamp,resource,scope,limit,used,available")?;
+                    self.needs_header = false;
+                }
+                for r in &snapshot.readings {
+                    let (kind, scope) = r.resource.metric_name();
+                    writeln!(self.out,
+                             "{},{},{},{},{},{}",
+                             timestamp,
+                             kind,
+                             csv_field(scope.as_deref().unwrap_or("")),
+                             format_value(r.limit, ""),
+                             format_value(r.used, ""),
+                             format_value(r.available, ""))?;
+                }
+            }
+            RecordFormat::JsonLines => {
+                writeln!(self.out, "{}", snapshot_json(snapshot))?;
+            }
+        }
+        Ok(())
+    }
+}
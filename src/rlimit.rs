@@ -0,0 +1,91 @@
+//! Per-process limits from `getrlimit`.
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::mem;
+
+use errors::*;
+#[cfg(not(unix))]
+use Resource;
+
+/// The type `getrlimit` uses for resource names, which differs between C
+/// libraries.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub type RlimitResource = libc::__rlimit_resource_t;
+/// The type `getrlimit` uses for resource names, which differs between C
+/// libraries.
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+pub type RlimitResource = libc::c_int;
+
+/// The limits we read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rlimit {
+    /// `RLIMIT_STACK`, the size of the main thread's stack.
+    Stack,
+    /// `RLIMIT_AS`, the size of our virtual address space.
+    AddressSpace,
+    /// `RLIMIT_MEMLOCK`, how much memory we may lock into RAM.
+    LockedMemory,
+    /// `RLIMIT_NOFILE`, how many file descriptors we may have open.
+    OpenFiles,
+}
+
+impl Rlimit {
+    /// The resource we report as `Unsupported` if we can't read this
+    /// limit.
+    #[cfg(not(unix))]
+    fn resource(self) -> Resource {
+        match self {
+            Rlimit::Stack => Resource::Stack,
+            Rlimit::AddressSpace => Resource::AddressSpace,
+            Rlimit::LockedMemory => Resource::LockedMemory,
+            Rlimit::OpenFiles => Resource::Sockets,
+        }
+    }
+
+    /// The C library's name for this limit.
+    #[cfg(unix)]
+    fn raw(self) -> RlimitResource {
+        match self {
+            Rlimit::Stack => libc::RLIMIT_STACK,
+            Rlimit::AddressSpace => libc::RLIMIT_AS,
+            Rlimit::LockedMemory => libc::RLIMIT_MEMLOCK,
+            Rlimit::OpenFiles => libc::RLIMIT_NOFILE,
+        }
+    }
+}
+
+/// Read the soft and hard limits for `limit`.  `RLIM_INFINITY` is
+/// reported as `u64::MAX`.
+#[cfg(unix)]
+pub fn get(limit: Rlimit) -> Result<(u64, u64)> {
+    let mut rlim: libc::rlimit = unsafe { mem::zeroed() };
+    if unsafe { libc::getrlimit(limit.raw(), &mut rlim) } != 0 {
+        return Err(io::Error::last_os_error())
+            .chain_err(|| "could not call getrlimit");
+    }
+    // `rlim_t` is only 32 bits on some targets.
+    #[allow(clippy::unnecessary_cast)]
+    let value = |v: libc::rlim_t| {
+        if v == libc::RLIM_INFINITY {
+            u64::MAX
+        } else {
+            v as u64
+        }
+    };
+    Ok((value(rlim.rlim_cur), value(rlim.rlim_max)))
+}
+
+/// Without `getrlimit`, we can't read any limits.
+#[cfg(not(unix))]
+pub fn get(limit: Rlimit) -> Result<(u64, u64)> {
+    Err(ErrorKind::Unsupported(limit.resource()).into())
+}
+
+/// Read the soft limit for `limit`, which is the one the kernel enforces.
+pub fn soft_limit(limit: Rlimit) -> Result<u64> {
+    get(limit).map(|(soft, _)| soft)
+}
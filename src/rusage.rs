@@ -0,0 +1,108 @@
+//! Process statistics from `getrusage`.  These are cheap to read and
+//! available everywhere, so they complement the cgroup data nicely.
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::mem;
+use std::time::Duration;
+
+use errors::*;
+#[cfg(not(unix))]
+use Resource;
+
+/// Which processes to report on.
+#[derive(Clone, Copy)]
+enum Who {
+    /// The current process.
+    Current,
+    /// Children of the current process which have been waited for.
+    Children,
+}
+
+/// Resource usage reported by `getrusage`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RusageStats {
+    /// The largest resident set size, in bytes.  For `children`, this is
+    /// the largest RSS of any single child, not their total.
+    pub max_rss: u64,
+    /// Page faults which didn't require any I/O.
+    pub minor_faults: u64,
+    /// Page faults which required reading from disk.  A high rate of
+    /// these usually means we're short of memory.
+    pub major_faults: u64,
+    /// Times we gave up the CPU voluntarily, usually to wait for I/O or a
+    /// lock.
+    pub voluntary_context_switches: u64,
+    /// Times the scheduler took the CPU away from us, which happens a lot
+    /// when we exceed our CPU quota.
+    pub involuntary_context_switches: u64,
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in the kernel.
+    pub system_time: Duration,
+}
+
+impl RusageStats {
+    /// Statistics for the current process, including all its threads.
+    pub fn current() -> Result<RusageStats> {
+        RusageStats::read(Who::Current)
+    }
+
+    /// Statistics for all the children of the current process which have
+    /// exited and been waited for.
+    pub fn children() -> Result<RusageStats> {
+        RusageStats::read(Who::Children)
+    }
+
+    /// Call `getrusage` for `who`.
+    #[cfg(unix)]
+    fn read(who: Who) -> Result<RusageStats> {
+        let who = match who {
+            Who::Current => libc::RUSAGE_SELF,
+            Who::Children => libc::RUSAGE_CHILDREN,
+        };
+        let mut usage: libc::rusage = unsafe { mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+            return Err(io::Error::last_os_error())
+                .chain_err(|| "could not call getrusage");
+        }
+        let count = |n: libc::c_long| n.max(0) as u64;
+        Ok(RusageStats {
+            max_rss: count(usage.ru_maxrss) * MAX_RSS_UNIT,
+            minor_faults: count(usage.ru_minflt),
+            major_faults: count(usage.ru_majflt),
+            voluntary_context_switches: count(usage.ru_nvcsw),
+            involuntary_context_switches: count(usage.ru_nivcsw),
+            user_time: timeval_to_duration(usage.ru_utime),
+            system_time: timeval_to_duration(usage.ru_stime),
+        })
+    }
+
+    /// Without `getrusage`, we have no statistics.
+    #[cfg(not(unix))]
+    fn read(_who: Who) -> Result<RusageStats> {
+        Err(ErrorKind::Unsupported(Resource::CpuTime).into())
+    }
+
+    /// Total CPU time, in both user and kernel mode.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+/// The units of `ru_maxrss`, in bytes.  Apple's systems report bytes.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const MAX_RSS_UNIT: u64 = 1;
+/// Linux and the BSDs report `ru_maxrss` in kilobytes.
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios"))))]
+const MAX_RSS_UNIT: u64 = 1024;
+
+/// Convert a `timeval` to a `Duration`, treating negative values as zero.
+#[cfg(unix)]
+fn timeval_to_duration(tv: libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec.max(0) as u64) +
+    Duration::from_micros(tv.tv_usec.max(0) as u64)
+}
@@ -0,0 +1,167 @@
+//! Context switches and scheduler latency, which tell a latency-sensitive
+//! service whether its slow requests are waiting for a CPU, rather than
+//! doing too much work.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use errors::*;
+use rusage::RusageStats;
+use Resource;
+
+/// How the scheduler has treated this process, as returned by
+/// `SchedulerStats::read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SchedulerStats {
+    /// Times our threads gave up the CPU voluntarily, usually to wait for
+    /// I/O or a lock.
+    pub voluntary_context_switches: u64,
+    /// Times the scheduler took the CPU away from one of our threads,
+    /// because another task needed it or we exceeded our CPU quota.
+    pub involuntary_context_switches: u64,
+    /// The time our threads have spent running, or `None` if the kernel
+    /// doesn't provide `schedstat`.
+    pub run_time: Option<Duration>,
+    /// The time our threads have spent ready to run, but waiting for a
+    /// CPU, or `None` if the kernel doesn't provide `schedstat`.  If this
+    /// grows quickly, CPU contention is adding to our latency.
+    pub run_delay: Option<Duration>,
+}
+
+impl SchedulerStats {
+    /// Read statistics for this process.  Context switches include threads
+    /// which have exited, but `run_time` and `run_delay` only include
+    /// threads which are still running.
+    ///
+    /// ```
+    /// use resource_monitor::SchedulerStats;
+    ///
+    /// let stats = SchedulerStats::read().unwrap();
+    /// if let Some(delay) = stats.run_delay {
+    ///     println!("waited {:?} for a CPU", delay);
+    /// }
+    /// ```
+    pub fn read() -> Result<SchedulerStats> {
+        let usage = RusageStats::current()?;
+        let threads = thread_scheduler_stats().unwrap_or_default();
+        let sum = |get: fn(&ThreadSchedStats) -> Option<Duration>| {
+            let mut total = Duration::from_secs(0);
+            for thread in &threads {
+                total += get(thread)?;
+            }
+            // If we couldn't list our threads, we don't know.
+            if threads.is_empty() {
+                None
+            } else {
+                Some(total)
+            }
+        };
+        Ok(SchedulerStats {
+            voluntary_context_switches: usage.voluntary_context_switches,
+            involuntary_context_switches: usage.involuntary_context_switches,
+            run_time: sum(|t| t.run_time),
+            run_delay: sum(|t| t.run_delay),
+        })
+    }
+
+    /// The fraction of the time our threads wanted a CPU which they spent
+    /// waiting for one, between `earlier` and now.  Returns `None` if
+    /// `schedstat` isn't available, or the threads did nothing.
+    pub fn delay_ratio_since(&self, earlier: &SchedulerStats) -> Option<f64> {
+        let run = self.run_time?.saturating_sub(earlier.run_time?);
+        let delay = self.run_delay?.saturating_sub(earlier.run_delay?);
+        let total = (run + delay).as_secs_f64();
+        if total > 0.0 {
+            Some(delay.as_secs_f64() / total)
+        } else {
+            None
+        }
+    }
+}
+
+/// How the scheduler has treated one thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ThreadSchedStats {
+    /// The kernel's ID for this thread.
+    pub tid: u32,
+    /// The thread's name, truncated to 15 bytes by the kernel.
+    pub name: String,
+    /// Times it gave up the CPU voluntarily.
+    pub voluntary_context_switches: u64,
+    /// Times the scheduler took the CPU away from it.
+    pub involuntary_context_switches: u64,
+    /// The time it has spent running, if the kernel provides `schedstat`.
+    pub run_time: Option<Duration>,
+    /// The time it has spent waiting for a CPU, if the kernel provides
+    /// `schedstat`.
+    pub run_delay: Option<Duration>,
+}
+
+/// Scheduler statistics for each thread of the current process, those
+/// which have waited longest for a CPU first.
+pub fn thread_scheduler_stats() -> Result<Vec<ThreadSchedStats>> {
+    thread_scheduler_stats_in(Path::new("/proc/self"))
+}
+
+/// Scheduler statistics for each thread of the process in `proc_dir`, such
+/// as `/proc/self`, those which have waited longest for a CPU first.
+/// Threads which exit while we're reading them are skipped.
+pub fn thread_scheduler_stats_in(proc_dir: &Path)
+                                 -> Result<Vec<ThreadSchedStats>> {
+    let task_dir = proc_dir.join("task");
+    let entries = fs::read_dir(&task_dir)
+        .chain_err(|| ErrorKind::File(task_dir.clone()))?;
+    let mut threads = vec![];
+    for entry in entries.filter_map(|e| e.ok()) {
+        let tid = match entry.file_name().to_str().map(str::parse) {
+            Some(Ok(tid)) => tid,
+            _ => continue,
+        };
+        let status = match fs::read_to_string(entry.path().join("status")) {
+            Ok(status) => status,
+            Err(_) => continue,
+        };
+        let schedstat = fs::read_to_string(entry.path().join("schedstat"))
+            .ok()
+            .and_then(|s| parse_schedstat(&s));
+        let field = |key: &str| {
+            status.lines()
+                .find(|l| l.starts_with(key))
+                .and_then(|l| l[key.len()..].trim().parse().ok())
+                .unwrap_or(0)
+        };
+        threads.push(ThreadSchedStats {
+            tid,
+            name: status.lines()
+                .find(|l| l.starts_with("Name:"))
+                .map_or_else(String::new, |l| l[5..].trim().to_owned()),
+            voluntary_context_switches: field("voluntary_ctxt_switches:"),
+            involuntary_context_switches: field("nonvoluntary_ctxt_switches:"),
+            run_time: schedstat.map(|(run, _)| run),
+            run_delay: schedstat.map(|(_, delay)| delay),
+        });
+    }
+    threads.sort_by_key(|t| ::std::cmp::Reverse(t.run_delay));
+    Ok(threads)
+}
+
+/// The time this process's threads have spent waiting for a CPU, in
+/// nanoseconds, for `Resource::RunDelay`.
+pub fn run_delay() -> Result<u64> {
+    SchedulerStats::read()?
+        .run_delay
+        .map(|d| d.as_nanos() as u64)
+        .ok_or_else(|| ErrorKind::Unsupported(Resource::RunDelay).into())
+}
+
+/// Parse a thread's `schedstat`, which holds its time running and waiting
+/// in nanoseconds, followed by the number of timeslices it has run.
+fn parse_schedstat(schedstat: &str) -> Option<(Duration, Duration)> {
+    let mut fields = schedstat.split_whitespace();
+    let run = fields.next()?.parse().ok()?;
+    let delay = fields.next()?.parse().ok()?;
+    Some((Duration::from_nanos(run), Duration::from_nanos(delay)))
+}
@@ -0,0 +1,264 @@
+//! Dumping resource usage when the process receives a signal, so that a
+//! process which is ballooning in production can be inspected without
+//! restarting it.
+
+#[cfg(unix)]
+use libc::{self, c_void};
+#[cfg(unix)]
+use std::fs;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::io::Read;
+#[cfg(unix)]
+use std::mem;
+use std::os::raw::c_int;
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+#[cfg(unix)]
+use std::ptr;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+#[cfg(unix)]
+use std::thread;
+
+use allocator_stats::{write_allocator_stats, StatsOptions};
+use errors::*;
+use snapshot::{ReadError, Snapshot};
+use Resource;
+
+/// The write end of our self-pipe, or -1 if no handler is installed.
+#[cfg(unix)]
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Our signal handler.  This may only call async-signal-safe functions,
+/// so all it does is wake up our background thread.
+#[cfg(unix)]
+extern "C" fn on_signal(_: c_int) {
+    let fd = WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 1u8;
+        unsafe {
+            // `write` may clobber `errno` in the interrupted code.  If it
+            // fails with `EAGAIN`, the pipe is full and our thread is
+            // already due to wake up.
+            let errno = errno_location().map(|e| *e);
+            libc::write(fd, &byte as *const u8 as *const c_void, 1);
+            if let (Some(location), Some(errno)) = (errno_location(), errno) {
+                *location = errno;
+            }
+        }
+    }
+}
+
+/// The address of this thread's `errno`, on targets where we know how to
+/// find it.
+#[cfg(any(target_os = "linux", target_os = "emscripten"))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    Some(libc::__errno_location())
+}
+
+/// The address of this thread's `errno`, on targets where we know how to
+/// find it.
+#[cfg(any(target_os = "android",
+          target_os = "netbsd",
+          target_os = "openbsd"))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    Some(libc::__errno())
+}
+
+/// The address of this thread's `errno`, on targets where we know how to
+/// find it.
+#[cfg(any(target_os = "macos",
+          target_os = "ios",
+          target_os = "freebsd",
+          target_os = "dragonfly"))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    Some(libc::__error())
+}
+
+/// On other Unix targets, we don't know where `errno` lives, so the signal
+/// handler can't preserve it.
+#[cfg(all(unix,
+          not(any(target_os = "linux",
+                  target_os = "emscripten",
+                  target_os = "android",
+                  target_os = "netbsd",
+                  target_os = "openbsd",
+                  target_os = "macos",
+                  target_os = "ios",
+                  target_os = "freebsd",
+                  target_os = "dragonfly"))))]
+unsafe fn errno_location() -> Option<*mut c_int> {
+    None
+}
+
+/// Create a pipe whose ends are closed on `exec`.
+#[cfg(any(target_os = "linux",
+          target_os = "android",
+          target_os = "freebsd",
+          target_os = "dragonfly",
+          target_os = "netbsd",
+          target_os = "openbsd"))]
+fn cloexec_pipe() -> io::Result<[c_int; 2]> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fds)
+}
+
+/// Create a pipe whose ends are closed on `exec`.  Without `pipe2`, a
+/// process forked by another thread may briefly inherit them.
+#[cfg(all(unix,
+          not(any(target_os = "linux",
+                  target_os = "android",
+                  target_os = "freebsd",
+                  target_os = "dragonfly",
+                  target_os = "netbsd",
+                  target_os = "openbsd"))))]
+fn cloexec_pipe() -> io::Result<[c_int; 2]> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for &fd in &fds {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+            }
+            return Err(err);
+        }
+    }
+    Ok(fds)
+}
+
+/// Make writes to `fd` fail with `EAGAIN` instead of blocking.
+#[cfg(unix)]
+fn set_nonblocking(fd: c_int) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 ||
+           libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Call `callback` from a background thread whenever the process
+/// receives `signal`, such as `libc::SIGUSR1`.  Only one signal handler
+/// may be installed per process, and it can't be removed.
+#[cfg(unix)]
+pub fn on_signal_dump<F>(signal: c_int, mut callback: F) -> Result<()>
+    where F: FnMut() + Send + 'static
+{
+    let fds = cloexec_pipe().chain_err(|| "could not create signal pipe")?;
+    let mut reader = unsafe { fs::File::from_raw_fd(fds[0]) };
+    let writer = unsafe { fs::File::from_raw_fd(fds[1]) };
+    // If the pipe is full, a wakeup is already pending, so the handler can
+    // drop its byte rather than block.
+    set_nonblocking(fds[1]).chain_err(|| "could not configure signal pipe")?;
+    if WRITE_FD.compare_exchange(-1, fds[1], Ordering::SeqCst, Ordering::SeqCst)
+        .is_err() {
+        return Err("a signal dump handler is already installed".into());
+    }
+    // Start reading before installing the handler, so that the handler
+    // never writes to a pipe without a reader.  If we fail after this,
+    // dropping `writer` lets the thread see end-of-file and exit.
+    let spawned = thread::Builder::new()
+        .name("resource_monitor_signal".to_owned())
+        .spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                callback();
+            }
+        });
+    if let Err(err) = spawned {
+        WRITE_FD.store(-1, Ordering::SeqCst);
+        return Err(err).chain_err(|| "could not spawn signal dump thread");
+    }
+    let mut action: libc::sigaction = unsafe { mem::zeroed() };
+    let handler = on_signal as extern "C" fn(c_int);
+    action.sa_sigaction = handler as libc::sighandler_t;
+    // Restart system calls which the signal interrupts, rather than
+    // making them fail with `EINTR` in code which doesn't expect it.
+    action.sa_flags = libc::SA_RESTART;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    if unsafe { libc::sigaction(signal, &action, ptr::null_mut()) } < 0 {
+        WRITE_FD.store(-1, Ordering::SeqCst);
+        return Err(io::Error::last_os_error())
+            .chain_err(|| format!("could not install handler for {}", signal));
+    }
+    // The signal handler owns the write end from now on.
+    mem::forget(writer);
+    Ok(())
+}
+
+/// Targets without Unix signals can't install a handler, so this returns
+/// `ErrorKind::UnsupportedPlatform`.
+#[cfg(not(unix))]
+pub fn on_signal_dump<F>(_signal: c_int, _callback: F) -> Result<()>
+    where F: FnMut() + Send + 'static
+{
+    Err(ErrorKind::UnsupportedPlatform.into())
+}
+
+/// Whenever the process receives `signal`, print a snapshot of
+/// `resources` to standard error, followed by the allocator's own
+/// statistics.
+///
+/// ```no_run
+/// extern crate libc;
+/// extern crate resource_monitor;
+///
+/// use resource_monitor::{dump_on_signal, Resource};
+///
+/// # fn main() {
+/// dump_on_signal(libc::SIGUSR1, &[Resource::Memory, Resource::OsMemory])
+///     .unwrap();
+/// # }
+/// ```
+pub fn dump_on_signal(signal: c_int, resources: &[Resource]) -> Result<()> {
+    let resources = resources.to_owned();
+    on_signal_dump(signal, move || {
+        let (snapshot, errors) = Snapshot::capture_with_errors(&resources);
+        print_dump(&snapshot, &errors);
+    })
+}
+
+/// Print `snapshot` and `errors` to standard error, followed by the
+/// allocator's own statistics.
+pub fn print_dump(snapshot: &Snapshot, errors: &[ReadError]) {
+    // Write the whole dump at once, so that it isn't interleaved with
+    // other output.
+    let stderr = io::stderr();
+    let mut out = stderr.lock();
+    let _ = write_dump(&mut out, snapshot, errors);
+    // We may not have an allocator backend, which is fine.
+    let _ = write_allocator_stats(&mut out, StatsOptions::new());
+}
+
+/// Write `snapshot` and `errors` to `out`.
+fn write_dump(out: &mut dyn Write,
+              snapshot: &Snapshot,
+              errors: &[ReadError])
+              -> io::Result<()> {
+    writeln!(out, "resource_monitor: snapshot at {:?}", snapshot.taken_at)?;
+    for r in &snapshot.readings {
+        writeln!(out,
+                 "  {:?}: limit {:?}, used {:?}, available {:?}",
+                 r.resource,
+                 r.limit,
+                 r.used,
+                 r.available)?;
+    }
+    for e in errors {
+        writeln!(out, "  {:?}.{}: {}", e.resource, e.value, e.message)?;
+    }
+    Ok(())
+}
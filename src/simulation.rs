@@ -0,0 +1,137 @@
+//! Reporting synthetic memory limits and usage, configured using
+//! environment variables, so that you can rehearse low-memory behavior on
+//! a machine with plenty of memory.
+
+use std::env;
+use std::sync::{Arc, OnceLock};
+
+use bytes::Bytes;
+use errors::*;
+use provider::{ResourceProvider, SystemProvider};
+use Resource;
+
+/// Overrides the memory limit, such as `512MiB`.
+const LIMIT_VAR: &str = "RESOURCE_MONITOR_FAKE_MEMORY_LIMIT";
+/// Overrides memory usage, such as `400MiB`.
+const USED_VAR: &str = "RESOURCE_MONITOR_FAKE_MEMORY_USED";
+/// Multiplies real memory usage, such as `4`.
+const SCALE_VAR: &str = "RESOURCE_MONITOR_FAKE_MEMORY_SCALE";
+
+/// A `ResourceProvider` which reports synthetic values for `Memory` and
+/// `OsMemory`, and real values for everything else.
+///
+/// If any of these environment variables are set, we install a
+/// `SimulatedProvider` automatically, unless you call
+/// `set_resource_provider`:
+///
+/// - `RESOURCE_MONITOR_FAKE_MEMORY_LIMIT`: report this limit, such as
+///   `512MiB`, instead of our cgroup's limit.
+/// - `RESOURCE_MONITOR_FAKE_MEMORY_USED`: report this usage instead of
+///   the real usage.
+/// - `RESOURCE_MONITOR_FAKE_MEMORY_SCALE`: multiply the real usage by
+///   this factor, such as `4`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulatedProvider {
+    /// The memory limit to report, in bytes.
+    pub memory_limit: Option<u64>,
+    /// The memory usage to report, in bytes.
+    pub memory_used: Option<u64>,
+    /// A factor to multiply real memory usage by.  This is ignored if
+    /// `memory_used` is set.
+    pub memory_scale: Option<f64>,
+}
+
+impl SimulatedProvider {
+    /// Read our environment variables, returning `None` if none of them
+    /// are set.
+    pub fn from_env() -> Result<Option<SimulatedProvider>> {
+        let var = |name| env::var(name).ok().filter(|v| !v.is_empty());
+        let bytes = |name| -> Result<Option<u64>> {
+            match var(name) {
+                Some(v) => Ok(Some(v.parse::<Bytes>()?.as_u64())),
+                None => Ok(None),
+            }
+        };
+        let memory_scale = match var(SCALE_VAR) {
+            Some(v) => {
+                match v.parse::<f64>() {
+                    Ok(scale) if scale.is_finite() && scale >= 0.0 => {
+                        Some(scale)
+                    }
+                    _ => {
+                        let msg = format!("invalid {}: {:?}", SCALE_VAR, v);
+                        return Err(msg.into());
+                    }
+                }
+            }
+            None => None,
+        };
+        let provider = SimulatedProvider {
+            memory_limit: bytes(LIMIT_VAR)?,
+            memory_used: bytes(USED_VAR)?,
+            memory_scale,
+        };
+        if provider == SimulatedProvider::default() {
+            Ok(None)
+        } else {
+            Ok(Some(provider))
+        }
+    }
+
+    /// Is `resource` one that we simulate?
+    fn simulates(resource: &Resource) -> bool {
+        matches!(*resource, Resource::Memory | Resource::OsMemory)
+    }
+}
+
+impl ResourceProvider for SimulatedProvider {
+    fn limit(&self, resource: &Resource) -> Result<u64> {
+        match self.memory_limit {
+            Some(limit) if SimulatedProvider::simulates(resource) => Ok(limit),
+            _ => SystemProvider.limit(resource),
+        }
+    }
+
+    fn used(&self, resource: &Resource) -> Result<u64> {
+        if !SimulatedProvider::simulates(resource) {
+            return SystemProvider.used(resource);
+        }
+        if let Some(used) = self.memory_used {
+            return Ok(used);
+        }
+        let used = SystemProvider.used(resource)?;
+        match self.memory_scale {
+            Some(scale) => Ok((used as f64 * scale) as u64),
+            None => Ok(used),
+        }
+    }
+
+    fn available(&self, resource: &Resource) -> Result<u64> {
+        if SimulatedProvider::simulates(resource) {
+            Ok(self.limit(resource)?.saturating_sub(self.used(resource)?))
+        } else {
+            SystemProvider.available(resource)
+        }
+    }
+}
+
+/// The provider configured by our environment variables, if any.  We
+/// only read them once.
+pub fn from_env() -> Option<Arc<dyn ResourceProvider>> {
+    static PROVIDER: OnceLock<Option<Arc<dyn ResourceProvider>>> =
+        OnceLock::new();
+    PROVIDER.get_or_init(|| {
+            match SimulatedProvider::from_env() {
+                Ok(provider) => {
+                    provider.map(|p| Arc::new(p) as Arc<dyn ResourceProvider>)
+                }
+                Err(err) => {
+                    eprintln!("resource_monitor: ignoring simulation \
+                               settings: {}",
+                              err);
+                    None
+                }
+            }
+        })
+        .clone()
+}
@@ -0,0 +1,43 @@
+//! Filtering noisy readings.  Allocator stats jump around as memory is
+//! purged, and OS usage jumps around with page cache churn, so reacting to
+//! individual samples can cause flapping.
+
+/// How to smooth a series of readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// An exponentially-weighted moving average, where each new sample
+    /// gets this weight, between 0.0 and 1.0.  Smaller values smooth more.
+    Ewma(f64),
+    /// The median of the last N samples, which ignores brief spikes
+    /// entirely.
+    Median(usize),
+}
+
+impl Default for Smoothing {
+    fn default() -> Smoothing {
+        Smoothing::Ewma(0.3)
+    }
+}
+
+impl Smoothing {
+    /// Smooth `values`, which must be in chronological order.  Returns
+    /// `None` if there are no values.
+    pub fn apply(&self, values: &[u64]) -> Option<u64> {
+        let (&first, rest) = values.split_first()?;
+        match *self {
+            Smoothing::Ewma(alpha) => {
+                let alpha = alpha.clamp(0.0, 1.0);
+                let avg = rest.iter().fold(first as f64, |avg, &v| {
+                    avg + alpha * (v as f64 - avg)
+                });
+                Some(avg.round() as u64)
+            }
+            Smoothing::Median(n) => {
+                let start = values.len().saturating_sub(n.max(1));
+                let mut window = values[start..].to_vec();
+                window.sort_unstable();
+                Some(window[window.len() / 2])
+            }
+        }
+    }
+}
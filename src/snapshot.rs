@@ -0,0 +1,320 @@
+//! Consistent readings of several resources at once.
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use profile::SampleDetails;
+use read_cache::ReadCache;
+use source::ReadingSources;
+use {Error, ErrorKind, Resource, Result};
+
+/// The values of a single resource at the time a `Snapshot` was taken.
+/// Values which couldn't be read, or which don't apply to this resource,
+/// are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Reading {
+    /// The resource we measured.
+    pub resource: Resource,
+    /// The value of `resource.limit()`.
+    pub limit: Option<u64>,
+    /// The value of `resource.used()`.
+    pub used: Option<u64>,
+    /// The value of `resource.available()`.
+    pub available: Option<u64>,
+    /// Where each value came from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sources: ReadingSources,
+}
+
+impl Reading {
+    /// What fraction of its limit is this resource using?  Returns `None`
+    /// if we don't have both values.
+    pub fn fraction_used(&self) -> Option<f64> {
+        match (self.used, self.limit) {
+            (Some(used), Some(limit)) if limit > 0 => {
+                Some(used as f64 / limit as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Like `fraction_used`, but as a percentage.
+    pub fn percent_used(&self) -> Option<f64> {
+        self.fraction_used().map(|f| f * 100.0)
+    }
+}
+
+/// An error which occurred while taking a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadError {
+    /// The resource we were trying to read.
+    pub resource: Resource,
+    /// Which value we were reading: `"limit"`, `"used"` or `"available"`.
+    pub value: &'static str,
+    /// The error message.
+    pub message: String,
+    /// What kind of error it was.  Use `kind.is_unsupported()` to find
+    /// out whether it's worth reading this resource again.
+    pub kind: ErrorKind,
+}
+
+/// Is this an `ErrorKind::NotApplicable` error?
+fn is_not_applicable(err: &Error) -> bool {
+    matches!(*err.kind(), ErrorKind::NotApplicable(..))
+}
+
+/// Convert `result` to an `Option`, recording any unexpected errors.
+fn check(resource: &Resource,
+         value: &'static str,
+         result: Result<u64>,
+         errors: &mut Vec<ReadError>)
+         -> Option<u64> {
+    match result {
+        Ok(v) => Some(v),
+        // Many resources don't have a limit, and that's not news.
+        Err(ref e) if is_not_applicable(e) => None,
+        Err(e) => {
+            errors.push(ReadError {
+                resource: resource.clone(),
+                value,
+                message: e.to_string(),
+                kind: e.kind().clone(),
+            });
+            None
+        }
+    }
+}
+
+/// Readings for several resources, taken at the same time.
+///
+/// Calling `limit()`, `used()` and `available()` separately reads the
+/// underlying files several times, and the results may not agree with
+/// each other.  A snapshot reads each underlying value once, and computes
+/// every reading from those values.
+///
+/// ```
+/// use resource_monitor::{Resource, Snapshot};
+///
+/// let snapshot = Snapshot::capture(&[Resource::Memory, Resource::OsMemory]);
+/// for reading in &snapshot.readings {
+///     println!("{:?}: {:?} used", reading.resource, reading.used);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    /// When we started taking this snapshot.
+    pub taken_at: SystemTime,
+    /// One reading for each resource, in the order they were requested.
+    pub readings: Vec<Reading>,
+    /// Expensive values, which only a `Monitor` with a detailed
+    /// `SamplingProfile` reads.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub details: Option<SampleDetails>,
+}
+
+impl Snapshot {
+    /// Read all of `resources` in a single pass.
+    pub fn capture(resources: &[Resource]) -> Snapshot {
+        Snapshot::capture_with_errors(resources).0
+    }
+
+    /// Like `capture`, but also report any errors, other than values
+    /// which are not applicable to a resource.
+    pub fn capture_with_errors(resources: &[Resource])
+                               -> (Snapshot, Vec<ReadError>) {
+        capture_with_cache(resources, &mut ReadCache::new())
+    }
+
+    /// Look up the reading for `resource`, if we have one.
+    pub fn get(&self, resource: &Resource) -> Option<&Reading> {
+        self.readings.iter().find(|r| &r.resource == resource)
+    }
+
+    /// What fraction of its limit is `resource` using?  Returns `None`
+    /// if we don't have both values.
+    pub fn fraction_used(&self, resource: &Resource) -> Option<f64> {
+        self.get(resource)?.fraction_used()
+    }
+
+    /// How usage has changed between `earlier` and this snapshot, for each
+    /// of our resources.
+    ///
+    /// ```
+    /// use resource_monitor::{Resource, Snapshot};
+    ///
+    /// let resources = [Resource::Memory, Resource::Sockets];
+    /// let before = Snapshot::capture(&resources);
+    /// let buffer = vec![1u8; 1 << 20];
+    /// let after = Snapshot::capture(&resources);
+    /// // Prints something like "memory +1.0 MiB, sockets +0 over 2ms".
+    /// println!("{}", after.diff(&before));
+    /// # drop(buffer);
+    /// ```
+    pub fn diff(&self, earlier: &Snapshot) -> SnapshotDiff {
+        let deltas = self.readings
+            .iter()
+            .map(|r| {
+                ResourceDelta {
+                    resource: r.resource.clone(),
+                    before: earlier.get(&r.resource).and_then(|e| e.used),
+                    after: r.used,
+                }
+            })
+            .collect();
+        SnapshotDiff {
+            elapsed: self.taken_at
+                .duration_since(earlier.taken_at)
+                .unwrap_or_default(),
+            deltas,
+        }
+    }
+}
+
+/// The change in one resource's usage between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResourceDelta {
+    /// The resource we measured.
+    pub resource: Resource,
+    /// Its `used` value in the earlier snapshot.
+    pub before: Option<u64>,
+    /// Its `used` value in the later snapshot.
+    pub after: Option<u64>,
+}
+
+impl ResourceDelta {
+    /// How much usage grew, or `None` if either snapshot is missing it.
+    /// This is negative if usage fell.
+    pub fn change(&self) -> Option<i64> {
+        Some(self.after? as i64 - self.before? as i64)
+    }
+}
+
+/// Shows the change with a sign, in the resource's own units, such as
+/// `memory +312.0 MiB`.
+impl fmt::Display for ResourceDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let change = match self.change() {
+            Some(change) => change,
+            None => return write!(f, "{} ?", self.resource),
+        };
+        let sign = if change < 0 { '-' } else { '+' };
+        let size = change.unsigned_abs();
+        match self.resource.metric_unit() {
+            "bytes" => write!(f, "{} {}{}", self.resource, sign, Bytes(size)),
+            "nanoseconds" => {
+                let time = format_duration(Duration::from_nanos(size));
+                write!(f, "{} {}{}", self.resource, sign, time)
+            }
+            _ => write!(f, "{} {}{}", self.resource, sign, size),
+        }
+    }
+}
+
+/// How usage changed between two snapshots, as returned by
+/// `Snapshot::diff`.  Use this to report what a workload cost, or to check
+/// in tests that it stays within bounds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotDiff {
+    /// The time between the two snapshots.
+    pub elapsed: Duration,
+    /// One delta for each resource in the later snapshot, in order.
+    pub deltas: Vec<ResourceDelta>,
+}
+
+impl SnapshotDiff {
+    /// Look up the delta for `resource`, if we have one.
+    pub fn get(&self, resource: &Resource) -> Option<&ResourceDelta> {
+        self.deltas.iter().find(|d| &d.resource == resource)
+    }
+
+    /// How much `resource`'s usage grew, if we know.
+    pub fn change(&self, resource: &Resource) -> Option<i64> {
+        self.get(resource)?.change()
+    }
+
+    /// How fast `resource`'s usage grew, in units per second.
+    pub fn rate(&self, resource: &Resource) -> Option<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            Some(self.change(resource)? as f64 / secs)
+        } else {
+            None
+        }
+    }
+}
+
+/// A one-line report, such as `memory +312.0 MiB, sockets +14 over 30.0s`.
+/// Resources we couldn't compare are left out.
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let known = self.deltas.iter().filter(|d| d.change().is_some());
+        for (i, delta) in known.enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", delta)?;
+        }
+        write!(f, " over {}", format_duration(self.elapsed))
+    }
+}
+
+/// Format `duration` in seconds, or milliseconds if it's short.
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// Implementation of `Snapshot::capture_with_errors`, reading raw values
+/// through `cache`.
+pub fn capture_with_cache(resources: &[Resource],
+                          cache: &mut ReadCache)
+                          -> (Snapshot, Vec<ReadError>) {
+    let taken_at = SystemTime::now();
+    let mut errors = vec![];
+    cache.record_sources();
+    cache.prefetch_cgroup_memory(resources);
+    let readings = resources.iter()
+        .map(|r| {
+            let mut sources = ReadingSources::default();
+            let limit = r.limit_with(cache);
+            let (limit_sources, limit_fallback) = cache.take_sources();
+            let used = r.used_with(cache);
+            let (used_sources, used_fallback) = cache.take_sources();
+            let available = r.available_with(cache);
+            let (available_sources, available_fallback) = cache.take_sources();
+            if limit.is_ok() {
+                sources.limit = limit_sources;
+                sources.fallback |= limit_fallback;
+            }
+            if used.is_ok() {
+                sources.used = used_sources;
+                sources.fallback |= used_fallback;
+            }
+            if available.is_ok() {
+                sources.available = available_sources;
+                sources.fallback |= available_fallback;
+            }
+            Reading {
+                resource: r.clone(),
+                limit: check(r, "limit", limit, &mut errors),
+                used: check(r, "used", used, &mut errors),
+                available: check(r, "available", available, &mut errors),
+                sources,
+            }
+        })
+        .collect();
+    let snapshot = Snapshot {
+        taken_at,
+        readings,
+        details: None,
+    };
+    (snapshot, errors)
+}
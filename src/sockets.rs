@@ -0,0 +1,144 @@
+//! Open sockets.  Each socket uses a file descriptor, so a busy server
+//! can run out of connection slots even though it has plenty of memory.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use errors::*;
+use rlimit::{self, Rlimit};
+
+/// The number of sockets this process has open, by type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketCounts {
+    /// TCP sockets over IPv4, including listening sockets.
+    pub tcp: u64,
+    /// TCP sockets over IPv6, including listening sockets.
+    pub tcp6: u64,
+    /// UDP sockets over IPv4.
+    pub udp: u64,
+    /// UDP sockets over IPv6.
+    pub udp6: u64,
+    /// Unix domain sockets.
+    pub unix: u64,
+    /// Other sockets, such as netlink and raw sockets, and any sockets
+    /// which were closed while we were counting.
+    pub other: u64,
+}
+
+impl SocketCounts {
+    /// Count our open sockets, by matching the socket inodes in
+    /// `/proc/self/fd` against the tables in `/proc/self/net`.  This reads
+    /// every socket on the system in our network namespace, so on a busy
+    /// host it's much slower than `Resource::Sockets`, which only counts
+    /// the total.
+    pub fn read() -> Result<SocketCounts> {
+        let mut inodes = socket_inodes()?;
+        let total = inodes.len() as u64;
+        let mut count = |table: &str, inode_column: usize| -> Result<u64> {
+            let path = Path::new("/proc/self/net").join(table);
+            count_matching(&path, inode_column, &mut inodes)
+        };
+        let mut counts = SocketCounts {
+            tcp: count("tcp", 9)?,
+            tcp6: count("tcp6", 9)?,
+            udp: count("udp", 9)?,
+            udp6: count("udp6", 9)?,
+            unix: count("unix", 6)?,
+            other: 0,
+        };
+        counts.other = total - counts.matched();
+        Ok(counts)
+    }
+
+    /// The total number of sockets.
+    pub fn total(&self) -> u64 {
+        self.matched() + self.other
+    }
+
+    /// The number of TCP sockets, over both IPv4 and IPv6.
+    pub fn tcp_total(&self) -> u64 {
+        self.tcp + self.tcp6
+    }
+
+    /// The number of sockets we found in the tables.
+    fn matched(&self) -> u64 {
+        self.tcp + self.tcp6 + self.udp + self.udp6 + self.unix
+    }
+}
+
+/// How many sockets can we have open?  This is our `RLIMIT_NOFILE`, which
+/// all file descriptors share.
+pub fn limit() -> Result<u64> {
+    rlimit::soft_limit(Rlimit::OpenFiles)
+}
+
+/// How many sockets do we have open?
+pub fn used() -> Result<u64> {
+    Ok(socket_inodes()?.len() as u64)
+}
+
+/// How many more sockets could we open?  Sockets compete with every other
+/// file descriptor for slots, so this subtracts all our open descriptors,
+/// not just sockets.
+pub fn available() -> Result<u64> {
+    let path = PathBuf::from("/proc/self/fd");
+    let fds = fs::read_dir(&path)
+        .chain_err(|| ErrorKind::File(path.clone()))?
+        .count() as u64;
+    Ok(limit()?.saturating_sub(fds))
+}
+
+/// Find the inode of every socket in `/proc/self/fd`.  Two descriptors
+/// may refer to the same socket, such as after `dup`, so we count each
+/// inode once.
+fn socket_inodes() -> Result<HashSet<u64>> {
+    let path = PathBuf::from("/proc/self/fd");
+    let entries = fs::read_dir(&path)
+        .chain_err(|| ErrorKind::File(path.clone()))?;
+    let mut inodes = HashSet::new();
+    for entry in entries {
+        let entry = entry.chain_err(|| ErrorKind::File(path.clone()))?;
+        // Descriptors may be closed while we're looking at them, including
+        // the one `read_dir` is using, so skip any we can't read.
+        let target = match fs::read_link(entry.path()) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let inode = target.to_str()
+            .and_then(|t| t.strip_prefix("socket:["))
+            .and_then(|t| t.strip_suffix(']'))
+            .and_then(|t| t.parse().ok());
+        if let Some(inode) = inode {
+            inodes.insert(inode);
+        }
+    }
+    Ok(inodes)
+}
+
+/// Count the entries in the socket table at `path` whose inode, in
+/// `inode_column`, is in `inodes`, and remove them from `inodes`.  A
+/// missing table means the protocol isn't available, so it has no sockets.
+fn count_matching(path: &Path,
+                  inode_column: usize,
+                  inodes: &mut HashSet<u64>)
+                  -> Result<u64> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) if !path.exists() => return Ok(0),
+        Err(err) => {
+            return Err(err).chain_err(|| ErrorKind::File(path.to_owned()))
+        }
+    };
+    let mut count = 0;
+    for line in contents.lines().skip(1) {
+        let inode = line.split_whitespace()
+            .nth(inode_column)
+            .and_then(|i| i.parse().ok())
+            .ok_or_else(|| ErrorKind::Parse(path.to_owned()))?;
+        if inodes.remove(&inode) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
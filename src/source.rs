@@ -0,0 +1,119 @@
+//! Recording where each value in a `Snapshot` came from, so that anyone
+//! puzzling over a strange reading can see which file to look at.
+
+use std::path::PathBuf;
+
+use cache_policy::CachedValue;
+use cgroup::{self, CgroupVersion};
+use hugepages;
+use Resource;
+
+/// Where a value came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum ReadSource {
+    /// Read from this file, such as a cgroup control file or
+    /// `/proc/meminfo`.
+    File(PathBuf),
+    /// Returned by this system call or library function, such as
+    /// `getrlimit` or `statvfs`.
+    Call(String),
+    /// The heap allocator's statistics.
+    Allocator,
+    /// Counters kept by this crate, such as those behind
+    /// `Resource::ScopedMemory`.
+    Counters,
+    /// An installed `ResourceProvider`.
+    Provider,
+    /// A value read earlier and reused, under a `CachePolicy`.
+    Cached,
+    /// A private internal variant to allow future extensibility.
+    #[doc(hidden)]
+    __Private,
+}
+
+/// Where the values of a `Reading` came from.  A value computed from
+/// several others lists all of their sources.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReadingSources {
+    /// The sources of `limit`.
+    pub limit: Vec<ReadSource>,
+    /// The sources of `used`.
+    pub used: Vec<ReadSource>,
+    /// The sources of `available`.
+    pub available: Vec<ReadSource>,
+    /// Did we fill in anything we couldn't read with an assumption?  For
+    /// example, if we can't read the heap allocator's statistics, we
+    /// assume that it has no free memory in reserve.
+    pub fallback: bool,
+}
+
+/// The sources of `value` of `resource`, for resources which we don't read
+/// through a `ReadCache`.
+pub fn static_sources(resource: &Resource,
+                      value: CachedValue)
+                      -> Vec<ReadSource> {
+    let file = |path: &str| ReadSource::File(PathBuf::from(path));
+    let call = |name: &str| ReadSource::Call(name.to_owned());
+    let status = || file("/proc/self/status");
+    match (resource, value) {
+        (_, CachedValue::Available) => {
+            let mut sources = static_sources(resource, CachedValue::Limit);
+            for source in static_sources(resource, CachedValue::Used) {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+            }
+            sources
+        }
+        (&Resource::HugePages(size), value) => {
+            let (v1, v2) = match value {
+                CachedValue::Limit => ("limit_in_bytes", "max"),
+                _ => ("usage_in_bytes", "current"),
+            };
+            let path = hugepages::hugetlb_file(size, v1, v2);
+            if path.exists() {
+                vec![ReadSource::File(path)]
+            } else {
+                vec![file("/proc/meminfo")]
+            }
+        }
+        (&Resource::Swap, value) => {
+            let names: &[(&str, &str)] = match value {
+                CachedValue::Limit => {
+                    &[("memory.memsw.limit_in_bytes", "memory.swap.max"),
+                      ("memory.limit_in_bytes", "")]
+                }
+                _ => {
+                    &[("memory.memsw.usage_in_bytes", "memory.swap.current"),
+                      ("memory.usage_in_bytes", "")]
+                }
+            };
+            let v2 = cgroup::version() == CgroupVersion::V2;
+            names.iter()
+                .filter(|&&(_, name)| !v2 || !name.is_empty())
+                .map(|&(v1, v2)| cgroup::memory_file(v1, v2))
+                .map(ReadSource::File)
+                .collect()
+        }
+        (&Resource::CpuTime, _) => vec![call("clock_gettime")],
+        (&Resource::GpuMemory(_), _) => {
+            vec![call("nvmlDeviceGetMemoryInfo")]
+        }
+        (&Resource::Tmpfs(_), _) |
+        (&Resource::SharedMemory, _) => vec![call("statvfs")],
+        (&Resource::Quota(_), _) => vec![call("quotactl")],
+        (&Resource::Stack, _) => vec![call("pthread_getattr_np")],
+        (&Resource::ScopedMemory(_), _) => vec![ReadSource::Counters],
+        (&Resource::LockedMemory, CachedValue::Limit) |
+        (&Resource::AddressSpace, CachedValue::Limit) |
+        (&Resource::Sockets, CachedValue::Limit) => vec![call("getrlimit")],
+        (&Resource::LockedMemory, _) |
+        (&Resource::AddressSpace, _) => vec![status()],
+        (&Resource::Sockets, _) => vec![file("/proc/self/fd")],
+        (&Resource::RunDelay, _) => vec![file("/proc/self/task")],
+        _ => vec![],
+    }
+}
@@ -0,0 +1,88 @@
+//! Stack space of the current thread, so that deeply recursive code can
+//! give up cleanly instead of hitting the guard page.
+
+#[cfg(any(target_os = "linux",
+          target_os = "android",
+          target_os = "macos",
+          target_os = "ios"))]
+use libc;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::mem;
+
+use errors::*;
+use rlimit::{self, Rlimit};
+#[cfg(not(any(target_os = "linux",
+              target_os = "android",
+              target_os = "macos",
+              target_os = "ios")))]
+use Resource;
+
+/// The lowest address and size of the current thread's stack, from
+/// `pthread_getattr_np`.  For the main thread, glibc derives the size from
+/// `RLIMIT_STACK`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bounds() -> Result<(usize, usize)> {
+    unsafe {
+        let mut attr: libc::pthread_attr_t = mem::zeroed();
+        let err = libc::pthread_getattr_np(libc::pthread_self(), &mut attr);
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err))
+                .chain_err(|| "could not call pthread_getattr_np");
+        }
+        let mut addr: *mut libc::c_void = ::std::ptr::null_mut();
+        let mut size: libc::size_t = 0;
+        let err = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err))
+                .chain_err(|| "could not call pthread_attr_getstack");
+        }
+        Ok((addr as usize, size))
+    }
+}
+
+/// The lowest address and size of the current thread's stack, from
+/// `pthread_get_stackaddr_np`, which returns the highest address, and
+/// `pthread_get_stacksize_np`.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn bounds() -> Result<(usize, usize)> {
+    unsafe {
+        let thread = libc::pthread_self();
+        let top = libc::pthread_get_stackaddr_np(thread) as usize;
+        let size = libc::pthread_get_stacksize_np(thread);
+        Ok((top.saturating_sub(size), size))
+    }
+}
+
+/// Elsewhere, we don't know how to find our stack, so `limit` falls back
+/// to `RLIMIT_STACK`.
+#[cfg(not(any(target_os = "linux",
+              target_os = "android",
+              target_os = "macos",
+              target_os = "ios")))]
+fn bounds() -> Result<(usize, usize)> {
+    Err(ErrorKind::Unsupported(Resource::Stack).into())
+}
+
+/// The size of the current thread's stack, or `RLIMIT_STACK` if we can't
+/// ask pthreads.
+pub fn limit() -> Result<u64> {
+    match bounds() {
+        Ok((_, size)) => Ok(size as u64),
+        Err(_) => rlimit::soft_limit(Rlimit::Stack),
+    }
+}
+
+/// An estimate of how much of the current thread's stack is in use: the
+/// distance from the top of the stack to a local variable in this
+/// function.  This assumes that the stack grows downwards, as it does on
+/// all the architectures Linux commonly runs on.
+#[inline(never)]
+pub fn used() -> Result<u64> {
+    let (addr, size) = bounds()?;
+    let marker = 0u8;
+    let here = &marker as *const u8 as usize;
+    Ok((addr + size).saturating_sub(here) as u64)
+}
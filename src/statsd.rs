@@ -0,0 +1,143 @@
+//! Pushing resource readings to a StatsD or DogStatsD server over UDP.
+
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use errors::*;
+use snapshot::Snapshot;
+
+/// Replace characters which have a special meaning in the StatsD protocol.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ':' | '|' | ',' | '#' | '@' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Sends gauges for each resource in a `Snapshot` to a StatsD server.
+///
+/// By default, we use the DogStatsD format, sending gauges named
+/// `<prefix>.limit_bytes`, `<prefix>.used_bytes` and
+/// `<prefix>.available_bytes`, tagged with `resource:<name>` plus any tags
+/// added with `tag`.  Call `plain` to use the original StatsD format
+/// instead, which puts the resource name in the gauge name and sends no
+/// tags.  `Resource::CpuTime` is sent in nanoseconds, with `_nanoseconds`
+/// in place of `_bytes`.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use resource_monitor::{Monitor, Resource, StatsdEmitter};
+///
+/// let emitter = StatsdEmitter::new("127.0.0.1:8125")
+///     .unwrap()
+///     .prefix("myapp.resources")
+///     .tag("container_id", "abc123");
+/// let monitor = Monitor::new(&[Resource::Memory], Duration::from_secs(10))
+///     .unwrap();
+/// monitor.emit_to_statsd(emitter);
+/// ```
+#[derive(Debug)]
+pub struct StatsdEmitter {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+    plain: bool,
+}
+
+impl StatsdEmitter {
+    /// Send gauges to the server at `addr`, using the prefix
+    /// `"resource"`.
+    pub fn new<A: ToSocketAddrs>(addr: A) -> Result<StatsdEmitter> {
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|s| s.connect(addr).map(|_| s))
+            .chain_err(|| "could not connect to StatsD server")?;
+        Ok(StatsdEmitter {
+            socket,
+            prefix: "resource".to_owned(),
+            tags: vec![],
+            plain: false,
+        })
+    }
+
+    /// Use `prefix` at the start of each gauge name.
+    pub fn prefix(mut self, prefix: &str) -> StatsdEmitter {
+        self.prefix = sanitize(prefix);
+        self
+    }
+
+    /// Add the tag `key:value` to every gauge we send.
+    pub fn tag(mut self, key: &str, value: &str) -> StatsdEmitter {
+        self.tags.push(format!("{}:{}", sanitize(key), sanitize(value)));
+        self
+    }
+
+    /// Use the original StatsD format, without tags.
+    pub fn plain(mut self) -> StatsdEmitter {
+        self.plain = true;
+        self
+    }
+
+    /// Format one line of our protocol.
+    fn line(&self,
+            resource: &str,
+            scope: Option<&str>,
+            value: &str,
+            unit: &str,
+            v: u64)
+            -> String {
+        if self.plain {
+            let resource = match scope {
+                Some(scope) => format!("{}.{}", resource, sanitize(scope)),
+                None => resource.to_owned(),
+            };
+            format!("{}.{}.{}_{}:{}|g",
+                    self.prefix,
+                    resource,
+                    value,
+                    unit,
+                    v)
+        } else {
+            let mut tags = vec![format!("resource:{}", resource)];
+            if let Some(scope) = scope {
+                tags.push(format!("scope:{}", sanitize(scope)));
+            }
+            tags.extend(self.tags.iter().cloned());
+            format!("{}.{}_{}:{}|g|#{}",
+                    self.prefix,
+                    value,
+                    unit,
+                    v,
+                    tags.join(","))
+        }
+    }
+
+    /// Send every value in `snapshot` in a single packet.  Values which
+    /// weren't read are skipped.
+    pub fn emit(&self, snapshot: &Snapshot) -> Result<()> {
+        let mut lines = vec![];
+        for reading in &snapshot.readings {
+            let (resource, scope) = reading.resource.metric_name();
+            let unit = reading.resource.metric_unit();
+            let values = [("limit", reading.limit),
+                          ("used", reading.used),
+                          ("available", reading.available)];
+            for &(value, v) in &values {
+                if let Some(v) = v {
+                    lines.push(self.line(resource,
+                                         scope.as_deref(),
+                                         value,
+                                         unit,
+                                         v));
+                }
+            }
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.socket
+            .send(lines.join("\n").as_bytes())
+            .chain_err(|| "could not send to StatsD server")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! Async streams of resource samples, for use with tokio.
+
+use futures::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+use snapshot::Snapshot;
+use threshold::{Threshold, ThresholdEvent, ThresholdWatch};
+use Resource;
+
+/// A `Stream` which yields a `Snapshot` of a set of resources at a fixed
+/// interval.
+///
+/// ```no_run
+/// # extern crate futures;
+/// # extern crate resource_monitor;
+/// # extern crate tokio;
+/// use futures::StreamExt;
+/// use resource_monitor::{Resource, ResourceStream};
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let rt = tokio::runtime::Builder::new_current_thread()
+///     .enable_time()
+///     .build()
+///     .unwrap();
+/// let _guard = rt.enter();
+/// let mut samples = ResourceStream::new(&[Resource::Memory],
+///                                       Duration::from_secs(1));
+/// let snapshot = rt.block_on(samples.next()).unwrap();
+/// println!("{:?}", snapshot.get(&Resource::Memory));
+/// # }
+/// ```
+///
+/// Taking a sample reads a few small files from `/sys` and `/proc`.  This
+/// technically blocks, but it's normally faster than a context switch to
+/// another thread would be.
+pub struct ResourceStream {
+    resources: Vec<Resource>,
+    interval: Interval,
+}
+
+impl ResourceStream {
+    /// Sample `resources` every `period`, starting immediately.  This must
+    /// be called from inside a tokio runtime.
+    pub fn new(resources: &[Resource], period: Duration) -> ResourceStream {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ResourceStream {
+            resources: resources.to_owned(),
+            interval,
+        }
+    }
+
+    /// Convert this into a stream of `ThresholdEvent`s for `resource`,
+    /// using a recovery threshold 5% below `threshold`.
+    pub fn thresholds(mut self,
+                      resource: Resource,
+                      threshold: Threshold)
+                      -> ThresholdStream {
+        if !self.resources.contains(&resource) {
+            self.resources.push(resource.clone());
+        }
+        ThresholdStream {
+            samples: self,
+            watch: ThresholdWatch::with_default_recovery(resource,
+                                                         threshold),
+        }
+    }
+}
+
+impl Stream for ResourceStream {
+    type Item = Snapshot;
+
+    fn poll_next(mut self: Pin<&mut Self>,
+                 cx: &mut Context)
+                 -> Poll<Option<Snapshot>> {
+        match self.interval.poll_tick(cx) {
+            Poll::Ready(_) => {
+                Poll::Ready(Some(Snapshot::capture(&self.resources)))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A `Stream` of threshold crossings, created by
+/// `ResourceStream::thresholds`.  This never ends, so it's suitable for
+/// use in a `select!` loop.
+pub struct ThresholdStream {
+    samples: ResourceStream,
+    watch: ThresholdWatch,
+}
+
+impl Stream for ThresholdStream {
+    type Item = ThresholdEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>,
+                 cx: &mut Context)
+                 -> Poll<Option<ThresholdEvent>> {
+        let this = &mut *self;
+        loop {
+            match Pin::new(&mut this.samples).poll_next(cx) {
+                Poll::Ready(Some(snapshot)) => {
+                    if let Some(event) = this.watch.update(&snapshot) {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Creating and managing cgroups nested inside our own, so that a job
+//! runner can give each workload its own limits.  This needs write access
+//! to our cgroup, which systemd calls delegation.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use cgroup::{self, CgroupVersion};
+use cgroup_resources::CgroupResources;
+use errors::*;
+use read_file_u64;
+
+/// Limits for a `SubCgroup`.  Limits which are `None` are left alone,
+/// which means unlimited in a new cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubCgroupLimits {
+    /// The most memory its processes may use, in bytes.
+    pub memory_max: Option<u64>,
+    /// The most processes and threads it may contain.
+    pub pids_max: Option<u64>,
+}
+
+/// A cgroup nested inside ours.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use resource_monitor::{Bytes, Resource, SubCgroup, SubCgroupLimits};
+///
+/// let limits = SubCgroupLimits {
+///     memory_max: Some(512 * Bytes::MIB),
+///     pids_max: Some(64),
+/// };
+/// let job = SubCgroup::create("job-42", &limits).unwrap();
+/// let mut child = Command::new("./untrusted").spawn().unwrap();
+/// job.add_process(child.id()).unwrap();
+/// child.wait().unwrap();
+/// println!("usage: {:?}", job.resources().used(&Resource::Memory));
+/// job.remove().unwrap();
+/// ```
+///
+/// Under cgroup v2, the kernel only lets us limit a child cgroup's memory
+/// or processes if there are no processes in our own cgroup, so a job
+/// runner usually moves itself into a child cgroup of its own first.
+/// Under cgroup v1, the memory and pids controllers have separate
+/// hierarchies, and we create a directory in each one we need.
+#[derive(Debug)]
+pub struct SubCgroup {
+    resources: CgroupResources,
+    /// Our directory in the pids hierarchy, if it's separate.
+    pids_dir: Option<PathBuf>,
+}
+
+impl SubCgroup {
+    /// Create a cgroup called `name` inside ours, with `limits`.  This
+    /// fails if it already exists.
+    pub fn create(name: &str, limits: &SubCgroupLimits) -> Result<SubCgroup> {
+        if name.is_empty() || name.contains('/') || name == "." ||
+           name == ".." {
+            return Err(format!("invalid cgroup name: {:?}", name).into());
+        }
+        let version = cgroup::version();
+        let memory_parent = cgroup::controller_dir("memory", version);
+        let pids_parent = match version {
+            CgroupVersion::V1 if limits.pids_max.is_some() => {
+                Some(cgroup::controller_dir("pids", version))
+            }
+            _ => None,
+        };
+        if version == CgroupVersion::V2 {
+            let mut controllers = vec![];
+            if limits.memory_max.is_some() {
+                controllers.push("+memory");
+            }
+            if limits.pids_max.is_some() {
+                controllers.push("+pids");
+            }
+            // If this fails, setting the limits below reports it.
+            let control = memory_parent.join("cgroup.subtree_control");
+            for controller in controllers {
+                let _ = cgroup::write_file(&control, controller);
+            }
+        }
+        let dir = make_dir(&memory_parent.join(name))?;
+        let pids_dir = match pids_parent {
+            Some(parent) => {
+                match make_dir(&parent.join(name)) {
+                    Ok(dir) => Some(dir),
+                    Err(err) => {
+                        let _ = fs::remove_dir(&dir);
+                        return Err(err);
+                    }
+                }
+            }
+            None => None,
+        };
+        let sub = SubCgroup {
+            resources: CgroupResources::at(&dir)?,
+            pids_dir,
+        };
+        if let Err(err) = sub.set_limits(limits) {
+            let _ = sub.remove_dirs();
+            return Err(err);
+        }
+        Ok(sub)
+    }
+
+    /// The directory of this cgroup, in the memory controller's hierarchy
+    /// under cgroup v1.
+    pub fn path(&self) -> &Path {
+        self.resources.path()
+    }
+
+    /// Read this cgroup's memory limits and usage, using the same
+    /// `Resource` API as our own.
+    pub fn resources(&self) -> &CgroupResources {
+        &self.resources
+    }
+
+    /// Change the limits which aren't `None` in `limits`.
+    pub fn set_limits(&self, limits: &SubCgroupLimits) -> Result<()> {
+        if let Some(bytes) = limits.memory_max {
+            let name = match self.resources.version() {
+                CgroupVersion::V1 => "memory.limit_in_bytes",
+                CgroupVersion::V2 => "memory.max",
+            };
+            let path = self.path().join(name);
+            cgroup::write_file(&path, &bytes.to_string())?;
+        }
+        if let Some(pids) = limits.pids_max {
+            let path = self.pids_dir().join("pids.max");
+            cgroup::write_file(&path, &pids.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// The number of processes and threads in this cgroup.  Under cgroup
+    /// v1, this requires a `pids_max` limit.
+    pub fn pids_current(&self) -> Result<u64> {
+        read_file_u64(&self.pids_dir().join("pids.current"))
+    }
+
+    /// The most processes and threads this cgroup may contain, or
+    /// `u64::MAX` if there's no limit.  Under cgroup v1, this requires a
+    /// `pids_max` limit.
+    pub fn pids_max(&self) -> Result<u64> {
+        cgroup::read_limit(&self.pids_dir().join("pids.max"))
+    }
+
+    /// Move process `pid`, and all its threads, into this cgroup.  Its
+    /// children stay where they are, but any it starts later will be in
+    /// this cgroup too.
+    pub fn add_process(&self, pid: u32) -> Result<()> {
+        let procs = pid.to_string();
+        cgroup::write_file(&self.path().join("cgroup.procs"), &procs)?;
+        if let Some(ref dir) = self.pids_dir {
+            cgroup::write_file(&dir.join("cgroup.procs"), &procs)?;
+        }
+        // If we moved ourselves, our own cgroup directories have changed.
+        if pid == process::id() {
+            cgroup::forget_dirs();
+        }
+        Ok(())
+    }
+
+    /// The processes in this cgroup.
+    pub fn processes(&self) -> Result<Vec<u32>> {
+        let path = self.path().join("cgroup.procs");
+        let contents = fs::read_to_string(&path)
+            .chain_err(|| ErrorKind::File(path.clone()))?;
+        contents.lines()
+            .map(|line| line.trim().parse())
+            .collect::<::std::result::Result<_, _>>()
+            .chain_err(|| ErrorKind::Parse(path))
+    }
+
+    /// Remove this cgroup.  This fails if any processes are still in it.
+    pub fn remove(self) -> Result<()> {
+        self.remove_dirs()
+    }
+
+    /// Remove our directories.
+    fn remove_dirs(&self) -> Result<()> {
+        let dirs = Some(self.path())
+            .into_iter()
+            .chain(self.pids_dir.as_deref());
+        for dir in dirs {
+            fs::remove_dir(dir)
+                .chain_err(|| ErrorKind::File(dir.to_owned()))?;
+        }
+        Ok(())
+    }
+
+    /// Our directory in the pids controller's hierarchy.
+    fn pids_dir(&self) -> &Path {
+        self.pids_dir.as_deref().unwrap_or_else(|| self.path())
+    }
+}
+
+/// Create the directory `dir`.
+fn make_dir(dir: &Path) -> Result<PathBuf> {
+    fs::create_dir(dir).chain_err(|| ErrorKind::File(dir.to_owned()))?;
+    Ok(dir.to_owned())
+}
@@ -0,0 +1,66 @@
+//! Swap charged to our cgroup.  The kernel only tracks this if swap
+//! accounting is enabled, which it isn't on kernels booted with
+//! `swapaccount=0`, and on some distributions by default.
+
+use cgroup::{self, CgroupVersion};
+use errors::*;
+use read_file_u64;
+use Resource;
+
+/// Does the kernel keep track of how much swap each cgroup uses?  If not,
+/// `Resource::Swap` returns `ErrorKind::Unsupported`.
+pub fn swap_accounting_enabled() -> bool {
+    cgroup::memory_file("memory.memsw.usage_in_bytes", "memory.swap.current")
+        .exists()
+}
+
+/// Return an error unless swap accounting is enabled, so that we don't
+/// report a confusing missing file.
+fn require_accounting() -> Result<()> {
+    if swap_accounting_enabled() {
+        Ok(())
+    } else {
+        Err(ErrorKind::Unsupported(Resource::Swap).into())
+    }
+}
+
+/// Our cgroup's swap limit.  Under cgroup v1, the kernel limits memory
+/// plus swap, so we subtract the memory limit.
+pub fn limit() -> Result<u64> {
+    require_accounting()?;
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let memsw = cgroup::read_limit(&cgroup::memory_file(
+                "memory.memsw.limit_in_bytes", ""))?;
+            let memory = cgroup::read_limit(&cgroup::memory_file(
+                "memory.limit_in_bytes", ""))?;
+            // cgroup v1 reports "unlimited" as a huge number, just short
+            // of `i64::MAX`, and we don't want to subtract from that.
+            if memsw >= cgroup::V1_UNLIMITED {
+                return Ok(u64::MAX);
+            }
+            Ok(memsw.saturating_sub(memory))
+        }
+        CgroupVersion::V2 => {
+            cgroup::read_limit(&cgroup::memory_file("", "memory.swap.max"))
+        }
+    }
+}
+
+/// Swap charged to our cgroup.  Under cgroup v1, the kernel reports
+/// memory plus swap, so we subtract memory usage.
+pub fn used() -> Result<u64> {
+    require_accounting()?;
+    match cgroup::version() {
+        CgroupVersion::V1 => {
+            let memsw = read_file_u64(&cgroup::memory_file(
+                "memory.memsw.usage_in_bytes", ""))?;
+            let memory = read_file_u64(&cgroup::memory_file(
+                "memory.usage_in_bytes", ""))?;
+            Ok(memsw.saturating_sub(memory))
+        }
+        CgroupVersion::V2 => {
+            read_file_u64(&cgroup::memory_file("", "memory.swap.current"))
+        }
+    }
+}
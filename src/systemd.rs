@@ -0,0 +1,278 @@
+//! The limits systemd has set on our unit, read over D-Bus.
+//!
+//! When we run as a systemd service, the unit's properties are the
+//! authoritative limits, and systemd writes them to our cgroup.  Comparing
+//! the two catches limits which were changed behind systemd's back, such
+//! as by writing to the cgroup directly, and units whose cgroup settings
+//! were never applied.
+
+use libc::{self, c_char, c_int, c_void, pid_t};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::path::Path;
+use std::ptr;
+
+use cgroup::{self, CgroupVersion};
+use compressed::page_size;
+use cpu::cpu_quota;
+use errors::*;
+
+/// The limits of the systemd unit we're running in.  Unlimited values are
+/// reported as `u64::MAX`.
+///
+/// ```no_run
+/// use resource_monitor::SystemdUnit;
+///
+/// let unit = SystemdUnit::current().unwrap();
+/// println!("{}: MemoryMax={}", unit.name, unit.memory_max);
+/// for d in unit.discrepancies() {
+///     eprintln!("{} is {} in systemd, but {} in our cgroup",
+///               d.property, d.systemd, d.cgroup);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemdUnit {
+    /// The name of the unit, such as `myapp.service`.
+    pub name: String,
+    /// Is this a unit of the per-user service manager, rather than of the
+    /// system?
+    pub user: bool,
+    /// `MemoryMax`, in bytes.
+    pub memory_max: u64,
+    /// `MemoryHigh`, in bytes.
+    pub memory_high: u64,
+    /// `TasksMax`, the number of processes and threads we may have.
+    pub tasks_max: u64,
+    /// `CPUQuota`, in microseconds of CPU time per second.  `CPUQuota=50%`
+    /// is 500,000.
+    pub cpu_quota_per_sec_usec: u64,
+}
+
+impl SystemdUnit {
+    /// Find the unit we're running in, and read its limits.  This requires
+    /// the `systemd` feature, which links against `libsystemd`.
+    pub fn current() -> Result<SystemdUnit> {
+        let (name, user) = current_unit()?;
+        let bus = Bus::open(user)?;
+        let path = unit_path(&name)?;
+        let interface = unit_interface(&name)?;
+        let get = |property| bus.get_u64(&path, &interface, property);
+        Ok(SystemdUnit {
+            memory_max: get("MemoryMax")?,
+            memory_high: get("MemoryHigh")?,
+            tasks_max: get("TasksMax")?,
+            cpu_quota_per_sec_usec: get("CPUQuotaPerSecUSec")?,
+            name,
+            user,
+        })
+    }
+
+    /// `CPUQuota`, as a number of CPUs, or `None` if there is no quota.
+    pub fn cpu_quota(&self) -> Option<f64> {
+        if self.cpu_quota_per_sec_usec == u64::MAX {
+            None
+        } else {
+            Some(self.cpu_quota_per_sec_usec as f64 / 1_000_000.0)
+        }
+    }
+
+    /// Compare our limits with the ones in our cgroup, and return any which
+    /// differ.  Limits we can't read from the cgroup are skipped, as is
+    /// `MemoryHigh` under cgroup v1, which has no equivalent.
+    ///
+    /// The kernel rounds memory limits down to a whole number of pages, so
+    /// we only report memory limits which differ by at least a page.
+    pub fn discrepancies(&self) -> Vec<LimitDiscrepancy> {
+        let mut found = vec![];
+        let mut compare = |property, systemd, cgroup: Option<u64>, unit| {
+            if let Some(cgroup) = cgroup {
+                if systemd / unit != cgroup / unit {
+                    found.push(LimitDiscrepancy {
+                        property,
+                        systemd,
+                        cgroup,
+                    });
+                }
+            }
+        };
+        let page = page_size().max(1);
+        let version = cgroup::version();
+        let memory_max =
+            cgroup_limit(&cgroup::memory_file("memory.limit_in_bytes",
+                                              "memory.max"));
+        compare("MemoryMax", self.memory_max, memory_max, page);
+        if version == CgroupVersion::V2 {
+            let memory_high =
+                cgroup_limit(&cgroup::memory_file("", "memory.high"));
+            compare("MemoryHigh", self.memory_high, memory_high, page);
+        }
+        let tasks_max =
+            cgroup_limit(&cgroup::controller_file("pids",
+                                                  "pids.max",
+                                                  "pids.max"));
+        compare("TasksMax", self.tasks_max, tasks_max, 1);
+        // We can't tell "no quota" apart from "can't read the quota", so
+        // we only compare quotas we can see.
+        if let Some(quota) = cpu_quota() {
+            let usec = (quota * 1_000_000.0).round() as u64;
+            compare("CPUQuota", self.cpu_quota_per_sec_usec, Some(usec), 1);
+        }
+        found
+    }
+}
+
+/// A limit which systemd and our cgroup disagree about, as returned by
+/// `SystemdUnit::discrepancies`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitDiscrepancy {
+    /// The name of the unit property, such as `"MemoryMax"`.
+    pub property: &'static str,
+    /// The value systemd has, in the units used by `SystemdUnit`.
+    pub systemd: u64,
+    /// The value in our cgroup, in the same units.
+    pub cgroup: u64,
+}
+
+/// Read a cgroup limit, reporting cgroup v1's "unlimited" as `u64::MAX`,
+/// the same as systemd.
+fn cgroup_limit(path: &Path) -> Option<u64> {
+    match cgroup::read_limit(path) {
+        Ok(limit) if limit >= cgroup::V1_UNLIMITED => Some(u64::MAX),
+        Ok(limit) => Some(limit),
+        Err(_) => None,
+    }
+}
+
+/// A connection to `sd_bus`.
+enum SdBus {}
+
+#[link(name = "systemd")]
+extern "C" {
+    fn sd_pid_get_unit(pid: pid_t, unit: *mut *mut c_char) -> c_int;
+    fn sd_pid_get_user_unit(pid: pid_t, unit: *mut *mut c_char) -> c_int;
+    fn sd_bus_open_system(bus: *mut *mut SdBus) -> c_int;
+    fn sd_bus_open_user(bus: *mut *mut SdBus) -> c_int;
+    fn sd_bus_unref(bus: *mut SdBus) -> *mut SdBus;
+    fn sd_bus_path_encode(prefix: *const c_char,
+                          external_id: *const c_char,
+                          ret_path: *mut *mut c_char)
+                          -> c_int;
+    fn sd_bus_get_property_trivial(bus: *mut SdBus,
+                                   destination: *const c_char,
+                                   path: *const c_char,
+                                   interface: *const c_char,
+                                   member: *const c_char,
+                                   ret_error: *mut c_void,
+                                   kind: c_char,
+                                   ret: *mut c_void)
+                                   -> c_int;
+}
+
+/// Turn a return value from `libsystemd`, which is a negated `errno` on
+/// failure, into a `Result`.
+fn check(result: c_int, what: &str) -> Result<()> {
+    if result >= 0 {
+        return Ok(());
+    }
+    Err(io::Error::from_raw_os_error(-result))
+        .chain_err(|| format!("systemd: could not {}", what))
+}
+
+/// Take ownership of a string allocated by `libsystemd`.
+unsafe fn take_string(s: *mut c_char) -> String {
+    let owned = CStr::from_ptr(s).to_string_lossy().into_owned();
+    libc::free(s as *mut c_void);
+    owned
+}
+
+/// Find the unit we're running in, and whether it belongs to the user's
+/// service manager.  Processes in a user service are also inside the
+/// system's `user@.service`, so we check for a user unit first.
+fn current_unit() -> Result<(String, bool)> {
+    let mut unit: *mut c_char = ptr::null_mut();
+    if unsafe { sd_pid_get_user_unit(0, &mut unit) } >= 0 {
+        return Ok((unsafe { take_string(unit) }, true));
+    }
+    check(unsafe { sd_pid_get_unit(0, &mut unit) },
+          "find our unit")?;
+    Ok((unsafe { take_string(unit) }, false))
+}
+
+/// The D-Bus object path of `unit`.
+fn unit_path(unit: &str) -> Result<CString> {
+    let prefix = cstring("/org/freedesktop/systemd1/unit")?;
+    let unit = cstring(unit)?;
+    let mut path: *mut c_char = ptr::null_mut();
+    check(unsafe {
+              sd_bus_path_encode(prefix.as_ptr(), unit.as_ptr(), &mut path)
+          },
+          "encode unit path")?;
+    cstring(&unsafe { take_string(path) })
+}
+
+/// The D-Bus interface with the cgroup properties of `unit`, which
+/// depends on its type, such as `org.freedesktop.systemd1.Service`.
+fn unit_interface(unit: &str) -> Result<CString> {
+    let kind = unit.rsplit('.').next().unwrap_or("");
+    let mut chars = kind.chars();
+    let kind = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+        None => return Err(format!("unknown unit type: {}", unit).into()),
+    };
+    cstring(&format!("org.freedesktop.systemd1.{}", kind))
+}
+
+/// Convert `s` to a C string.
+fn cstring(s: &str) -> Result<CString> {
+    CString::new(s).chain_err(|| format!("invalid D-Bus string: {:?}", s))
+}
+
+/// An open bus connection, which we close when dropped.
+struct Bus(*mut SdBus);
+
+impl Bus {
+    /// Connect to the user's bus if `user` is true, and the system bus
+    /// otherwise.
+    fn open(user: bool) -> Result<Bus> {
+        let mut bus: *mut SdBus = ptr::null_mut();
+        let result = unsafe {
+            if user {
+                sd_bus_open_user(&mut bus)
+            } else {
+                sd_bus_open_system(&mut bus)
+            }
+        };
+        check(result, "connect to D-Bus")?;
+        Ok(Bus(bus))
+    }
+
+    /// Read an unsigned 64-bit property of the object at `path`.
+    fn get_u64(&self,
+               path: &CStr,
+               interface: &CStr,
+               property: &str)
+               -> Result<u64> {
+        let destination = cstring("org.freedesktop.systemd1")?;
+        let member = cstring(property)?;
+        let mut value: u64 = 0;
+        check(unsafe {
+                  sd_bus_get_property_trivial(self.0,
+                                              destination.as_ptr(),
+                                              path.as_ptr(),
+                                              interface.as_ptr(),
+                                              member.as_ptr(),
+                                              ptr::null_mut(),
+                                              b't' as c_char,
+                                              &mut value as *mut u64
+                                                  as *mut c_void)
+              },
+              &format!("read {}", property))?;
+        Ok(value)
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        unsafe { sd_bus_unref(self.0) };
+    }
+}
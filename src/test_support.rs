@@ -0,0 +1,209 @@
+//! Resource budgets for tests, so that CI can catch memory regressions
+//! using the same measurements we rely on in production.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate resource_monitor;
+//!
+//! # fn main() {
+//! let total = assert_memory_growth_under!(64 << 20, {
+//!     let buffer = vec![1u8; 1 << 20];
+//!     buffer.iter().map(|&b| b as u64).sum::<u64>()
+//! });
+//! assert_eq!(total, 1 << 20);
+//! # }
+//! ```
+//!
+//! Memory usage is shared by every thread in the process, so budgets are
+//! most reliable in tests which run alone, such as integration tests with
+//! a single test function, or with `--test-threads=1`.
+
+use std::thread;
+
+use bytes::Bytes;
+use cost::{CostMeter, CostReport};
+use errors::*;
+use snapshot::{Snapshot, SnapshotDiff};
+use Resource;
+
+/// How much each measurement may grow while a block runs.  Limits which are
+/// `None` aren't checked.  A limit which we can't measure on this system
+/// counts as exceeded, so that a test can't pass without checking it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceBudget {
+    memory_growth: Option<u64>,
+    peak_rss_growth: Option<u64>,
+    allocator_growth: Option<u64>,
+}
+
+impl ResourceBudget {
+    /// A budget with no limits.
+    pub fn new() -> ResourceBudget {
+        ResourceBudget::default()
+    }
+
+    /// Allow `Resource::Memory.used()` to grow by at most `bytes`.  This
+    /// includes page cache, so it can be noisy for code which does I/O.
+    pub fn memory_growth(mut self, bytes: u64) -> ResourceBudget {
+        self.memory_growth = Some(bytes);
+        self
+    }
+
+    /// Allow our peak resident set size to grow by at most `bytes`.  This
+    /// only grows when we exceed our previous peak, so it's most useful
+    /// early in a test process.
+    pub fn peak_rss_growth(mut self, bytes: u64) -> ResourceBudget {
+        self.peak_rss_growth = Some(bytes);
+        self
+    }
+
+    /// Allow at most `bytes` more to be allocated than freed on the heap.
+    /// This requires a `CountingAllocator` or the `jemalloc` feature, as
+    /// described for `CostReport::allocated_bytes`.
+    pub fn allocator_growth(mut self, bytes: u64) -> ResourceBudget {
+        self.allocator_growth = Some(bytes);
+        self
+    }
+
+    /// Describe each limit which `cost` and `diff` exceed.
+    fn violations(&self,
+                  cost: &CostReport,
+                  diff: &SnapshotDiff)
+                  -> Vec<String> {
+        let memory = diff.change(&Resource::Memory)
+            .map(|growth| growth.max(0) as u64);
+        let allocator = cost.net_allocated()
+            .map(|growth| growth.max(0) as u64);
+        let checks = [("memory", self.memory_growth, memory),
+                      ("peak RSS", self.peak_rss_growth, cost.peak_rss_growth),
+                      ("heap", self.allocator_growth, allocator)];
+        let mut violations = vec![];
+        for &(name, budget, growth) in &checks {
+            match (budget, growth) {
+                (Some(budget), Some(growth)) if growth > budget => {
+                    violations.push(format!("{} grew by {}, over its budget \
+                                             of {}",
+                                            name,
+                                            Bytes(growth),
+                                            Bytes(budget)))
+                }
+                (Some(_), None) => {
+                    violations.push(format!("could not measure {} growth",
+                                            name))
+                }
+                _ => {}
+            }
+        }
+        violations
+    }
+}
+
+/// Checks that everything done while it exists stays within a
+/// `ResourceBudget`, and panics when it's dropped if it doesn't.  Call
+/// `finish` to check at a particular point, or `check` to get an error
+/// instead of a panic.
+///
+/// ```
+/// use resource_monitor::test_support::{BudgetGuard, ResourceBudget};
+///
+/// let budget = ResourceBudget::new().memory_growth(64 << 20);
+/// let guard = BudgetGuard::new(budget);
+/// let buffer = vec![1u8; 1 << 20];
+/// guard.finish();
+/// # drop(buffer);
+/// ```
+#[derive(Debug)]
+pub struct BudgetGuard {
+    budget: ResourceBudget,
+    before: Snapshot,
+    meter: CostMeter,
+    checked: bool,
+}
+
+impl BudgetGuard {
+    /// Start measuring against `budget`.
+    pub fn new(budget: ResourceBudget) -> BudgetGuard {
+        BudgetGuard {
+            budget,
+            before: Snapshot::capture(&[Resource::Memory]),
+            meter: CostMeter::start(),
+            checked: false,
+        }
+    }
+
+    /// Return an error describing every limit we've exceeded so far.
+    pub fn check(&self) -> Result<()> {
+        let cost = self.meter.report();
+        let diff = Snapshot::capture(&[Resource::Memory]).diff(&self.before);
+        let violations = self.budget.violations(&cost, &diff);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("resource budget exceeded: {} ({:?} elapsed)",
+                        violations.join("; "),
+                        cost.elapsed)
+                .into())
+        }
+    }
+
+    /// Panic if we've exceeded our budget.  After this, dropping the guard
+    /// doesn't check again.
+    pub fn finish(mut self) {
+        self.checked = true;
+        if let Err(err) = self.check() {
+            panic!("{}", err);
+        }
+    }
+}
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        // Don't turn a failing test into an abort by panicking twice.
+        if self.checked || thread::panicking() {
+            return;
+        }
+        if let Err(err) = self.check() {
+            panic!("{}", err);
+        }
+    }
+}
+
+/// Run a block, and panic if it exceeds a `ResourceBudget`.  Evaluates to
+/// the value of the block.
+///
+/// ```
+/// #[macro_use]
+/// extern crate resource_monitor;
+///
+/// use resource_monitor::test_support::ResourceBudget;
+///
+/// # fn main() {
+/// let budget = ResourceBudget::new()
+///     .memory_growth(64 << 20)
+///     .peak_rss_growth(64 << 20);
+/// assert_within_budget!(budget, {
+///     let _buffer = vec![1u8; 1 << 20];
+/// });
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_within_budget {
+    ($budget:expr, $body:block) => {{
+        let guard = $crate::test_support::BudgetGuard::new($budget);
+        let result = $body;
+        guard.finish();
+        result
+    }};
+}
+
+/// Run a block, and panic if `Resource::Memory.used()` grows by more than
+/// the given number of bytes.  Evaluates to the value of the block.  See
+/// the `test_support` module for an example.
+#[macro_export]
+macro_rules! assert_memory_growth_under {
+    ($bytes:expr, $body:block) => {
+        $crate::assert_within_budget!(
+            $crate::test_support::ResourceBudget::new().memory_growth($bytes),
+            $body)
+    };
+}
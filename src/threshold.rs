@@ -0,0 +1,127 @@
+//! Detecting when a resource crosses a usage threshold, with hysteresis
+//! so that we don't send a flood of notifications when usage hovers near
+//! the threshold.
+
+use snapshot::{Reading, Snapshot};
+use Resource;
+
+/// A level of resource usage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// A fraction of the resource's limit, between 0.0 and 1.0.  Ignored
+    /// for resources without a limit.
+    Fraction(f64),
+    /// An absolute amount of the resource.
+    Used(u64),
+}
+
+impl Threshold {
+    /// How far past this threshold are we?  Returns a value `>= 0.0` if
+    /// we've reached it, and `None` if we can't tell.
+    fn compare(&self, reading: &Reading) -> Option<f64> {
+        let used = reading.used?;
+        match *self {
+            Threshold::Fraction(f) => {
+                match reading.limit {
+                    Some(limit) if limit > 0 => {
+                        Some(used as f64 / limit as f64 - f)
+                    }
+                    _ => None,
+                }
+            }
+            Threshold::Used(u) => Some(used as f64 - u as f64),
+        }
+    }
+
+    /// Return a slightly lower threshold, used as the default recovery
+    /// point.
+    fn default_recovery(&self) -> Threshold {
+        match *self {
+            Threshold::Fraction(f) => Threshold::Fraction(f - 0.05),
+            Threshold::Used(u) => Threshold::Used(u - u / 20),
+        }
+    }
+}
+
+/// Which way a threshold was crossed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    /// Usage rose to or above the trigger threshold.
+    Exceeded,
+    /// Usage fell below the recovery threshold.
+    Recovered,
+}
+
+/// Sent to threshold callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThresholdEvent {
+    /// Which way we crossed the threshold.
+    pub crossing: Crossing,
+    /// The reading which caused the crossing.
+    pub reading: Reading,
+}
+
+/// Tracks whether a single resource is above or below a threshold.
+#[derive(Debug, Clone)]
+pub struct ThresholdWatch {
+    resource: Resource,
+    trigger: Threshold,
+    recover: Threshold,
+    exceeded: bool,
+}
+
+impl ThresholdWatch {
+    /// Watch `resource`, reporting `Exceeded` when usage reaches
+    /// `trigger`, and `Recovered` once it falls below `recover`.
+    pub fn new(resource: Resource,
+               trigger: Threshold,
+               recover: Threshold)
+               -> ThresholdWatch {
+        ThresholdWatch {
+            resource,
+            trigger,
+            recover,
+            exceeded: false,
+        }
+    }
+
+    /// Watch `resource`, using a recovery threshold 5% below `trigger`.
+    pub fn with_default_recovery(resource: Resource,
+                                 trigger: Threshold)
+                                 -> ThresholdWatch {
+        let recover = trigger.default_recovery();
+        ThresholdWatch::new(resource, trigger, recover)
+    }
+
+    /// The resource we're watching.
+    pub fn resource(&self) -> &Resource {
+        &self.resource
+    }
+
+    /// Are we currently above the threshold?
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    /// Check a new snapshot, and return an event if we crossed the
+    /// threshold in either direction.
+    pub fn update(&mut self, snapshot: &Snapshot) -> Option<ThresholdEvent> {
+        let reading = snapshot.get(&self.resource)?;
+        let crossing = if self.exceeded {
+            if self.recover.compare(reading)? < 0.0 {
+                Crossing::Recovered
+            } else {
+                return None;
+            }
+        } else if self.trigger.compare(reading)? >= 0.0 {
+            Crossing::Exceeded
+        } else {
+            return None;
+        };
+        self.exceeded = crossing == Crossing::Exceeded;
+        Some(ThresholdEvent {
+            crossing,
+            reading: reading.clone(),
+        })
+    }
+}
@@ -0,0 +1,65 @@
+//! Space on memory-backed filesystems, such as `/dev/shm`.  These are
+//! usually much smaller than RAM, so shared-memory users can run out of
+//! space (`ENOSPC`) long before we run out of memory.
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use errors::*;
+#[cfg(not(unix))]
+use Resource;
+
+/// Size and free space of the filesystem mounted at `path`, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsSpace {
+    /// The size of the filesystem.
+    pub total: u64,
+    /// Space which is in use.
+    pub used: u64,
+    /// Space which unprivileged processes may still use.
+    pub available: u64,
+    /// Files which unprivileged processes may still create, or `u64::MAX`
+    /// if the filesystem doesn't have a fixed number of inodes.
+    pub inodes_available: u64,
+}
+
+impl FsSpace {
+    /// Call `statvfs` on `path`.  This works for any filesystem, but it's
+    /// mostly useful for `tmpfs` mounts, whose contents live in RAM.
+    #[cfg(unix)]
+    pub fn read(path: &Path) -> Result<FsSpace> {
+        let mkerr = || ErrorKind::File(path.to_owned());
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .chain_err(mkerr)?;
+        let mut st: libc::statvfs = unsafe { mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut st) } != 0 {
+            return Err(io::Error::last_os_error()).chain_err(mkerr);
+        }
+        let block = st.f_frsize as u64;
+        let (blocks, free) = (st.f_blocks as u64, st.f_bfree as u64);
+        Ok(FsSpace {
+            total: blocks * block,
+            used: blocks.saturating_sub(free) * block,
+            available: st.f_bavail as u64 * block,
+            inodes_available: match st.f_files {
+                0 => u64::MAX,
+                _ => st.f_favail as u64,
+            },
+        })
+    }
+
+    /// Without `statvfs`, we can't measure filesystems.
+    #[cfg(not(unix))]
+    pub fn read(path: &Path) -> Result<FsSpace> {
+        Err(ErrorKind::Unsupported(Resource::Tmpfs(path.to_owned())).into())
+    }
+}
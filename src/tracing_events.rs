@@ -0,0 +1,103 @@
+//! Reporting samples, threshold crossings and state changes as structured
+//! `tracing` events, so that resource pressure shows up alongside request
+//! traces.
+
+use tracing::{debug, info, warn, Span};
+
+use monitor::MonitorEvent;
+use snapshot::Snapshot;
+use threshold::Crossing;
+use Resource;
+
+/// Emit a `tracing` event for each of `events`.  Samples are reported at
+/// `DEBUG` level, and everything else at `INFO` or `WARN`.
+pub fn trace_events(events: &[MonitorEvent]) {
+    for event in events {
+        match *event {
+            MonitorEvent::Sample(ref snapshot) => {
+                for r in &snapshot.readings {
+                    debug!(resource = ?r.resource,
+                           used = r.used,
+                           limit = r.limit,
+                           fraction = r.fraction_used(),
+                           "resource sample");
+                }
+            }
+            MonitorEvent::ReadError(ref err) => {
+                warn!(resource = ?err.resource,
+                      value = err.value,
+                      error = %err.message,
+                      "could not read resource");
+            }
+            MonitorEvent::Threshold(ref evt) => {
+                let r = &evt.reading;
+                match evt.crossing {
+                    Crossing::Exceeded => {
+                        warn!(resource = ?r.resource,
+                              used = r.used,
+                              limit = r.limit,
+                              fraction = r.fraction_used(),
+                              "resource threshold exceeded");
+                    }
+                    Crossing::Recovered => {
+                        info!(resource = ?r.resource,
+                              used = r.used,
+                              limit = r.limit,
+                              fraction = r.fraction_used(),
+                              "resource threshold recovered");
+                    }
+                }
+            }
+            MonitorEvent::StateChanged(ref t) => {
+                if t.to > t.from {
+                    warn!(from = ?t.from,
+                          state = ?t.to,
+                          fraction = t.fraction,
+                          "memory state changed");
+                } else {
+                    info!(from = ?t.from,
+                          state = ?t.to,
+                          fraction = t.fraction,
+                          "memory state changed");
+                }
+            }
+            MonitorEvent::LeakSuspected(ref leak) => {
+                warn!(resource = ?leak.resource,
+                      growth_rate = leak.growth_rate,
+                      duration = ?leak.duration,
+                      "possible memory leak");
+            }
+            MonitorEvent::OomKill(count) => {
+                warn!(count, "OOM killer killed processes in our cgroup");
+            }
+            MonitorEvent::WatchdogTriggered(ref snapshot) => {
+                let available = snapshot.get(&Resource::Memory)
+                    .and_then(|r| r.available);
+                warn!(available, "memory watchdog triggered");
+            }
+            MonitorEvent::GuardrailViolated(ref result) => {
+                warn!(metric = %result.rule.metric,
+                      max = result.rule.max,
+                      value = result.value,
+                      "guardrail violated");
+            }
+        }
+    }
+}
+
+/// Record memory usage from `snapshot` on `span`.  The span must declare
+/// the fields `memory_used`, `memory_limit` and `memory_fraction` (using
+/// `tracing::field::Empty`), or they will be ignored.
+pub fn record_memory(span: &Span, snapshot: &Snapshot) {
+    if let Some(r) = snapshot.get(&Resource::Memory) {
+        if let Some(used) = r.used {
+            span.record("memory_used", used);
+        }
+        if let Some(limit) = r.limit {
+            span.record("memory_limit", limit);
+        }
+        if let Some(fraction) = r.fraction_used() {
+            span.record("memory_fraction", fraction);
+        }
+    }
+}
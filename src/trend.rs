@@ -0,0 +1,63 @@
+//! Estimating how fast a resource is growing, and when it will run out.
+
+use std::time::{Duration, SystemTime};
+
+use snapshot::Snapshot;
+use Resource;
+
+/// Fit a straight line to the `used` values of `resource` in `history`,
+/// which must be in chronological order, and return its slope in bytes
+/// per second.  Returns `None` unless we have at least two samples at
+/// different times.
+pub fn growth_rate<'a, I>(history: I, resource: &Resource) -> Option<f64>
+    where I: IntoIterator<Item = &'a Snapshot>
+{
+    let mut start: Option<SystemTime> = None;
+    let mut points = vec![];
+    for snapshot in history {
+        let used = match snapshot.get(resource).and_then(|r| r.used) {
+            Some(used) => used as f64,
+            None => continue,
+        };
+        let start = *start.get_or_insert(snapshot.taken_at);
+        let t = snapshot.taken_at
+            .duration_since(start)
+            .unwrap_or_default()
+            .as_secs_f64();
+        points.push((t, used));
+    }
+    if points.len() < 2 {
+        return None;
+    }
+
+    // Ordinary least squares.
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_u = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut cov, mut var) = (0.0, 0.0);
+    for &(t, u) in &points {
+        cov += (t - mean_t) * (u - mean_u);
+        var += (t - mean_t) * (t - mean_t);
+    }
+    if var == 0.0 {
+        None
+    } else {
+        Some(cov / var)
+    }
+}
+
+/// How long until `resource` reaches its limit, if it keeps growing at
+/// `rate` bytes per second from the values in `latest`?  Returns `None`
+/// if it isn't growing, or has no limit.
+pub fn time_to_limit(latest: &Snapshot,
+                     resource: &Resource,
+                     rate: f64)
+                     -> Option<Duration> {
+    let reading = latest.get(resource)?;
+    let (used, limit) = (reading.used?, reading.limit?);
+    if rate <= 0.0 || limit == u64::MAX {
+        return None;
+    }
+    let remaining = limit.saturating_sub(used) as f64;
+    Duration::try_from_secs_f64(remaining / rate).ok()
+}
@@ -0,0 +1,111 @@
+//! Dirty page cache and pages under writeback, so that write-heavy
+//! applications can slow down their flushing when the kernel is already
+//! behind, instead of waiting until `write` blocks.
+
+use std::path::{Path, PathBuf};
+
+use cgroup;
+use errors::*;
+use memory_stat::MemoryStat;
+use read_file_u64;
+
+/// How much written data is waiting to reach disk, in bytes.
+///
+/// ```
+/// use resource_monitor::Writeback;
+///
+/// if let Ok(writeback) = Writeback::read() {
+///     if writeback.dirty_fraction().is_some_and(|f| f > 0.8) {
+///         println!("the kernel is behind on writeback; flushing less");
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Writeback {
+    /// Page cache in our cgroup waiting to be written, from
+    /// `memory.stat`, or `None` if we couldn't read it.
+    pub cgroup_dirty: Option<u64>,
+    /// Page cache in our cgroup being written, from `memory.stat`.
+    pub cgroup_writeback: Option<u64>,
+    /// Page cache on the whole system waiting to be written, from `Dirty`
+    /// in `/proc/meminfo`.
+    pub system_dirty: u64,
+    /// Page cache on the whole system being written, from `Writeback` in
+    /// `/proc/meminfo`.
+    pub system_writeback: u64,
+    /// The amount of dirty memory at which the kernel starts writing it
+    /// back in the background.  This is `vm.dirty_background_bytes`, or
+    /// `vm.dirty_background_ratio` of `MemAvailable`, which approximates
+    /// the memory the kernel counts.
+    pub background_threshold: Option<u64>,
+    /// The amount of dirty memory at which the kernel makes processes
+    /// which are writing wait for writeback, from `vm.dirty_bytes` or
+    /// `vm.dirty_ratio` in the same way.
+    pub dirty_threshold: Option<u64>,
+}
+
+impl Writeback {
+    /// Read our cgroup's `memory.stat`, `/proc/meminfo` and the kernel's
+    /// writeback settings.  Only `/proc/meminfo` is required.
+    pub fn read() -> Result<Writeback> {
+        let path = PathBuf::from("/proc/meminfo");
+        let meminfo = cgroup::read_keyed_file(&path)?;
+        let get = |key| {
+            meminfo.get(key)
+                .map(|&kb| kb * 1024)
+                .ok_or_else(|| Error::from(ErrorKind::Parse(path.clone())))
+        };
+        let available = get("MemAvailable:").ok();
+        let stat = MemoryStat::read().ok();
+        Ok(Writeback {
+            cgroup_dirty: stat.as_ref().and_then(|s| s.dirty),
+            cgroup_writeback: stat.as_ref().and_then(|s| s.writeback),
+            system_dirty: get("Dirty:")?,
+            system_writeback: get("Writeback:")?,
+            background_threshold: threshold("dirty_background", available),
+            dirty_threshold: threshold("dirty", available),
+        })
+    }
+
+    /// Our cgroup's dirty and writeback pages together, in bytes.
+    pub fn cgroup_backlog(&self) -> Option<u64> {
+        Some(self.cgroup_dirty? + self.cgroup_writeback?)
+    }
+
+    /// The system's dirty and writeback pages together, in bytes.
+    pub fn system_backlog(&self) -> u64 {
+        self.system_dirty + self.system_writeback
+    }
+
+    /// The system's backlog as a fraction of `dirty_threshold`.  When this
+    /// reaches 1.0, writers start blocking, so pace flushing well before
+    /// then.
+    pub fn dirty_fraction(&self) -> Option<f64> {
+        match self.dirty_threshold? {
+            0 => None,
+            t => Some(self.system_backlog() as f64 / t as f64),
+        }
+    }
+
+    /// Has the system's dirty memory passed `background_threshold`, so
+    /// that the kernel is already writing it back?
+    pub fn is_flushing(&self) -> bool {
+        self.background_threshold
+            .is_some_and(|t| self.system_dirty >= t)
+    }
+}
+
+/// Read `vm.<name>_bytes`, or if that's 0, apply `vm.<name>_ratio` to
+/// `available`.
+fn threshold(name: &str, available: Option<u64>) -> Option<u64> {
+    let dir = Path::new("/proc/sys/vm");
+    match read_file_u64(&dir.join(format!("{}_bytes", name))).ok()? {
+        0 => {
+            let ratio =
+                read_file_u64(&dir.join(format!("{}_ratio", name))).ok()?;
+            Some(available? / 100 * ratio)
+        }
+        bytes => Some(bytes),
+    }
+}